@@ -1,5 +1,7 @@
 use std::str::FromStr;
-use std::io;
+use std::cmp;
+use std::io::{self, Write, BufWriter};
+use std::fs::{self, File};
 use std::collections::HashMap;
 
 use crate::config::*;
@@ -9,7 +11,7 @@ use super::cli_utils;
 use clap::ArgMatches;
 use crate::dbsnp;
 
-pub const OPTS: [(&str, ConfVar);21] = [
+pub const OPTS: [(&str, ConfVar);37] = [
 	("haploid", ConfVar::Bool(false)),
 	("keep_duplicates", ConfVar::Bool(false)),
 	("keep_supplementary", ConfVar::Bool(false)),
@@ -18,10 +20,18 @@ pub const OPTS: [(&str, ConfVar);21] = [
 	("blank_trim", ConfVar::Bool(false)),
 	("benchmark_mode", ConfVar::Bool(false)),
 	("all_positions", ConfVar::Bool(false)),
+	("no_indels", ConfVar::Bool(false)),
+	("auto_trim", ConfVar::Bool(false)),
+	("auto_conversion", ConfVar::Bool(false)),
+	("rrbs", ConfVar::Bool(false)),
 	("filter_contigs", ConfVar::Bool(false)),
+	("dbsnp_priors", ConfVar::Bool(false)),
 	("mapq_threshold", ConfVar::Int(20)),
 	("bq_threshold", ConfVar::Int(13)),
 	("max_template_length", ConfVar::Int(1000)),
+	("auto_trim_reads", ConfVar::Int(1000000)),
+	("conversion_reads", ConfVar::Int(1000000)),
+	("umi_mismatches", ConfVar::Int(1)),
 	("reference_bias", ConfVar::Float(2.0)),
 	("sample", ConfVar::String(None)),
 	("output", ConfVar::String(None)),
@@ -31,11 +41,18 @@ pub const OPTS: [(&str, ConfVar);21] = [
 	("contig_include", ConfVar::String(None)),
 	("dbsnp", ConfVar::String(None)),	
 	("report_file", ConfVar::String(None)),	
+	("asm_output", ConfVar::String(None)),	
+	("read_meth_output", ConfVar::String(None)),	
+	("umi_tag", ConfVar::String(None)),	
+	("sex", ConfVar::String(None)),	
+	("ploidy_map", ConfVar::String(None)),	
+	("chemistry", ConfVar::String(None)),	
+	("underconversion_sequence", ConfVar::String(None)),	
+	("overconversion_sequence", ConfVar::String(None)),	
 ];
 
-fn distribute_threads(conf_hash: &mut HashMap<&'static str, ConfVar>, in_file: &mut htslib::SamFile, out_file: &mut htslib::VcfFile) -> io::Result<()> {
-	let format = in_file.format();
-	let input_compressed = format.is_compressed();
+fn distribute_threads(conf_hash: &mut HashMap<&'static str, ConfVar>, in_files: &mut [htslib::SamFile], out_file: &mut htslib::VcfFile) -> io::Result<()> {
+	let input_compressed = in_files.iter().any(|f| f.format().is_compressed());
 	let otype = if let Some(ConfVar::OType(x)) = conf_hash.get(&"output_type") { *x } else { panic!("Output_type config var not set"); };
 	let output_compressed = otype.is_compressed();
 	let mut nn = 20;
@@ -56,11 +73,37 @@ fn distribute_threads(conf_hash: &mut HashMap<&'static str, ConfVar>, in_file: &
 	conf_hash.insert(&"calc_threads", ConfVar::Int(calc_threads));
 	conf_hash.insert(&"input_threads", ConfVar::Int(input_threads));
 	conf_hash.insert(&"output_threads", ConfVar::Int(output_threads));	
-	if input_threads > 0 { in_file.set_threads(input_threads)? }
+	if input_threads > 0 {
+		// Input threads are shared between the input files
+		let t = cmp::max(1, input_threads / in_files.len());
+		for in_file in in_files.iter_mut() { in_file.set_threads(t)? }
+	}
 	if output_threads > 0 { out_file.set_threads(output_threads)? }
 	Ok(())
 }
 
+// Check that all input files have the same sequence dictionary as the first
+fn check_input_headers(in_files: &[htslib::SamFile]) -> io::Result<()> {
+	let f0 = &in_files[0];
+	for (ix, f) in in_files.iter().enumerate().skip(1) {
+		if f.nref() != f0.nref() || (0..f0.nref()).any(|i| f.tid2name(i) != f0.tid2name(i) || f.tid2len(i) != f0.tid2len(i)) {
+			return Err(new_err(format!("Sequence dictionary of input file {} does not match that of the first input file", ix + 1)))
+		}
+	}
+	Ok(())
+}
+
+// Check that the input files can be opened a second time (i.e., are not stdin or pipes), as required if
+// a sample of the reads is looked at before the main pass through the data
+fn check_rereadable_inputs(in_names: &[&str], opt: &str) -> io::Result<()> {
+	for name in in_names.iter() {
+		if *name == "-" || !fs::metadata(name).map(|m| m.is_file()).unwrap_or(true) {
+			return Err(new_err(format!("Option {} can not be used with input {} as it can only be read once", opt, name)))
+		}
+	}
+	Ok(())
+}
+
 pub fn get_trim_values(m: &ArgMatches, name: &str) -> io::Result<(usize, usize)> {
 	if let Some(v) = cli_utils::get_ivec(m, name, 0, 63)? {
 		match v.len() {
@@ -83,11 +126,32 @@ pub fn handle_options(m: &ArgMatches) -> io::Result<(BsCallConfig, BsCallFiles)>
 		conf_hash.insert(opt, x);
 	}
 	
+	// In RRBS mode reads from the same MspI fragment share their start positions, so position based
+	// duplicate removal (and the duplicate flag from the SAM records) is switched off
+	if let Some(ConfVar::Bool(true)) = conf_hash.get("rrbs") {
+		conf_hash.insert("keep_duplicates", ConfVar::Bool(true));
+		conf_hash.insert("ignore_duplicates", ConfVar::Bool(true));
+	}
+	
+	// With UMIs, duplicates are identified by position and UMI so the (position based) duplicate flag
+	// from the SAM records is ignored
+	if let Some(ConfVar::String(Some(tag))) = conf_hash.get("umi_tag") {
+		if tag.len() != 2 { return Err(new_err(format!("Invalid UMI tag '{}': SAM tags should have 2 characters", tag))) }
+		conf_hash.insert("ignore_duplicates", ConfVar::Bool(true));
+	}
+	
 	// And now the odd options
 	
-	// Conversion rates
+	// Conversion chemistry (stored in normalized form)
+	let chemistry = match conf_hash.get("chemistry") {
+		Some(ConfVar::String(Some(s))) => <process::Chemistry>::from_str(s).map_err(|_| new_err(format!("Couldn't parse chemistry argument '{}': should be bisulfite, em-seq or taps", s)))?,
+		_ => process::Chemistry::Bisulfite,
+	};
+	conf_hash.insert("chemistry", ConfVar::String(Some(chemistry.to_string())));
+	
+	// Conversion rates (defaults depend on the chemistry)
 	let (under, over) = if let Some(v) = cli_utils::get_fvec(m, "conversion", 1.0e-8, 1.0 - 1.0e-8)? { (v[0], v[1]) }
-	else { (0.01, 0.05) };
+	else { chemistry.default_conversion() };
 	conf_hash.insert(&"under_conversion", ConfVar::Float(under));
 	conf_hash.insert(&"over_conversion", ConfVar::Float(over));	
 	
@@ -115,29 +179,71 @@ pub fn handle_options(m: &ArgMatches) -> io::Result<(BsCallConfig, BsCallFiles)>
 	} else { OType::new(htslib::FT_VCF) };
 	conf_hash.insert(&"output_type", ConfVar::OType(otype));
 	
-	// Input file
-	let mut in_file= process::open_sam_input(m.value_of("input"))?;
+	// Input files (one per sample)
+	let in_names: Vec<&str> = if let Some(v) = m.values_of("input") { v.collect() } else { vec!("-") };
+	let mut in_files = in_names.iter().map(|f| process::open_sam_input(Some(f))).collect::<io::Result<Vec<_>>>()?;
+	check_input_headers(&in_files)?;
+	if let Some(ConfVar::String(Some(s))) = conf_hash.get(&"report_file") {
+		let n = s.split(',').count();
+		if n != in_files.len() { return Err(new_err(format!("Number of report files ({}) does not match number of input files ({})", n, in_files.len()))) }
+	}
 	
 	// Output file
 	let mut out_file = process::open_vcf_output(ocopy.as_deref(), otype)?;
 	
 	// Threads
 	conf_hash.insert(&"threads", cli_utils::get_option(m, "threads", ConfVar::Int(num_cpus::get()))?);
-	distribute_threads(&mut conf_hash, &mut in_file, &mut out_file)?;
+	distribute_threads(&mut conf_hash, &mut in_files, &mut out_file)?;
 	
-	let chash = ConfHash::new(conf_hash);
+	let mut chash = ConfHash::new(conf_hash);
 	// Reference
 	let rf = chash.get_str(&"reference");
-	let ref_idx = reference::handle_reference(rf.unwrap(), &mut in_file)?;
+	let ref_idx = reference::handle_reference(rf.unwrap(), &mut in_files)?;
 	
 	// dbSNP index
 	let dbsnp_index = if let Some(dbsnp_file) = chash.get_str(&"dbsnp") { Some(dbsnp::DBSnpIndex::new(dbsnp_file)?) } else { None };
+	if chash.get_bool("dbsnp_priors") {
+		match &dbsnp_index {
+			None => warn!("dbSNP priors requested without a dbSNP index: option ignored"),
+			Some(idx) if !idx.has_freqs() => warn!("dbSNP index does not have allele frequency information (rebuild with a newer version of dbsnp_index): dbSNP priors will not be used"),
+			_ => (),
+		}
+	}
 	
 	// Set up contigs and contig regions
-	let (ctgs, ctg_regions) = defs::setup_contigs(&chash, &in_file, &ref_idx)?;
-	in_file.set_region_itr(&ctg_regions);
-	let bs_cfg = BsCallConfig::new(chash, ctgs, ctg_regions);
-	let bs_files = BsCallFiles::new(in_file, out_file, ref_idx, dbsnp_index);
+	let (ctgs, ctg_regions) = defs::setup_contigs(&chash, &in_files[0], &ref_idx)?;
+	for in_file in in_files.iter_mut() { in_file.set_region_itr(&ctg_regions) }
+	
+	// Estimate read trimming from M-bias profile
+	if chash.get_bool("auto_trim") { 
+		check_rereadable_inputs(&in_names, "--auto-trim")?;
+		process::estimate_trims(&mut chash, &in_names, &ctg_regions, &ref_idx)? 
+	}
+	
+	// Estimate conversion rates from control sequences and CHH sites
+	let conversion = if chash.get_bool("auto_conversion") { 
+		check_rereadable_inputs(&in_names, "--auto-conversion")?;
+		process::estimate_conversion(&chash, &in_names, &ctg_regions, &ref_idx)? 
+	} else { Vec::new() };
+	
+	// Ploidy of contigs / regions
+	let ploidy = defs::PloidyMap::setup(&chash, &in_files[0])?;
+	let mut bs_cfg = BsCallConfig::new(chash, ctgs, ctg_regions, ploidy);
+	bs_cfg.conversion = conversion;
+	let mut bs_files = BsCallFiles::new(in_files, out_file, ref_idx, dbsnp_index);
+	
+	// Allele specific methylation output
+	if let Some(f) = bs_cfg.conf_hash.get_str("asm_output") {
+		let mut wrt = BufWriter::new(File::create(f).map_err(|e| new_err(format!("Couldn't open ASM output file {}: {}", f, e)))?);
+		writeln!(wrt, "{}", process::ASM_HEADER)?;
+		bs_files.asm_output = Some(Box::new(wrt));
+	}
+	// Read level methylation output (bgzip compressed so it can be indexed with tabix)
+	if let Some(f) = bs_cfg.conf_hash.get_str("read_meth_output") {
+		let mut file = htslib::HtsFile::new(f, "wz").map_err(|e| new_err(format!("Couldn't open read level methylation output file {}: {}", f, e)))?;
+		writeln!(file.as_mut(), "{}", process::READ_METH_HEADER)?;
+		bs_files.read_meth_output = Some(file);
+	}
 
 	Ok((bs_cfg, bs_files))
 }
@@ -1,10 +1,12 @@
 use std::collections::HashMap;
 use std::str::FromStr;
 use std::{io, fmt};
-use std::io::{Error, ErrorKind};
+use std::io::{Error, ErrorKind, Write};
 
 use crate::htslib;
-use crate::defs::{CtgRegion, CtgInfo};
+use crate::defs::{CtgRegion, CtgInfo, PloidyMap};
+use crate::process::Chemistry;
+use crate::stats::ConversionEstimate;
 
 use crate::dbsnp;
 
@@ -95,11 +97,14 @@ pub struct BsCallConfig {
 	pub conf_hash: ConfHash,
 	pub contigs: Vec<CtgInfo>,
 	pub regions: Vec<CtgRegion>,
+	pub samples: Vec<String>,
+	pub ploidy: PloidyMap,
+	pub conversion: Vec<ConversionEstimate>, // Estimated conversion rates (empty if not estimated)
 }
 
 impl BsCallConfig {
-	pub fn new(conf_hash: ConfHash, contigs: Vec<CtgInfo>, regions: Vec<CtgRegion>) -> Self { 
-		Self{conf_hash, contigs, regions} 
+	pub fn new(conf_hash: ConfHash, contigs: Vec<CtgInfo>, regions: Vec<CtgRegion>, ploidy: PloidyMap) -> Self { 
+		Self{conf_hash, contigs, regions, samples: Vec::new(), ploidy, conversion: Vec::new()} 
 	}
 	
 	pub fn set_conf(&mut self, key: &'static str, var: ConfVar) -> Option<ConfVar> {
@@ -112,22 +117,35 @@ impl BsCallConfig {
 	pub fn get_conf_float(&self, key: &str) -> f64 { self.conf_hash.get_float(key) }
 	pub fn get_conf_str(&self, key: &str) -> Option<&str> { self.conf_hash.get_str(key) }
 	pub fn get_conf_otype(&self) -> OType { self.conf_hash.get_otype() }
+	pub fn chemistry(&self) -> Chemistry { 
+		self.conf_hash.get_str("chemistry").and_then(|s| <Chemistry>::from_str(s).ok()).unwrap_or(Chemistry::Bisulfite) 
+	}
+	// Under and over conversion rates for sample - estimated if available, otherwise from the conf_hash
+	pub fn conversion_rates(&self, sample: usize) -> (f64, f64) {
+		match self.conversion.iter().find(|c| c.sample_idx == sample && c.read_group.is_none()) {
+			Some(c) => (c.under_conversion, c.over_conversion),
+			None => (self.conf_hash.get_float("under_conversion"), self.conf_hash.get_float("over_conversion")),
+		}
+	}
 	pub fn add_contigs(&mut self, ctgs: &mut[CtgInfo]) { self.contigs.extend_from_slice(ctgs); }
 	pub fn ctg_in_header(&self, idx: usize) -> bool { self.contigs[idx].in_header() }
 	pub fn ctg_vcf_id(&self, idx: usize) -> Option<usize> { self.contigs[idx].vcf_id() }
 	pub fn ctg_ref_id(&self, idx: usize) -> Option<usize> { self.contigs[idx].ref_id() }
 	pub fn ctg_name(&self, idx: usize) -> &str { self.contigs[idx].name() }
+	pub fn sample_name(&self, idx: usize) -> &str { &self.samples[idx] }
 }
 
 pub struct BsCallFiles {
-	pub sam_input: Option<htslib::SamFile>,
+	pub sam_input: Option<Vec<htslib::SamFile>>, // One input file per sample
 	pub ref_index: Option<htslib::Faidx>,
 	pub vcf_output: Option<htslib::VcfFile>,
 	pub dbsnp_index: Option<dbsnp::DBSnpIndex>,	
+	pub asm_output: Option<Box<dyn Write + Send>>,
+	pub read_meth_output: Option<htslib::HtsFile>,
 }
 
 impl BsCallFiles {
-	pub fn new(sam_input: htslib::SamFile, vcf_output: htslib::VcfFile, ref_index: htslib::Faidx, dbsnp_index: Option<dbsnp::DBSnpIndex>) -> Self { 
-		Self{sam_input: Some(sam_input), vcf_output: Some(vcf_output), ref_index: Some(ref_index), dbsnp_index} 
+	pub fn new(sam_input: Vec<htslib::SamFile>, vcf_output: htslib::VcfFile, ref_index: htslib::Faidx, dbsnp_index: Option<dbsnp::DBSnpIndex>) -> Self { 
+		Self{sam_input: Some(sam_input), vcf_output: Some(vcf_output), ref_index: Some(ref_index), dbsnp_index, asm_output: None, read_meth_output: None} 
 	}	
 }
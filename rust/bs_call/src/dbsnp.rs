@@ -32,7 +32,7 @@ impl DBSnpFile {
 		match self.index.dbsnp.get_mut(name) {
 			Some(ctg) => {
 				info!("Loading dbSNP data for {}", name);	
-				ctg.load_data(&mut self.file, self.index.bufsize)?;			
+				ctg.load_data(&mut self.file, self.index.bufsize, self.index.version)?;			
 				info!("dbSNP data loaded");			
 			},
 			None => {
@@ -46,6 +46,13 @@ impl DBSnpFile {
 	}
 }
 
+// Alternate allele frequency and (if known) the reference and alternate alleles of a SNP.  Alleles are encoded as A=1, C=2, G=3, T=4
+#[derive(Copy, Clone, Debug)]
+pub struct SnpFreq {
+	pub af: f64,
+	pub alleles: Option<[u8; 2]>,
+}
+
 #[derive(Clone)]
 pub struct DBSnpContig {
 	min_bin: usize,
@@ -63,6 +70,15 @@ impl DBSnpContig {
 		}
 		None
 	}
+	pub fn lookup_freq(&self, x: usize) -> Option<SnpFreq> {
+		if let Some(bins) = &self.bins {
+			let bn = (x + 1) >> 8;
+			if bn >= self.min_bin && bn <= self.max_bin {
+				if let Some(bin) = &bins[bn - self.min_bin] { return bin.lookup_freq((x + 1) & 255) }
+			}
+		}
+		None
+	}
 }
 
 pub struct DBSnpIndex {
@@ -70,6 +86,7 @@ pub struct DBSnpIndex {
 	dbsnp: HashMap<String, DBSnpCtg>,
 	bufsize: usize,
 	header: String,	
+	version: u8,
 }
 
 impl DBSnpIndex {
@@ -82,7 +99,7 @@ impl DBSnpIndex {
 		if td[0] != 0xd7278434 { return Err(new_err(format!("Invalid format: bad magic number {:x}",td[0]))) }
 		trace!("Magic number OK");
 		let vs = read_n(&mut file, 4)?;
-		if vs[0] != 2 { return Err(new_err("Invalid version number".to_string())) }
+		if vs[0] != 2 && vs[0] != 3 { return Err(new_err("Invalid version number".to_string())) }
 		let mut td1 = [0u64; 3];
 		read_u64(&mut file, &mut td1)?;
 		file.seek(SeekFrom::Start(td1[0]))?;
@@ -114,10 +131,12 @@ impl DBSnpIndex {
 			trace!("Contigs read in OK");
 			info!("Read dbSNP header from {} with data on {} contigs", filename, n_ctgs);
 			info!("Header line: {}", header);
-			Ok(Self{filename: filename.to_owned(), dbsnp, bufsize: td1[1] as usize, header})
+			Ok(Self{filename: filename.to_owned(), dbsnp, bufsize: td1[1] as usize, header, version: vs[0]})
 		}
 	}
 	pub fn header(&self) -> &str { &self.header }
+	// Allele frequency information is only present from version 3 of the index format
+	pub fn has_freqs(&self) -> bool { self.version >= 3 }
 }
 
 /// 
@@ -132,6 +151,7 @@ struct DBSnpBin {
 	mask: [u128; 2],
 	name_len: Box<[u8]>,
 	name_buf: Box<[u8]>,
+	freq: Option<Box<[[u8; 2]]>>,
 }
 
 const DTAB: [char; 16] = [ '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', '?', '?', '?', '?', '?', '?'];
@@ -156,14 +176,27 @@ const WTAB: [&str; 256] = [
 ];
 
 impl DBSnpBin {
-	fn lookup_rs(&self, ix: usize) -> Option<(String, bool)> {
+	// Index of entry for position ix in the bin (if present)
+	fn entry_idx(&self, ix: usize) -> Option<usize> {
 		let (k, mk) = if ix < 128 { (0, 1u128 << ix) } else { (1, 1u128 << (ix & 127)) };
 		if (self.mask[k] & mk) != 0 {
-			let n_prev_entries = if k == 0 {
+			Some(if k == 0 {
 				(self.mask[k] & (mk - 1)).count_ones() as usize
 			} else {
 				(self.mask[0].count_ones() + (self.mask[1] & (mk - 1)).count_ones()) as usize
-			};
+			})
+		} else { None }
+	}
+	
+	fn lookup_freq(&self, ix: usize) -> Option<SnpFreq> {
+		let [m, a] = self.freq.as_ref()?[self.entry_idx(ix)?];
+		if m == 0 { return None }
+		let alleles = if (a >> 4) > 0 && (a >> 4) < 5 && (a & 0xf) > 0 && (a & 0xf) < 5 { Some([a >> 4, a & 0xf]) } else { None };
+		Some(SnpFreq{af: ((m - 1) as f64) / 254.0, alleles})
+	}
+	
+	fn lookup_rs(&self, ix: usize) -> Option<(String, bool)> {
+		if let Some(n_prev_entries) = self.entry_idx(ix) {
 			let start_x: usize = self.name_len[0..n_prev_entries].iter().map(|x| *x as usize).sum();
 			let mut rs = String::with_capacity(self.name_len[n_prev_entries] as usize + 2);
 			rs.push_str("rs");
@@ -192,7 +225,7 @@ struct DBSnpCtg {
 impl DBSnpCtg {
 	fn min_bin(&self) -> usize { self.ctg.min_bin }
 	fn max_bin(&self) -> usize { self.ctg.max_bin }
-	fn load_data(&mut self, mut file: &mut BufReader<File>, bufsize: usize) -> io::Result<()> {
+	fn load_data(&mut self, mut file: &mut BufReader<File>, bufsize: usize, version: u8) -> io::Result<()> {
 		file.seek(SeekFrom::Start(self.file_offset))?;
 		let mut bins = Vec::with_capacity(self.max_bin() + 1 - self.min_bin());
 		let mut ubuf: Vec<u8> = vec!(0; bufsize);
@@ -209,7 +242,7 @@ impl DBSnpCtg {
 			trace!("Read in compressed data for bin");
 			let sz = decompress_to_buffer(&cbuf, &mut ubuf)?;
 			trace!("bin data uncompressed OK");	
-			bins.append(&mut load_bins(&ubuf[..sz], gap)?);				
+			bins.append(&mut load_bins(&ubuf[..sz], gap, version)?);				
 		}
 		if bins.len() != self.max_bin() + 1 - self.min_bin() { Err(new_err(format!("Wrong number of bins read in.  Expected {}, Found {}", self.max_bin() + 1 - self.min_bin(), bins.len()))) }
 		else {
@@ -221,7 +254,7 @@ impl DBSnpCtg {
 	}
 }
 
-fn load_bins(mut buf: &[u8], gap: usize) -> io::Result<Vec<Option<DBSnpBin>>> {
+fn load_bins(mut buf: &[u8], gap: usize, version: u8) -> io::Result<Vec<Option<DBSnpBin>>> {
 	let format_err = || Err(new_err("Format error".to_string()));
 	let mut bins = Vec::with_capacity(256);	
 	let mut first = true;
@@ -279,7 +312,12 @@ fn load_bins(mut buf: &[u8], gap: usize) -> io::Result<Vec<Option<DBSnpBin>>> {
 				len = 0; 
 			}
 		}
-		bins.push(Some(DBSnpBin{mask, name_buf: name_buf.into_boxed_slice(), name_len: name_len.into_boxed_slice()}));
+		let freq = if version >= 3 {
+			let mut v = Vec::with_capacity(n as usize);
+			for _ in 0..n { v.push([read_1(&mut buf)?, read_1(&mut buf)?]) }
+			Some(v.into_boxed_slice())
+		} else { None };
+		bins.push(Some(DBSnpBin{mask, name_buf: name_buf.into_boxed_slice(), name_len: name_len.into_boxed_slice(), freq}));
 	}
 	Ok(bins)
 }	
@@ -1,3 +1,5 @@
 pub mod contigs;
 pub use contigs::{CtgInfo, CtgRegion, setup_contigs};
+pub mod ploidy;
+pub use ploidy::PloidyMap;
 
@@ -0,0 +1,129 @@
+use std::io;
+use std::str::FromStr;
+
+use utils::compress;
+use crate::config::*;
+use crate::htslib::SamFile;
+
+// Pseudo-autosomal regions on chrX (0 offset, inclusive) for GRCh38 and GRCh37, identified by the length of chrX
+const PAR_GRCH38: (usize, [(usize, usize); 2]) = (156040895, [(10000, 2781478), (155701382, 156030894)]);
+const PAR_GRCH37: (usize, [(usize, usize); 2]) = (155270560, [(60000, 2699519), (154931043, 155260559)]);
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Sex { Male, Female }
+
+impl FromStr for Sex {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"m" | "male" => Ok(Sex::Male),
+			"f" | "female" => Ok(Sex::Female),
+			_ => Err("Sex: no match"),
+		}
+	}
+}
+
+#[derive(Clone)]
+struct CtgPloidy {
+	ploidy: u8,
+	regions: Vec<(usize, usize, u8)>, // Regions (start, end, ploidy) with a different ploidy from the contig (0 offset, inclusive)
+}
+
+impl CtgPloidy {
+	fn new(ploidy: u8) -> Self { Self{ploidy, regions: Vec::new()} }
+}
+
+// Ploidy (1 or 2) for each contig (indexed by SAM tid).  The ploidy is taken, in order of increasing precedence,
+// from the haploid option, from the sex of the sample (for the sex chromosomes and mitochondria), and from the
+// ploidy map file
+pub struct PloidyMap {
+	ctgs: Vec<CtgPloidy>,
+}
+
+fn check_ploidy(s: &str, fname: &str, l: usize) -> io::Result<u8> {
+	match <u8>::from_str(s.trim()) {
+		Ok(x) if x == 1 || x == 2 => Ok(x),
+		_ => Err(new_err(format!("Error parsing ploidy map file {} at line {}: ploidy should be 1 or 2", fname, l))),
+	}
+}
+
+impl PloidyMap {
+	pub fn ploidy(&self, tid: usize, pos: usize) -> u8 {
+		let ctg = &self.ctgs[tid];
+		ctg.regions.iter().find(|(x, y, _)| pos >= *x && pos <= *y).map(|(_, _, p)| *p).unwrap_or(ctg.ploidy)
+	}
+
+	pub fn is_haploid(&self, tid: usize, pos: usize) -> bool { self.ploidy(tid, pos) == 1 }
+
+	fn set_sex(&mut self, sex: Sex, sam_file: &SamFile) {
+		for (tid, ctg) in self.ctgs.iter_mut().enumerate() {
+			let name = sam_file.tid2name(tid);
+			match name.trim_start_matches("chr") {
+				"X" if sex == Sex::Male => {
+					ctg.ploidy = 1;
+					let len = sam_file.tid2len(tid);
+					match [PAR_GRCH38, PAR_GRCH37].iter().find(|(l, _)| *l == len) {
+						Some((_, par)) => ctg.regions.extend(par.iter().map(|(x, y)| (*x, *y, 2))),
+						None => warn!("Could not identify assembly from length of {} - pseudo-autosomal regions should be given in a ploidy map file", name),
+					}
+				},
+				"X" => ctg.ploidy = 2,
+				"Y" | "M" | "MT" => ctg.ploidy = 1,
+				_ => (),
+			}
+		}
+	}
+
+	// Ploidy map file has lines with either 2 (contig, ploidy) or 4 (contig, start, end, ploidy) tab separated columns.
+	// Coordinates are as for BED files (0 offset, half open).  Lines starting with '#' are ignored
+	fn read_map_file(&mut self, fname: &str, sam_file: &SamFile) -> io::Result<()> {
+		let mut rdr = compress::open_bufreader(fname)?;
+		debug!("Reading ploidy map from {}", fname);
+		let mut line = String::with_capacity(256);
+		let mut l = 0;
+		loop {
+			l += 1;
+			match rdr.read_line(&mut line) {
+				Ok(0) => break,
+				Ok(_) => {
+					let v: Vec<_> = line.trim_end().split('\t').collect();
+					if !(v[0].starts_with('#') || (v.len() == 1 && v[0].trim().is_empty())) {
+						let tid = sam_file.name2tid(v[0].trim());
+						match (v.len(), tid) {
+							(_, None) => warn!("Contig {} in ploidy map file {} not present in SAM header (ignored)", v[0].trim(), fname),
+							(2, Some(tid)) => {
+								let ctg = &mut self.ctgs[tid];
+								ctg.ploidy = check_ploidy(v[1], fname, l)?;
+								ctg.regions.clear();
+							},
+							(4, Some(tid)) => {
+								let ploidy = check_ploidy(v[3], fname, l)?;
+								match (<usize>::from_str(v[1].trim()), <usize>::from_str(v[2].trim())) {
+									(Ok(x), Ok(y)) if x < y => self.ctgs[tid].regions.insert(0, (x, y - 1, ploidy)),
+									_ => return Err(new_err(format!("Error parsing ploidy map file {} at line {}: coordinates invalid", fname, l))),
+								}
+							},
+							_ => return Err(new_err(format!("Error parsing ploidy map file {} at line {}", fname, l))),
+						}
+					}
+				},
+				Err(e) => return Err(e),
+			}
+			line.clear();
+		}
+		debug!("Done");
+		Ok(())
+	}
+
+	pub fn setup(chash: &ConfHash, sam_file: &SamFile) -> io::Result<Self> {
+		let ploidy = if chash.get_bool("haploid") { 1 } else { 2 };
+		let mut pmap = Self{ctgs: vec![CtgPloidy::new(ploidy); sam_file.nref()]};
+		if let Some(s) = chash.get_str("sex") {
+			let sex = <Sex>::from_str(s).map_err(|_| new_err(format!("Couldn't parse sex argument '{}': should be male or female", s)))?;
+			pmap.set_sex(sex, sam_file);
+		}
+		if let Some(fname) = chash.get_str("ploidy_map") { pmap.read_map_file(fname, sam_file)? }
+		Ok(pmap)
+	}
+}
@@ -8,6 +8,7 @@ pub struct SamInner {
 	itr: Option<HtsItr>,
     index: HtsIndex,
     regions: Vec<CtgRegion>,
+    reg_idx: usize, // Number of regions started
 }
 
 impl SamInner {
@@ -25,6 +26,7 @@ impl SamInner {
                 self.itr = None;
 		    } 
             if let Some(reg) = self.regions.pop() {
+                self.reg_idx += 1;
                 match self.index.sam_itr_queryi(reg.sam_tid as isize, reg.start, reg.stop) {
                     Ok(itr) => self.itr = Some(itr),
                     Err(e) => {
@@ -36,6 +38,8 @@ impl SamInner {
         }
         SamReadResult::EOF
 	}	
+	// Index of the region of the last record read (regions are read in the order they were given)
+	pub fn region_idx(&self) -> usize { self.reg_idx }
 }
 
 pub struct SamFile {
@@ -50,7 +54,7 @@ impl SamFile {
 		let mut file = HtsFile::new(name, "r")?;
 		let index = file.sam_index_load()?;
 		let hdr = SamHeader::read(&mut file)?;
-		Ok(Self{inner: SamInner{file, itr: None, index, regions: Vec::new()}, hdr})
+		Ok(Self{inner: SamInner{file, itr: None, index, regions: Vec::new(), reg_idx: 0}, hdr})
 	}
 	pub fn nref(&self) -> usize { self.hdr.nref() }
 	pub fn tid2name(&self, i: usize) -> &str { self.hdr.tid2name(i) }
@@ -70,68 +74,3 @@ impl SamFile {
 //		self.inner.itr = Some(self.index.sam_itr_regarray(&mut self.hdr, &reg_str)?);
 	}
 }
-
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub enum BSStrand {Unknown, StrandC2T, StrandG2A, Unconverted}
-
-impl BSStrand {
-	pub fn get_num(&self) -> u16 {
-		match self {
-			BSStrand::Unknown => 0,
-			BSStrand::StrandC2T => 1,
-			BSStrand::StrandG2A => 2,
-			BSStrand::Unconverted => 3,
-		}
-	}
-}
-
-
-#[derive(PartialEq)]
-pub enum Aligner { Unknown, GEM, Bowtie, Novoalign, BSMap, BWAMeth }
-
-pub fn get_bs_strand(b: &BamRec) -> (BSStrand, Option<&[u8]>) {
-	let mut sa_tag = None;
-	let mut strand = BSStrand::Unconverted;
-	if let Some(itr) = b.get_aux_iter() {
-		for tag in itr {
-			let aligner = {
-				if tag[0] == b'Z' {
-					if tag[1] == b'B' { Aligner::Novoalign }
-					else if tag[1] == b'S' { Aligner::BSMap }
-					else {Aligner::Unknown}
-				} else if tag[0] == b'X' {
-					if tag[1] == b'G' { Aligner::Bowtie }
-					else if tag[1] == b'B' { Aligner::GEM }
-					else { Aligner::Unknown }
-				} else if tag[0] == b'Y' && tag[1] == b'D' { Aligner::BWAMeth }
-				else { Aligner::Unknown } 
-			};
-			if aligner != Aligner::Unknown {
-				match tag[2] {
-					b'A' if aligner == Aligner::GEM => {
-						if tag[3] == b'C' { strand = BSStrand::StrandC2T }
-						else if tag[3] == b'G' { strand = BSStrand::StrandG2A }
-					},
-					b'Z' => match aligner {
-						Aligner::Bowtie | Aligner::Novoalign => {
-							if tag[3] == b'C' { strand = BSStrand::StrandC2T }
-							else if tag[3] == b'G' { strand = BSStrand::StrandG2A }						
-						},
-						Aligner::BSMap => {
-							if tag[3] == b'+' { strand = BSStrand::StrandC2T }
-							else if tag[3] == b'-' { strand = BSStrand::StrandG2A }													
-						}
-						Aligner::BWAMeth => {
-							if tag[3] == b'f' { strand = BSStrand::StrandC2T }
-							else if tag[3] == b'r' { strand = BSStrand::StrandG2A }													
-						}
-						_ => (),
-					},
-					_ => (),
-				}
-			} else if tag[0] == b'S' && tag[1] == b'A' && tag[2] == b'Z' { sa_tag = Some(&tag[3..]) }
-			if strand != BSStrand::Unconverted && sa_tag.is_some() { break }
-		}
-	}
-	(strand, sa_tag)
-}
@@ -9,19 +9,42 @@ pub mod sam;
 pub mod records;
 pub mod read_data;
 pub mod pileup;
+pub mod read_meth;
+pub mod mbias;
+pub mod conversion;
+pub mod rrbs;
 pub mod call_genotypes;
 
 pub use vcf::*;
 pub use sam::*;
+pub use call_genotypes::asm::ASM_HEADER;
+pub use call_genotypes::Chemistry;
+pub use read_meth::READ_METH_HEADER;
+pub use mbias::estimate_trims;
+pub use conversion::estimate_conversion;
 
 pub const GT_HET: [bool; 10] = [false, true, true, true, false, true, true, false, true, false];
+// Bases (A=1, C=2, G=3, T=4) of called genotype [0-9]
+pub const GT_BASES: [[u8; 2]; 10] = [[1, 1], [1, 2], [1, 3], [1, 4], [2, 2], [2, 3], [2, 4], [3, 3], [3, 4], [4, 4]];
 
 pub fn process(bs_cfg: Arc<BsCallConfig>, bs_files: BsCallFiles) -> io::Result<()> {
 	// Set up stats logging thread
 	let (stats_tx, stats_rx) = mpsc::channel();
-	let sname = bs_cfg.get_conf_str("report_file").map(|s| s.to_owned());
+	// One report file per sample (comma separated if multiple input files)
+	let snames = bs_cfg.get_conf_str("report_file").map(|s| s.split(',').map(|x| x.to_owned()).collect());
 	let source = bs_cfg.get_conf_str("bs_call_source").expect("No bs_call_source variable").to_owned();
-	let stat_handle = thread::spawn(move || { stats::stat_thread(sname, source, stats_rx) });
+	let stat_handle = thread::spawn(move || { stats::stat_thread(snames, source, stats_rx) });
+	let chash = &bs_cfg.conf_hash;
+	if chash.get_bool("auto_trim") {
+		let _ = stats_tx.send(stats::StatJob::SetAutoTrim(stats::AutoTrim{
+			left_trim_read_1: chash.get_int("left_trim_read_1"), right_trim_read_1: chash.get_int("right_trim_read_1"),
+			left_trim_read_2: chash.get_int("left_trim_read_2"), right_trim_read_2: chash.get_int("right_trim_read_2"),
+		}));
+	}
+	if !bs_cfg.conversion.is_empty() {
+		let v = bs_cfg.conversion.iter().map(|c| stats::ConversionEstimate{sample: bs_cfg.sample_name(c.sample_idx).to_owned(), ..c.clone()}).collect();
+		let _ = stats_tx.send(stats::StatJob::SetConversion(v));
+	}
 	read_data::read_data(Arc::clone(&bs_cfg), mpsc::Sender::clone(&stats_tx), bs_files)?;
 	
 	if stats_tx.send(stats::StatJob::Quit).is_err() { warn!("Error trying to sent QUIT signal to stats thread") }
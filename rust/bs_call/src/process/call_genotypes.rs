@@ -4,25 +4,49 @@ use std::{cmp, io, thread};
 use libc::c_int;
 
 use crate::config::{BsCallConfig, BsCallFiles};
-use super::pileup::{Pileup, GC_BIN_SIZE};
+use crate::defs::PloidyMap;
+use super::pileup::{Pileup, PileupPos, GC_BIN_SIZE};
+use super::records::ReadTrims;
 use crate::stats::StatJob;
 use super::vcf::{write_vcf_entry, WriteVcfJob};
 use crate::htslib::hts_err;
-use crate::dbsnp::DBSnpContig;
+use crate::dbsnp::{DBSnpContig, SnpFreq};
 
 mod model;
 pub mod fisher;
+pub mod indel;
+pub mod asm;
 
 use model::Model;
+pub use model::Chemistry;
 use fisher::FisherTest;
+use indel::{IndelModel, IndelCall, call_indels};
+use asm::{call_asm, write_asm_regions};
+use std::io::Write;
 use crate::rusage::*;
 
 pub enum CallEntry {
-	Call(GenotypeCall),
+	Call(Vec<Option<GenotypeCall>>), // One entry per sample (None if sample has no coverage)
 	Skip(u8),
 	Starting(u8),
 }
 
+impl CallEntry {
+	pub fn ref_base(&self) -> u8 {
+		match self {
+			CallEntry::Call(calls) => calls.iter().flatten().next().map(|c| c.ref_base).unwrap_or(0),
+			CallEntry::Skip(c) | CallEntry::Starting(c) => *c,
+		}
+	}
+	// Called genotype (1-10) for sample, or 0 if no call
+	pub fn called_gt(&self, sample: usize) -> usize {
+		match self {
+			CallEntry::Call(calls) => calls[sample].as_ref().map(|c| c.max_gt as usize + 1).unwrap_or(0),
+			_ => 0,
+		}
+	}
+}
+
 pub struct GenotypeCall {
 	pub counts: [c_int; 8],
 	pub gt_ll: [f64; 10],
@@ -33,6 +57,7 @@ pub struct GenotypeCall {
 	pub max_gt: u8,
 	pub ref_base: u8,
 	pub gc: u8,
+	pub haploid: bool,
 }
 
 pub struct CallBlock {
@@ -40,36 +65,43 @@ pub struct CallBlock {
 	pub start: usize,
 	pub sam_tid: usize,
 	pub prec_ref_bases: [u8; 2], // the 2 reference bases before the block begins (or N if not present) 
+	pub indels: Vec<IndelCall>, // Indel calls for the block (sorted on position)
 }
 
 const BLOCK_SIZE: usize = 4096;
 
-fn call_from_pileup(mut pileup: Pileup, model: &Model, fisher: &FisherTest, write_tx: &mpsc::SyncSender<WriteVcfJob>) -> io::Result<()> {
+// Output handle and config needed for allele specific methylation
+struct AsmOutput<'a> {
+	out: Box<dyn Write + Send>,
+	bs_cfg: &'a BsCallConfig,
+	trims: ReadTrims,
+	min_qual: u8,
+	taps: bool,
+}
+
+// models (one entry per sample) and indel_models have the haploid followed by the diploid model
+#[allow(clippy::too_many_arguments)]
+fn call_from_pileup(mut pileup: Pileup, models: &[[Model; 2]], indel_models: Option<&[IndelModel; 2]>, ploidy: &PloidyMap, fisher: &FisherTest, dbsnp_priors: bool, asm_output: Option<&mut AsmOutput>, write_tx: &mpsc::SyncSender<WriteVcfJob>) -> io::Result<()> {
 	
 	let dbsnp_contig = pileup.dbsnp_contig.take();
-	let call_block = CallBlock{dbsnp_contig, start: pileup.start, sam_tid: pileup.sam_tid, prec_ref_bases: pileup.get_prec_2_bases()};
+	// Only needed if we are using the dbSNP allele frequencies for the genotype priors
+	let prior_contig = if dbsnp_priors { dbsnp_contig.clone() } else { None };
+	let indels = if let Some(m) = indel_models { call_indels(&pileup, m, ploidy, fisher) } else { Vec::new() };
+	let call_block = CallBlock{dbsnp_contig, start: pileup.start, sam_tid: pileup.sam_tid, prec_ref_bases: pileup.get_prec_2_bases(), indels};
 	// Send call_block to output thread
 	send_write_job(WriteVcfJob::CallBlock(call_block), write_tx)?;
 	let gc_bin_start = pileup.ref_start / (GC_BIN_SIZE as usize);
 	let mut call_vec = Vec::with_capacity(BLOCK_SIZE);
+	// Called genotypes per sample (only needed for allele specific methylation)
+	let mut gts: Vec<Vec<u8>> = if asm_output.is_some() { vec![Vec::with_capacity(pileup.n_positions()); pileup.n_samples()] } else { Vec::new() };
 	
-	for (ix, (pp, ref_base)) in pileup.data.iter().zip(pileup.get_ref_iter()).enumerate() {
-		let mut counts: [c_int; 8] = [0; 8];
-		let total = pp.counts.iter().map(|x| *x as c_int).enumerate().fold(0, |s, (i, x)| { counts[i & 7] += x; s + x} );
-		let call = if total > 0 {
-			let total_flt = total as f32;
-			let mut qual: [c_int; 8] = [0; 8];
-			let total_qual = counts.iter().enumerate().filter(|(_, n)| *n > &0).fold(0.0, |s, (i, n)| {
-				qual[i] = cmp::min((pp.quality[i] / (*n as f32)).round() as c_int, 63);
-				s + pp.quality[i]
-			});
-			let aq = cmp::min((total_qual / (total_flt as f32)).round() as usize, 255) as u8;
-			let mq = cmp::min((pp.mapq2 / (total_flt as f32)).sqrt().round() as usize, 255) as u8;
-			let (mx, gt_ll) = model.calc_gt_prob(&counts, &qual, *ref_base, None);
-			let fisher_strand = fisher.calc_fs_stat(mx, &pp.counts);
-			let gc = pileup.gc_bins[(ix + pileup.start) / (GC_BIN_SIZE as usize) - gc_bin_start];
-			CallEntry::Call(GenotypeCall{counts, gt_ll, fisher_strand, qual, mq, aq, max_gt: mx as u8, gc, ref_base: *ref_base})
-		} else { CallEntry::Skip(*ref_base) };
+	for (ix, ref_base) in pileup.get_ref_iter().take(pileup.n_positions()).enumerate() {
+		let gc = pileup.gc_bins[(ix + pileup.start) / (GC_BIN_SIZE as usize) - gc_bin_start];
+		let pl = ploidy.ploidy(pileup.sam_tid, ix + pileup.start) as usize - 1;
+		let snp = prior_contig.as_ref().and_then(|c| c.lookup_freq(ix + pileup.start));
+		let calls: Vec<_> = pileup.data.iter().zip(models.iter()).map(|(d, m)| call_from_pos(&d[ix], *ref_base, gc, &m[pl], snp.as_ref(), fisher)).collect();
+		for (v, c) in gts.iter_mut().zip(calls.iter()) { v.push(c.as_ref().map(|x| x.max_gt + 1).unwrap_or(0)) }
+		let call = if calls.iter().any(|c| c.is_some()) { CallEntry::Call(calls) } else { CallEntry::Skip(*ref_base) };
 		call_vec.push(call);
 		if call_vec.len() == BLOCK_SIZE {
 			send_write_job(WriteVcfJob::GenotypeCall(call_vec), write_tx)?;	
@@ -77,9 +109,31 @@ fn call_from_pileup(mut pileup: Pileup, model: &Model, fisher: &FisherTest, writ
 		}	
 	}
 	if !call_vec.is_empty() { send_write_job(WriteVcfJob::GenotypeCall(call_vec), write_tx)?; }
+	if let Some(asm) = asm_output {
+		let regions = call_asm(&pileup, &gts, fisher, &asm.trims, asm.min_qual, asm.taps);
+		write_asm_regions(&regions, pileup.sam_tid, asm.bs_cfg, &mut asm.out)?;
+	}
 	Ok(())	
 }
 
+fn call_from_pos(pp: &PileupPos, ref_base: u8, gc: u8, model: &Model, snp: Option<&SnpFreq>, fisher: &FisherTest) -> Option<GenotypeCall> {
+	let mut counts: [c_int; 8] = [0; 8];
+	let total = pp.counts.iter().map(|x| *x as c_int).enumerate().fold(0, |s, (i, x)| { counts[i & 7] += x; s + x} );
+	if total > 0 {
+		let total_flt = total as f32;
+		let mut qual: [c_int; 8] = [0; 8];
+		let total_qual = counts.iter().enumerate().filter(|(_, n)| *n > &0).fold(0.0, |s, (i, n)| {
+			qual[i] = cmp::min((pp.quality[i] / (*n as f32)).round() as c_int, 63);
+			s + pp.quality[i]
+		});
+		let aq = cmp::min((total_qual / (total_flt as f32)).round() as usize, 255) as u8;
+		let mq = cmp::min((pp.mapq2 / (total_flt as f32)).sqrt().round() as usize, 255) as u8;
+		let (mx, gt_ll) = model.calc_gt_prob(&counts, &qual, ref_base, snp.map(|s| (s.af, s.alleles)), None);
+		let fisher_strand = fisher.calc_fs_stat(mx, &pp.counts);
+		Some(GenotypeCall{counts, gt_ll, fisher_strand, qual, mq, aq, max_gt: mx as u8, gc, ref_base, haploid: model.haploid()})
+	} else { None }
+}
+
 fn send_write_job(job: WriteVcfJob, write_tx: &mpsc::SyncSender<WriteVcfJob>) -> io::Result<()> {
 	match write_tx.send(job) { 
 		Err(e) => {
@@ -90,21 +144,28 @@ fn send_write_job(job: WriteVcfJob, write_tx: &mpsc::SyncSender<WriteVcfJob>) ->
 	} 	
 }
 
-pub fn call_genotypes(bs_cfg: Arc<BsCallConfig>, rx: mpsc::Receiver<Option<Pileup>>, bs_files: BsCallFiles, stat_tx: mpsc::Sender<StatJob>) {
+pub fn call_genotypes(bs_cfg: Arc<BsCallConfig>, rx: mpsc::Receiver<Option<Pileup>>, mut bs_files: BsCallFiles, stat_tx: mpsc::Sender<StatJob>) {
 	info!("call_genotypes_thread starting up");
 	let ref_bias = bs_cfg.conf_hash.get_float("reference_bias");
-	let haploid = bs_cfg.conf_hash.get_bool("haploid");
-	let conversion = (bs_cfg.conf_hash.get_float("under_conversion"), bs_cfg.conf_hash.get_float("over_conversion"));
+	let indel_models = if bs_cfg.conf_hash.get_bool("no_indels") { None } else { Some([IndelModel::new(true), IndelModel::new(false)]) };
+	let cfg = Arc::clone(&bs_cfg);
+	let mut asm_output = bs_files.asm_output.take().map(|out| AsmOutput{out, bs_cfg: &cfg, trims: ReadTrims::new(&cfg.conf_hash), min_qual: cfg.conf_hash.get_int("bq_threshold") as u8, taps: cfg.chemistry().converts_methylated()});
 	let (write_tx, write_rx) = mpsc::sync_channel(32);
 	let write_handle = thread::spawn(move || { write_vcf_entry(Arc::clone(&bs_cfg), write_rx, bs_files, stat_tx) });
-	let model = Model::new(conversion, ref_bias, haploid, true);
+	let chemistry = cfg.chemistry();
+	// Conversion rates can differ between samples if they have been estimated
+	let models: Vec<_> = (0..cfg.samples.len()).map(|ix| {
+		let conversion = cfg.conversion_rates(ix);
+		[Model::new(conversion, ref_bias, chemistry, true, true), Model::new(conversion, ref_bias, chemistry, false, true)]
+	}).collect();
 	let fisher = FisherTest::new();
+	let dbsnp_priors = cfg.conf_hash.get_bool("dbsnp_priors");
 	loop {
 		match rx.recv() {
 			Ok(None) => break,
 			Ok(Some(pileup)) => {
-				debug!("Received new pileup: {}:{}-{}", pileup.sam_tid, pileup.start, pileup.start + pileup.data.len() - 1);
-				if let Err(e) = call_from_pileup(pileup, &model, &fisher, &write_tx) {
+				debug!("Received new pileup: {}:{}-{}", pileup.sam_tid, pileup.start, pileup.start + pileup.n_positions() - 1);
+				if let Err(e) = call_from_pileup(pileup, &models, indel_models.as_ref(), &cfg.ploidy, &fisher, dbsnp_priors, asm_output.as_mut(), &write_tx) {
 					error!("call_from_pileup failed with error: {}", e);
 					break;
 				}
@@ -115,6 +176,9 @@ pub fn call_genotypes(bs_cfg: Arc<BsCallConfig>, rx: mpsc::Receiver<Option<Pileu
 			}
 		}
 	}
+	if let Some(asm) = asm_output.as_mut() {
+		if let Err(e) = asm.out.flush() { error!("Error writing to ASM output file: {}", e) }
+	}
 	if write_tx.send(WriteVcfJob::Quit).is_err() { warn!("Error trying to send QUIT signal to write_vcf thread") }
 	if write_handle.join().is_err() { warn!("Error waiting for call_genotype thread to finish") }
 	if let Ok(ru_thread) = Rusage::get(RusageWho::RusageThread) {
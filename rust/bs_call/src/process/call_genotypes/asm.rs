@@ -0,0 +1,139 @@
+use std::io::{self, Write};
+use std::collections::BTreeSet;
+
+use super::fisher::FisherTest;
+use crate::config::BsCallConfig;
+use crate::process::{GT_HET, GT_BASES};
+use crate::process::pileup::{Pileup, OBS_BASES};
+use crate::process::records::{Fragment, ReadTrims, get_fragments};
+
+// Genotypes containing a C or a G respectively
+const GT_C: [bool; 10] = [false, true, false, false, true, true, true, false, false, false];
+const GT_G: [bool; 10] = [false, false, true, false, false, true, false, true, true, false];
+
+const PBASE: &[u8] = b"NACGT";
+
+// Minimum number of fragments supporting the phase of two adjacent heterozygous SNVs for them to be linked
+const MIN_LINK_FRAGS: usize = 2;
+
+pub const ASM_HEADER: &str = "#chrom\tstart\tend\tn_snp\tsnps\tsample\tn_cpg\tmeth_1\tunmeth_1\tmeth_2\tunmeth_2\tp_value";
+
+// Heterozygous SNV in a phased block with the alleles in haplotype order
+pub struct PhasedSnp {
+	pub pos: usize,
+	pub alleles: [u8; 2],
+}
+
+// A block of heterozygous SNVs phased using the fragments covering them, with the methylation at
+// CpGs covered by the same fragments split by haplotype
+pub struct AsmRegion {
+	pub sample: usize,
+	pub snps: Vec<PhasedSnp>,
+	pub start: usize, // Position of first CpG
+	pub end: usize, // Position of last CpG
+	pub n_cpg: usize,
+	pub counts: [[u32; 2]; 2], // Methylated and non-methylated CpG observations for each haplotype
+	pub p_value: f64,
+}
+
+// Which of the two alleles (if any) is supported by the fragment (taking into account the bisulfite conversion)
+fn get_allele(frag: &Fragment, pos: usize, alleles: [u8; 2], min_qual: u8) -> Option<usize> {
+	let c = frag.get_obs(pos, min_qual)?;
+	match (OBS_BASES[c][alleles[0] as usize - 1], OBS_BASES[c][alleles[1] as usize - 1]) {
+		(true, false) => Some(0),
+		(false, true) => Some(1),
+		_ => None,
+	}
+}
+
+// Check if the fragments covering two adjacent SNVs support them being in cis (same allele index, returns Some(0))
+// or in trans (returns Some(1)).  links has the number of fragments supporting cis and trans respectively
+fn phase_link(links: [usize; 2]) -> Option<usize> {
+	let (n, flip) = if links[0] >= links[1] { (links[0], 0) } else { (links[1], 1) };
+	let minor = links[0] + links[1] - n;
+	if n >= MIN_LINK_FRAGS && minor * 10 <= n { Some(flip) } else { None }
+}
+
+// Heterozygous SNVs are phased into blocks using the alleles carried by the fragments (read pairs) covering
+// adjacent SNVs, and each fragment is assigned to a haplotype of a block.  For each block, the methylation
+// calls at CpGs on the fragments are split by haplotype and tested for a difference.  gts has the called
+// genotype (1-10, or 0 if no call) for each position in the pileup for each sample
+pub fn call_asm(pileup: &Pileup, gts: &[Vec<u8>], fisher: &FisherTest, trims: &ReadTrims, min_qual: u8, taps: bool) -> Vec<AsmRegion> {
+	let mut regions = Vec::new();
+	for (sample, (reads, sgts)) in pileup.reads.iter().zip(gts.iter()).enumerate() {
+		let called = |ix: usize| sgts.get(ix).and_then(|g| if *g > 0 { Some((*g - 1) as usize) } else { None });
+		let hets: Vec<_> = (0..sgts.len()).filter_map(|ix| called(ix).filter(|g| GT_HET[*g]).map(|g| (ix + pileup.start, GT_BASES[g]))).collect();
+		if hets.is_empty() { continue }
+		let is_het = |x: usize| hets.binary_search_by_key(&x, |(p, _)| *p).is_ok();
+		// CpGs not overlapping a heterozygous SNV
+		let cpgs: Vec<_> = (0..sgts.len()).filter(|ix| matches!((called(*ix), called(ix + 1)), (Some(a), Some(b)) if GT_C[a] && GT_G[b]))
+			.map(|ix| ix + pileup.start).filter(|x| !(is_het(*x) || is_het(x + 1))).collect();
+		let frags = get_fragments(reads, trims);
+		// Alleles carried by each fragment at the SNVs it covers as (index into hets, allele)
+		let frag_alleles: Vec<Vec<(usize, usize)>> = frags.iter().map(|f| {
+			let i = hets.partition_point(|(x, _)| *x < f.start);
+			hets[i..].iter().take_while(|(x, _)| *x <= f.end).enumerate()
+				.filter_map(|(j, (x, alleles))| get_allele(f, *x, *alleles, min_qual).map(|a| (i + j, a))).collect()
+		}).collect();
+		// Count fragments supporting cis and trans phasing of each SNV with the next one
+		let mut links = vec![[0; 2]; hets.len()];
+		for fa in frag_alleles.iter() {
+			for w in fa.windows(2).filter(|w| w[1].0 == w[0].0 + 1) { links[w[0].0][w[0].1 ^ w[1].1] += 1 }
+		}
+		// Split SNVs into phased blocks.  phase[i] is the haplotype carrying the first allele of SNV i
+		let mut block_idx = Vec::with_capacity(hets.len());
+		let mut phase = Vec::with_capacity(hets.len());
+		let mut n_blocks = 0;
+		for i in 0..hets.len() {
+			match if i > 0 { phase_link(links[i - 1]) } else { None } {
+				Some(flip) => phase.push(phase[i - 1] ^ flip),
+				None => {
+					n_blocks += 1;
+					phase.push(0)
+				},
+			}
+			block_idx.push(n_blocks - 1);
+		}
+		let mut counts = vec![[[0; 2]; 2]; n_blocks];
+		let mut used = vec![BTreeSet::new(); n_blocks];
+		for (frag, fa) in frags.iter().zip(frag_alleles.iter()) {
+			// Fragments covering SNVs from more than one block, or with alleles from both haplotypes are not used
+			let b = match fa.first() {
+				Some((i, _)) if fa.iter().all(|(j, _)| block_idx[*j] == block_idx[*i]) => block_idx[*i],
+				_ => continue,
+			};
+			let mut hap_obs = [0; 2];
+			for (i, a) in fa.iter() { hap_obs[a ^ phase[*i]] += 1 }
+			let hap = if hap_obs[1] == 0 { 0 } else if hap_obs[0] == 0 { 1 } else { continue };
+			let i = cpgs.partition_point(|x| *x < frag.start);
+			for x in cpgs[i..].iter().copied().take_while(|x| *x <= frag.end) {
+				if let Some(m) = frag.get_meth(x, min_qual, taps) {
+					counts[b][hap][m] += 1;
+					used[b].insert(x);
+				}
+			}
+		}
+		for (b, (counts, used)) in counts.into_iter().zip(used).enumerate() {
+			if counts[0][0] + counts[0][1] > 0 && counts[1][0] + counts[1][1] > 0 {
+				let p_value = fisher.fisher(&[counts[0][0], counts[0][1], counts[1][0], counts[1][1]]);
+				let start = *used.iter().next().unwrap();
+				let end = *used.iter().next_back().unwrap();
+				let snps = hets.iter().zip(phase.iter()).zip(block_idx.iter()).filter(|(_, bl)| **bl == b)
+					.map(|(((pos, alleles), ph), _)| PhasedSnp{pos: *pos, alleles: if *ph == 0 { *alleles } else { [alleles[1], alleles[0]] }}).collect();
+				regions.push(AsmRegion{sample, snps, start, end, n_cpg: used.len(), counts, p_value});
+			}
+		}
+	}
+	regions
+}
+
+pub fn write_asm_regions(regions: &[AsmRegion], sam_tid: usize, bs_cfg: &BsCallConfig, out: &mut dyn Write) -> io::Result<()> {
+	let cname = bs_cfg.ctg_name(sam_tid);
+	for r in regions.iter() {
+		// SNVs are output as pos:allele_1/allele_2 with the alleles in haplotype order
+		let snps: Vec<_> = r.snps.iter().map(|s| format!("{}:{}/{}", s.pos + 1, PBASE[s.alleles[0] as usize] as char, PBASE[s.alleles[1] as usize] as char)).collect();
+		writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.4e}", cname, r.start, r.end + 2, snps.len(), snps.join(","),
+			bs_cfg.sample_name(r.sample), r.n_cpg, r.counts[0][0], r.counts[0][1], r.counts[1][0], r.counts[1][1], r.p_value)?;
+	}
+	Ok(())
+}
@@ -0,0 +1,134 @@
+use std::f64::consts::LN_10;
+use std::cmp;
+use std::collections::BTreeMap;
+
+use libc::c_int;
+
+use super::fisher::FisherTest;
+use crate::process::pileup::{Pileup, PileupPos, Indel, IndelObs};
+use crate::defs::PloidyMap;
+
+const INDEL_ERROR: f64 = 1.0e-4; // Minimum per read error rate for indel observations
+const INDEL_HET_PRIOR: f64 = 1.0e-4;
+const INDEL_HOM_PRIOR: f64 = 0.5e-4;
+const PBASE: &[u8] = b"NACGT";
+
+pub struct IndelGenotype {
+	pub counts: [c_int; 4], // Reference and alternate supporting reads on the forward strand, followed by the reverse strand
+	pub gt_ll: [f64; 3], // Log10 genotype probabilities (RR, RA, AA)
+	pub fisher_strand: f64,
+	pub max_gt: u8,
+	pub mq: u8,
+	pub haploid: bool,
+}
+
+impl IndelGenotype {
+	pub fn ref_depth(&self) -> c_int { self.counts[0] + self.counts[2] }
+	pub fn alt_depth(&self) -> c_int { self.counts[1] + self.counts[3] }
+}
+
+pub struct IndelCall {
+	pub pos: usize,
+	pub ref_allele: Vec<u8>,
+	pub alt_allele: Vec<u8>,
+	pub samples: Vec<Option<IndelGenotype>>,
+}
+
+// Simple realignment free model for indels where each read covering the anchor position either supports the indel
+// or the reference.  The error rate for each read is taken from the RMS mapping quality at the anchor position
+pub struct IndelModel {
+	ln_prior: [f64; 3],
+	haploid: bool,
+}
+
+impl IndelModel {
+	pub fn new(haploid: bool) -> Self {
+		let ln_prior = if haploid { [(1.0 - INDEL_HOM_PRIOR).ln(), f64::NEG_INFINITY, INDEL_HOM_PRIOR.ln()] }
+		else { [(1.0 - INDEL_HET_PRIOR - INDEL_HOM_PRIOR).ln(), INDEL_HET_PRIOR.ln(), INDEL_HOM_PRIOR.ln()] };
+		Self{ln_prior, haploid}
+	}
+
+	fn calc_gt_prob(&self, n_ref: c_int, n_alt: c_int, mq: u8) -> (usize, [f64; 3]) {
+		let e = ((mq as f64) * LN_10 * -0.1).exp().clamp(INDEL_ERROR, 0.5);
+		let (le, le1) = (e.ln(), (1.0 - e).ln());
+		let (nr, na) = (n_ref as f64, n_alt as f64);
+		let mut ll = [
+			self.ln_prior[0] + nr * le1 + na * le,
+			self.ln_prior[1] + (nr + na) * 0.5f64.ln(),
+			self.ln_prior[2] + nr * le + na * le1,
+		];
+		let (mut mx, mut max) = (0, ll[0]);
+		for (i, z) in ll.iter().enumerate().skip(1) {
+			if *z > max {
+				mx = i;
+				max = *z;
+			}
+		}
+		let sum = ll.iter().fold(0.0, |s, z| s + (z - max).exp()).ln() + max;
+		ll.iter_mut().for_each(|z| *z = (*z - sum) / LN_10);
+		(mx, ll)
+	}
+
+	fn call_sample(&self, alt: [u32; 2], pp: &PileupPos, fisher: &FisherTest) -> IndelGenotype {
+		let depth = [pp.counts[..8].iter().sum::<u32>(), pp.counts[8..].iter().sum::<u32>()];
+		let rf = [depth[0].saturating_sub(alt[0]), depth[1].saturating_sub(alt[1])];
+		let mq = if depth[0] + depth[1] > 0 { 
+			cmp::min((pp.mapq2 / ((depth[0] + depth[1]) as f32)).sqrt().round() as usize, 255) as u8
+		} else { 0 };
+		let counts = [rf[0] as c_int, alt[0] as c_int, rf[1] as c_int, alt[1] as c_int];
+		let (mx, gt_ll) = self.calc_gt_prob(counts[0] + counts[2], counts[1] + counts[3], mq);
+		let fisher_strand = if mx == 1 {
+			let z = fisher.fisher(&[rf[0], alt[0], rf[1], alt[1]]);
+			(if z < 1.0e-20 { 1.0e-20 } else { z }).ln() / LN_10
+		} else { 0.0 };
+		IndelGenotype{counts, gt_ll, fisher_strand, max_gt: mx as u8, mq, haploid: self.haploid}
+	}
+}
+
+// Get REF and ALT alleles for indel anchored at pos.  Returns None if the reference is not available
+// or contains Ns
+fn get_alleles(pileup: &Pileup, pos: usize, indel: Indel, obs: &IndelObs) -> Option<(Vec<u8>, Vec<u8>)> {
+	let ix = pos - pileup.ref_start;
+	let (ref_seq, alt_seq) = match indel {
+		Indel::Del(l) => (pileup.ref_seq.get(ix..=ix + l)?.to_vec(), pileup.ref_seq.get(ix..=ix)?.to_vec()),
+		Indel::Ins(_) => {
+			let r = pileup.ref_seq.get(ix..=ix)?.to_vec();
+			let mut a = r.clone();
+			a.extend(obs.ins_seq());
+			(r, a)
+		},
+	};
+	if ref_seq.iter().chain(alt_seq.iter()).any(|c| *c == 0) { None }
+	else { Some((ref_seq.iter().map(|c| PBASE[*c as usize]).collect(), alt_seq.iter().map(|c| PBASE[*c as usize]).collect())) }
+}
+
+// Call indels for all samples in pileup.  Returns a vector of calls sorted on position
+// where at least one sample has a non-reference genotype.  The haploid (models[0]) or diploid
+// (models[1]) model is used depending on the ploidy at the anchor position
+pub fn call_indels(pileup: &Pileup, models: &[IndelModel; 2], ploidy: &PloidyMap, fisher: &FisherTest) -> Vec<IndelCall> {
+	// Collect candidates from all samples, merging the observations so that the consensus for insertions
+	// uses information from all samples
+	let mut candidates: BTreeMap<(usize, Indel), IndelObs> = BTreeMap::new();
+	for (key, obs) in pileup.indels.iter().flat_map(|x| x.iter()) { 
+		candidates.entry(*key).or_insert_with(|| IndelObs::new(obs.ins_counts.len())).merge(obs)
+	}
+	let mut calls = Vec::new();
+	for ((pos, indel), obs) in candidates.iter() {
+		let (ref_allele, alt_allele) = match get_alleles(pileup, *pos, *indel, obs) {
+			Some(x) => x,
+			None => continue,
+		};
+		let ix = pos - pileup.start;
+		let model = &models[ploidy.ploidy(pileup.sam_tid, *pos) as usize - 1];
+		let samples: Vec<_> = pileup.data.iter().zip(pileup.indels.iter()).map(|(d, ind)| {
+			match ind.get(&(*pos, *indel)) {
+				Some(obs) => Some(model.call_sample(obs.counts, &d[ix], fisher)),
+				None => if d[ix].counts.iter().any(|c| *c > 0) { Some(model.call_sample([0; 2], &d[ix], fisher)) } else { None },
+			}
+		}).collect();
+		if samples.iter().flatten().any(|g| g.max_gt > 0) {
+			calls.push(IndelCall{pos: *pos, ref_allele, alt_allele, samples})
+		}
+	}
+	calls
+}
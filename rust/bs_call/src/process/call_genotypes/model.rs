@@ -3,6 +3,13 @@ use libc::c_int;
 
 pub const MAX_QUAL: usize = 64;
 
+pub use utils::chemistry::Chemistry;
+
+// Weight given to the population (Hardy-Weinberg) genotype frequencies at known SNPs when dbSNP priors are used
+const DBSNP_PRIOR_WEIGHT: f64 = 0.9;
+// Allele indices (A=0, C=1, G=2, T=3) of genotypes [0-9]
+const GT_ALLELES: [[usize; 2]; 10] = [[0, 0], [0, 1], [0, 2], [0, 3], [1, 1], [1, 2], [1, 3], [2, 2], [2, 3], [3, 3]];
+
 #[derive(Debug)]
 pub struct QualProb {
 	k: f64,
@@ -19,11 +26,14 @@ pub struct Model {
 	theta: f64, // over_conversion rate
 	haploid: bool, // Haploid genome - don't call heterozygotes
 	log10: bool, // Give log probs as log10 (rather than ln)
+	chemistry: Chemistry,
 }
 
 
 impl Model {
-	pub fn new(conv: (f64, f64), ref_bias: f64, haploid: bool, log10: bool) -> Self {
+	pub fn haploid(&self) -> bool { self.haploid }
+	
+	pub fn new(conv: (f64, f64), ref_bias: f64, chemistry: Chemistry, haploid: bool, log10: bool) -> Self {
 		assert!(conv.0 > 0.0 && conv.0 < 1.0 && conv.1 > 0.0 && conv.1 < 1.0 && ref_bias > 0.0);
 		let mut v = Vec::with_capacity(MAX_QUAL + 1);
 		for q in 0..=MAX_QUAL {
@@ -39,7 +49,7 @@ impl Model {
 				ln_k_one: k.ln_1p(),
 			})
 		}
-		Self{qtab: v, lambda: 1.0 - conv.0, theta: conv.1, haploid, log10, ln_ref_bias: ref_bias.ln(), ln_ref_bias_1:(0.5 * (1.0 + ref_bias)).ln() }
+		Self{qtab: v, lambda: 1.0 - conv.0, theta: conv.1, haploid, log10, chemistry, ln_ref_bias: ref_bias.ln(), ln_ref_bias_1:(0.5 * (1.0 + ref_bias)).ln() }
 	}
 
   /*********************************************************************************************
@@ -72,10 +82,11 @@ impl Model {
    * only the 10 possible diploid genotypes)
    *
    **********************************************************************************************/	
-	pub fn calc_gt_prob(&self, counts: &[c_int; 8], qual: &[c_int; 8], ref_base: u8, meth: Option<&mut [f64; 6]>) -> (usize, [f64; 10]) {
+	pub fn calc_gt_prob(&self, counts: &[c_int; 8], qual: &[c_int; 8], ref_base: u8, snp: Option<(f64, Option<[u8; 2]>)>, meth: Option<&mut [f64; 6]>) -> (usize, [f64; 10]) {
 		let qp: Vec<_> = qual.iter().map(|x| &self.qtab[*x as usize]).collect();
 		let n: Vec<_> = counts.iter().map(|x| *x as f64).collect();
 		let mut ll = self.add_ref_prior(ref_base);
+		if let Some((af, alleles)) = snp { self.add_dbsnp_prior(ref_base, af, alleles, &mut ll) }
 		let get_par = |i: usize| (n[i] * qp[i].ln_k_one, n[i] * qp[i].ln_k_half, n[i] * qp[i].ln_k);		
 		let mut add_contrib = |v: &[f64]| ll.iter_mut().zip(v.iter()).for_each(|(l, x)| *l += *x);
 		if counts[0] != 0 {
@@ -155,7 +166,12 @@ impl Model {
 		if let Some(mv) = meth {
 			if c1 == 0 && c2 == 0 { for m in mv.iter_mut() { *m = -1.0 }}
 			else {
-				let fchk = |x| if x < 0.0 { -1.0 } else if x > 1.0 { 1.0} else { x };			
+				// With TAPS the estimated proportion of non-converted Cs corresponds to the non-methylated proportion
+				let taps = self.chemistry.converts_methylated();
+				let fchk = |x: f64| {
+					let x = if taps { 1.0 - x } else { x };
+					if x < 0.0 { -1.0 } else if x > 1.0 { 1.0} else { x }
+				};
 				let d = self.lambda - self.theta;
 				mv[0] = fchk((z.0 - 1.0 + self.lambda) / d);
 				mv[1] = fchk((z.1 - 1.0 + self.lambda) / d);
@@ -197,4 +213,53 @@ impl Model {
 		}
 		ll
 	}
+
+	// At known SNPs the prior is a mixture of the reference bias prior and the genotype frequencies expected under
+	// Hardy-Weinberg equilibrium given the alternate allele frequency p (which can be > 0.5 if the reference allele is
+	// the minor allele).  If the alleles (reference, alternate encoded as A=1, C=2, G=3, T=4) are not known the alternate
+	// frequency is split equally between the 3 non-reference bases
+	fn add_dbsnp_prior(&self, ref_base: u8, p: f64, alleles: Option<[u8; 2]>, ll: &mut [f64; 10]) {
+		if !(1..=4).contains(&ref_base) { return }
+		let mut f = [p / 3.0; 4];
+		match alleles {
+			Some([r, a]) if r == ref_base => {
+				f = [0.0; 4];
+				f[a as usize - 1] = p;
+			},
+			Some(_) => return, // Reference allele doesn't match
+			None => (),
+		}
+		f[ref_base as usize - 1] = 1.0 - p;
+		let total = ll.iter().fold(0.0, |s, x| s + x.exp());
+		for (l, [a, b]) in ll.iter_mut().zip(GT_ALLELES.iter().copied()) {
+			let hwe = if a != b { 
+				if self.haploid { 0.0 } else { 2.0 * f[a] * f[b] }
+			} else if self.haploid { f[a] } else { f[a] * f[a] };
+			*l = ((1.0 - DBSNP_PRIOR_WEIGHT) * l.exp() + DBSNP_PRIOR_WEIGHT * total * hwe).ln();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn max_gt(ll: &[f64; 10]) -> usize {
+		ll.iter().enumerate().fold((0, f64::NEG_INFINITY), |(i, m), (j, x)| if *x > m { (j, *x) } else { (i, m) }).0
+	}
+
+	#[test]
+	fn dbsnp_prior_minor_ref() {
+		let model = Model::new((0.01, 0.05), 2.0, Chemistry::Bisulfite, false, true);
+		// A/G SNP where the alternate allele G is the major allele (AF = 0.9)
+		let mut ll = model.add_ref_prior(1);
+		model.add_dbsnp_prior(1, 0.9, Some([1, 3]), &mut ll);
+		assert_eq!(max_gt(&ll), 7);
+		assert!(ll[1] > ll[0]);
+		// Same SNP with G as the minor allele (AF = 0.1)
+		let mut ll = model.add_ref_prior(1);
+		model.add_dbsnp_prior(1, 0.1, Some([1, 3]), &mut ll);
+		assert_eq!(max_gt(&ll), 0);
+		assert!(ll[1] > ll[7]);
+	}
 }
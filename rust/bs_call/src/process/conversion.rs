@@ -0,0 +1,201 @@
+use std::io;
+use std::str::FromStr;
+use std::collections::{HashMap, HashSet};
+
+use crate::config::{ConfHash, new_err};
+use crate::defs::CtgRegion;
+use crate::htslib::{BamRec, BSStrand, Faidx, SamFile, SamReadResult, Sequence};
+use crate::stats::{ConversionCounts, ConversionEstimate};
+use super::records::ReadEnd;
+use super::Chemistry;
+
+const CONV_MIN_OBS: usize = 1000; // Minimum observations to estimate a conversion rate
+const CONV_MIN_RATE: f64 = 0.001;
+// Estimates above these values are assumed to be unreliable (as in gemBS)
+const CONV_MAX_UNDER: f64 = 0.1;
+const CONV_MAX_OVER: f64 = 0.15;
+// Reads from the genome are sampled from windows spread evenly across the called regions
+const CONV_N_WINDOWS: usize = 256;
+const CONV_WINDOW_SIZE: usize = 100000;
+
+#[derive(Copy, Clone, PartialEq)]
+enum CtgType { Genome, Unmethylated, Methylated }
+
+fn read_control_list(chash: &ConfHash, key: &str, sam_file: &SamFile) -> io::Result<Vec<usize>> {
+	let mut v = Vec::new();
+	if let Some(s) = chash.get_str(key) {
+		for name in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+			match sam_file.name2tid(name) {
+				Some(tid) => v.push(tid),
+				None => return Err(new_err(format!("Control sequence {} not present in SAM header", name))),
+			}
+		}
+	}
+	Ok(v)
+}
+
+// Count non-converted (0) and converted (1) Cs from a read.  For the unmethylated control all Cs are used, for
+// the methylated control only CpGs, and for the rest of the genome only CHH sites (which are assumed to be largely
+// non-methylated)
+fn add_read(counts: &mut ConversionCounts, read: &ReadEnd, rf: &[u8], ctg_type: CtgType, min_qual: u8) {
+	let map = &read.maps[0];
+	let bs = map.bs_strand();
+	if !matches!(bs, BSStrand::StrandC2T | BSStrand::StrandG2A) { return }
+	let base = |x: usize| rf.get(x).map(|c| c.to_ascii_uppercase());
+	let prev_base = |x: usize, k: usize| x.checked_sub(k).and_then(base);
+	let is_ctxt = |x: usize| match (bs, ctg_type) {
+		(BSStrand::StrandC2T, CtgType::Unmethylated) => true,
+		(BSStrand::StrandC2T, CtgType::Methylated) => base(x + 1) == Some(b'G'),
+		(BSStrand::StrandC2T, _) => matches!((base(x + 1), base(x + 2)), (Some(b'A' | b'C' | b'T'), Some(b'A' | b'C' | b'T'))),
+		(_, CtgType::Unmethylated) => true,
+		(_, CtgType::Methylated) => prev_base(x, 1) == Some(b'C'),
+		_ => matches!((prev_base(x, 1), prev_base(x, 2)), (Some(b'A' | b'G' | b'T'), Some(b'A' | b'G' | b'T'))),
+	};
+	let ct = match ctg_type {
+		CtgType::Genome => &mut counts.chh,
+		CtgType::Unmethylated => &mut counts.unmethylated_control,
+		CtgType::Methylated => &mut counts.methylated_control,
+	};
+	for ab in read.aligned_iter().filter(|a| a.qual >= min_qual) {
+		let r = ab.ref_pos;
+		let m = match (bs, base(r)) {
+			(BSStrand::StrandC2T, Some(b'C')) if is_ctxt(r) => match ab.base { 1 => Some(0), 3 => Some(1), _ => None },
+			(BSStrand::StrandG2A, Some(b'G')) if is_ctxt(r) => match ab.base { 2 => Some(0), 0 => Some(1), _ => None },
+			_ => None,
+		};
+		if let Some(m) = m { ct[m] += 1 }
+	}
+}
+
+// Non-overlapping windows of up to CONV_WINDOW_SIZE bases evenly spaced along the regions
+fn sample_windows(regions: &[CtgRegion]) -> Vec<CtgRegion> {
+	let total: usize = regions.iter().map(|r| r.stop + 1 - r.start).sum();
+	let step = (total / CONV_N_WINDOWS).max(1);
+	let wsize = CONV_WINDOW_SIZE.min(step);
+	let mut v = Vec::with_capacity(CONV_N_WINDOWS);
+	let mut x = 0; // Start of current region in concatenated coordinates
+	let mut next = step / 2; // Start of next window in concatenated coordinates
+	for r in regions.iter() {
+		let len = r.stop + 1 - r.start;
+		while next < x + len {
+			let start = r.start + next - x;
+			v.push(CtgRegion{sam_tid: r.sam_tid, start, stop: (start + wsize - 1).min(r.stop)});
+			next += step;
+		}
+		x += len;
+	}
+	v
+}
+
+// Collect counts from up to conversion_reads reads from the regions, taking at most max_per_region reads from each region
+fn sample_reads(chash: &ConfHash, name: &str, regions: &[CtgRegion], max_per_region: usize, ctg_types: &HashMap<usize, CtgType>, ref_idx: &Faidx,
+	rg_counts: &mut HashMap<Option<String>, ConversionCounts>) -> io::Result<()> {
+	let n_reads = chash.get_int("conversion_reads");
+	let min_qual = chash.get_int("bq_threshold") as u8;
+	let fai = format!("{}.fai", chash.get_str("reference").expect("No reference"));
+	let mut file = SamFile::new(name)?;
+	file.set_fai_filename(&fai)?;
+	file.set_region_itr(regions);
+	let mut brec = BamRec::new()?;
+	let mut curr_seq: Option<(usize, Sequence)> = None;
+	let (mut n, mut curr_reg, mut n_reg) = (0, 0, 0);
+	while n < n_reads {
+		match file.inner.get_next(&mut brec) {
+			SamReadResult::Ok => (),
+			SamReadResult::EOF => break,
+			SamReadResult::Error => return Err(new_err(format!("Error reading from input file {}", name))),
+		}
+		if file.inner.region_idx() != curr_reg {
+			curr_reg = file.inner.region_idx();
+			n_reg = 0;
+		}
+		if n_reg >= max_per_region { continue }
+		let read = match ReadEnd::from_bam_rec(chash, &file.hdr, &brec) {
+			(Some(r), _) if r.is_primary() => r,
+			_ => continue,
+		};
+		let tid = read.maps[0].map_pos.tid as usize;
+		if curr_seq.as_ref().map(|(t, _)| *t != tid).unwrap_or(true) {
+			curr_seq = Some((tid, ref_idx.fetch_seq(file.tid2name(tid))?))
+		}
+		let seq = &curr_seq.as_ref().unwrap().1;
+		let rf = seq.get_seq(0, seq.len() - 1)?;
+		let rg = brec.get_tag("RG", 'Z').map(|s| String::from_utf8_lossy(&s[..s.iter().position(|c| *c == 0).unwrap_or(s.len())]).into_owned());
+		let ctg_type = ctg_types.get(&tid).copied().unwrap_or(CtgType::Genome);
+		add_read(rg_counts.entry(rg).or_default(), &read, rf, ctg_type, min_qual);
+		n += 1;
+		n_reg += 1;
+	}
+	Ok(())
+}
+
+// Proportion of errors (non-converted Cs that should be converted or vice versa)
+fn err_rate(ct: &[usize; 2], converted: bool) -> Option<f64> {
+	let n = ct[0] + ct[1];
+	if n < CONV_MIN_OBS { None } else { Some((ct[if converted { 1 } else { 0 }] as f64 / n as f64).max(CONV_MIN_RATE)) }
+}
+
+// Under and over conversion rates from counts.  With bisulfite (and enzymatic conversion) it is the non-methylated Cs
+// that are converted, so unconverted Cs in non-methylated sequence give the under conversion rate and converted Cs
+// in methylated sequence give the over conversion rate.  With TAPS this is reversed.  The estimate from the unmethylated
+// control is preferred to that from CHH sites
+fn calc_rates(counts: &ConversionCounts, taps: bool) -> (Option<f64>, Option<f64>) {
+	let unmeth = err_rate(&counts.unmethylated_control, taps).or_else(|| err_rate(&counts.chh, taps));
+	let meth = err_rate(&counts.methylated_control, !taps);
+	let (under, over) = if taps { (meth, unmeth) } else { (unmeth, meth) };
+	let check = |x: Option<f64>, max: f64, s: &str| x.filter(|z| if *z > max {
+		warn!("Estimated {} conversion rate {:.4} too high (ignored)", s, z);
+		false
+	} else { true });
+	(check(under, CONV_MAX_UNDER, "under"), check(over, CONV_MAX_OVER, "over"))
+}
+
+// Estimate under and over conversion rates for each input file (sample) and each read group within the sample from
+// the control sequences (if given) and from CHH sites in a sample of reads from windows spread across the rest of
+// the genome.  Returns an estimate for each sample (with read_group None) followed by estimates for the read groups
+// in the sample.  Rates that can not be estimated are set from the command line or the defaults for the chemistry,
+// and for read groups from the estimates for the sample.  Only the sample estimates are used for calling; the read
+// group estimates are reported in the JSON output to allow inconsistent read groups to be identified
+pub fn estimate_conversion(chash: &ConfHash, in_names: &[&str], regions: &[CtgRegion], ref_idx: &Faidx) -> io::Result<Vec<ConversionEstimate>> {
+	let taps = chash.get_str("chemistry").and_then(|s| <Chemistry>::from_str(s).ok()).map(|c| c.converts_methylated()).unwrap_or(false);
+	let default = (chash.get_float("under_conversion"), chash.get_float("over_conversion"));
+	let mut ctg_types = HashMap::new();
+	let mut ctrl_regions = Vec::new();
+	{
+		let sam_file = SamFile::new(in_names[0])?;
+		for (key, tp) in [("underconversion_sequence", CtgType::Unmethylated), ("overconversion_sequence", CtgType::Methylated)].iter() {
+			for tid in read_control_list(chash, key, &sam_file)? {
+				if ctg_types.insert(tid, *tp).is_some() { return Err(new_err(format!("Sequence {} used for both conversion controls", sam_file.tid2name(tid)))) }
+				ctrl_regions.push(CtgRegion{sam_tid: tid, start: 0, stop: sam_file.tid2len(tid) - 1});
+			}
+		}
+	}
+	let ctrl_set: HashSet<_> = ctg_types.keys().copied().collect();
+	let genome_regions: Vec<_> = regions.iter().filter(|r| !ctrl_set.contains(&r.sam_tid)).copied().collect();
+	let genome_windows = sample_windows(&genome_regions);
+	let n_reads = chash.get_int("conversion_reads");
+	let max_per_window = n_reads.div_ceil(genome_windows.len().max(1));
+	let mut est = Vec::new();
+	for (sample_idx, name) in in_names.iter().enumerate() {
+		info!("Collecting conversion information from {}", name);
+		let mut rg_counts = HashMap::new();
+		if !ctrl_regions.is_empty() { sample_reads(chash, name, &ctrl_regions, n_reads, &ctg_types, ref_idx, &mut rg_counts)? }
+		sample_reads(chash, name, &genome_windows, max_per_window, &ctg_types, ref_idx, &mut rg_counts)?;
+		let mut counts = ConversionCounts::default();
+		for ct in rg_counts.values() { counts.merge(ct) }
+		let (under, over) = calc_rates(&counts, taps);
+		let dflt = |x: Option<f64>| if x.is_some() { "" } else { " (default)" };
+		info!("Conversion rates for {}: under {:.4}{}, over {:.4}{}", name, under.unwrap_or(default.0), dflt(under), over.unwrap_or(default.1), dflt(over));
+		let (under, over) = (under.unwrap_or(default.0), over.unwrap_or(default.1));
+		est.push(ConversionEstimate{sample_idx, sample: String::new(), read_group: None, under_conversion: under, over_conversion: over, counts});
+		let mut rgs: Vec<_> = rg_counts.into_iter().filter_map(|(rg, ct)| rg.map(|r| (r, ct))).collect();
+		if rgs.len() > 1 {
+			rgs.sort_by(|a, b| a.0.cmp(&b.0));
+			for (rg, ct) in rgs {
+				let (u, o) = calc_rates(&ct, taps);
+				est.push(ConversionEstimate{sample_idx, sample: String::new(), read_group: Some(rg), under_conversion: u.unwrap_or(under), over_conversion: o.unwrap_or(over), counts: ct});
+			}
+		}
+	}
+	Ok(est)
+}
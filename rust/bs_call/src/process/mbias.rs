@@ -0,0 +1,143 @@
+use std::io;
+use std::str::FromStr;
+
+use crate::config::{ConfHash, ConfVar, new_err};
+use crate::defs::CtgRegion;
+use crate::htslib::{BamRec, BSStrand, Faidx, SamFile, SamReadResult, Sequence};
+use super::records::ReadEnd;
+use super::Chemistry;
+
+const MBIAS_MIN_OBS: usize = 100; // Minimum CpG observations at a read position for the position to be tested
+const MBIAS_MIN_TOTAL: usize = 10000; // Minimum total CpG observations for a read end to estimate trimming
+const MBIAS_MIN_DIFF: f64 = 0.02; // Minimum deviation from the overall methylation level for a position to be biased
+const MBIAS_WINDOW: usize = 5; // Consecutive unbiased positions required to stop trimming
+const MBIAS_MAX_TRIM: usize = 63;
+
+const TRIM_KEYS: [[&str; 2]; 2] = [["left_trim_read_1", "right_trim_read_1"], ["left_trim_read_2", "right_trim_read_2"]];
+
+// Methylated and non-methylated CpG counts per position in the read, counting from the 5' end (prof5)
+// and from the 3' end (prof3) of the read in its original orientation
+#[derive(Default)]
+struct MBiasProfile {
+	prof5: Vec<[usize; 2]>,
+	prof3: Vec<[usize; 2]>,
+}
+
+fn add_count(prof: &mut Vec<[usize; 2]>, x: usize, m: usize) {
+	if x >= prof.len() { prof.resize(x + 1, [0, 0]) }
+	prof[x][m] += 1;
+}
+
+impl MBiasProfile {
+	fn n_obs(&self) -> usize { self.prof5.iter().fold(0, |s, c| s + c[0] + c[1]) }
+
+	// Median methylation level over read positions with sufficient coverage
+	fn level(&self) -> Option<f64> {
+		let mut v: Vec<_> = self.prof5.iter().filter(|c| c[0] + c[1] >= MBIAS_MIN_OBS).map(|c| (c[0] as f64) / ((c[0] + c[1]) as f64)).collect();
+		if v.is_empty() { return None }
+		v.sort_by(|a, b| a.partial_cmp(b).unwrap());
+		Some(v[v.len() >> 1])
+	}
+
+	fn add_read(&mut self, read: &ReadEnd, rf: &[u8], min_qual: u8) {
+		let map = &read.maps[0];
+		let bs = map.bs_strand();
+		if !matches!(bs, BSStrand::StrandC2T | BSStrand::StrandG2A) { return }
+		let is_cpg = |x: usize| matches!((rf.get(x).map(|c| c.to_ascii_uppercase()), rf.get(x + 1).map(|c| c.to_ascii_uppercase())), (Some(b'C'), Some(b'G')));
+		let rev = map.is_reverse();
+		let total_len = map.cigar.qlen1() as usize;
+		for ab in read.aligned_iter().filter(|a| a.qual >= min_qual) {
+			let r = ab.ref_pos;
+			let m = match bs {
+				BSStrand::StrandC2T if is_cpg(r) => match ab.base { 1 => Some(0), 3 => Some(1), _ => None },
+				BSStrand::StrandG2A if r > 0 && is_cpg(r - 1) => match ab.base { 2 => Some(0), 0 => Some(1), _ => None },
+				_ => None,
+			};
+			if let Some(m) = m {
+				let p = ab.read_pos;
+				let (x5, x3) = if rev { (total_len - 1 - p, p) } else { (p, total_len - 1 - p) };
+				add_count(&mut self.prof5, x5, m);
+				add_count(&mut self.prof3, x3, m);
+			}
+		}
+	}
+}
+
+// Trim positions from the start of the profile until we find MBIAS_WINDOW consecutive positions
+// where the methylation level does not differ significantly from the overall level
+fn get_trim(prof: &[[usize; 2]], level: f64) -> usize {
+	let biased = |c: &[usize; 2]| {
+		let n = c[0] + c[1];
+		if n < MBIAS_MIN_OBS { return false }
+		let sd = (level * (1.0 - level) / (n as f64)).sqrt();
+		((c[0] as f64) / (n as f64) - level).abs() > MBIAS_MIN_DIFF.max(3.0 * sd)
+	};
+	let max_trim = MBIAS_MAX_TRIM.min(prof.len() >> 2);
+	let (mut trim, mut run) = (0, 0);
+	for (ix, c) in prof.iter().enumerate() {
+		if biased(c) {
+			trim = ix + 1;
+			run = 0;
+		} else {
+			run += 1;
+			if run >= MBIAS_WINDOW { break }
+		}
+	}
+	trim.min(max_trim)
+}
+
+// Estimate the read trimming for read 1 and read 2 from the CpG methylation profile (M-bias) of a sample of reads
+// from the start of the input files.  The trim values in the conf_hash are set to the maximum of the estimated
+// values and the values given on the command line
+pub fn estimate_trims(chash: &mut ConfHash, in_names: &[&str], regions: &[CtgRegion], ref_idx: &Faidx) -> io::Result<()> {
+	let n_reads = chash.get_int("auto_trim_reads");
+	let min_qual = chash.get_int("bq_threshold") as u8;
+	let taps = chash.get_str("chemistry").and_then(|s| <Chemistry>::from_str(s).ok()).map(|c| c.converts_methylated()).unwrap_or(false);
+	let fai = format!("{}.fai", chash.get_str("reference").expect("No reference"));
+	let mut prof = [MBiasProfile::default(), MBiasProfile::default()];
+	for name in in_names.iter() {
+		info!("Collecting M-bias profile from {}", name);
+		let mut file = SamFile::new(name)?;
+		file.set_fai_filename(&fai)?;
+		file.set_region_itr(regions);
+		let mut brec = BamRec::new()?;
+		let mut curr_seq: Option<(usize, Sequence)> = None;
+		let mut n = 0;
+		while n < n_reads {
+			match file.inner.get_next(&mut brec) {
+				SamReadResult::Ok => (),
+				SamReadResult::EOF => break,
+				SamReadResult::Error => return Err(new_err(format!("Error reading from input file {}", name))),
+			}
+			let read = match ReadEnd::from_bam_rec(chash, &file.hdr, &brec) {
+				(Some(r), _) if r.is_primary() => r,
+				_ => continue,
+			};
+			let tid = read.maps[0].map_pos.tid as usize;
+			if curr_seq.as_ref().map(|(t, _)| *t != tid).unwrap_or(true) {
+				curr_seq = Some((tid, ref_idx.fetch_seq(file.tid2name(tid))?))
+			}
+			let seq = &curr_seq.as_ref().unwrap().1;
+			let rf = seq.get_seq(0, seq.len() - 1)?;
+			prof[if read.read_two() { 1 } else { 0 }].add_read(&read, rf, min_qual);
+			n += 1;
+		}
+	}
+	for (ix, p) in prof.iter().enumerate() {
+		if p.n_obs() < MBIAS_MIN_TOTAL {
+			if ix == 0 || p.n_obs() > 0 { warn!("Not enough CpG observations to estimate trimming for read {}", ix + 1) }
+			continue
+		}
+		if let Some(level) = p.level() {
+			let trims = [get_trim(&p.prof5, level), get_trim(&p.prof3, level)];
+			// With TAPS the proportion of non-converted Cs is the non-methylated proportion
+			let meth_level = if taps { 1.0 - level } else { level };
+			info!("Estimated trimming for read {} (methylation level {:.3}): left {}, right {}", ix + 1, meth_level, trims[0], trims[1]);
+			for (key, t) in TRIM_KEYS[ix].iter().zip(trims.iter()) {
+				let x = chash.get_int(key).max(*t);
+				chash.set(key, ConfVar::Int(x));
+			}
+		}
+	}
+	Ok(())
+}
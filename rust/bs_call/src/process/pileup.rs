@@ -1,21 +1,24 @@
+use std::collections::BTreeMap;
 use std::sync::{mpsc, Arc};
 use std::{cmp, io, slice, thread};
 
 use super::call_genotypes;
-use super::records::ReadEnd;
+use super::read_meth::ReadMethOutput;
+use super::rrbs::handle_rrbs_reads;
+use super::records::{ReadEnd, ReadTrims};
 use crate::config::{BsCallConfig, BsCallFiles};
 use crate::dbsnp::{DBSnpContig, DBSnpFile};
 use crate::defs::CtgRegion;
 use crate::htslib::{hts_err, BSStrand, CigarOp, Faidx, Sequence};
 use crate::rusage::*;
-use crate::stats::{FSBaseLevelType, FSType, MethProfile, StatJob};
+use crate::stats::{FSBaseLevelType, FSType, MethProfile, RrbsStats, StatJob};
 
 pub struct PileupRegion {
 	start: usize,
 	end: usize,
 	sam_tid: usize,
 	cname: String,
-	reads: Vec<Option<ReadEnd>>,
+	reads: Vec<Vec<Option<ReadEnd>>>, // One vector of reads per sample
 }
 
 impl PileupRegion {
@@ -24,7 +27,7 @@ impl PileupRegion {
 		start: usize,
 		end: usize,
 		sam_tid: usize,
-		reads: Vec<Option<ReadEnd>>,
+		reads: Vec<Vec<Option<ReadEnd>>>,
 	) -> Self {
 		Self {
 			cname: cname.as_ref().to_owned(),
@@ -46,6 +49,15 @@ const TAB_UNCONV: [usize; 4] = [0, 1, 2, 3];
 const TAB_C2T: [usize; 4] = [0, 5, 2, 7];
 const TAB_G2A: [usize; 4] = [4, 1, 6, 3];
 
+// Get observed base type (0-7) from base (0-3) and bisulfite strand
+pub fn base_class(base: usize, bs: BSStrand) -> usize {
+	match bs {
+		BSStrand::StrandC2T => TAB_C2T[base],
+		BSStrand::StrandG2A => TAB_G2A[base],
+		_ => TAB_UNCONV[base],
+	}
+}
+
 impl PileupPos {
 	pub fn new() -> Self {
 		Self {
@@ -59,12 +71,7 @@ impl PileupPos {
 		if qual < min_qual {
 			1
 		} else {
-			let base = (x & 3) as usize;
-			let c = match bs {
-				BSStrand::StrandC2T => TAB_C2T[base],
-				BSStrand::StrandG2A => TAB_G2A[base],
-				_ => TAB_UNCONV[base],
-			};
+			let c = base_class((x & 3) as usize, bs);
 			self.quality[c] += qual as f32;
 			self.counts[c + if rev { 8 } else { 0 }] += 1;
 			self.mapq2 += mapq2;
@@ -78,8 +85,78 @@ impl Default for PileupPos {
 	}
 }
 
+// Indel observed in a read, identified by its type and length.  The inserted bases
+// for insertions are collected in IndelObs so that a consensus can be generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Indel {
+	Del(usize),
+	Ins(usize),
+}
+
+pub struct IndelObs {
+	pub counts: [u32; 2], // Supporting reads on the forward and reverse strands
+	pub ins_counts: Vec<[u32; 8]>, // Counts of inserted bases (using the same encoding as PileupPos)
+}
+
+// Bases consistent with each of the 8 observed base types
+// (A, C, G, T, A|G from G2A strand, C from C2T, G from G2A, C|T from C2T)
+pub const OBS_BASES: [[bool; 4]; 8] = [
+	[true, false, false, false],
+	[false, true, false, false],
+	[false, false, true, false],
+	[false, false, false, true],
+	[true, false, true, false],
+	[false, true, false, false],
+	[false, false, true, false],
+	[false, true, false, true],
+];
+
+impl IndelObs {
+	pub fn new(ins_len: usize) -> Self {
+		Self {
+			counts: [0; 2],
+			ins_counts: vec![[0; 8]; ins_len],
+		}
+	}
+	pub fn merge(&mut self, other: &IndelObs) {
+		self.counts[0] += other.counts[0];
+		self.counts[1] += other.counts[1];
+		for (c1, c2) in self.ins_counts.iter_mut().zip(other.ins_counts.iter()) {
+			c1.iter_mut().zip(c2.iter()).for_each(|(a, b)| *a += b)
+		}
+	}
+	// Consensus of inserted bases (A=1, C=2, G=3, T=4) taking into account the bisulfite conversion,
+	// so a T on the C2T strand supports both C and T.  Positions with no evidence are returned as N (0)
+	pub fn ins_seq(&self) -> Vec<u8> {
+		self.ins_counts
+			.iter()
+			.map(|cts| {
+				let mut support = [0; 4];
+				for (c, bases) in cts.iter().zip(OBS_BASES.iter()) {
+					for (s, b) in support.iter_mut().zip(bases.iter()) {
+						if *b {
+							*s += c
+						}
+					}
+				}
+				// Prefer the more specific call when the support is equal (i.e., C over T for C|T)
+				let (mut best, mut best_ct) = (0, 0);
+				for (i, ct) in support.iter().copied().enumerate() {
+					if ct > best_ct || (ct == best_ct && ct > 0 && i == 1) {
+						best = i + 1;
+						best_ct = ct;
+					}
+				}
+				best as u8
+			})
+			.collect()
+	}
+}
+
 pub struct Pileup {
-	pub data: Vec<PileupPos>,
+	pub data: Vec<Vec<PileupPos>>, // One vector of pileup positions per sample
+	pub indels: Vec<BTreeMap<(usize, Indel), IndelObs>>, // Indels per sample, keyed on anchor position (base before the indel)
+	pub reads: Vec<Vec<Option<ReadEnd>>>, // Reads per sample (only kept if required for allele specific methylation)
 	pub ref_seq: Vec<u8>,
 	pub gc_bins: Vec<u8>,
 	pub dbsnp_contig: Option<DBSnpContig>,
@@ -90,7 +167,7 @@ pub struct Pileup {
 
 impl Pileup {
 	fn new(
-		data: Vec<PileupPos>,
+		data: Vec<Vec<PileupPos>>,
 		ref_seq: Vec<u8>,
 		start: usize,
 		ref_start: usize,
@@ -106,8 +183,11 @@ impl Pileup {
 		} else {
 			None
 		};
+		let indels = data.iter().map(|_| BTreeMap::new()).collect();
 		Self {
 			data,
+			indels,
+			reads: Vec::new(),
 			ref_seq,
 			gc_bins,
 			dbsnp_contig,
@@ -119,6 +199,7 @@ impl Pileup {
 
 	fn add_obs(
 		&mut self,
+		sample: usize,
 		pos: usize,
 		sq: &[u8],
 		rev: bool,
@@ -127,16 +208,52 @@ impl Pileup {
 		mapq2: f32,
 	) -> usize {
 		let mut lq_ct = 0;
+		let data = &mut self.data[sample];
 		for (i, x) in sq.iter().enumerate() {
 			if i + pos >= self.start {
 				let j = i + pos - self.start;
-				if j < self.data.len() {
-					lq_ct += self.data[j].add_obs(*x, rev, bs, min_qual, mapq2)
+				if j < data.len() {
+					lq_ct += data[j].add_obs(*x, rev, bs, min_qual, mapq2)
 				}
 			}
 		}
 		lq_ct
 	}
+	#[allow(clippy::too_many_arguments)]
+	fn add_indel(
+		&mut self,
+		sample: usize,
+		pos: usize,
+		indel: Indel,
+		sq: &[u8],
+		rev: bool,
+		bs: BSStrand,
+		min_qual: u8,
+	) {
+		// Only count indels where the anchor base is in the region
+		if pos < self.start + 1 || pos - 1 - self.start >= self.n_positions() {
+			return;
+		}
+		let len = match indel {
+			Indel::Ins(l) => l,
+			_ => 0,
+		};
+		let obs = self.indels[sample]
+			.entry((pos - 1, indel))
+			.or_insert_with(|| IndelObs::new(len));
+		obs.counts[if rev { 1 } else { 0 }] += 1;
+		for (cts, x) in obs.ins_counts.iter_mut().zip(sq.iter()) {
+			if (x >> 2) >= min_qual {
+				cts[base_class((x & 3) as usize, bs)] += 1;
+			}
+		}
+	}
+	pub fn n_samples(&self) -> usize {
+		self.data.len()
+	}
+	pub fn n_positions(&self) -> usize {
+		self.data[0].len()
+	}
 	pub fn get_prec_2_bases(&self) -> [u8; 2] {
 		match self.start - self.ref_start {
 			0 => [0, 0],
@@ -316,6 +433,7 @@ fn check_regions(preg: &mut PileupRegion, regions: &[CtgRegion]) -> io::Result<(
 
 fn add_read_to_pileup(
 	read: &ReadEnd,
+	sample: usize,
 	pileup: &mut Pileup,
 	ltrim: usize,
 	rtrim: usize,
@@ -384,6 +502,7 @@ fn add_read_to_pileup(
 					match op {
 						CigarOp::Match | CigarOp::Equal | CigarOp::Diff => {
 							low_qual += pileup.add_obs(
+								sample,
 								ref_pos,
 								&sq[seq_pos..seq_pos + add],
 								rev,
@@ -443,7 +562,30 @@ fn add_read_to_pileup(
 						}
 						CigarOp::Overlap => overlap += add,
 						CigarOp::HardClip | CigarOp::SoftClip => clipped += add,
-						CigarOp::Ins => inserts += add,
+						CigarOp::Ins => {
+							inserts += add;
+							// Only use insertions that are not cut by trimming
+							if add == l as usize {
+								pileup.add_indel(
+									sample,
+									ref_pos,
+									Indel::Ins(add),
+									&sq[seq_pos..seq_pos + add],
+									rev,
+									bs,
+									min_qual,
+								)
+							}
+						}
+						CigarOp::Del => pileup.add_indel(
+							sample,
+							ref_pos,
+							Indel::Del(add),
+							&[],
+							rev,
+							bs,
+							min_qual,
+						),
 						_ => (),
 					}
 					if (op_type & 1) != 0 {
@@ -486,15 +628,12 @@ fn handle_pileup(
 	stat_tx: &mpsc::Sender<StatJob>,
 	call_tx: &mpsc::SyncSender<Option<Pileup>>,
 ) -> io::Result<()> {
-	if preg.reads.is_empty() {
+	if preg.reads.iter().all(|r| r.is_empty()) {
 		warn!("make_pileup received empty read vector");
 		return Ok(());
 	}
 	let bs_cfg = &pileup_data.bs_cfg;
-	let ltrim1 = bs_cfg.conf_hash.get_int("left_trim_read_1");
-	let ltrim2 = bs_cfg.conf_hash.get_int("left_trim_read_2");
-	let rtrim1 = bs_cfg.conf_hash.get_int("right_trim_read_1");
-	let rtrim2 = bs_cfg.conf_hash.get_int("right_trim_read_2");
+	let trims = ReadTrims::new(&bs_cfg.conf_hash);
 	let min_qual = bs_cfg.conf_hash.get_int("bq_threshold") as u8;
 	let seq_data = &mut pileup_data.seq_data;
 	let ref_index = &pileup_data.ref_index;
@@ -503,11 +642,15 @@ fn handle_pileup(
 	check_regions(&mut preg, &bs_cfg.regions)?;
 	let meth_prof = &mut pileup_data.meth_prof;
 	let (ref_seq, ref_start, gc_bins) = load_ref_seq(&mut preg, &seq_data)?;
-	let mut fs_stats = FSType::new();
+	let mut fs_stats: Vec<_> = preg.reads.iter().map(|_| FSType::new()).collect();
 	let size = preg.end + 1 - preg.start;
-	let mut pileup_vec = Vec::with_capacity(size);
-	for _ in 0..size {
-		pileup_vec.push(PileupPos::new())
+	let mut pileup_vec = Vec::with_capacity(preg.reads.len());
+	for _ in 0..preg.reads.len() {
+		let mut v = Vec::with_capacity(size);
+		for _ in 0..size {
+			v.push(PileupPos::new())
+		}
+		pileup_vec.push(v)
 	}
 	let mut pileup = Pileup::new(
 		pileup_vec,
@@ -519,17 +662,20 @@ fn handle_pileup(
 		&preg.cname,
 		dbsnp_file,
 	);
-	for read in preg.reads.drain(..).flatten() {
-		let (ltrim, rtrim) = if read.read_one() {
-			(ltrim1, rtrim1)
-		} else if read.read_two() {
-			(ltrim2, rtrim2)
-		} else {
-			(0, 0)
-		};
+	if let Some(rrbs) = pileup_data.rrbs.as_mut() {
+		for (reads, rs) in preg.reads.iter_mut().zip(rrbs.iter_mut()) { handle_rrbs_reads(reads, &pileup.ref_seq, pileup.ref_start, rs) }
+	}
+	for (sample, read) in preg
+		.reads
+		.iter()
+		.enumerate()
+		.flat_map(|(i, v)| v.iter().flatten().map(move |r| (i, r)))
+	{
+		let (ltrim, rtrim) = trims.get(read);
 		let (l, clipped, trimmed, overlap, low_qual, inserts) =
-			add_read_to_pileup(&read, &mut pileup, ltrim, rtrim, min_qual, meth_prof);
+			add_read_to_pileup(read, sample, &mut pileup, ltrim, rtrim, min_qual, &mut meth_prof[sample]);
 		if read.is_primary() {
+			let fs_stats = &mut fs_stats[sample];
 			if clipped > 0 {
 				fs_stats.add_base_level_count(FSBaseLevelType::Clipped, clipped)
 			};
@@ -550,9 +696,17 @@ fn handle_pileup(
 			fs_stats.add_base_level_count(FSBaseLevelType::Passed, l - nflt);
 		}
 	}
+	if let Some(rm) = pileup_data.read_meth.as_mut() {
+		rm.output_reads(&pileup, &preg.reads, bs_cfg)?
+	}
+	if pileup_data.keep_reads {
+		pileup.reads = preg.reads
+	}
 	send_call_job(pileup, &call_tx)?;
-	for (flag, ct) in fs_stats.base_level().iter() {
-		let _ = stat_tx.send(StatJob::AddFSBaseLevelCounts(*flag, *ct));
+	for (sample, fs) in fs_stats.iter().enumerate() {
+		for (flag, ct) in fs.base_level().iter() {
+			let _ = stat_tx.send(StatJob::AddFSBaseLevelCounts(sample, *flag, *ct));
+		}
 	}
 	Ok(())
 }
@@ -567,7 +721,10 @@ pub struct PileupData {
 	ref_index: Faidx,
 	dbsnp_file: Option<DBSnpFile>,
 	bs_cfg: Arc<BsCallConfig>,
-	meth_prof: MethProfile,
+	meth_prof: Vec<MethProfile>, // Per sample
+	keep_reads: bool,
+	read_meth: Option<ReadMethOutput>,
+	rrbs: Option<Vec<RrbsStats>>, // Per sample
 }
 
 pub fn make_pileup(
@@ -589,11 +746,15 @@ pub fn make_pileup(
 		None
 	};
 	let min_qual = bs_cfg.conf_hash.get_int("bq_threshold") as u8;
+	let n_samples = bs_cfg.samples.len();
 	let mut pileup_data = PileupData {
 		seq_data: None,
 		ref_index: bs_files.ref_index.take().unwrap(),
 		dbsnp_file,
-		meth_prof: MethProfile::new(min_qual as usize),
+		meth_prof: (0..n_samples).map(|_| MethProfile::new(min_qual as usize)).collect(),
+		keep_reads: bs_cfg.conf_hash.get_str("asm_output").is_some(),
+		read_meth: bs_files.read_meth_output.take().map(|f| ReadMethOutput::new(f, &bs_cfg)),
+		rrbs: if bs_cfg.conf_hash.get_bool("rrbs") { Some((0..n_samples).map(|_| RrbsStats::default()).collect()) } else { None },
 		bs_cfg: Arc::clone(&bs_cfg),
 	};
 	let (call_tx, call_rx) = mpsc::sync_channel(32);
@@ -623,13 +784,23 @@ pub fn make_pileup(
 	if call_tx.send(None).is_err() {
 		warn!("Error trying to send QUIT signal to call_genotypes thread")
 	} else {
-		let _ = stat_tx.send(StatJob::SetNonCpgReadProfile(
-			pileup_data.meth_prof.take_profile(),
-		));
+		for (sample, mp) in pileup_data.meth_prof.drain(..).enumerate() {
+			let _ = stat_tx.send(StatJob::SetNonCpgReadProfile(sample, mp.take_profile()));
+		}
+		if let Some(mut rrbs) = pileup_data.rrbs.take() {
+			for (sample, rs) in rrbs.drain(..).enumerate() {
+				let _ = stat_tx.send(StatJob::SetRrbsStats(sample, rs));
+			}
+		}
 		if call_handle.join().is_err() {
 			warn!("Error waiting for call_genotype thread to finish")
 		}
 	}
+	if let Some(rm) = pileup_data.read_meth.take() {
+		if let Err(e) = rm.finish() {
+			error!("Error finishing read level methylation output: {}", e)
+		}
+	}
 	if let Ok(ru_thread) = Rusage::get(RusageWho::RusageThread) {
 		info!(
 			"pileup_thread shutting down: user {} sys {}",
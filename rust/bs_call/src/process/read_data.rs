@@ -9,7 +9,7 @@ use super::pileup;
 use crate::stats::{StatJob, FSReadLevelType, FSType};
 
 enum ReadState {
-	Duplicate,
+	Duplicate(Option<usize>), // Index of the read this is a duplicate of (if reads are being merged)
 	Present(usize),
 } 
 
@@ -18,7 +18,6 @@ struct StateInner {
 	start_x: u32,
 	end_x: u32,
 	curr_x: u32,
-	idx: usize,	
 }
 
 struct State(Option<StateInner>);
@@ -28,10 +27,10 @@ impl State {
 		let map_pos = map.map_pos;
 		let start_x = map_pos.pos;
 		let end_x = start_x + map.cigar.rlen();
-		StateInner{tid: map_pos.tid, start_x, end_x, curr_x: start_x, idx: 0 }
+		StateInner{tid: map_pos.tid, start_x, end_x, curr_x: start_x }
 	}
 	
-	fn update(&mut self, map: &Map, idx: usize) -> StateChange {
+	fn update(&mut self, map: &Map) -> StateChange {
 		if let Some(mut state) = self.0.take() { 
 			let mut change = StateChange::None;
 			let mp = map.map_pos;
@@ -41,7 +40,6 @@ impl State {
 				s.start_x = start_x;
 				s.curr_x = start_x;
 				s.end_x = end_x;
-				s.idx = 0;
 			};
 			if mp.tid != state.tid {
 				change = StateChange::NewContig((state.tid, state.start_x, state.end_x));
@@ -52,10 +50,7 @@ impl State {
 				state_cp(&mut state);
 			} else {
 				if start_x < state.curr_x { panic!("BAM is not sorted!") }
-				if start_x > state.curr_x {
-					state.curr_x = start_x;
-					state.idx = idx;
-				}
+				state.curr_x = start_x;
 				state.end_x = cmp::max(state.end_x, end_x);
 			}
 			self.0 = Some(state);
@@ -67,6 +62,87 @@ impl State {
 	} 
 }
 
+// Reads stored for the current block for one sample, along with the information
+// required for duplicate checking and pairing of reads and the read level stats for the sample
+struct SampleReads {
+	reads: Vec<Option<ReadEnd>>,
+	state_hash: HashMap<String, ReadState>,
+	curr_x: u32,
+	idx: usize, // Index of first read starting at curr_x
+	fs_stats: FSType,
+	umi_fam: Option<Vec<usize>>,
+	// Second reads of merged duplicate pairs that arrived before the mate of the read they are merged with,
+	// keyed on the index of the first read of the pair
+	pending_dups: HashMap<usize, Vec<ReadEnd>>,
+}
+
+impl SampleReads {
+	fn new(umi_merge: bool) -> Self { 
+		Self{reads: Vec::new(), state_hash: HashMap::new(), curr_x: 0, idx: 0, fs_stats: FSType::new(), umi_fam: if umi_merge { Some(Vec::new()) } else { None }, pending_dups: HashMap::new()} 
+	}
+	fn take_reads(&mut self) -> Vec<Option<ReadEnd>> {
+		self.state_hash.clear();
+		self.pending_dups.clear();
+		self.curr_x = 0;
+		self.idx = 0;
+		std::mem::take(&mut self.reads)
+	}
+	fn update(&mut self, start_x: u32) {
+		if start_x > self.curr_x {
+			self.curr_x = start_x;
+			self.idx = self.reads.len();
+		}
+	}
+}
+
+// Input file with the next record (if any) waiting to be processed
+struct Input {
+	file: SamFile,
+	brec: BamRec,
+	pending: bool,
+	eof: bool,
+}
+
+impl Input {
+	// Sort key used to merge the inputs.  Records are read region by region (in the order of the region list, which need
+	// not be coordinate order), so we merge first on the region and then on coordinates (unmapped reads go last)
+	fn key(&self) -> (usize, usize, usize) { 
+		(self.file.inner.region_idx(), self.brec.tid().unwrap_or(usize::MAX), self.brec.pos().unwrap_or(usize::MAX))
+	}
+}
+
+// Get the index of the input with the next record in region order
+fn next_input(inputs: &mut [Input]) -> Option<usize> {
+	for inp in inputs.iter_mut().filter(|i| !(i.pending || i.eof)) {
+		match inp.file.inner.get_next(&mut inp.brec) {
+			SamReadResult::Ok => inp.pending = true,
+			SamReadResult::EOF => inp.eof = true,
+			_ => panic!("Error reading record"),
+		}
+	}
+	inputs.iter().enumerate().filter(|(_, i)| i.pending).min_by_key(|(_, i)| i.key()).map(|(ix, _)| ix)
+}
+
+fn take_reads(samples: &mut [SampleReads]) -> Vec<Vec<Option<ReadEnd>>> {
+	samples.iter_mut().map(|s| {
+		let reads = s.take_reads();
+		count_passed_reads(&reads, &mut s.fs_stats);
+		if let Some(v) = s.umi_fam.as_mut() { count_umi_families(&reads, v) }
+		reads
+	}).collect()
+}
+
+// Accumulate distribution of UMI family sizes (counting only the first read of pairs)
+fn count_umi_families(reads: &[Option<ReadEnd>], fam: &mut Vec<usize>) {
+	for read in reads.iter().flatten().filter(|r| r.is_primary() && r.mate_idx.is_none()) {
+		let ix = read.family_size as usize;
+		if ix >= fam.len() { fam.resize(ix + 1, 0) }
+		fam[ix] += 1;
+	}
+}
+
+fn n_reads(samples: &[SampleReads]) -> usize { samples.iter().map(|s| s.reads.len()).sum() }
+
 enum StateChange { 
 	None,
 	Init,
@@ -74,7 +150,7 @@ enum StateChange {
 	NewContig((u32, u32, u32)),
 }
 
-fn send_pileup_job(reads: Vec<Option<ReadEnd>>, cname: &str, x: u32, y: u32, tid: u32, pileup_tx: &mpsc::SyncSender<Option<pileup::PileupRegion>>) -> io::Result<()> {
+fn send_pileup_job(reads: Vec<Vec<Option<ReadEnd>>>, cname: &str, x: u32, y: u32, tid: u32, pileup_tx: &mpsc::SyncSender<Option<pileup::PileupRegion>>) -> io::Result<()> {
 	let preg = pileup::PileupRegion::new(cname, x as usize, y as usize, tid as usize, reads);
 	match pileup_tx.send(Some(preg)) { 
 		Err(e) => {
@@ -95,66 +171,80 @@ fn 	count_passed_reads(reads: &[Option<ReadEnd>], fs_stats: &mut FSType) {
 
 pub fn read_data(bs_cfg: Arc<BsCallConfig>, stat_tx: mpsc::Sender<StatJob>, mut bs_files: BsCallFiles) -> io::Result<()> {
 	
-	let mut sam_input = bs_files.sam_input.take().unwrap();
-	let mut fs_stats = FSType::new();
+	let mut inputs: Vec<_> = bs_files.sam_input.take().unwrap().drain(..)
+		.map(|file| Input{file, brec: BamRec::new().unwrap(), pending: false, eof: false}).collect();
+	let hdr = inputs[0].file.hdr.dup()?;
 	let (pileup_tx, pileup_rx) = mpsc::sync_channel(32);
 	let cfg = Arc::clone(&bs_cfg);
 	let st_tx = mpsc::Sender::clone(&stat_tx);
 	let pileup_handle = thread::spawn(move || { pileup::make_pileup(Arc::clone(&bs_cfg), pileup_rx, bs_files, st_tx) });
-	let hdr = &mut sam_input.hdr;
 
 	let keep_duplicates = cfg.conf_hash.get_bool("keep_duplicates");
-	let mut brec = BamRec::new().unwrap();
-	let mut reads: Vec<Option<ReadEnd>> = Vec::new();
-	let mut state_hash: HashMap<String, ReadState> = HashMap::new();
+	// If a UMI tag is set, duplicates (same position and UMI) are merged into a consensus read
+	let umi_merge = cfg.conf_hash.get_str("umi_tag").is_some();
+	let max_umi_diff = cfg.conf_hash.get_int("umi_mismatches");
+	let mut samples: Vec<_> = inputs.iter().map(|_| SampleReads::new(umi_merge)).collect();
 	let mut curr_state = State(None);
 	loop {
-		match sam_input.inner.get_next(&mut brec) {
-			SamReadResult::Ok => (),
-			SamReadResult::EOF => {
+		let ix = match next_input(&mut inputs) {
+			Some(ix) => ix,
+			None => {
 				if let Some(cstate) = curr_state.0.as_ref() {
 					let cname = hdr.tid2name(cstate.tid as usize);
 					let (x, y) = (cstate.start_x, cstate.end_x);
-					trace!("Last block ({}:{}-{} len = {}, {} maps)", cname, x, y, y - x + 1, reads.len());
-					count_passed_reads(&reads, &mut fs_stats);
+					trace!("Last block ({}:{}-{} len = {}, {} maps)", cname, x, y, y - x + 1, n_reads(&samples));
+					let reads = take_reads(&mut samples);
 					send_pileup_job(reads, cname, x, y, cstate.tid, &pileup_tx)?;
 				}
 				break;
 			},
-			_ => panic!("Error reading record"),
-		}
-		let (read_end, read_flag) = ReadEnd::from_bam_rec(&cfg.conf_hash, hdr, &brec);
+		};
+		let inp = &mut inputs[ix];
+		inp.pending = false;
+		let brec = &inp.brec;
+		let (read_end, read_flag) = ReadEnd::from_bam_rec(&cfg.conf_hash, &inp.file.hdr, brec);
 		if let Some(mut read) = read_end {
 			let map = &read.maps[0];
-			let change = curr_state.update(map, reads.len());
+			let change = curr_state.update(map);
 			let cstate = curr_state.0.as_ref().unwrap();
 			match change {
 				StateChange::NewBlock((x,y)) => {
 					let cname = hdr.tid2name(cstate.tid as usize);
-					trace!("Ending block ({}:{}-{} len = {}, {} maps)", cname, x, y, y - x + 1, reads.len());
-					count_passed_reads(&reads, &mut fs_stats);
+					trace!("Ending block ({}:{}-{} len = {}, {} maps)", cname, x, y, y - x + 1, n_reads(&samples));
+					let reads = take_reads(&mut samples);
 					send_pileup_job(reads, cname, x, y, cstate.tid, &pileup_tx)?;
-					reads = Vec::new();
-					state_hash.clear();
 				},
 				StateChange::NewContig((tid, x, y)) => {
 					let cname = hdr.tid2name(tid as usize);
-					trace!("Ending contig with block ({}:{}-{} len = {}, {} maps)", cname, x, y, y - x + 1, reads.len());
-					count_passed_reads(&reads, &mut fs_stats);
+					trace!("Ending contig with block ({}:{}-{} len = {}, {} maps)", cname, x, y, y - x + 1, n_reads(&samples));
+					let reads = take_reads(&mut samples);
 					send_pileup_job(reads, cname, x, y, tid, &pileup_tx)?;
-					reads = Vec::new();
-					state_hash.clear();
 				},
 				StateChange::Init => {
 					debug!("Initiating run")
 				}
 				_ => (),
 			}	
+			let sample = &mut samples[ix];
+			sample.update(map.map_pos.pos);
+			let reads = &mut sample.reads;
+			let state_hash = &mut sample.state_hash;
+			let fs_stats = &mut sample.fs_stats;
+			let pending_dups = &mut sample.pending_dups;
 			let id = brec.qname();
+			let mut pending = None;
 			let insert = if let Some(state) = state_hash.get(id) {
 				match state {
-					ReadState::Duplicate => {
+					ReadState::Duplicate(dup_idx) => {
 						if read.is_primary() { fs_stats.add_read_level_count(FSReadLevelType::Duplicate, brec.l_qseq() as usize); }
+						// Merge with the mate of the read that the first read of the pair was merged with.  If the mate
+						// has not been seen yet, keep the read until it arrives
+						if let Some(x) = dup_idx {
+							match reads[*x..].iter_mut().rev().flatten().find(|r| r.mate_idx == Some(*x)) {
+								Some(r) => { r.merge_dup(&read); },
+								None => pending = Some(*x),
+							}
+						}
 						false
 					},
 					ReadState::Present(x) => {
@@ -182,16 +272,29 @@ pub fn read_data(bs_cfg: Arc<BsCallConfig>, stat_tx: mpsc::Sender<StatJob>, mut
 										fs_stats.add_read_level_count(FSReadLevelType::ZeroUnclipped, brec.l_qseq() as usize);
 									}
 									false
-								} else { true }
+								} else { 
+									read.mate_idx = Some(*x);
+									if let Some(v) = pending_dups.remove(x) { for d in v.iter() { read.merge_dup(d); } }
+									true 
+								}
 							}
 						} else { true }
 					}
 				}
 			} else {
 				// Check if duplicate of already stored read
-				if !keep_duplicates && read.check_dup(&reads[cstate.idx..]) {
+				let idx = sample.idx;
+				let dup = if keep_duplicates { None } else { read.check_dup(&reads[idx..], max_umi_diff).map(|i| i + idx) };
+				if let Some(x) = dup {
 					if read.is_primary() { fs_stats.add_read_level_count(FSReadLevelType::Duplicate, brec.l_qseq() as usize); }
-					state_hash.insert(id.to_owned(), ReadState::Duplicate);
+					let dup_idx = if umi_merge {
+						// Only count the read in the family (and merge its mate later) if the reads could be merged
+						reads[x].as_mut().and_then(|r| if r.merge_dup(&read) {
+							r.family_size += 1;
+							Some(x)
+						} else { None })
+					} else { None };
+					state_hash.insert(id.to_owned(), ReadState::Duplicate(dup_idx));
 					false
 				} else if map.is_last() {
 					// println!("Inserting entry for {} at index {}", id, reads.len());
@@ -199,17 +302,21 @@ pub fn read_data(bs_cfg: Arc<BsCallConfig>, stat_tx: mpsc::Sender<StatJob>, mut
 					true
 				} else { true }
 			};
-			if insert { reads.push(Some(read)) };
+			if insert { reads.push(Some(read)) }
+			else if let Some(x) = pending { pending_dups.entry(x).or_insert_with(Vec::new).push(read) }
 		} else { // Only collect stats on primary reads unless they are flagged for being secondary or supplementary
 			if match read_flag {
 				FSReadLevelType::SupplementaryAlignment | FSReadLevelType::SecondaryAlignment => true,
 				_ => brec.flag() & (BAM_FSECONDARY | BAM_FSUPPLEMENTARY) == 0,
-			} {	fs_stats.add_read_level_count(read_flag, brec.l_qseq() as usize) } 
+			} {	samples[ix].fs_stats.add_read_level_count(read_flag, brec.l_qseq() as usize) } 
 		}
 	}
 	if pileup_tx.send(None).is_err() { warn!("Error trying to send QUIT signal to pileup thread") }
 	else {
-		for (flag, ct) in fs_stats.read_level().iter() { let _ = stat_tx.send(StatJob::AddFSReadLevelCounts(*flag, *ct)); }
+		for (ix, s) in samples.drain(..).enumerate() {
+			for (flag, ct) in s.fs_stats.read_level().iter() { let _ = stat_tx.send(StatJob::AddFSReadLevelCounts(ix, *flag, *ct)); }
+			if let Some(v) = s.umi_fam { let _ = stat_tx.send(StatJob::SetUmiFamilySizes(ix, v)); }
+		}
 		if pileup_handle.join().is_err() { warn!("Error waiting for pileup thread to finish") }
 	}
 	Ok(())
@@ -0,0 +1,65 @@
+use std::io::{self, Write};
+
+use crate::config::BsCallConfig;
+use crate::htslib::{BSStrand, HtsFile, Tbx, tbx_conf_bed};
+use super::pileup::Pileup;
+use super::records::{ReadEnd, ReadTrims, get_fragments};
+
+pub const READ_METH_HEADER: &str = "#chrom\tstart\tend\tread_id\tsample\tbs_strand\tn_cpg\tcpg_pos\tmeth";
+
+// Read level methylation output.  For each read or read pair we output the methylation state (M = methylated,
+// U = non-methylated) of all reference CpGs covered.  The output is bgzip compressed and sorted on the position
+// of the first CpG, so it can be indexed with tabix once complete
+pub struct ReadMethOutput {
+	file: HtsFile,
+	trims: ReadTrims,
+	min_qual: u8,
+	taps: bool,
+}
+
+impl ReadMethOutput {
+	pub fn new(file: HtsFile, bs_cfg: &BsCallConfig) -> Self {
+		Self{file, trims: ReadTrims::new(&bs_cfg.conf_hash), min_qual: bs_cfg.conf_hash.get_int("bq_threshold") as u8, taps: bs_cfg.chemistry().converts_methylated()}
+	}
+
+	fn is_cpg(pileup: &Pileup, pos: usize) -> bool {
+		match pos.checked_sub(pileup.ref_start) {
+			Some(ix) => matches!((pileup.ref_seq.get(ix), pileup.ref_seq.get(ix + 1)), (Some(2), Some(3))),
+			None => false,
+		}
+	}
+
+	pub fn output_reads(&mut self, pileup: &Pileup, reads: &[Vec<Option<ReadEnd>>], bs_cfg: &BsCallConfig) -> io::Result<()> {
+		let cname = bs_cfg.ctg_name(pileup.sam_tid);
+		let mut lines = Vec::new();
+		for (sample, sreads) in reads.iter().enumerate() {
+			for frag in get_fragments(sreads, &self.trims).iter() {
+				let cpgs: Vec<_> = (frag.start.saturating_sub(1)..=frag.end).filter(|x| Self::is_cpg(pileup, *x))
+					.filter_map(|x| frag.get_meth(x, self.min_qual, self.taps).map(|m| (x, m))).collect();
+				if cpgs.is_empty() { continue }
+				let id = sreads[frag.read_idx].as_ref().and_then(|r| r.id.as_deref()).unwrap_or(".");
+				let strand = match frag.bases[0].2 {
+					BSStrand::StrandC2T => "C2T",
+					BSStrand::StrandG2A => "G2A",
+					_ => ".",
+				};
+				let pos: Vec<_> = cpgs.iter().map(|(x, _)| format!("{}", x + 1)).collect();
+				let meth: String = cpgs.iter().map(|(_, m)| if *m == 0 { 'M' } else { 'U' }).collect();
+				let (start, end) = (cpgs[0].0, cpgs[cpgs.len() - 1].0 + 2);
+				lines.push((start, format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n", cname, start, end, id, bs_cfg.sample_name(sample),
+					strand, cpgs.len(), pos.join(","), meth)));
+			}
+		}
+		lines.sort_by_key(|(x, _)| *x);
+		let file = self.file.as_mut();
+		for (_, s) in lines.iter() { file.write_all(s.as_bytes())? }
+		Ok(())
+	}
+
+	// Close output file and generate index
+	pub fn finish(self) -> io::Result<()> {
+		let name = self.file.name().to_owned();
+		drop(self.file);
+		Tbx::build(&name, &tbx_conf_bed)
+	}
+}
@@ -2,6 +2,7 @@ use std::str::{FromStr, from_utf8};
 use crate::htslib::*;
 use crate::config::ConfHash;
 use crate::stats::stats_json::FSReadLevelType as ReadFlag;
+use super::pileup::base_class;
 
 #[derive(Copy,Clone,PartialEq,Eq)]
 pub struct MapPos {
@@ -126,6 +127,53 @@ fn map_from_sa_tag(sam_hdr: &SamHeader, tag: &str) -> Result<Map, String> {
 	} else { Err("Empty SA tag".to_string()) }
 }
 
+// Read base (0-3 for A, C, G, T) and quality at an aligned position (Match, Equal or Diff cigar operation)
+// of the primary mapping of a read.  read_pos is the position in the original read (including hard clips)
+#[derive(Copy, Clone, Debug)]
+pub struct AlignedBase {
+	pub read_pos: usize,
+	pub ref_pos: usize,
+	pub base: u8,
+	pub qual: u8,
+}
+
+pub struct AlignedBases<'a> {
+	cigar: &'a [CigarElem],
+	seq_qual: &'a [u8],
+	ix: usize, // Current cigar element
+	off: usize, // Offset within current element
+	ref_pos: usize,
+	seq_pos: usize,
+	read_pos: usize,
+}
+
+impl<'a> Iterator for AlignedBases<'a> {
+	type Item = AlignedBase;
+	fn next(&mut self) -> Option<Self::Item> {
+		while let Some(elem) = self.cigar.get(self.ix) {
+			let (op, l) = elem.op_pair();
+			let l = l as usize;
+			if self.off < l && matches!(op, CigarOp::Match | CigarOp::Equal | CigarOp::Diff) {
+				let i = self.off;
+				self.off += 1;
+				if let Some(x) = self.seq_qual.get(self.seq_pos + i) {
+					return Some(AlignedBase{read_pos: self.read_pos + i, ref_pos: self.ref_pos + i, base: x & 3, qual: x >> 2})
+				}
+				continue
+			}
+			let op_type = elem.op_type1();
+			if (op_type & 1) != 0 {
+				if op != CigarOp::HardClip { self.seq_pos += l }
+				self.read_pos += l
+			}
+			if (op_type & 2) != 0 { self.ref_pos += l }
+			self.ix += 1;
+			self.off = 0;
+		}
+		None
+	}
+}
+
 pub struct ReadEnd {
 	// For split mapped reads we get the details of all of the splits from the SA tag
 	// The current mapping is always the first element in maps
@@ -134,9 +182,37 @@ pub struct ReadEnd {
 	// For paired reads
 	pub mate_pos: Option<MapPos>,
 	pub mate_idx: Option<usize>,
+	// Read id (only kept if needed for output)
+	pub id: Option<String>,
+	// UMI (if UMI tag set)
+	pub umi: Option<Box<[u8]>>,
+	// Number of reads with the same position and UMI merged into this read
+	pub family_size: u32,
+}
+
+// Normalized cigar used to check whether duplicate reads can be merged: trimmed (overlap) bases are
+// treated as matches and adjacent elements of the same type are joined
+fn cigar_key(cigar: &CigarBuf) -> Vec<(CigarOp, u32)> {
+	let mut v: Vec<(CigarOp, u32)> = Vec::with_capacity(cigar.len());
+	for elem in cigar.iter() {
+		let (op, l) = elem.op_pair();
+		let op = match op {
+			CigarOp::Overlap | CigarOp::Equal | CigarOp::Diff => CigarOp::Match,
+			_ => op,
+		};
+		match v.last_mut() {
+			Some((op1, l1)) if *op1 == op => *l1 += l,
+			_ => v.push((op, l)),
+		}
+	}
+	v
 }
 
 impl ReadEnd {
+	pub fn aligned_iter(&self) -> AlignedBases<'_> {
+		let map = &self.maps[0];
+		AlignedBases{cigar: &map.cigar, seq_qual: &self.seq_qual, ix: 0, off: 0, ref_pos: map.map_pos.pos as usize, seq_pos: 0, read_pos: 0}
+	}
 	pub fn from_bam_rec(conf_hash: &ConfHash, sam_hdr: &SamHeader, brec: &BamRec) -> (Option<Self>, ReadFlag) {
 		// Pull in config options
 		let keep_duplicates = conf_hash.get_bool("keep_duplicates");
@@ -182,7 +258,9 @@ impl ReadEnd {
 		} else { None };
 		let maps = maps_from_bam_rec(sam_hdr, brec, keep_supplementary).unwrap_or_else(|e| panic!("Couldn't get map record from BAM read (id: {}: {}", brec.qname(), e));		
 		let seq_qual = brec.get_seq_qual().unwrap_or_else(|e| panic!("Error in BAM: (id: {} - {})", brec.qname(), e));
-		(Some(ReadEnd{seq_qual, maps, mate_pos, mate_idx: None }), read_flag)
+		let id = if conf_hash.get_str("read_meth_output").is_some() { Some(brec.qname().to_owned()) } else { None };
+		let umi = conf_hash.get_str("umi_tag").and_then(|t| brec.get_tag(t, 'Z')).map(|s| s.iter().copied().take_while(|c| *c != 0).collect());
+		(Some(ReadEnd{seq_qual, maps, mate_pos, mate_idx: None, id, umi, family_size: 1 }), read_flag)
 	}
 
 	fn maps_eq(&self, maps: &[Map]) -> bool {
@@ -192,16 +270,43 @@ impl ReadEnd {
 		} else { false }
 	}
 	
-	pub fn check_dup(&self, read_list: &[Option<Self>]) -> bool {
-		for rd in read_list.iter() {
+	// UMIs match if they have the same length and differ at no more than max_diff positions
+	fn umi_eq(&self, other: &Self, max_diff: usize) -> bool {
+		match (&self.umi, &other.umi) {
+			(Some(a), Some(b)) => a.len() == b.len() && a.iter().zip(b.iter()).filter(|(x, y)| x != y).count() <= max_diff,
+			(None, None) => true,
+			_ => false,
+		}
+	}
+	
+	// Returns the index in read_list of the first read that this read is a duplicate of
+	pub fn check_dup(&self, read_list: &[Option<Self>], max_umi_diff: usize) -> Option<usize> {
+		for (ix, rd) in read_list.iter().enumerate() {
 			if let Some(r) = rd {
 				if self.maps_eq(&r.maps) && match (r.mate_pos, self.mate_pos) {
 					(Some(x), Some(y)) => x == y,
 					_ => true,
-				} { return true }
+				} && self.umi_eq(r, max_umi_diff) { return Some(ix) }
 			}
 		}
-		false
+		None
+	}
+	
+	// Merge a duplicate read into this read, forming a quality aware consensus of the two sequences.
+	// Where the bases agree the qualities are added, otherwise the base with the higher quality is kept with
+	// the difference in qualities.  Returns false if the reads are not compatible (different lengths or alignments)
+	pub fn merge_dup(&mut self, other: &Self) -> bool {
+		if self.seq_qual.len() != other.seq_qual.len() || cigar_key(&self.maps[0].cigar) != cigar_key(&other.maps[0].cigar) { return false }
+		for (a, b) in self.seq_qual.iter_mut().zip(other.seq_qual.iter()) {
+			let (qa, qb) = (*a >> 2, *b >> 2);
+			*a = if qb == 0 { *a }
+			else if qa == 0 { *b }
+			else if (*a & 3) == (*b & 3) { (*a & 3) | ((qa + qb).min(62) << 2) }
+			else if qa > qb { (*a & 3) | ((qa - qb) << 2) }
+			else if qb > qa { (*b & 3) | ((qb - qa) << 2) }
+			else { 0 };
+		}
+		true
 	}
 	pub fn check_pair(&mut self, read: &mut Self, conf_hash: &ConfHash) -> (bool, ReadFlag) {
 		let keep_unmatched = conf_hash.get_bool("keep_unmatched");
@@ -240,7 +345,101 @@ impl ReadEnd {
 		}
 		(false, ReadFlag::Passed)
 	}
+	// Get the aligned bases as (reference position, base and quality) pairs for the primary mapping,
+	// skipping bases removed by trimming (ltrim and rtrim refer to the original direction of the read)
+	pub fn aligned_bases(&self, ltrim: usize, rtrim: usize) -> Vec<(usize, u8)> {
+		let map = &self.maps[0];
+		let (t1, t2) = if map.is_reverse() { (rtrim, ltrim) } else { (ltrim, rtrim) };
+		let total_len = map.cigar.qlen1() as usize;
+		if t1 + t2 >= total_len { return Vec::new() }
+		let right_cut = total_len - t2;
+		self.aligned_iter().filter(|a| a.read_pos >= t1 && a.read_pos < right_cut).map(|a| (a.ref_pos, (a.qual << 2) | a.base)).collect()
+	}
 	pub fn read_one(&self) -> bool { (self.maps[0].flags & (MFLAG_READ1 | MFLAG_READ2)) == MFLAG_READ1 }
 	pub fn read_two(&self) -> bool { (self.maps[0].flags & (MFLAG_READ1 | MFLAG_READ2)) == MFLAG_READ2 }
 	pub fn is_primary(&self) -> bool { (self.maps[0].flags & MFLAG_NON_PRIMARY) == 0 }
 }
+
+// Left and right trimming for the first and second reads of a pair
+pub struct ReadTrims {
+	read1: (usize, usize),
+	read2: (usize, usize),
+}
+
+impl ReadTrims {
+	pub fn new(conf_hash: &ConfHash) -> Self {
+		Self {
+			read1: (conf_hash.get_int("left_trim_read_1"), conf_hash.get_int("right_trim_read_1")),
+			read2: (conf_hash.get_int("left_trim_read_2"), conf_hash.get_int("right_trim_read_2")),
+		}
+	}
+	pub fn get(&self, read: &ReadEnd) -> (usize, usize) {
+		if read.read_one() { self.read1 } else if read.read_two() { self.read2 } else { (0, 0) }
+	}
+}
+
+// Aligned bases from a read or read pair, sorted on position
+pub struct Fragment {
+	pub start: usize,
+	pub end: usize,
+	pub read_idx: usize, // Index of first read of fragment
+	pub bases: Vec<(usize, u8, BSStrand)>,
+}
+
+impl Fragment {
+	pub fn get_obs(&self, pos: usize, min_qual: u8) -> Option<usize> {
+		match self.bases.binary_search_by_key(&pos, |(x, _, _)| *x) {
+			Ok(ix) => {
+				let (_, x, bs) = self.bases[ix];
+				if (x >> 2) >= min_qual { Some(base_class((x & 3) as usize, bs)) } else { None }
+			},
+			Err(_) => None,
+		}
+	}
+	// Methylation state (0 = methylated, 1 = non-methylated) of the CpG at pos.  If taps is set then
+	// methylated rather than non-methylated Cs are converted
+	pub fn get_meth(&self, pos: usize, min_qual: u8, taps: bool) -> Option<usize> {
+		let m = match self.get_obs(pos, min_qual) {
+			Some(5) => Some(0),
+			Some(7) => Some(1),
+			_ => match self.get_obs(pos + 1, min_qual) {
+				Some(6) => Some(0),
+				Some(4) => Some(1),
+				_ => None,
+			},
+		};
+		if taps { m.map(|x| 1 - x) } else { m }
+	}
+}
+
+// Collect reads into fragments, using the mate indices to join pairs
+pub fn get_fragments(reads: &[Option<ReadEnd>], trims: &ReadTrims) -> Vec<Fragment> {
+	let mut frags: Vec<Fragment> = Vec::new();
+	let mut frag_idx = vec![None; reads.len()];
+	for (ix, read) in reads.iter().enumerate() {
+		if let Some(rd) = read {
+			let bs = rd.maps[0].bs_strand();
+			let (ltrim, rtrim) = trims.get(rd);
+			let bases = rd.aligned_bases(ltrim, rtrim);
+			if bases.is_empty() { continue }
+			let (start, end) = (bases[0].0, bases[bases.len() - 1].0);
+			let bases = bases.into_iter().map(|(x, c)| (x, c, bs));
+			match rd.mate_idx.and_then(|m| frag_idx[m]) {
+				Some(f) => {
+					let frag: &mut Fragment = &mut frags[f];
+					frag.bases.extend(bases);
+					frag.bases.sort_unstable_by_key(|(x, _, _)| *x);
+					frag.bases.dedup_by_key(|(x, _, _)| *x);
+					frag.start = frag.start.min(start);
+					frag.end = frag.end.max(end);
+					frag_idx[ix] = Some(f);
+				},
+				None => {
+					frag_idx[ix] = Some(frags.len());
+					frags.push(Fragment{start, end, read_idx: ix, bases: bases.collect()});
+				}
+			}
+		}
+	}
+	frags
+}
@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+
+use crate::htslib::BSStrand;
+use crate::stats::RrbsStats;
+use super::records::ReadEnd;
+
+// Number of positions filled in by end repair at the 3' end of MspI fragments (the CG overhang)
+const RRBS_END_REPAIR: u32 = 2;
+// Reads per fragment end above this are accumulated in the last bin of the coverage histogram
+const RRBS_MAX_COVERAGE: usize = 1000;
+// Reference encoding of CCGG in the pileup reference (A=1, C=2, G=3, T=4)
+const MSPI_SITE: [u8; 4] = [2, 2, 3, 3];
+
+// In RRBS libraries, fragments start and end at MspI sites (C^CGG).  Reads on the forward strand therefore
+// have their 5' end at x where the site starts at x - 1 while reads on the reverse strand have their 5' end
+// at x where the site starts at x - 2.  The 3' ends of the fragments are filled in with unmethylated Cs during
+// end repair, so the CpG methylation at these positions is an artefact: for reads from the C2T strand this
+// affects the last 2 positions of reads finishing at the right end of a fragment, and for reads from the G2A
+// strand the first 2 positions of reads starting at the left end of a fragment.  These are trimmed from the reads.
+pub fn handle_rrbs_reads(reads: &mut [Option<ReadEnd>], ref_seq: &[u8], ref_start: usize, stats: &mut RrbsStats) {
+	let is_site = |x: usize| x.checked_sub(ref_start).and_then(|ix| ref_seq.get(ix..ix + 4)).map(|s| s == MSPI_SITE).unwrap_or(false);
+	// Reads per fragment end, with fragment ends identified by the site position and the direction of the fragment
+	let mut ends: HashMap<(usize, bool), usize> = HashMap::new();
+	for read in reads.iter_mut().flatten() {
+		let primary = read.is_primary();
+		let map = &mut read.maps[0];
+		let (start, end) = (map.start() as usize, map.end() as usize);
+		if primary {
+			let key = if map.is_reverse() { end.checked_sub(2).filter(|x| is_site(*x)).map(|x| (x, false)) }
+			else { start.checked_sub(1).filter(|x| is_site(*x)).map(|x| (x, true)) };
+			if let Some(k) = key {
+				stats.mspi_reads += 1;
+				*ends.entry(k).or_insert(0) += 1;
+			} else { stats.non_mspi_reads += 1 }
+		}
+		if map.rlen() <= RRBS_END_REPAIR << 1 { continue }
+		match map.bs_strand() {
+			BSStrand::StrandC2T if end >= 2 && is_site(end - 2) => map.cigar.trim_end(RRBS_END_REPAIR),
+			BSStrand::StrandG2A if start >= 1 && is_site(start - 1) => {
+				map.cigar.trim_start(RRBS_END_REPAIR);
+				map.map_pos.pos += RRBS_END_REPAIR;
+			},
+			_ => continue,
+		}
+		if primary { stats.end_repair_trimmed_reads += 1 }
+	}
+	stats.mspi_fragment_ends += ends.len();
+	let hist = &mut stats.mspi_fragment_end_coverage;
+	for ct in ends.values() {
+		let ix = (*ct).min(RRBS_MAX_COVERAGE);
+		if ix >= hist.len() { hist.resize(ix + 1, 0) }
+		hist[ix] += 1;
+	}
+}
@@ -7,8 +7,9 @@ use crate::config::*;
 use crate::defs::contigs;
 use crate::htslib::{VcfHeader, SamFile};
 
-const FIXED_HEADERS: [&str; 20] = [
+const FIXED_HEADERS: [&str; 22] = [
 	"##INFO=<ID=CX,Number=1,Type=String,Description=\"5 base sequence context (from position -2 to +2 on the positive strand) determined from the reference\">",
+	"##INFO=<ID=INDEL,Number=0,Type=Flag,Description=\"Indicates that the variant is an indel\">",
 	"##FILTER=<ID=fail,Description=\"No sample passed filters\">",
 	"##FILTER=<ID=q20,Description=\"Genotype Quality below 20\">",
 	"##FILTER=<ID=qd2,Description=\"Quality By Depth below 2\">",
@@ -22,6 +23,7 @@ const FIXED_HEADERS: [&str; 20] = [
 	"##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Read Depth (non converted reads only)\">",
 	"##FORMAT=<ID=MQ,Number=1,Type=Integer,Description=\"RMS Mapping Quality\">",
 	"##FORMAT=<ID=QD,Number=1,Type=Integer,Description=\"Quality By Depth (Variant quality / read depth (non-converted reads only))\">",
+	"##FORMAT=<ID=AD,Number=R,Type=Integer,Description=\"Read depth for each allele (indels only)\">",
 	"##FORMAT=<ID=MC8,Number=8,Type=Integer,Description=\"Base counts: non-informative for methylation (ACGT) followed by informative for methylation (ACGT)\">",
 	"##FORMAT=<ID=AMQ,Number=.,Type=Integer,Description=\"Average base quailty for where MC8 base count non-zero\">",
 	"##FORMAT=<ID=CS,Number=1,Type=String,Description=\"Strand of Cytosine relative to reference sequence (+/-/+-/NA)\">",
@@ -86,7 +88,8 @@ fn add_seq_info(hd: &mut VcfHeader, ctgs: &[contigs::CtgInfo], sam_file: &SamFil
 
 pub fn write_vcf_header(bs_cfg: &mut BsCallConfig, bs_files: &mut BsCallFiles, source: &str) -> io::Result<()> {
 	let mut hd = &mut bs_files.vcf_output.as_mut().unwrap().hdr;
-	let sam_file = &bs_files.sam_input.as_ref().unwrap();
+	let sam_files = bs_files.sam_input.as_ref().unwrap();
+	let sam_file = &sam_files[0];
 	let chash = &bs_cfg.conf_hash;
 	let mut sbuf = format!("##fileformat={}", hd.get_version());
 	hd.append(&sbuf)?;
@@ -104,14 +107,39 @@ pub fn write_vcf_header(bs_cfg: &mut BsCallConfig, bs_files: &mut BsCallFiles, s
 			}
 		}
 	}
-	let sam_sample = add_sample_info(&mut hd, sam_file.text(), benchmark)?;
+	// Conversion chemistry, so that downstream tools can interpret the methylation information
+	sbuf = format!("##bs_call_chemistry={}", chash.get_str("chemistry").unwrap_or("bisulfite"));
+	hd.append(&sbuf)?;
+	if chash.get_bool("auto_trim") {
+		sbuf = format!("##bs_call_auto_trim=<LeftTrimRead1={},RightTrimRead1={},LeftTrimRead2={},RightTrimRead2={}>", 
+			chash.get_int("left_trim_read_1"), chash.get_int("right_trim_read_1"), chash.get_int("left_trim_read_2"), chash.get_int("right_trim_read_2"));
+		hd.append(&sbuf)?;
+	}
+	let mut sam_samples = Vec::with_capacity(sam_files.len());
+	for f in sam_files.iter() { sam_samples.push(add_sample_info(&mut hd, f.text(), benchmark)?) }
 	let contigs = &mut bs_cfg.contigs;
 	add_seq_info(&mut hd, contigs, sam_file)?;	
 	for line in FIXED_HEADERS.iter() { hd.append(line)?; }
-	let sample = if let Some(s) = chash.get_str("sample") { s }
-	else if let Some(s) = sam_sample { s }
-	else { "SAMPLE" };
-	hd.add_sample(sample)?;
+	// Sample names are taken from the command line (comma separated if multiple input files), 
+	// from the BC tag of the read groups in each input file or a default name
+	let samples: Vec<String> = if let Some(s) = chash.get_str("sample") {
+		let v: Vec<_> = s.split(',').map(|x| x.to_owned()).collect();
+		if v.len() != sam_files.len() { 
+			return Err(new_err(format!("Number of sample names ({}) does not match number of input files ({})", v.len(), sam_files.len())))
+		}
+		v
+	} else {
+		sam_samples.iter().enumerate().map(|(ix, s)| match s {
+			Some(x) => (*x).to_owned(),
+			None => if sam_files.len() > 1 { format!("SAMPLE{}", ix + 1) } else { "SAMPLE".to_owned() },
+		}).collect()
+	};
+	let mut sample_set = HashSet::new();
+	for sample in samples.iter() {
+		if !sample_set.insert(sample) { return Err(new_err(format!("Duplicate sample name {}", sample))) }
+		hd.add_sample(sample)?;
+	}
+	bs_cfg.samples = samples;
 	hd.sync()?;
 	// Get VCF/BCF header IDs for contigs
 	contigs::set_contig_vcf_ids(&hd, contigs, sam_file); 
@@ -6,6 +6,8 @@ use crate::htslib::*;
 use libc::{c_char, c_int};
 use crate::stats::{StatJob, collect_vcf_stats};
 use crate::process::call_genotypes::{CallBlock, GenotypeCall, CallEntry};
+use crate::process::call_genotypes::indel::IndelCall;
+use crate::process::GT_BASES;
 use crate::dbsnp::DBSnpContig;
 use crate::rusage::*;
 
@@ -17,7 +19,7 @@ pub enum WriteVcfJob {
 
 use std::f64::consts::LN_10;
 
-pub const FLT_NAMES: [&str; 16] = ["PASS", "mac1", "fail", "GT", "FT", "DP", "MQ", "GQ", "QD", "GL", "MC8", "AMQ", "CS", "CG", "CX", "FS" ];	
+pub const FLT_NAMES: [&str; 18] = ["PASS", "mac1", "fail", "GT", "FT", "DP", "MQ", "GQ", "QD", "GL", "MC8", "AMQ", "CS", "CG", "CX", "FS", "INDEL", "AD" ];	
 const FLT_ID_PASS: usize = 0;
 const FLT_ID_MAC1: usize = 1;
 const FLT_ID_FAIL: usize = 2;
@@ -34,6 +36,8 @@ const FLT_ID_CS: usize = 12;
 const FLT_ID_CG: usize = 13;
 const FLT_ID_CX: usize = 14;
 const FLT_ID_FS: usize = 15;
+const FLT_ID_INDEL: usize = 16;
+const FLT_ID_AD: usize = 17;
 
 fn get_filter_ids(hdr: &VcfHeader) -> Vec<u8> {
 	let mut v = Vec::with_capacity(FLT_NAMES.len());
@@ -121,12 +125,16 @@ const ALL_IDX: [[[usize; 2]; 5]; 10] = [
 
 const GT_HET: [bool; 10] = [ false, true, true, true, false, true, true, false, true, false ];
 
+const BCF_FLOAT_MISSING: u32 = 0x7F80_0001;
+const BCF_FLOAT_VECTOR_END: u32 = 0x7F80_0002;
+
 const FLT_NAME: [&str; 4] = [ "q20", "qd2", "fs60", "mq40" ];
 
 pub const CALL_STATS_SKIP: u8 = 1;
 pub const CALL_STATS_RS_FOUND: u8 = 2;
 pub const CALL_STATS_SNP: u8 = 4;
 pub const CALL_STATS_MULTI: u8 = 8;
+pub const CALL_STATS_INDEL: u8 = 16;
 
 pub const CPG_STATUS_REF_CPG: u8 = 8;
 
@@ -198,17 +206,23 @@ fn get_filter_string(filter: usize) -> String {
 	} else { "PASS".to_string() }
 }
 
+// Genotype log likelihood for genotype with bases a and b (A=1, C=2, G=3, T=4)
+fn get_gt_ll(call: &GenotypeCall, a: usize, b: usize) -> f32 {
+	let j = if a < b { a * (9 - a) / 2 + b - 5 } else { b * (9 - b) / 2 + a - 5};
+	if call.gt_ll[j] < -99.999 { -99.999 } else { call.gt_ll[j] as f32}
+}
+
 fn get_gt_like(call: &GenotypeCall) -> Vec<f32> {
 	let mut v = Vec::with_capacity(6);
 	let rfix = call.ref_base as usize;
 	let aix = &ALL_IDX[call.max_gt as usize][rfix];
-	let get_z = |a, b| { 
-		let j = if a < b { a * (9 - a) / 2 + b - 5 } else { b * (9 - b) / 2 + a - 5};
-		if call.gt_ll[j] < -99.999 { -99.999 } else { call.gt_ll[j] as f32}
-	};
+	let get_z = |a, b| get_gt_ll(call, a, b);
 	 
 	v.push(if rfix != 0 { get_z(rfix, rfix) } else { -99.999 }); 
-	if aix[0] > 0 {
+	// For haploid calls only the homozygous genotypes are output
+	if call.haploid {
+		for a in aix.iter().copied().filter(|a| *a > 0) { v.push(get_z(a, a)) }
+	} else if aix[0] > 0 {
         v.push(if rfix != 0 { get_z(rfix, aix[0]) } else { -99.999 }); 
 		v.push(get_z(aix[0], aix[0]));
 		if aix[1] > 0 {
@@ -220,6 +234,19 @@ fn get_gt_like(call: &GenotypeCall) -> Vec<f32> {
 	v
 }
 
+// Genotype likelihoods for all genotypes formed from the site alleles (in VCF order)
+fn get_multi_gt_like(call: &GenotypeCall, alleles: &[u8]) -> Vec<f32> {
+	let mut v = Vec::with_capacity(alleles.len() * (alleles.len() + 1) / 2);
+	for (k, b) in alleles.iter().map(|x| *x as usize).enumerate() {
+		let hom = &[alleles[k]];
+		let a_alleles = if call.haploid { hom } else { &alleles[..=k] };
+		for a in a_alleles.iter().map(|x| *x as usize) {
+			v.push(if a != 0 && b != 0 { get_gt_ll(call, a, b) } else { -99.999 })
+		}
+	}
+	v
+}
+
 
 // In this part we re-implement some of the htslib functions for creating VCF/BCF entries
 // so that we can write to a Rust Vec<u8> rather than a kstring, and then we copy the Vec
@@ -298,6 +325,51 @@ fn enc_vint_(v: &mut Vec<u8>, s: &[c_int], min: c_int, max: c_int) {
 	}
 }
 
+// Multi-sample versions of the encoding functions.  Each sample has a vector of values (None if the sample 
+// is missing), and vectors shorter than the longest are padded with vector_end values.
+fn enc_sample_vint(v: &mut Vec<u8>, s: &[Option<Vec<c_int>>]) {
+	let n = s.iter().flatten().map(|x| x.len()).max().unwrap_or(0).max(1);
+	let (min, max) = s.iter().flatten().flatten().copied().fold((0, 0), |(mn, mx), x| (cmp::min(mn, x), cmp::max(mx, x)));
+	let (bcf_type, missing, vector_end, sz) = if max <= BCF_MAX_BT_INT8 && min >= BCF_MIN_BT_INT8 {
+		(BCF_BT_INT8, bcf_int8_missing, bcf_int8_vector_end, 1)
+	} else if max <= BCF_MAX_BT_INT16 && min >= BCF_MIN_BT_INT16 {
+		(BCF_BT_INT16, bcf_int16_missing, bcf_int16_vector_end, 2)
+	} else {
+		(BCF_BT_INT32, bcf_int32_missing, bcf_int32_vector_end, 4)
+	};
+	enc_size(v, n as c_int, bcf_type);
+	for x in s.iter() {
+		let (vals, pad) = match x {
+			Some(a) => (a.as_slice(), n - a.len()),
+			None => (&[missing][..], n - 1),
+		};
+		vals.iter().chain([vector_end].iter().cycle().take(pad)).for_each(|x| v.extend_from_slice(&x.to_le_bytes()[..sz]));
+	}
+}
+
+fn enc_sample_vfloat(v: &mut Vec<u8>, s: &[Option<Vec<f32>>]) {
+	let n = s.iter().flatten().map(|x| x.len()).max().unwrap_or(0).max(1);
+	enc_size(v, n as c_int, BCF_BT_FLOAT);
+	for x in s.iter() {
+		let (vals, pad) = match x {
+			Some(a) => (a.as_slice(), n - a.len()),
+			None => (&[f32::from_bits(BCF_FLOAT_MISSING)][..], n - 1),
+		};
+		vals.iter().for_each(|x| v.extend_from_slice(&x.to_le_bytes()));
+		for _ in 0..pad { v.extend_from_slice(&BCF_FLOAT_VECTOR_END.to_le_bytes()) }
+	}
+}
+
+fn enc_sample_vchar(v: &mut Vec<u8>, s: &[Option<&[u8]>]) {
+	let n = s.iter().map(|x| x.map(|a| a.len()).unwrap_or(1)).max().unwrap_or(1);
+	enc_size(v, n as c_int, BCF_BT_CHAR);
+	for x in s.iter() {
+		let a = x.unwrap_or(b".");
+		v.extend_from_slice(a);
+		for _ in a.len()..n { v.push(0) }
+	}
+}
+
 fn write_fixed_columns(call: &GenotypeCall, filter_ids: &[u8], v: &mut Vec<u8>, call_stats: &mut CallStats, ref_context: &[u8], rs: &Option<String>, bcf_rec: &mut BcfRec) -> io::Result<()> {
 	v.clear();
 	// Alternate alleles
@@ -344,7 +416,8 @@ fn write_format_columns(call: &GenotypeCall, filter_ids: &[u8], called_context:
 	v.clear();
 	// GT
 	enc_u8(v, filter_ids[FLT_ID_GT]);
-	enc_vint(v, &GT_INT[call.max_gt as usize][call.ref_base as usize]);
+	let gt = &GT_INT[call.max_gt as usize][call.ref_base as usize];
+	enc_vint(v, if call.haploid { &gt[..1] } else { gt });
 	// FT
 	let flt_str = get_filter_string(call_stats.filter as usize);
 	enc_u8(v, filter_ids[FLT_ID_FT]);
@@ -399,6 +472,223 @@ fn write_format_columns(call: &GenotypeCall, filter_ids: &[u8], called_context:
 	Ok(())
 }
 
+// For multi-sample output the alternate alleles are the union of the non-reference alleles of the sample calls.
+// The site filter is PASS if any sample passes, otherwise mac1 if any sample has this filter, otherwise fail.
+// Returns the list of site alleles (reference first).
+#[allow(clippy::too_many_arguments)]
+fn write_multi_fixed_columns(calls: &[Option<GenotypeCall>], filter_ids: &[u8], filter_id: usize, v: &mut Vec<u8>, sample_stats: &[Option<CallStats>], ref_context: &[u8], rs: &Option<String>, bcf_rec: &mut BcfRec) -> io::Result<Vec<u8>> {
+	v.clear();
+	// Alternate alleles
+	let ref_base = calls.iter().flatten().next().expect("No sample calls").ref_base;
+	let mut alt_alleles = Vec::with_capacity(4);
+	for call in calls.iter().flatten() {
+		for b in GT_BASES[call.max_gt as usize].iter().copied() {
+			if b != ref_base && !alt_alleles.contains(&b) { alt_alleles.push(b) }
+		}
+	}
+	alt_alleles.sort_unstable();
+	// ID
+	if let Some(s) = rs.as_ref() {
+		enc_size(v, s.len() as c_int, BCF_BT_CHAR);
+		v.extend_from_slice(s.as_bytes());
+	} else { v.push(BCF_BT_CHAR) };	
+	// REF allele
+	v.push(0x10 | BCF_BT_CHAR);
+	v.push(ref_context[2]);
+	// ALT alleles
+	for allele in alt_alleles.iter() {
+		v.push(0x10 | BCF_BT_CHAR);
+		v.push(PBASE.as_bytes()[*allele as usize]);
+	}
+	// FILTER
+	enc_u8(v, filter_ids[filter_id]);	
+	// INFO
+	enc_u8(v, filter_ids[FLT_ID_CX]);
+	enc_vchar(v, ref_context);	
+
+	let kstr = bcf_rec.shared();
+	kstr.putsn(v.as_slice().as_ptr() as *const c_char, v.len());
+
+	bcf_rec.set_n_sample(calls.len() as u32);
+	bcf_rec.set_rlen(1);	
+	bcf_rec.set_n_allele(1 + alt_alleles.len() as u16);
+	bcf_rec.set_qual(sample_stats.iter().flatten().map(|cs| cs.phred).max().unwrap_or(0) as f32);	
+	bcf_rec.set_n_info(1);
+	let mut alleles = Vec::with_capacity(1 + alt_alleles.len());
+	alleles.push(ref_base);
+	alleles.extend_from_slice(&alt_alleles);
+	Ok(alleles)
+}
+
+fn write_multi_format_columns(calls: &[Option<GenotypeCall>], filter_ids: &[u8], called_contexts: &[Option<Vec<u8>>], alleles: &[u8], v: &mut Vec<u8>, sample_stats: &[Option<CallStats>], bcf_rec: &mut BcfRec) -> io::Result<()> {
+	let mut n_fmt = 11;
+	v.clear();
+	let allele_idx = |b: u8| alleles.iter().position(|x| *x == b).expect("Allele not found") as c_int;
+	let sample_int = |f: &dyn Fn(&GenotypeCall, &CallStats) -> c_int| -> Vec<Option<Vec<c_int>>> {
+		calls.iter().zip(sample_stats.iter()).map(|(c, cs)| match (c, cs) {
+			(Some(call), Some(cs)) => Some(vec!(f(call, cs))),
+			_ => None,
+		}).collect()
+	};
+	// GT
+	let gt: Vec<_> = calls.iter().map(|c| Some(if let Some(call) = c {
+		let (i, j) = { 
+			let [a, b] = GT_BASES[call.max_gt as usize]; 
+			let (i, j) = (allele_idx(a), allele_idx(b));
+			if i <= j { (i, j) } else { (j, i) }
+		};
+		if call.haploid { vec!((i + 1) << 1) } else { vec!((i + 1) << 1, (j + 1) << 1) }
+	} else { vec!(0, 0) })).collect();
+	enc_u8(v, filter_ids[FLT_ID_GT]);
+	enc_sample_vint(v, &gt);
+	// FT
+	let flt_str: Vec<_> = sample_stats.iter().map(|cs| cs.as_ref().map(|x| get_filter_string(x.filter as usize))).collect();
+	let flt: Vec<_> = flt_str.iter().map(|x| x.as_ref().map(|s| s.as_bytes())).collect();
+	enc_u8(v, filter_ids[FLT_ID_FT]);
+	enc_sample_vchar(v, &flt);
+	// DP
+	enc_u8(v, filter_ids[FLT_ID_DP]);
+	enc_sample_vint(v, &sample_int(&|_, cs| cs.dp1));
+	// MQ
+	enc_u8(v, filter_ids[FLT_ID_MQ]);
+	enc_sample_vint(v, &sample_int(&|call, _| call.mq as c_int));
+	// GQ
+	enc_u8(v, filter_ids[FLT_ID_GQ]);
+	enc_sample_vint(v, &sample_int(&|_, cs| cs.phred));
+	// QD
+	enc_u8(v, filter_ids[FLT_ID_QD]);
+	enc_sample_vint(v, &sample_int(&|_, cs| cs.qd));
+	// GL
+	let gl: Vec<_> = calls.iter().map(|c| c.as_ref().map(|call| get_multi_gt_like(call, alleles))).collect();
+	enc_u8(v, filter_ids[FLT_ID_GL]);
+	enc_sample_vfloat(v, &gl);
+	// MC8
+	let mc8: Vec<_> = calls.iter().map(|c| c.as_ref().map(|call| call.counts.to_vec())).collect();
+	enc_u8(v, filter_ids[FLT_ID_MC8]);
+	enc_sample_vint(v, &mc8);
+	// AMQ
+	let amq: Vec<_> = calls.iter().map(|c| c.as_ref().and_then(|call| {
+		let q: Vec<_> = call.qual.iter().copied().filter(|q| *q > 0).collect();
+		if q.is_empty() { None } else { Some(q) }
+	})).collect();
+	if amq.iter().any(|x| x.is_some()) {
+		enc_u8(v, filter_ids[FLT_ID_AMQ]);
+		enc_sample_vint(v, &amq);
+		n_fmt += 1;
+	}
+	// CS
+	let cs: Vec<_> = calls.iter().map(|c| c.as_ref().map(|call| CS_STR[call.max_gt as usize].as_bytes())).collect();
+	enc_u8(v, filter_ids[FLT_ID_CS]);
+	enc_sample_vchar(v, &cs);
+	// CG
+	let cg: Vec<_> = sample_stats.iter().map(|cs| cs.as_ref().map(|x| [CPG_DISPLAY[(x.cpg_status & 7) as usize]])).collect();
+	let cg: Vec<_> = cg.iter().map(|x| x.as_ref().map(|a| &a[..])).collect();
+	enc_u8(v, filter_ids[FLT_ID_CG]);
+	enc_sample_vchar(v, &cg);
+	// CX
+	let cx: Vec<_> = called_contexts.iter().map(|x| x.as_deref()).collect();
+	enc_u8(v, filter_ids[FLT_ID_CX]);
+	enc_sample_vchar(v, &cx);
+	// FS
+	if calls.iter().flatten().any(|call| GT_HET[call.max_gt as usize]) {
+		let fs: Vec<_> = calls.iter().zip(sample_stats.iter()).map(|(c, cs)| match (c, cs) {
+			(Some(call), Some(cs)) if GT_HET[call.max_gt as usize] => Some(vec!(cs.fs)),
+			_ => None,
+		}).collect();
+		enc_u8(v, filter_ids[FLT_ID_FS]);
+		enc_sample_vint(v, &fs);
+		n_fmt += 1;		
+	}
+	let kstr = bcf_rec.indiv();
+	kstr.putsn(v.as_slice().as_ptr() as *const c_char, v.len());
+	bcf_rec.set_n_fmt(n_fmt);
+	Ok(())
+}
+
+// Indels are output as biallelic records with the INDEL flag set.  Only the GT, FT, DP, MQ, GQ, QD, GL, AD and FS 
+// FORMAT fields are output, and the site filter is PASS if any sample passes, otherwise fail.  The dbSNP index only
+// holds SNVs, so no ID is set for indels
+fn write_indel_columns(indel: &IndelCall, filter_ids: &[u8], v: &mut Vec<u8>, sample_stats: &[Option<CallStats>], bcf_rec: &mut BcfRec) -> io::Result<()> {
+	v.clear();
+	// ID
+	v.push(BCF_BT_CHAR);
+	// REF and ALT alleles
+	enc_vchar(v, &indel.ref_allele);
+	enc_vchar(v, &indel.alt_allele);
+	// FILTER
+	let filter_id = if sample_stats.iter().flatten().any(|cs| cs.filter == 0) { FLT_ID_PASS } else { FLT_ID_FAIL };
+	enc_u8(v, filter_ids[filter_id]);	
+	// INFO
+	enc_u8(v, filter_ids[FLT_ID_INDEL]);
+	v.push(BCF_BT_NULL);
+	let kstr = bcf_rec.shared();
+	kstr.putsn(v.as_slice().as_ptr() as *const c_char, v.len());
+	bcf_rec.set_n_sample(indel.samples.len() as u32);
+	bcf_rec.set_rlen(indel.ref_allele.len());	
+	bcf_rec.set_n_allele(2);
+	bcf_rec.set_qual(sample_stats.iter().flatten().map(|cs| cs.phred).max().unwrap_or(0) as f32);	
+	bcf_rec.set_n_info(1);
+
+	// FORMAT fields
+	let mut n_fmt = 8;
+	v.clear();
+	let samples = &indel.samples;
+	let sample_int = |f: &dyn Fn(&CallStats) -> c_int| -> Vec<Option<Vec<c_int>>> {
+		sample_stats.iter().map(|cs| cs.as_ref().map(|x| vec!(f(x)))).collect()
+	};
+	// GT
+	let gt: Vec<_> = samples.iter().map(|g| Some(match g.as_ref().map(|x| (x.max_gt, x.haploid)) {
+		Some((0, true)) => vec!(2),
+		Some((_, true)) => vec!(4),
+		Some((0, _)) => vec!(2, 2),
+		Some((1, _)) => vec!(2, 4),
+		Some(_) => vec!(4, 4),
+		None => vec!(0, 0),
+	})).collect();
+	enc_u8(v, filter_ids[FLT_ID_GT]);
+	enc_sample_vint(v, &gt);
+	// FT
+	let flt_str: Vec<_> = sample_stats.iter().map(|cs| cs.as_ref().map(|x| get_filter_string(x.filter as usize))).collect();
+	let flt: Vec<_> = flt_str.iter().map(|x| x.as_ref().map(|s| s.as_bytes())).collect();
+	enc_u8(v, filter_ids[FLT_ID_FT]);
+	enc_sample_vchar(v, &flt);
+	// DP
+	enc_u8(v, filter_ids[FLT_ID_DP]);
+	enc_sample_vint(v, &sample_int(&|cs| cs.dp1));
+	// MQ
+	enc_u8(v, filter_ids[FLT_ID_MQ]);
+	enc_sample_vint(v, &sample_int(&|cs| cs.mq as c_int));
+	// GQ
+	enc_u8(v, filter_ids[FLT_ID_GQ]);
+	enc_sample_vint(v, &sample_int(&|cs| cs.phred));
+	// QD
+	enc_u8(v, filter_ids[FLT_ID_QD]);
+	enc_sample_vint(v, &sample_int(&|cs| cs.qd));
+	// GL
+	let gl: Vec<_> = samples.iter().map(|g| g.as_ref().map(|x| x.gt_ll.iter().enumerate().filter(|(i, _)| !(x.haploid && *i == 1))
+		.map(|(_, z)| if *z < -99.999 { -99.999 } else { *z as f32 }).collect())).collect();
+	enc_u8(v, filter_ids[FLT_ID_GL]);
+	enc_sample_vfloat(v, &gl);
+	// AD
+	let ad: Vec<_> = samples.iter().map(|g| g.as_ref().map(|x| vec!(x.ref_depth(), x.alt_depth()))).collect();
+	enc_u8(v, filter_ids[FLT_ID_AD]);
+	enc_sample_vint(v, &ad);
+	// FS
+	if samples.iter().flatten().any(|g| g.max_gt == 1) {
+		let fs: Vec<_> = samples.iter().zip(sample_stats.iter()).map(|(g, cs)| match (g, cs) {
+			(Some(g), Some(cs)) if g.max_gt == 1 => Some(vec!(cs.fs)),
+			_ => None,
+		}).collect();
+		enc_u8(v, filter_ids[FLT_ID_FS]);
+		enc_sample_vint(v, &fs);
+		n_fmt += 1;		
+	}
+	let kstr = bcf_rec.indiv();
+	kstr.putsn(v.as_slice().as_ptr() as *const c_char, v.len());
+	bcf_rec.set_n_fmt(n_fmt);
+	Ok(())
+}
+
 struct WriteState {
 	sam_tid: usize,
 	vcf_rid: usize,
@@ -407,8 +697,10 @@ struct WriteState {
 	call_buf: VecDeque<CallEntry>,
 	bcf_rec: BcfRec,
 	tvec: Vec<u8>,
-	call_stats: Vec<CallStats>,
+	call_stats: Vec<Vec<CallStats>>, // Per sample
+	indels: VecDeque<IndelCall>,
 	all_positions: bool,
+	taps: bool, // Methylated Cs are converted
 }
 
 impl WriteState {
@@ -418,18 +710,22 @@ impl WriteState {
 		for c in call_block.prec_ref_bases.iter() { v.push_back(CallEntry::Starting(*c)) }
 		let bcf_rec = BcfRec::new().expect("Couldn't allocate Bcf Record");
 		let tvec = Vec::with_capacity(256);
-		let call_stats = Vec::with_capacity(4096);
+		let call_stats = bs_cfg.samples.iter().map(|_| Vec::with_capacity(4096)).collect();
 		let sam_tid = call_block.sam_tid;
 		let vcf_rid = bs_cfg.ctg_vcf_id(sam_tid).expect("Contig not in VCF list");
 		let all_positions = bs_cfg.conf_hash.get_bool("all_positions");
+		let taps = bs_cfg.chemistry().converts_methylated();
 		let dbsnp_contig = call_block.dbsnp_contig.take();
-		Self { sam_tid, vcf_rid, all_positions, curr_x: call_block.start, call_buf: v, bcf_rec, tvec, call_stats, dbsnp_contig}
+		let indels = call_block.indels.drain(..).collect();
+		Self { sam_tid, vcf_rid, all_positions, taps, curr_x: call_block.start, call_buf: v, bcf_rec, tvec, call_stats, indels, dbsnp_contig}
 	}
-	fn finish_block(mut self, vcf_output: &mut VcfFile, filter_ids: &[u8], vcf_stats_tx: &mpsc::SyncSender<Option<Vec<CallStats>>>) -> io::Result<()> {
+	fn finish_block(mut self, vcf_output: &mut VcfFile, filter_ids: &[u8], vcf_stats_tx: &mpsc::SyncSender<Option<Vec<Vec<CallStats>>>>) -> io::Result<()> {
 		for _ in 0..2 {
 			self.add_entry(CallEntry::Skip(0));
 			self.write_entry(vcf_output, filter_ids)?;
 		}
+		// Output any remaining indels (should not normally happen)
+		self.write_indels(usize::MAX, vcf_output, filter_ids)?;
 		send_vcf_stats_job(self.call_stats, vcf_stats_tx)
 	}
 	fn handle_calls(&mut self, mut call_vec: Vec<CallEntry>, vcf_output: &mut VcfFile, filter_ids: &[u8]) -> io::Result<()> {
@@ -444,9 +740,47 @@ impl WriteState {
 		self.call_buf.push_back(entry);
 		let _ = self.call_buf.pop_front();		
 	}
+	// Write out call at current position followed by any indels anchored at this position
 	fn write_entry(&mut self, vcf_output: &mut VcfFile, filter_ids: &[u8]) -> io::Result<()> {
+		if matches!(&self.call_buf[2], CallEntry::Starting(_)) { return Ok(()) }
+		let x = self.curr_x;
+		self.write_call(vcf_output, filter_ids)?;
+		self.write_indels(x, vcf_output, filter_ids)
+	}
+	fn write_indels(&mut self, x: usize, vcf_output: &mut VcfFile, filter_ids: &[u8]) -> io::Result<()> {
+		while self.indels.front().map(|ind| ind.pos <= x).unwrap_or(false) {
+			let indel = self.indels.pop_front().unwrap();
+			let flags = CALL_STATS_INDEL;
+			let sample_stats: Vec<_> = indel.samples.iter().map(|g| g.as_ref().map(|g| {
+				let dp1 = g.ref_depth() + g.alt_depth();
+				let phred = ln_prob_2_phred(g.gt_ll[g.max_gt as usize]);
+				let fs = (g.fisher_strand * -10.0 + 0.5).round() as c_int;
+				let qd = if dp1 > 0 { phred / dp1 } else { phred };
+				let filter = if phred < 20 { 1 } else { 0 } 
+					| if qd < 2 { 2 } else { 0 }
+					| if fs > 60 { 4 } else { 0 }
+					| if g.mq < 40 { 8 } else { 0 };
+				CallStats{sam_tid: self.sam_tid, phred, fs, dp1, d_inf: 0, qd, cpg_status: 0, flags, gc: 255, 
+					meth_cts: None, filter, gt: g.max_gt, mq: g.mq, ref_base: 0}
+			})).collect();
+			let bcf_rec = &mut self.bcf_rec;
+			bcf_rec.clear();
+			bcf_rec.set_rid(self.vcf_rid); 
+			bcf_rec.set_pos(indel.pos);
+			write_indel_columns(&indel, filter_ids, &mut self.tvec, &sample_stats, bcf_rec)?;
+			bcf_rec.write(&mut vcf_output.file, &mut vcf_output.hdr)?;
+			// Only keep stats for samples with an indel genotype
+			for ((cs, g), v) in sample_stats.into_iter().zip(indel.samples.iter()).zip(self.call_stats.iter_mut()) {
+				if let Some(c) = cs.filter(|_| g.as_ref().map(|x| x.max_gt > 0).unwrap_or(false)) { v.push(c) }
+			}
+		}
+		Ok(())
+	}
+	fn write_call(&mut self, vcf_output: &mut VcfFile, filter_ids: &[u8]) -> io::Result<()> {
+		if matches!(&self.call_buf[2], CallEntry::Call(calls) if calls.len() > 1) { return self.write_multi_entry(vcf_output, filter_ids) }
 		match &self.call_buf[2] {
-			CallEntry::Call(call) => {
+			CallEntry::Call(calls) => {
+				let call = calls[0].as_ref().expect("Missing sample call");
 				let dp1: c_int = call.counts[0..4].iter().sum();
 				let d_inf: c_int = call.counts[4..].iter().sum();
 				// Skip sites with no coverage
@@ -466,12 +800,8 @@ impl WriteState {
 				let mut ref_context = Vec::with_capacity(5);
 				let mut called_gt = Vec::with_capacity(5);
 				for call_entry in self.call_buf.iter() {
-					let (ref_base, gt) = match call_entry {
-						CallEntry::Call(call) => (call.ref_base, call.max_gt + 1),
-						CallEntry::Skip(c) | CallEntry::Starting(c) => (*c, 0),
-					};
-					ref_context.push(PBASE.as_bytes()[ref_base as usize]);
-					called_gt.push(gt as usize);		
+					ref_context.push(PBASE.as_bytes()[call_entry.ref_base() as usize]);
+					called_gt.push(call_entry.called_gt(0));		
 				}
 				let called_context: Vec<u8> = called_gt.iter().copied().map(|g| IUPAC.as_bytes()[g]).collect(); 
 				
@@ -480,7 +810,7 @@ impl WriteState {
 					if (ref_context[2] == b'C' && ref_context[3] == b'G') || (ref_context[1] == b'C' && ref_context[2] == b'G') { CPG_STATUS_REF_CPG } else { 0 }
 				};
 				let flags = if skip { CALL_STATS_SKIP } else { 0 } | if rs_found { CALL_STATS_RS_FOUND } else { 0 };
-				let meth_cts = CPG_ST_CTS[call.max_gt as usize].map(|(x, y)| (call.counts[x] as usize, call.counts[y] as usize));
+				let meth_cts = Self::meth_cts(call, self.taps);
 				let mut call_stats = CallStats{sam_tid: self.sam_tid, phred, fs, dp1, d_inf, qd, cpg_status, flags, gc: call.gc, 
 					meth_cts, filter: 0, gt: call.max_gt, mq: call.mq, ref_base: call.ref_base};
 				if !skip {
@@ -492,7 +822,7 @@ impl WriteState {
 					write_format_columns(call, filter_ids, &called_context, tvec, &call_stats, bcf_rec)?;
 					bcf_rec.write(&mut vcf_output.file, &mut vcf_output.hdr)?;			
 				}
-				self.call_stats.push(call_stats);
+				self.call_stats[0].push(call_stats);
 				self.curr_x += 1;
 			},	
 			CallEntry::Skip(_) => self.curr_x += 1,
@@ -500,9 +830,78 @@ impl WriteState {
 		}
 		Ok(())
 	}
+	// Methylated and non-methylated counts for CpG sites
+	fn meth_cts(call: &GenotypeCall, taps: bool) -> Option<(usize, usize)> {
+		CPG_ST_CTS[call.max_gt as usize].map(|(x, y)| if taps { (call.counts[y] as usize, call.counts[x] as usize) } else { (call.counts[x] as usize, call.counts[y] as usize) })
+	}
+	
+	// Multi-sample version of write_entry().  A separate CallStats is generated for each called sample
+	fn write_multi_entry(&mut self, vcf_output: &mut VcfFile, filter_ids: &[u8]) -> io::Result<()> {
+		let calls = match &self.call_buf[2] {
+			CallEntry::Call(calls) => calls,
+			_ => panic!("Unexpected call entry"),
+		};
+		let ref_context: Vec<u8> = self.call_buf.iter().map(|c| PBASE.as_bytes()[c.ref_base() as usize]).collect();
+		let ref_cpg = if (ref_context[2] == b'C' && ref_context[3] == b'G') || (ref_context[1] == b'C' && ref_context[2] == b'G') { CPG_STATUS_REF_CPG } else { 0 };
+		let (rs, rs_reqd) = match if let Some(ctg) = &self.dbsnp_contig { ctg.lookup_rs(self.curr_x) } else { None } {
+			Some((s, fg)) => (Some(s), fg),
+			None => (None, false),
+		};
+		let rs_found = rs.is_some();
+		// Skip sites where no sample has a call that would be output (unless all sites option is given)
+		let mut skip = !self.all_positions && !rs_reqd;
+		let mut sample_stats = Vec::with_capacity(calls.len());
+		let mut called_contexts = Vec::with_capacity(calls.len());
+		let mut filter_id = FLT_ID_FAIL;
+		for (ix, c) in calls.iter().enumerate() {
+			let call = match c {
+				Some(call) => call,
+				None => {
+					sample_stats.push(None);
+					called_contexts.push(None);
+					continue
+				},
+			};
+			let dp1: c_int = call.counts[0..4].iter().sum();
+			let d_inf: c_int = call.counts[4..].iter().sum();
+			let phred = ln_prob_2_phred(call.gt_ll[call.max_gt as usize]);
+			let fs = (call.fisher_strand * -10.0 + 0.5).round() as c_int;
+			let qd = if dp1 > 0 { phred / dp1 } else { phred };
+			if GT_FLAG[call.max_gt as usize][call.ref_base as usize] { skip = false }
+			let called_gt: Vec<_> = self.call_buf.iter().map(|e| e.called_gt(ix)).collect();
+			called_contexts.push(Some(called_gt.iter().map(|g| IUPAC.as_bytes()[*g]).collect::<Vec<u8>>()));
+			let cpg_status = cmp::max(CPG_STATE[called_gt[1]][called_gt[2]], CPG_STATE[called_gt[2]][called_gt[3]]) | ref_cpg;
+			let meth_cts = Self::meth_cts(call, self.taps);
+			let flags = if rs_found { CALL_STATS_RS_FOUND } else { 0 } | match REF_ALT[call.max_gt as usize][call.ref_base as usize].len() {
+				0 => 0,
+				1 => CALL_STATS_SNP,
+				_ => CALL_STATS_SNP | CALL_STATS_MULTI,
+			};
+			let mut cs = CallStats{sam_tid: self.sam_tid, phred, fs, dp1, d_inf, qd, cpg_status, flags, gc: call.gc, 
+				meth_cts, filter: 0, gt: call.max_gt, mq: call.mq, ref_base: call.ref_base};
+			let (filter, id) = handle_filters(call, &cs);
+			cs.filter = filter as u8;
+			filter_id = cmp::min(filter_id, id);
+			sample_stats.push(Some(cs));
+		}
+		if skip { sample_stats.iter_mut().flatten().for_each(|cs| cs.flags |= CALL_STATS_SKIP) }
+		else {
+			let bcf_rec = &mut self.bcf_rec;
+			let tvec = &mut self.tvec;
+			bcf_rec.clear();
+			bcf_rec.set_rid(self.vcf_rid); 
+			bcf_rec.set_pos(self.curr_x);
+			let alleles = write_multi_fixed_columns(calls, filter_ids, filter_id, tvec, &sample_stats, &ref_context, &rs, bcf_rec)?;
+			write_multi_format_columns(calls, filter_ids, &called_contexts, &alleles, tvec, &sample_stats, bcf_rec)?;
+			bcf_rec.write(&mut vcf_output.file, &mut vcf_output.hdr)?;			
+		}
+		for (cs, v) in sample_stats.into_iter().zip(self.call_stats.iter_mut()) { v.extend(cs) }
+		self.curr_x += 1;
+		Ok(())
+	}
 }
 
-fn send_vcf_stats_job(call_stats: Vec<CallStats>, vcf_stats_tx: &mpsc::SyncSender<Option<Vec<CallStats>>>) -> io::Result<()> {
+fn send_vcf_stats_job(call_stats: Vec<Vec<CallStats>>, vcf_stats_tx: &mpsc::SyncSender<Option<Vec<Vec<CallStats>>>>) -> io::Result<()> {
 	match vcf_stats_tx.send(Some(call_stats)) { 
 		Err(e) => {
 			warn!("Error trying to send new region to call_genotypes thread");
@@ -34,31 +34,60 @@ impl Drop for Stats {
 	}
 }
 
+// Jobs with a sample index refer to a single sample, the others apply to all samples
 pub enum StatJob {
-	AddFSReadLevelCounts(FSReadLevelType, FSCounts),
-	AddFSBaseLevelCounts(FSBaseLevelType, usize),
-	SetNonCpgReadProfile(Vec<[usize; 4]>),
-	AddVcfStats(VcfStats),
+	AddFSReadLevelCounts(usize, FSReadLevelType, FSCounts),
+	AddFSBaseLevelCounts(usize, FSBaseLevelType, usize),
+	SetNonCpgReadProfile(usize, Vec<[usize; 4]>),
+	SetAutoTrim(AutoTrim),
+	SetRrbsStats(usize, RrbsStats),
+	SetConversion(Vec<ConversionEstimate>),
+	SetUmiFamilySizes(usize, Vec<usize>),
+	AddVcfStats(usize, VcfStats),
 	Quit,
 }
 
-fn accumulate_stats(name: String, source: String, rx: mpsc::Receiver<StatJob>) {
+// Stats accumulated for one sample
+struct SampleStats {
+	filter_stats: FSType,
+	non_cpg_read_profile: Option<Vec<[usize; 4]>>,
+	vcf_stats: Option<VcfStats>,
+	rrbs: Option<RrbsStats>,
+	umi_family_sizes: Option<Vec<usize>>,
+}
+
+impl SampleStats {
+	fn new() -> Self { Self{filter_stats: FSType::new(), non_cpg_read_profile: None, vcf_stats: None, rrbs: None, umi_family_sizes: None} }
+}
+
+// A separate JSON stats file is written for each sample
+fn accumulate_stats(names: Vec<String>, source: String, rx: mpsc::Receiver<StatJob>) {
 	info!("stat_thread starting up");
-	let mut filter_stats = FSType::new();
-	let mut non_cpg_read_profile: Option<Vec<[usize; 4]>> = None;
-	let mut vcf_stats: Option<VcfStats> = None;
+	let mut sample_stats: Vec<_> = names.iter().map(|_| SampleStats::new()).collect();
+	let mut auto_trim: Option<AutoTrim> = None;
+	let mut conversion: Option<Vec<ConversionEstimate>> = None;
 	loop {
 		match rx.recv() {
 			Ok(StatJob::Quit) => {
 				debug!("stat_thread recieved quit signal - generating report");
-				let mut stats = Stats::new(name, source, vcf_stats, filter_stats);
-				stats.stats.total_stats().methylation().non_cpg_read_profile = non_cpg_read_profile;
+				for (ix, (name, ss)) in names.into_iter().zip(sample_stats.drain(..)).enumerate() {
+					let mut stats = Stats::new(name, &source, ss.vcf_stats, ss.filter_stats);
+					stats.stats.total_stats().methylation().non_cpg_read_profile = ss.non_cpg_read_profile;
+					stats.stats.set_auto_trim(auto_trim);
+					stats.stats.set_rrbs(ss.rrbs);
+					stats.stats.set_conversion(conversion.as_ref().map(|v| v.iter().filter(|c| c.sample_idx == ix).cloned().collect()));
+					stats.stats.set_umi_family_sizes(ss.umi_family_sizes);
+				}
 				break;
 			},
-			Ok(StatJob::AddFSReadLevelCounts(fs_type, c)) => filter_stats.add_read_level_fs_counts(fs_type, c),
-			Ok(StatJob::AddFSBaseLevelCounts(fs_type, c)) => filter_stats.add_base_level_count(fs_type, c),
-			Ok(StatJob::SetNonCpgReadProfile(v)) => non_cpg_read_profile = Some(v),
-			Ok(StatJob::AddVcfStats(vs)) => vcf_stats = Some(vs),
+			Ok(StatJob::AddFSReadLevelCounts(ix, fs_type, c)) => sample_stats[ix].filter_stats.add_read_level_fs_counts(fs_type, c),
+			Ok(StatJob::AddFSBaseLevelCounts(ix, fs_type, c)) => sample_stats[ix].filter_stats.add_base_level_count(fs_type, c),
+			Ok(StatJob::SetNonCpgReadProfile(ix, v)) => sample_stats[ix].non_cpg_read_profile = Some(v),
+			Ok(StatJob::SetAutoTrim(t)) => auto_trim = Some(t),
+			Ok(StatJob::SetRrbsStats(ix, r)) => sample_stats[ix].rrbs = Some(r),
+			Ok(StatJob::SetConversion(v)) => conversion = Some(v),
+			Ok(StatJob::SetUmiFamilySizes(ix, v)) => sample_stats[ix].umi_family_sizes = Some(v),
+			Ok(StatJob::AddVcfStats(ix, vs)) => sample_stats[ix].vcf_stats = Some(vs),
 			Err(e) => {
 				warn!("stat_thread recieved error: {}", e);
 				break
@@ -86,7 +115,7 @@ fn dummy_job(rx: mpsc::Receiver<StatJob>) {
 	debug!("dummy stat_thread shutting down");
 }
 
-pub fn stat_thread(stats_name: Option<String>, source: String, rx: mpsc::Receiver<StatJob>) {
+pub fn stat_thread(stats_names: Option<Vec<String>>, source: String, rx: mpsc::Receiver<StatJob>) {
 	// Initialize Stats
-	if let Some(name) = stats_name { accumulate_stats(name, source, rx) } else { dummy_job(rx) }
+	if let Some(names) = stats_names { accumulate_stats(names, source, rx) } else { dummy_job(rx) }
 }
@@ -509,11 +509,96 @@ impl TSType {
 	pub fn methylation(&mut self) -> &mut Methylation { &mut self.methylation }
 }
 
+// Read trimming estimated from the M-bias profile
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AutoTrim {
+	pub left_trim_read_1: usize,
+	pub right_trim_read_1: usize,
+	pub left_trim_read_2: usize,
+	pub right_trim_read_2: usize,
+}
+
+// Observations used to estimate the conversion rates: non-converted and converted Cs at all Cs from the
+// unmethylated control, at CpGs from the methylated control and at CHH sites from the rest of the genome
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ConversionCounts {
+	pub unmethylated_control: [usize; 2],
+	pub methylated_control: [usize; 2],
+	#[serde(rename = "CHH")]
+	pub chh: [usize; 2],
+}
+
+impl ConversionCounts {
+	pub fn merge(&mut self, other: &Self) {
+		let add = |a: &mut [usize; 2], b: &[usize; 2]| { a[0] += b[0]; a[1] += b[1] };
+		add(&mut self.unmethylated_control, &other.unmethylated_control);
+		add(&mut self.methylated_control, &other.methylated_control);
+		add(&mut self.chh, &other.chh);
+	}
+}
+
+// Conversion rates estimated by bs_call for a sample, or for a read group within a sample (reported only; calling uses the sample rates)
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ConversionEstimate {
+	#[serde(skip)]
+	pub sample_idx: usize,
+	pub sample: String,
+	#[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+	pub read_group: Option<String>,
+	pub under_conversion: f64,
+	pub over_conversion: f64,
+	pub counts: ConversionCounts,
+}
+
+// MspI fragment statistics collected in RRBS mode
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RrbsStats {
+	#[serde(rename = "MspIReads")]
+	pub mspi_reads: usize, // Reads with 5' end at an MspI site
+	#[serde(rename = "NonMspIReads")]
+	pub non_mspi_reads: usize,
+	pub end_repair_trimmed_reads: usize,
+	#[serde(rename = "MspIFragmentEnds")]
+	pub mspi_fragment_ends: usize, // Distinct MspI fragment ends covered
+	#[serde(rename = "MspIFragmentEndCoverage")]
+	pub mspi_fragment_end_coverage: Vec<usize>, // Histogram of reads per MspI fragment end
+}
+
+impl RrbsStats {
+	pub fn merge(&mut self, other: &Self) {
+		self.mspi_reads += other.mspi_reads;
+		self.non_mspi_reads += other.non_mspi_reads;
+		self.end_repair_trimmed_reads += other.end_repair_trimmed_reads;
+		self.mspi_fragment_ends += other.mspi_fragment_ends;
+		let v = &mut self.mspi_fragment_end_coverage;
+		if other.mspi_fragment_end_coverage.len() > v.len() { v.resize(other.mspi_fragment_end_coverage.len(), 0) }
+		for (a, b) in v.iter_mut().zip(other.mspi_fragment_end_coverage.iter()) { *a += b }
+	}
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CallJson {
 	source: String,
 	date: String,
+	#[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+	auto_trim: Option<AutoTrim>,
+	#[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+	rrbs: Option<RrbsStats>,
+	#[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+	conversion: Option<Vec<ConversionEstimate>>,
+	// Number of UMI families by family size
+	#[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+	umi_family_sizes: Option<Vec<usize>>,
 	filter_stats: FSType,
 	contig_stats: HashMap<String, CSType>,
 	total_stats: TSType,
@@ -524,6 +609,10 @@ impl CallJson {
 		Self {
 			source: source.as_ref().to_owned(),
 			date: date.as_ref().to_owned(),
+			auto_trim: None,
+			rrbs: None,
+			conversion: None,
+			umi_family_sizes: None,
 			filter_stats: FSType::new(),
 			contig_stats: HashMap::new(),
 			total_stats: TSType::new(),
@@ -537,6 +626,10 @@ impl CallJson {
 	pub fn qc_dist(&self) -> &QCDist { &self.total_stats.qc_distributions }
 	pub fn methylation(&self) -> &Methylation { &self.total_stats.methylation }
 	pub fn filter_stats(&mut self) -> &mut FSType { &mut self.filter_stats}
+	pub fn set_auto_trim(&mut self, auto_trim: Option<AutoTrim>) { self.auto_trim = auto_trim }
+	pub fn set_rrbs(&mut self, rrbs: Option<RrbsStats>) { self.rrbs = rrbs }
+	pub fn set_conversion(&mut self, conversion: Option<Vec<ConversionEstimate>>) { self.conversion = conversion }
+	pub fn set_umi_family_sizes(&mut self, v: Option<Vec<usize>>) { self.umi_family_sizes = v }
 	pub fn total_stats(&mut self) -> &mut TSType { &mut self.total_stats}
 	pub fn basic_stats(&self) -> &BasicStats { &self.total_stats.basic_stats }
 	pub fn vcf_filter_stats(&self) -> &HashMap<String, QCCounts> { &self.total_stats.vcf_filter_stats }
@@ -549,6 +642,6 @@ impl CallJson {
 			let ts = TSType::from_vcf_stats(&vs);
 			(cs, ts)
 		} else { (HashMap::new(), TSType::new()) };
-		Self {source: source.as_ref().to_owned(), date: date.as_ref().to_owned(), filter_stats, contig_stats, total_stats}
+		Self {source: source.as_ref().to_owned(), date: date.as_ref().to_owned(), auto_trim: None, rrbs: None, conversion: None, umi_family_sizes: None, filter_stats, contig_stats, total_stats}
 	}
 }
\ No newline at end of file
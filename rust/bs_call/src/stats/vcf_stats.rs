@@ -148,7 +148,8 @@ const BS_REF_CPG: usize = 8;
 const BS_NON_REF_CPG: usize = 16;
 
 fn get_basic_stats(cs: &CallStats) -> usize { 
-	(if (cs.flags & CALL_STATS_MULTI) != 0 { BS_MULTI }
+	(if (cs.flags & CALL_STATS_INDEL) != 0 { BS_INDELS }
+	else if (cs.flags & CALL_STATS_MULTI) != 0 { BS_MULTI }
 	else if (cs.flags & CALL_STATS_SNP) != 0 { BS_SNPS }
 	else { 0 }) 
 	| (if (cs.cpg_status & 7) == 4 { // Homozygous CPG
@@ -207,6 +208,14 @@ fn handle_stats(call_stats: &[CallStats], vcf_stats: &mut VcfStats, bin_dist: &m
 	let mut ctg_stats = vcf_stats.contig_stats.entry(cname).or_insert_with(VCFBasic::default);
 	for cs in call_stats.iter() {
 		let flags = get_basic_stats(cs);
+		// Indels only contribute to the basic stats and the variant quality distribution
+		if (flags & BS_INDELS) != 0 {
+			let rs_found = (cs.flags & CALL_STATS_RS_FOUND) != 0;
+			add_basic_stats(&mut ctg_stats, cs.filter, rs_found, flags);
+			add_basic_stats(&mut vcf_stats.total_stats, cs.filter, rs_found, flags);
+			vcf_stats.qual[SITE_TYPE_VARIANT][cs.phred as usize] += 1;
+			continue;
+		}
 		let dp = (cs.d_inf + cs.dp1) as usize;
 		let gcov = vcf_stats.cov_stats.entry(dp).or_insert_with(CovStats::new);
 		gcov.all += 1;
@@ -221,24 +230,25 @@ fn handle_stats(call_stats: &[CallStats], vcf_stats: &mut VcfStats, bin_dist: &m
 			gcov.var += 1;
 		}
 	}
-	for cs in call_stats.iter().filter(|c| (c.flags & CALL_STATS_SKIP) == 0) {
+	for cs in call_stats.iter().filter(|c| (c.flags & (CALL_STATS_SKIP | CALL_STATS_INDEL)) == 0) {
 		add_filter_counts(vcf_stats, cs);
 		if let Some((a, b)) = cs.meth_cts { handle_meth_stats(vcf_stats, bin_dist, a, b, (cs.cpg_status & CPG_STATUS_REF_CPG) != 0, cs) }
 		mutation_stats(vcf_stats, cs);	
 	}
 }
 
-pub fn collect_vcf_stats(bs_cfg: Arc<BsCallConfig>, rx: mpsc::Receiver<Option<Vec<CallStats>>>, stat_tx: mpsc::Sender<StatJob>) {
+// Call stats are received and accumulated separately for each sample
+pub fn collect_vcf_stats(bs_cfg: Arc<BsCallConfig>, rx: mpsc::Receiver<Option<Vec<Vec<CallStats>>>>, stat_tx: mpsc::Sender<StatJob>) {
 	info!("collect_vcf_stats_thread starting up");
-	let mut vcf_stats = VcfStats::new();
+	let mut vcf_stats: Vec<_> = bs_cfg.samples.iter().map(|_| VcfStats::new()).collect();
 	let mut bin_dist = BinDist::new(100);
 	loop {
 		match rx.recv() {
 			Ok(None) => break,
 			Ok(Some(call_stats)) => {
 				debug!("Received new call stats block");
-				if !call_stats.is_empty() {
-					handle_stats(&call_stats, &mut vcf_stats, &mut bin_dist, &bs_cfg);
+				for (cs, vs) in call_stats.iter().zip(vcf_stats.iter_mut()).filter(|(cs, _)| !cs.is_empty()) {
+					handle_stats(cs, vs, &mut bin_dist, &bs_cfg);
 				}
 			},
 			Err(e) => {
@@ -247,7 +257,7 @@ pub fn collect_vcf_stats(bs_cfg: Arc<BsCallConfig>, rx: mpsc::Receiver<Option<Ve
 			}
 		}
 	}
-	let _ = stat_tx.send(StatJob::AddVcfStats(vcf_stats));
+	for (sample, vs) in vcf_stats.drain(..).enumerate() { let _ = stat_tx.send(StatJob::AddVcfStats(sample, vs)); }
 	if let Ok(ru_thread) = Rusage::get(RusageWho::RusageThread) {
 		info!("collect_vcf_stats_thread shutting down: user {} sys {}", ru_thread.utime(), ru_thread.stime());	
 	}
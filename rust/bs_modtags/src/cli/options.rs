@@ -0,0 +1,34 @@
+use std::io;
+use std::path::Path;
+use clap::{ArgMatches, ErrorKind};
+
+use crate::config::{Config, OutputType, new_err};
+
+pub fn handle_options(m: &ArgMatches) -> io::Result<Config> {
+	let infile = m.value_of("input").expect("No input filename"); // This should not be allowed by Clap	
+	let reference = m.value_of("reference").expect("No reference filename"); // Or this
+	if !Path::new(reference).exists() { return Err(new_err(format!("Couldn't access reference file {}", reference))) }
+	let fai = format!("{}.fai", reference);
+	if !Path::new(&fai).exists() { return Err(new_err(format!("Couldn't access reference file index {}", fai))) }
+	let mut conf = Config::new(infile, reference);
+	if let Some(s) = m.value_of("output") { conf.set_output(s); }
+	if let Some(s) = m.value_of("output_type") { 
+		conf.set_output_type(match s.to_lowercase().as_str() {
+			"c" => OutputType::Cram,
+			"s" => OutputType::Sam,
+			_ => OutputType::Bam,
+		});
+	}
+	match value_t!(m, "threads", usize) {
+		Ok(x) => { conf.set_threads(x); },
+		Err(e) if e.kind == ErrorKind::ArgumentNotFound => (),
+		Err(e) => return Err(new_err(format!("Error parsing option: {}", e))),		
+	}
+	match value_t!(m, "bq_threshold", u8) {
+		Ok(x) => { conf.set_min_qual(x); },
+		Err(e) if e.kind == ErrorKind::ArgumentNotFound => (),
+		Err(e) => return Err(new_err(format!("Error parsing option: {}", e))),		
+	}
+	conf.set_all_contexts(m.is_present("all_contexts"));
+	Ok(conf)
+}
@@ -0,0 +1,47 @@
+use std::io::{self, Error, ErrorKind};
+
+pub fn new_err(s: String) -> io::Error {
+	Error::new(ErrorKind::Other, s)	
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputType { Bam, Cram, Sam }
+
+impl OutputType {
+	pub fn mode(&self) -> &'static str {
+		match self {
+			OutputType::Bam => "wb",
+			OutputType::Cram => "wc",
+			OutputType::Sam => "w",
+		}
+	}
+}
+
+pub struct Config {
+	input: String,
+	reference: String,
+	output: Option<String>,
+	output_type: OutputType,
+	threads: usize,
+	min_qual: u8,
+	all_contexts: bool,
+}
+
+impl Config {
+	pub fn new<S: AsRef<str>, T: AsRef<str>>(input: S, reference: T) -> Self { 
+		Self{input: input.as_ref().to_owned(), reference: reference.as_ref().to_owned(), output: None, output_type: OutputType::Bam, 
+			threads: 0, min_qual: 13, all_contexts: false} 
+	}
+	pub fn set_output<S: AsRef<str>>(&mut self, fname: S) -> &mut Self { self.output = Some(fname.as_ref().to_owned()); self }
+	pub fn set_output_type(&mut self, otype: OutputType) -> &mut Self { self.output_type = otype; self }
+	pub fn set_threads(&mut self, threads: usize) -> &mut Self { self.threads = threads; self }
+	pub fn set_min_qual(&mut self, min_qual: u8) -> &mut Self { self.min_qual = min_qual; self }
+	pub fn set_all_contexts(&mut self, b: bool) -> &mut Self { self.all_contexts = b; self }
+	pub fn input(&self) -> &str { &self.input }
+	pub fn reference(&self) -> &str { &self.reference }
+	pub fn output(&self) -> Option<&str> { self.output.as_deref() }
+	pub fn output_type(&self) -> OutputType { self.output_type }
+	pub fn threads(&self) -> usize { self.threads }
+	pub fn min_qual(&self) -> u8 { self.min_qual }
+	pub fn all_contexts(&self) -> bool { self.all_contexts }
+}
@@ -0,0 +1,19 @@
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate clap;
+
+mod cli;
+pub mod config;
+pub mod process;
+
+fn main() -> Result<(), String> {
+	let conf = cli::process_cli().map_err(|e| format!("bs_modtags initialization failed with error: {}", e))?;
+	match process::process(conf) {
+		Ok(_) => Ok(()),
+		Err(e) => {
+			error!("bs_modtags failed with error: {}", e);
+			Err("Failed".to_string())
+		}
+	}
+}
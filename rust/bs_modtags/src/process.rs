@@ -0,0 +1,134 @@
+use std::io;
+use std::fmt::Write;
+
+use r_htslib::*;
+use crate::config::*;
+
+// Base codes used in SeqQual
+const BASE_C: u8 = 1;
+const BASE_G: u8 = 2;
+
+// Convert base quality to ML value (probability of methylation scaled to 0-255) for an observed methylated base
+fn qual_to_ml(q: u8) -> u8 {
+	let p = 1.0 - 10.0f64.powf(-0.1 * (q as f64));
+	((p * 256.0) as usize).min(255) as u8
+}
+
+// Check if reference position x is an informative cytosine for the given bisulfite strand.
+// For the C2T strand this is a C on the forward strand and for the G2A strand a C on the reverse strand
+fn is_site(rf: &[u8], x: usize, bs: BSStrand, all_contexts: bool) -> bool {
+	let base = |i: usize| rf.get(i).map(|c| c.to_ascii_uppercase());
+	match bs {
+		BSStrand::StrandC2T => base(x) == Some(b'C') && (all_contexts || base(x + 1) == Some(b'G')),
+		BSStrand::StrandG2A => base(x) == Some(b'G') && (all_contexts || (x > 0 && base(x - 1) == Some(b'C'))),
+		_ => false,
+	}
+}
+
+// Add MM and ML tags to a mapped read.  In the bisulfite converted reads, the methylated cytosines
+// are seen as C (or G for the G2A strand) while the non-methylated cytosines appear as T (or A), so 
+// only the methylated sites can be recorded in the MM tag.  Other Cs are reported as having
+// an unknown state (the '?' mode).  The MM tag refers to the original read direction, so for reverse
+// reads we go through the sequence backwards, and the C on the reverse strand of the reference becomes 
+// a G on the opposite strand of the original read.
+//
+// Returns false if the read was not annotated (no bisulfite strand information)
+fn add_mod_tags(brec: &mut BamRec, rf: &[u8], conf: &Config) -> io::Result<bool> {
+	let bs = get_bs_strand(brec).0;
+	let target = match bs {
+		BSStrand::StrandC2T => BASE_C,
+		BSStrand::StrandG2A => BASE_G,
+		_ => return Ok(false),
+	};
+	let (cigar, pos) = match (brec.cigar(), brec.pos()) {
+		(Some(c), Some(x)) => (c, x),
+		_ => return Ok(false),
+	};
+	let sq = brec.get_seq_qual()?;
+	let mut sites = Vec::new();
+	let (mut ref_pos, mut seq_pos) = (pos, 0);
+	for elem in cigar.iter() {
+		let (op, l) = elem.op_pair();
+		let l = l as usize;
+		if matches!(op, CigarOp::Match | CigarOp::Equal | CigarOp::Diff) {
+			for i in 0..l {
+				if let Some(x) = sq.get(seq_pos + i) {
+					if (x & 3) == target && (x >> 2) >= conf.min_qual() && is_site(rf, ref_pos + i, bs, conf.all_contexts()) {
+						sites.push((seq_pos + i, qual_to_ml(x >> 2)))
+					}
+				}
+			}
+		}
+		let op_type = elem.op_type();
+		if (op_type & 1) != 0 { seq_pos += l }
+		if (op_type & 2) != 0 { ref_pos += l }
+	}
+	// Positions of all target bases in the read
+	let mut bases: Vec<_> = sq.iter().enumerate().filter(|(_, x)| (*x & 3) == target).map(|(i, _)| i).collect();
+	let rev = (brec.flag() & BAM_FREVERSE) != 0;
+	if rev {
+		bases.reverse();
+		sites.reverse();
+	}
+	let mut mm = String::from(if (target == BASE_C) != rev { "C+m?" } else { "G-m?" });
+	let mut ml = Vec::with_capacity(sites.len());
+	let mut it = sites.iter().peekable();
+	let mut skip = 0;
+	for x in bases {
+		match it.peek() {
+			Some((y, p)) if *y == x => {
+				write!(mm, ",{}", skip).unwrap();
+				ml.push(*p);
+				skip = 0;
+				it.next();
+			},
+			_ => skip += 1,
+		}
+	}
+	mm.push(';');
+	brec.aux_update_str("MM", &mm)?;
+	brec.aux_update_u8_array("ML", &ml)?;
+	Ok(true)
+}
+
+pub fn process(conf: Config) -> io::Result<()> {
+	let mut in_file = HtsFile::new(conf.input(), "r")?;
+	let mut hdr = SamHeader::read(&mut in_file)?;
+	let ref_idx = Faidx::load(conf.reference())?;
+	let fai = format!("{}.fai", conf.reference());
+	in_file.set_fai_filename(&fai)?;
+	let mut out_file = HtsFile::new(conf.output().unwrap_or("-"), conf.output_type().mode())?;
+	if conf.output_type() == OutputType::Cram { out_file.set_fai_filename(&fai)? }
+	if conf.threads() > 0 {
+		in_file.set_threads(conf.threads())?;
+		out_file.set_threads(conf.threads())?;
+	}
+	hdr.add_lines(format!("@PG\tID:bs_modtags\tPN:bs_modtags\tVN:{}\n", crate_version!()))?;
+	hdr.write(&mut out_file)?;
+	let mut brec = BamRec::new()?;
+	let mut curr_seq: Option<(usize, Sequence)> = None;
+	let (mut n_reads, mut n_tagged) = (0, 0);
+	loop {
+		match brec.read(&mut in_file, &mut hdr) {
+			SamReadResult::Ok => (),
+			SamReadResult::EOF => break,
+			SamReadResult::Error => return Err(new_err(format!("Error reading from input file {}", conf.input()))),
+		}
+		n_reads += 1;
+		if (brec.flag() & BAM_FUNMAP) == 0 {
+			if let Some(tid) = brec.tid() {
+				if curr_seq.as_ref().map(|(t, _)| *t != tid).unwrap_or(true) {
+					let cname = hdr.tid2name(tid);
+					debug!("Loading reference sequence for {}", cname);
+					curr_seq = Some((tid, ref_idx.fetch_seq(cname)?));
+				}
+				let seq = &curr_seq.as_ref().unwrap().1;
+				let rf = seq.get_seq(0, seq.len() - 1)?;
+				if add_mod_tags(&mut brec, rf, &conf)? { n_tagged += 1 }
+			}
+		}
+		brec.write(&mut out_file, &mut hdr)?;
+	}
+	info!("Processed {} reads, {} with MM/ML tags added", n_reads, n_tagged);
+	Ok(())
+}
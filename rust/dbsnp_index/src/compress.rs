@@ -56,6 +56,8 @@ fn compress_contig(ctg: &Contig) -> (Vec<CompressBlock>, usize) {
 				write_u128(&mut ubuf, bin.mask()).expect("Write error");
 				// Write names
 				bin.write_names(&mut ubuf);
+				// Write allele frequencies and alleles
+				bin.write_freqs(&mut ubuf);
 				if ubuf.len() >= BUF_SIZE {
 					let cbuf = compress(&ubuf, 0).expect("Error when compressing");
 					total_usize += ubuf.len();
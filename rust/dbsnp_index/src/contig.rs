@@ -12,6 +12,31 @@ pub struct ContigBin {
 	half_full: bool,
 	entries: Vec<u8>,
 	name_buf: Vec<u8>,	
+	freq: Vec<[u8; 2]>, // Encoded alternate allele frequency and alleles for each entry
+}
+
+// The alternate allele frequency is stored as 1 + AF * 254 (so AF 1.0 => 255), with 0 indicating no information.  This is
+// not folded to the MAF so that the minor allele is known
+fn encode_af(af: Option<f32>) -> u8 {
+	match af {
+		Some(z) if (0.0..=1.0).contains(&z) => 1 + (z * 254.0).round() as u8,
+		_ => 0,
+	}
+}
+
+// Reference allele in the high nybble, alternate allele in the low nybble (A = 1, C = 2, G = 3, T = 4), with 0 indicating no information
+fn encode_alleles(alleles: Option<[u8; 2]>) -> u8 {
+	let enc = |c: u8| match c.to_ascii_uppercase() {
+		b'A' => Some(1),
+		b'C' => Some(2),
+		b'G' => Some(3),
+		b'T' => Some(4),
+		_ => None,
+	};
+	match alleles.map(|[r, a]| (enc(r), enc(a))) {
+		Some((Some(r), Some(a))) if r != a => (r << 4) | a,
+		_ => 0,
+	}
 }
 
 impl ContigBin {
@@ -46,6 +71,13 @@ impl ContigBin {
 		idx
 	}
 	
+	// Write allele frequency and allele information sorted by position within bin
+	pub fn write_freqs<W: Write>(&self, mut w: W) {
+		let mut idx: Vec<_> = self.entries.iter().zip(self.freq.iter()).collect();
+		idx.sort_unstable_by_key(|(off, _)| **off);
+		for (_, f) in idx.iter() { w.write_all(*f).expect("Write error") }
+	}
+	
 	// Write names sorted by position within bin
 	pub fn write_names<W: Write>(&mut self, w: W) {
 		let idx = self.sort_idx();
@@ -200,6 +232,7 @@ impl ContigData {
 				bin.half_full = false;
 			}	
 			bin.entries.push(off);
+			bin.freq.push([encode_af(snp.af()), encode_alleles(snp.alleles())]);
 		}
 	}
 	pub fn stats(&self) -> &ContigStats { &self.stats }
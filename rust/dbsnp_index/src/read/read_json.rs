@@ -300,7 +300,9 @@ fn snp_from_json(s: &str, rb: &mut SnpBuilder) -> Option<Snp> {
 		if (jsnp.mask & VALID_SNP) != 0 {
 			if let (Some(name), Some(cname), Some(pos)) = (jsnp.name, jsnp.cname, jsnp.pos) {
 //				println!("rs{} {} {} {:?}", name, cname, pos, jsnp.maf);
-				rb.build_snp(name, cname, pos, jsnp.maf)
+				// The allele counts are summed over all alleles so we only have the MAF and not the alternate allele frequency
+				let alleles = if jsnp.alleles[0] != 0 { Some([jsnp.alleles[1], jsnp.alleles[0]]) } else { None };
+				rb.build_snp(name, cname, pos, jsnp.maf, None, alleles)
 			} else { None }
 		} else { None }
 	} else { None }
@@ -3,11 +3,40 @@ use std::str::FromStr;
 use super::*;
 use crate::snp::SnpBuilder;
 
+// Get the alternate allele frequency from the INFO field, using the CAF (ref,alt frequencies), FREQ (study:ref,alt|study:...;
+// the first study is used) or AF (alt frequency) tags in that order
+fn af_from_info(info: &str) -> Option<f32> {
+	let mut af = None;
+	for fd in info.split(';') {
+		let (tag, val) = match fd.find('=') {
+			Some(i) => (&fd[..i], &fd[i + 1..]),
+			None => continue,
+		};
+		let alt_freq = |s: &str| s.split(',').nth(1).and_then(|x| <f32>::from_str(x).ok());
+		let z = match tag {
+			"CAF" => alt_freq(val),
+			"FREQ" => val.split('|').next().and_then(|s| s.split(':').nth(1)).and_then(alt_freq),
+			"AF" => {
+				if af.is_none() { af = <f32>::from_str(val).ok() }
+				continue
+			},
+			_ => continue,
+		};
+		if z.is_some() {
+			af = z;
+			break
+		}
+	}
+	af.filter(|z| (0.0..=1.0).contains(z))
+}
+
 fn snp_from_vcf(s: &str, rb: &mut SnpBuilder) -> Option<Snp> {
 	let v: Vec<&str> = s.split('\t').collect();
 	if v.len() > 4 && v[3].len() == 1 && v[4].len() == 1 {
 		let pos = <u32>::from_str(&v[1]).ok()?;
-		return rb.mk_snp(v[2], v[0], pos, None)
+		let af = v.get(7).and_then(|s| af_from_info(s));
+		let maf = af.map(|z| if z > 0.5 { 1.0 - z } else { z });
+		return rb.mk_snp(v[2], v[0], pos, maf, af, Some([v[3].as_bytes()[0], v[4].as_bytes()[0]]))
 	}
 	None	
 }
@@ -17,3 +46,15 @@ pub fn process_vcf_line(buf: &str, builder: &mut SnpBuilder, rbuf: &mut ReaderBu
 		if let Some(snp) = snp_from_vcf(&buf, builder) { rbuf.add_snp(snp) }
 	}	
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn af_not_folded() {
+		assert_eq!(af_from_info("RS=1;AF=0.9"), Some(0.9));
+		assert_eq!(af_from_info("CAF=0.1,0.9;AF=0.2"), Some(0.9));
+		assert_eq!(af_from_info("FREQ=GnomAD:0.1,0.9|TOPMED:0.5,0.5"), Some(0.9));
+	}
+}
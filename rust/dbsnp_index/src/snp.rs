@@ -11,12 +11,16 @@ pub struct RawSnp {
 	name: String,
 	pos: u32,
 	maf: Option<f32>,
+	af: Option<f32>, // Alternate allele frequency (if the alleles the frequency refers to are known)
+	alleles: Option<[u8; 2]>, // Reference and alternate alleles
 }
 
 impl RawSnp {
 	pub fn name(&self) -> &str { &self.name }
 	pub fn pos(&self) -> u32 { self.pos }
 	pub fn maf(&self) -> Option<f32> { self.maf }
+	pub fn af(&self) -> Option<f32> { self.af }
+	pub fn alleles(&self) -> Option<[u8; 2]> { self.alleles }
 }
 
 #[derive(Debug)]
@@ -58,21 +62,21 @@ impl <'a>SnpBuilder<'a> {
 	pub fn new(ctg_hash: &'a ContigHash) -> Self {
 		Self{ctg_lookup: ctg_hash.mk_lookup()}
 	}
-	pub fn build_snp(&mut self, name: &str, ctg: &str, pos: u32, maf: Option<f32>) -> Option<Snp> {
+	pub fn build_snp(&mut self, name: &str, ctg: &str, pos: u32, maf: Option<f32>, af: Option<f32>, alleles: Option<[u8; 2]>) -> Option<Snp> {
 		if let Some(contig) = self.ctg_lookup.get_contig(ctg) {
 			Some(Snp {
 				raw_snp: RawSnp {
 					name: name.to_owned(),
-					pos, maf
+					pos, maf, af, alleles
 				},
 				contig
 			})
 		} else { None }	
 	}
-	pub fn mk_snp(&mut self, name: &str, ctg: &str, pos: u32, maf: Option<f32>) -> Option<Snp> {		
+	pub fn mk_snp(&mut self, name: &str, ctg: &str, pos: u32, maf: Option<f32>, af: Option<f32>, alleles: Option<[u8; 2]>) -> Option<Snp> {		
 		if let Some(tname) = name.strip_prefix("rs") {
 			if tname.is_empty() || tname.find(|c :char| !char::is_ascii_digit(&c)).is_some() { None }
-            else { self.build_snp(tname, ctg, pos, maf) }
+            else { self.build_snp(tname, ctg, pos, maf, af, alleles) }
 		} else { None }
 	}
 }
@@ -32,7 +32,7 @@ pub fn new_err(s: String) -> io::Error {
 /// Name           Size         Description
 /// ---------------------------------------------------------------------------
 /// magic          32           Magic number (0xd7278434)
-/// version        8            Version number (should be 2)
+/// version        8            Version number (should be 3)
 /// reserved       24           For future use
 /// header_idx     64           File offset of contig header
 /// ubuf_size      64           Maximum size of uncompressed data block
@@ -73,6 +73,10 @@ pub fn new_err(s: String) -> io::Error {
 ///                                    Digits 0-9 represented by bit patterns 0000 - 1001, terminating characters encoded as 1110 or 1111 for
 ///                                    snps that are preselected (1111) or not (1110).  Bit patterns 1010, 1011, 1100, 1101 are illegal and should not occur.
 ///                                    The data for adjacent snps are packed together, so a snp does not need to start on a byte boundary.
+///   snp info        n * 16           For each snp (in the same order as the rs numbers):
+///                                      af (8 bits) alternate allele frequency stored as 1 + AF * 254, with 0 indicating no information
+///                                      alleles (8 bits) reference allele in the high nybble, alternate allele in the low nybble
+///                                      (A = 1, C = 2, G = 3, T = 4), with 0 indicating no information
 /// 
 /// Contig information (compressed)
 /// 
@@ -148,7 +152,7 @@ pub fn write_thread(conf: Arc<Config>, recv: Receiver<(Arc<Contig>, Vec<Compress
 	write_u32(ofile.by_ref(), &[IDX_MAGIC]).expect("Write error");
 	ofile.seek(SeekFrom::Start(0)).expect("IO error - can't seek to start of output file");
 	write_u32(ofile.by_ref(), &[IDX_MAGIC]).expect("Write error");
-	ofile.write_all(&[2,0,0,0]).expect("Write error");
+	ofile.write_all(&[3,0,0,0]).expect("Write error");
 	write_u64(ofile.by_ref(), &[pos, max_size as u64, cbuf.len() as u64]).expect("Write error");
 	debug!("Writer thread terminating");
 }
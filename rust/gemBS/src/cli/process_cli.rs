@@ -9,26 +9,36 @@ use yaml_rust::yaml;
 
 use crate::commands;
 use crate::config::GemBS;
-use crate::common::defs::{Section, DataValue};
+use crate::common::defs::{Section, DataValue, ClusterType};
 
 use super::utils::get_shell;
 
+#[cfg(feature = "slurm")]
+fn add_cluster_args<'a, 'b>(app: App<'a, 'b>, submit: bool) -> App<'a, 'b> {
+	let app = app
+		.arg(Arg::with_name("slurm_script").short("s").long("slurm-script").takes_value(true)
+		.value_name("SCRIPT_FILE").help("Generate PERL script to submit commands to slurm for execution"))
+		.arg(Arg::with_name("pbs_script").long("pbs-script").takes_value(true)
+		.value_name("SCRIPT_FILE").help("Generate PERL script to submit commands to PBS for execution"))
+		.arg(Arg::with_name("sge_script").long("sge-script").takes_value(true)
+		.value_name("SCRIPT_FILE").help("Generate PERL script to submit commands to SGE for execution"));
+	if submit {
+		app.arg(Arg::with_name("slurm").short("S").long("slurm").help("Submit commands to slurm for execution"))
+			.arg(Arg::with_name("pbs").long("pbs").help("Submit commands to PBS for execution"))
+			.arg(Arg::with_name("sge").long("sge").help("Submit commands to SGE for execution"))
+			.group(ArgGroup::with_name("cluster_opts").args(&["slurm", "slurm_script", "pbs", "pbs_script", "sge", "sge_script"]))
+	} else {
+		app.group(ArgGroup::with_name("cluster_opts").args(&["slurm_script", "pbs_script", "sge_script"]))
+	}
+}
+
 fn gen_cli(yaml: &yaml::Yaml) -> App {
 
 	#[cfg(feature = "slurm")]
 	{
 		let container: Option<&'static str> = option_env!("GEMBS_CONTAINER");
-		if container.is_none() {
-			App::from_yaml(yaml).version(crate_version!()).setting(AppSettings::VersionlessSubcommands)
-				.arg(Arg::with_name("slurm_script").short("s").long("slurm-script").takes_value(true)
-				.value_name("SCRIPT_FILE").help("Generate PERL script to submit commands to slurm for execution"))
-				.arg(Arg::with_name("slurm").short("S").long("slurm").help("Submit commands to slurm for execution"))
-				.group(ArgGroup::with_name("slurm_opts").args(&["slurm", "slurm_script"]))
-		} else {
-			App::from_yaml(yaml).version(crate_version!()).setting(AppSettings::VersionlessSubcommands)
-				.arg(Arg::with_name("slurm_script").short("s").long("slurm-script").takes_value(true)
-				.value_name("SCRIPT_FILE").help("Generate PERL script to submit commands to slurm for execution"))		
-		}
+		let app = App::from_yaml(yaml).version(crate_version!()).setting(AppSettings::VersionlessSubcommands);
+		add_cluster_args(app, container.is_none())
 	}
 	#[cfg(not(feature = "slurm"))]
 	{
@@ -68,11 +78,16 @@ pub fn process_cli(gem_bs: &mut GemBS) -> Result<(), String> {
 	if m.is_present("keep_logs") { gem_bs.set_keep_logs(true) }
 	if m.is_present("ignore_times") { gem_bs.set_ignore_times(true); }
 	if m.is_present("ignore_status") { gem_bs.set_ignore_status(true); }
+	if m.is_present("hash_check") { gem_bs.set_hash_check(true); }
 	if m.is_present("all") { gem_bs.set_all(true); }
 	if m.is_present("dry_run") { gem_bs.set_dry_run(true); }
-	if m.is_present("slurm") { gem_bs.set_slurm(true); }
+	if m.is_present("slurm") { gem_bs.set_cluster(ClusterType::Slurm); }
+	if m.is_present("pbs") { gem_bs.set_cluster(ClusterType::Pbs); }
+	if m.is_present("sge") { gem_bs.set_cluster(ClusterType::Sge); }
 	if let Some(s) = m.value_of("json") { gem_bs.set_json_out(s); }
-	if let Some(s) = m.value_of("slurm_script") { gem_bs.set_slurm_script(s); }
+	if let Some(s) = m.value_of("slurm_script") { gem_bs.set_cluster_script(ClusterType::Slurm, s); }
+	if let Some(s) = m.value_of("pbs_script") { gem_bs.set_cluster_script(ClusterType::Pbs, s); }
+	if let Some(s) = m.value_of("sge_script") { gem_bs.set_cluster_script(ClusterType::Sge, s); }
 
 	let mem = (gem_bs.total_mem() as f64) / 1073741824.0;
 	debug!("Total memory detected: {:.1} GB", mem);
@@ -95,6 +110,9 @@ pub fn process_cli(gem_bs: &mut GemBS) -> Result<(), String> {
 		("extract", Some(m_sum)) => {
 			commands::extract::extract_command(m_sum, gem_bs)
 		},
+		("dmr", Some(m_sum)) => {
+			commands::dmr::dmr_command(m_sum, gem_bs)
+		},
 		("report", Some(m_sum)) => {
 			commands::report::report_command(m_sum, gem_bs)
 		},
@@ -104,6 +122,15 @@ pub fn process_cli(gem_bs: &mut GemBS) -> Result<(), String> {
 		("clear", Some(m_sum)) => {
 			commands::clear::clear_command(m_sum, gem_bs)
 		},
+		("status", Some(m_sum)) => {
+			commands::status::status_command(m_sum, gem_bs)
+		},
+		("graph", Some(m_sum)) => {
+			commands::graph::graph_command(m_sum, gem_bs)
+		},
+		("history", Some(m_sum)) => {
+			commands::history::history_command(m_sum, gem_bs)
+		},
 		("completions", Some(m_sum)) => {
 			generate_completions(m_sum)
 		},
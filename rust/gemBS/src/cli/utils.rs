@@ -99,6 +99,7 @@ lazy_static! {
         m.push(("non_stranded", OptionType::Global("non_stranded", VarType::Bool)));
         m.push(("benchmark_mode", OptionType::Global("benchmark_mode", VarType::Bool)));
 		m.push(("jobs", OptionType::Global("jobs", VarType::Int)));
+		m.push(("max_jobs", OptionType::Special("_max_jobs", VarType::Int)));
         m.push(("non_bs", OptionType::Local(VarType::Bool)));
         m.push(("bs", OptionType::Local(VarType::Bool)));
       	m.push(("merge", OptionType::Special("_merge", VarType::Bool)));
@@ -118,6 +119,10 @@ lazy_static! {
       	m.push(("list_pools", OptionType::Special("_list_pools", VarType::Int)));
       	m.push(("pool", OptionType::Special("_pool", VarType::StringVec)));
       	m.push(("haploid", OptionType::Global("haploid", VarType::Bool)));
+      	m.push(("rrbs", OptionType::Global("rrbs", VarType::Bool)));
+      	m.push(("dbsnp_priors", OptionType::Global("dbsnp_priors", VarType::Bool)));
+      	m.push(("ploidy_map", OptionType::Global("ploidy_map", VarType::String)));
+      	m.push(("chemistry", OptionType::Global("chemistry", VarType::String)));
       	m.push(("keep_duplicates", OptionType::Global("keep_duplicates", VarType::Bool)));
       	m.push(("keep_logs", OptionType::Global("keep_logs", VarType::Bool)));
       	m.push(("ignore_duplicate_flag", OptionType::Global("ignore_duplicate_flag", VarType::Bool)));
@@ -139,9 +144,18 @@ lazy_static! {
      	m.push(("cpg", OptionType::Global("make_cpg", VarType::Bool)));
      	m.push(("non_cpg", OptionType::Global("make_non_cpg", VarType::Bool)));
      	m.push(("bed_methyl", OptionType::Global("make_bedmethyl", VarType::Bool)));
+     	m.push(("segmentation", OptionType::Global("make_segmentation", VarType::Bool)));
      	m.push(("snps", OptionType::Global("make_snps", VarType::Bool)));
     	m.push(("snp_list", OptionType::Global("snp_list", VarType::String)));
     	m.push(("snp_db", OptionType::Global("dbsnp_index", VarType::String)));
+    	m.push(("groups", OptionType::Special("_dmr_groups", VarType::StringVec)));
+    	m.push(("dmr_test", OptionType::Global("dmr_test", VarType::String)));
+    	m.push(("dmr_min_cov", OptionType::Global("dmr_min_cov", VarType::Int)));
+    	m.push(("dmr_min_samples", OptionType::Global("dmr_min_samples", VarType::Int)));
+    	m.push(("dmr_fdr", OptionType::Global("dmr_fdr", VarType::Float)));
+    	m.push(("dmr_min_diff", OptionType::Global("dmr_min_diff", VarType::Float)));
+    	m.push(("dmr_max_gap", OptionType::Global("dmr_max_gap", VarType::Int)));
+    	m.push(("dmr_min_cpg", OptionType::Global("dmr_min_cpg", VarType::Int)));
  	  	m.push(("sampling", OptionType::Global("sampling_rate", VarType::Int)));
  	  	m.push(("min_contig_size", OptionType::Global("min_contig_size", VarType::Int)));
 	  	m.push(("make_bs_index", OptionType::Local(VarType::Bool)));
@@ -160,6 +174,16 @@ lazy_static! {
      	m.push(("pdf", OptionType::Special("_pdf", VarType::Bool)));
      	m.push(("confirm", OptionType::Special("_confirm", VarType::Bool)));
      	m.push(("force", OptionType::Special("_force", VarType::Bool)));
+     	m.push(("task_id", OptionType::Special("_task_id", VarType::StringVec)));
+     	m.push(("task_command", OptionType::Special("_task_command", VarType::StringVec)));
+     	m.push(("failed", OptionType::Special("_failed", VarType::Bool)));
+     	m.push(("last", OptionType::Special("_last", VarType::Int)));
+     	m.push(("stages", OptionType::Special("_stages", VarType::Bool)));
+     	m.push(("history_json", OptionType::Special("_history_json", VarType::Bool)));
+     	m.push(("status_json", OptionType::Special("_status_json", VarType::Bool)));
+     	m.push(("graph_format", OptionType::Special("_graph_format", VarType::String)));
+     	m.push(("graph_output", OptionType::Special("_graph_output", VarType::String)));
+     	m.push(("tasks_only", OptionType::Special("_tasks_only", VarType::Bool)));
         m
     };
 }
@@ -1 +1,358 @@
 pub mod slurm;
+pub mod pbs;
+pub mod sge;
+
+use std::path::Path;
+use std::fs;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ffi::OsStr;
+use std::rc::Rc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::io::Write;
+use std::{thread, time};
+use std::os::unix::fs::PermissionsExt;
+
+use regex::Regex;
+
+use crate::config::GemBS;
+use crate::common::defs::{DataValue, JobLen, MemSize, Command, ClusterType};
+use crate::common::dry_run;
+use crate::common::utils::Pipeline;
+use crate::common::tasks::TaskList;
+use crate::scheduler::{get_retry_policy, RetryPolicy};
+use utils::log_level::LogLevel;
+
+// Interface to a cluster workload manager.  The grouping of tasks into jobs and the
+// dependency handling is common to all backends; the backend only has to know how to
+// express these for the particular job submission system
+pub trait ClusterBackend {
+	// Short name of the backend (used for log directory)
+	fn name(&self) -> &'static str;
+	// Command used to submit a job script
+	fn submit_command(&self) -> &'static str;
+	// Regex to extract the job id from the output of the submit command
+	fn job_id_regex(&self) -> &'static str;
+	// Environment variable holding the index of the current element of an array job
+	fn array_index_var(&self) -> &'static str;
+	// Index of first element of array jobs
+	fn array_base(&self) -> usize { 0 }
+	// Any commands that should be run at the start of the job script
+	fn script_prelude(&self) -> Option<&'static str> { None }
+	// Submission arguments for job name, resources and array size
+	fn job_args(&self, desc: &str, node: &JobNode, n_tasks: usize) -> Vec<String>;
+	// Submission arguments for the job log files
+	fn log_args(&self, array: bool) -> Vec<String>;
+	// Submission arguments for the log file of the final clean up job
+	fn pipeline_log_args(&self) -> Vec<String>;
+	// Dependency id for a job or an element of an array job.  If dependencies on individual array
+	// elements are not supported then this should return the id for the complete array job
+	fn dep_id(&self, job_id: &str, array_ix: Option<usize>) -> String;
+	// Submission arguments to make a job wait for the successful completion of a list of dependencies
+	fn dependency_args(&self, deps: &[String]) -> Vec<String>;
+	// Submission arguments for a retry job that should run once the previous attempt has terminated 
+	// (successfully or not).  Returns None if the backend does not support this
+	fn retry_args(&self, _prev: &[String]) -> Option<Vec<String>> { None }
+	// Path (or shell glob) matching the log file for a job or array element
+	fn log_file(&self, job_id: &str, array_ix: Option<usize>) -> String;
+	fn log_dir(&self) -> String { format!("{}_logs", self.name()) }
+}
+
+pub fn get_backend(gem_bs: &GemBS, ctype: ClusterType) -> Box<dyn ClusterBackend> {
+	match ctype {
+		ClusterType::Slurm => Box::new(slurm::Slurm::new()),
+		ClusterType::Pbs => Box::new(pbs::Pbs::new()),
+		ClusterType::Sge => Box::new(sge::Sge::new(gem_bs)),
+	}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct JobDep {
+	job_ix: usize,
+	task_ix: usize,
+}
+
+impl fmt::Display for JobDep {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "[{},{}]", self.job_ix, self.task_ix) }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+pub struct JobNode {
+	pub cores: usize,
+	pub mem: MemSize,
+	pub time: JobLen,
+	depend: Vec<JobDep>, // Index in vector of ClusterJobs
+}
+
+struct ClusterJob {
+	task_vec: Vec<usize>, // Index in task_list
+	node: Rc<JobNode>,
+}
+
+impl ClusterJob {
+	fn new(node: Rc<JobNode>) -> Self {
+		ClusterJob{task_vec: Vec::new(), node}
+	}
+}
+
+fn write_job_script<T: fmt::Write>(wrt: &mut T, backend: &dyn ClusterBackend, jv: &ClusterJob, tl: &TaskList,  options: &HashMap<&'static str, DataValue>, verbose: LogLevel, attempt: Option<usize>) -> fmt::Result {
+	writeln!(wrt, "#!/bin/sh")?;
+	if let Some(s) = backend.script_prelude() { writeln!(wrt, "{}", s)?; }
+	if let Some(x) = attempt { writeln!(wrt, "export GEMBS_ATTEMPT={}", x)?; }
+	let job_array = jv.task_vec.len() > 1;
+	if job_array {
+	writeln!(wrt, "coms=( \\")?;
+	for ix in jv.task_vec.iter() {
+			let task = &tl[*ix];
+			writeln!(wrt,"\"{} {}\" \\",task.command(), dry_run::get_arg_string(task, options))?;
+		}
+		writeln!(wrt, ")")?;
+		let idx = match backend.array_base() {
+			0 => format!("${}", backend.array_index_var()),
+			b => format!("${}-{}", backend.array_index_var(), b),
+		};
+		writeln!(wrt, "echo gemBS --loglevel {} ${{coms[{}]}}", verbose, idx)?;
+		writeln!(wrt, "gemBS --loglevel {} ${{coms[{}]}}", verbose, idx)?;
+	} else {
+		let task = &tl[jv.task_vec[0]];
+		writeln!(wrt,"gemBS {} {}",task.command(), dry_run::get_arg_string(task, options))?;
+	}
+	Ok(())
+}
+
+fn write_rm_script<T: fmt::Write>(wrt: &mut T, logfiles: &[String]) -> fmt::Result {
+	writeln!(wrt, "#!/bin/sh")?;
+	write!(wrt, "for f in")?;
+	for f in logfiles.iter() {
+		write!(wrt," \\\n {}", f)?;
+	}
+	writeln!(wrt,"\ndo\n rm -f ${{f}}\ndone\necho Pipeline terminated successfully")
+}
+
+fn run_submit<I, S>(sig: Arc<AtomicUsize>, backend: &dyn ClusterBackend, re: &Regex, script: String, args: I) -> Result<String, String>
+where
+	I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+	let mut pipeline = Pipeline::new();
+	let submit_path = Path::new(backend.submit_command());
+	pipeline.add_stage(submit_path, Some(args)).in_string(script).out_string().run(sig)?;
+
+	// We add a short delay here to avoid overloading the job scheduler
+	thread::sleep(time::Duration::from_millis(250));
+
+	let content = pipeline.out_string_ref().expect("No output from job submission pipeline");
+	if let Some(cap) = re.captures(content) {
+		return Ok(cap.get(1).unwrap().as_str().to_owned());
+	}
+	Err(format!("Could not parse output from {}: {}", backend.submit_command(), content))
+}
+
+fn write_script_file_prelude(file: &mut fs::File, backend: &dyn ClusterBackend) -> std::io::Result<()> {
+	let submit = backend.submit_command();
+	writeln!(file, "#!/usr/bin/perl\n\n# {} job submission script generated by gemBS\n", backend.name().to_uppercase())?;
+	writeln!(file, "use strict;\nuse warnings;\nrequire File::Temp;\nuse File::Temp qw/ :seekable /;\nuse IO::Handle;\nuse Time::HiRes qw/ usleep /;")?;
+	writeln!(file, "sub submit($$) {{\n  my ($args, $scr) = @_;\n  my $fh = File::Temp->new();\n  my $fname = $fh->filename;")?;
+	writeln!(file, "  print $fh $scr;\n  $fh->flush();\n  open(my $sb, \"-|\", \"{} $args $fname\") or die \"Couldn't start {}: $!\";", submit, submit)?;
+	writeln!(file, "  if (<$sb> =~ /{}/) {{\n    usleep(250000);\n    return $1;\n  }}\n  die \"Error running {}\\n\";\n}}\n", backend.job_id_regex(), submit)?;
+	writeln!(file, "sub sub_ids($$) {{\n  my ($s, $ids) = @_;\n  $s =~ s/ID_(\\d+)/$$ids[$1]/g;\n  $s;\n}}\n")?;
+	writeln!(file, "my @job_id = ();\nmy ($script, $args);\nmkdir \"{}\";\n", backend.log_dir())
+}
+
+fn add_dep(deps: &mut Vec<String>, dep: String) {
+	if !deps.contains(&dep) { deps.push(dep) }
+}
+
+// Retry policy for a job is the most generous of the policies for the tasks in the job
+fn get_job_retry_policy(gem_bs: &GemBS, jv: &ClusterJob) -> RetryPolicy {
+	let mut policy = RetryPolicy{max_attempts: 1, memory_factor: 1.0, time_factor: 1.0};
+	for ix in jv.task_vec.iter() {
+		let p = get_retry_policy(gem_bs, gem_bs.get_tasks()[*ix].command());
+		policy.max_attempts = policy.max_attempts.max(p.max_attempts);
+		policy.memory_factor = policy.memory_factor.max(p.memory_factor);
+		policy.time_factor = policy.time_factor.max(p.time_factor);
+	}
+	policy
+}
+
+// Submits jobs or writes submission commands to the script file
+struct Submitter<'a> {
+	backend: &'a dyn ClusterBackend,
+	re: Regex,
+	file: Option<fs::File>,
+	n_sub: usize,
+	sig: Arc<AtomicUsize>,
+}
+
+impl<'a> Submitter<'a> {
+	// Returns job id (or placeholder if writing a script)
+	fn submit(&mut self, desc: &str, script: String, args: &[String]) -> Result<String, String> {
+		let ferr = |e| {format!("{}", e)};
+		if let Some(ref mut f) = self.file {
+			writeln!(f, "print \"Submitting job: {}\\n\";", desc).map_err(ferr)?;
+			writeln!(f, "$args = sub_ids('{}', \\@job_id);", args.join(" ")).map_err(ferr)?;
+			writeln!(f, "$script = <<'EOF';\n{}EOF", script).map_err(ferr)?;
+			writeln!(f, "push @job_id, submit($args, $script);\n").map_err(ferr)?;
+			self.n_sub += 1;
+			Ok(format!("ID_{}", self.n_sub - 1))
+		} else {
+			run_submit(Arc::clone(&self.sig), self.backend, &self.re, script, args)
+		}
+	}
+}
+
+// Prepare job graph and submit to cluster (or write submission script)
+pub fn handle_cluster(gem_bs: &GemBS, ctype: ClusterType, options: &HashMap<&'static str, DataValue>, task_list: &[usize]) -> Result<(), String> {
+	let backend = get_backend(gem_bs, ctype);
+	let backend = backend.as_ref();
+	let re = Regex::new(backend.job_id_regex()).expect("Invalid job id regex");
+	let _ = fs::create_dir(backend.log_dir());
+	let mut job_vec: Vec<ClusterJob> = Vec::new();
+	// Job ids from the cluster.  If we are writing a script then these are placeholders
+	// of the form ID_x that are substituted by the script at submission time
+	let mut job_id: Vec<Vec<String>> = Vec::new(); // One entry per attempt
+	let mut job_hash: HashMap<Rc<JobNode>, usize> = HashMap::new();
+	let mut task_hash: HashMap<usize, JobDep> = HashMap::new();
+	let ferr = |e| {format!("{}", e)};
+	let mut file = if let Some(s) = gem_bs.cluster_script() {
+		match fs::File::create(Path::new(s)) {
+			Ok(f) => {
+		 		let metadata = f.metadata().map_err(ferr)?;
+    			let mut perm = metadata.permissions();
+				perm.set_mode(0o755);
+				f.set_permissions(perm).map_err(ferr)?;
+				Some(f)
+			},
+			Err(e) => return Err(format!("Couldn't open script file {} for output: {}", s, e)),
+		}
+	} else { None };
+
+	if let Some(ref mut f) = file { write_script_file_prelude(f, backend).map_err(|e| format!("Error writing perl file header: {}", e))?; }
+	for ix in task_list.iter().filter(|i| gem_bs.get_tasks()[**i].command() != Command::MergeCallJsons) {
+		let task = &gem_bs.get_tasks()[*ix];
+		let depend = {
+			let mut t = Vec::new();
+			for i in task.parents().iter() {
+				match task_hash.get(i) {
+					Some(x) => t.push(*x),
+					None => {
+						let ptask = &gem_bs.get_tasks()[*i];
+						if ptask.command() == Command::MergeCallJsons {
+							for j in ptask.parents().iter() {
+								if let Some(x) = task_hash.get(j) { t.push(*x) }
+							}
+						}
+					},
+				}
+			}
+			t
+		};
+		let cores = task.cores().unwrap_or(1);
+		let mem = task.memory().unwrap_or_else(|| MemSize::from(0x400000000)); // 1G
+		let time = task.time().unwrap_or_else(|| JobLen::from(3600)); // 1hr
+		let node = JobNode{cores, mem, time, depend};
+		let job_ix = if let Some(i) = job_hash.get(&node) {
+			job_vec[*i].task_vec.push(*ix);
+			JobDep{job_ix: *i, task_ix: job_vec[*i].task_vec.len() - 1}
+		} else {
+			let node_rc = Rc::new(node);
+			let mut job = ClusterJob::new(node_rc.clone());
+			job.task_vec.push(*ix);
+			let x = job_vec.len();
+			job_vec.push(job);
+			job_hash.insert(node_rc.clone(), x);
+			JobDep{job_ix: x, task_ix: 0}
+		};
+		task_hash.insert(*ix, job_ix);
+	}
+	let verbose = gem_bs.verbose();
+	let mut dep_hash = HashSet::new();
+	// Dependency id for a job (or array element).  This is the id of the last attempt, which only
+	// succeeds if one of the attempts completed the task(s)
+	let dep_id = |jid: &[Vec<String>], jv: &[ClusterJob], d: &JobDep| -> String {
+		let array_ix = if jv[d.job_ix].task_vec.len() > 1 { Some(d.task_ix + backend.array_base()) } else { None };
+		backend.dep_id(jid[d.job_ix].last().expect("No job id"), array_ix)
+	};
+	let retry_supported = backend.retry_args(&[]).is_some();
+	let mut retry_warn = false;
+	let mut sub = Submitter{backend, re, file, n_sub: 0, sig: gem_bs.get_signal_clone()};
+	for jv in job_vec.iter() {
+		let mut policy = get_job_retry_policy(gem_bs, jv);
+		if policy.max_attempts > 1 && !retry_supported {
+			if !retry_warn {
+				warn!("Retrying of failed jobs is not supported for {} - jobs will only be submitted once", backend.name().to_uppercase());
+				retry_warn = true;
+			}
+			policy.max_attempts = 1;
+		}
+		let mut hs = HashSet::new();
+		let mut desc = String::from("gemBS");
+		for ix in jv.task_vec.iter() {
+			let task = &gem_bs.get_tasks()[*ix];
+			if hs.insert(task.command()) {
+				desc.push_str(format!("_{:#}",task.command()).as_str());
+			}
+		}
+		let attempt = if policy.max_attempts > 1 { Some(1) } else { None };
+		let mut script = String::new();
+		write_job_script(&mut script, backend, jv, gem_bs.get_tasks(), options, verbose, attempt).map_err(|e| format!("Error writing job script: {}", e))?;
+		let mut args = backend.job_args(&desc, &jv.node, jv.task_vec.len());
+		args.extend(backend.log_args(jv.task_vec.len() > 1));
+		if !jv.node.depend.is_empty() {
+			let mut deps = Vec::new();
+			for ix in jv.node.depend.iter() {
+				add_dep(&mut deps, dep_id(&job_id, &job_vec, ix));
+				dep_hash.insert(ix);
+			}
+			args.extend(backend.dependency_args(&deps));
+		}
+		let mut ids = vec!(sub.submit(&desc, script, &args)?);
+		// Retry jobs with escalated resources that run after the previous attempt has terminated.  If the previous
+		// attempt succeeded then the task(s) will already be complete, so gemBS will exit without doing anything.
+		// The last attempt therefore only fails if all attempts failed, so other jobs can depend on its success
+		for att in 2..=policy.max_attempts {
+			let node = JobNode{cores: jv.node.cores, mem: MemSize::from(policy.memory(jv.node.mem.mem(), att)), 
+				time: JobLen::from(policy.time(jv.node.time.secs(), att)), depend: Vec::new()};
+			let rdesc = format!("{}_try{}", desc, att);
+			let mut script = String::new();
+			write_job_script(&mut script, backend, jv, gem_bs.get_tasks(), options, verbose, Some(att)).map_err(|e| format!("Error writing job script: {}", e))?;
+			let mut args = backend.job_args(&rdesc, &node, jv.task_vec.len());
+			args.extend(backend.log_args(jv.task_vec.len() > 1));
+			args.extend(backend.retry_args(&ids[ids.len() - 1..]).expect("Retry not supported by backend"));
+			let id = sub.submit(&rdesc, script, &args)?;
+			ids.push(id);
+		}
+		job_id.push(ids);
+	}
+	// The clean up job depends on all jobs (or array elements) that no other job depends on
+	let mut logfiles = Vec::new();
+	let mut deps = Vec::new();
+	for (ix, jv) in job_vec.iter().enumerate() {
+		let len = jv.task_vec.len();
+		let array_ix: Vec<_> = if len == 1 { vec!(None) } else { (0..len).map(|i| Some(i + backend.array_base())).collect() };
+		for (i, aix) in array_ix.iter().enumerate() {
+			let jdep = JobDep{job_ix: ix, task_ix: i};
+			if ! dep_hash.contains(&jdep) { add_dep(&mut deps, dep_id(&job_id, &job_vec, &jdep)) }
+			for id in job_id[ix].iter() { logfiles.push(backend.log_file(id, *aix)); }
+		}
+	}
+	if ! logfiles.is_empty() {
+		let mut script = String::new();
+		write_rm_script(&mut script, &logfiles).map_err(|e| format!("Error writing job script: {}", e))?;
+		let node = JobNode{cores: 1, mem: MemSize::from(0x40000000), time: JobLen::from(600), depend: Vec::new()};
+		let mut args = backend.job_args("gemBS_clean_logfiles", &node, 1);
+		args.extend(backend.pipeline_log_args());
+		if ! deps.is_empty() { args.extend(backend.dependency_args(&deps)); }
+		if let Some(ref mut f) = sub.file {
+			writeln!(f, "print \"Submitting job: clean_logfiles\\n\";").map_err(ferr)?;
+			writeln!(f, "$args = sub_ids('{}', \\@job_id);", args.join(" ")).map_err(ferr)?;
+			writeln!(f, "$script = <<'EOF';\n{}EOF", script).map_err(ferr)?;
+			writeln!(f, "$script = sub_ids($script, \\@job_id);").map_err(ferr)?;
+			writeln!(f, "push @job_id, submit($args, $script);").map_err(ferr)?;
+		} else {
+			run_submit(gem_bs.get_signal_clone(), backend, &sub.re, script, &args)?;
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,68 @@
+use super::{ClusterBackend, JobNode};
+use crate::common::defs::MemSize;
+
+// PBS Professional (also accepted by recent Torque versions).
+// PBS does not allow dependencies on individual elements of array jobs, so
+// dependent jobs wait for the whole array to complete.
+pub struct Pbs {}
+
+impl Pbs {
+	pub fn new() -> Self { Pbs{} }
+}
+
+fn pbs_mem(mem: MemSize) -> String {
+	let m = mem.mem();
+	if m.trailing_zeros() >= 30 { format!("{}gb", m >> 30) }
+	else if m.trailing_zeros() >= 20 { format!("{}mb", m >> 20) }
+	else { format!("{}kb", (m + 0x3ff) >> 10) }
+}
+
+impl ClusterBackend for Pbs {
+	fn name(&self) -> &'static str { "pbs" }
+	fn submit_command(&self) -> &'static str { "qsub" }
+	fn job_id_regex(&self) -> &'static str { r"^(\d+)" }
+	fn array_index_var(&self) -> &'static str { "PBS_ARRAY_INDEX" }
+	fn array_base(&self) -> usize { 1 }
+	fn script_prelude(&self) -> Option<&'static str> { Some("cd $PBS_O_WORKDIR") }
+	fn job_args(&self, desc: &str, node: &JobNode, n_tasks: usize) -> Vec<String> {
+		let secs = node.time.secs();
+		let mut args = vec!(
+			"-N".to_string(), desc.to_string(),
+			"-l".to_string(), format!("select=1:ncpus={}:mem={}", node.cores, pbs_mem(node.mem)),
+			"-l".to_string(), format!("walltime={:02}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60),
+			"-r".to_string(), "n".to_string(),
+			"-V".to_string(),
+			"-S".to_string(), "/bin/bash".to_string(),
+		);
+		if n_tasks > 1 { 
+			args.push("-J".to_string());
+			args.push(format!("1-{}", n_tasks)); 
+		}
+		args
+	}
+	fn log_args(&self, _array: bool) -> Vec<String> {
+		vec!("-j".to_string(), "oe".to_string(), "-o".to_string(), "pbs_logs/".to_string())
+	}
+	fn pipeline_log_args(&self) -> Vec<String> {
+		vec!("-j".to_string(), "oe".to_string(), "-o".to_string(), "pbs_logs/pbs_gemBS_pipeline.out".to_string())
+	}
+	fn dep_id(&self, job_id: &str, array_ix: Option<usize>) -> String {
+		match array_ix {
+			Some(_) => format!("{}[]", job_id),
+			None => job_id.to_string(),
+		}
+	}
+	fn dependency_args(&self, deps: &[String]) -> Vec<String> {
+		vec!("-W".to_string(), format!("depend=afterok:{}", deps.join(":")))
+	}
+	fn retry_args(&self, prev: &[String]) -> Option<Vec<String>> {
+		Some(vec!("-W".to_string(), format!("depend=afterany:{}", prev.join(":"))))
+	}
+	// PBS names log files as <job name>.o<job id>[.<array index>] when output is directed to a directory
+	fn log_file(&self, job_id: &str, array_ix: Option<usize>) -> String {
+		match array_ix {
+			Some(i) => format!("pbs_logs/*.o{}.{}", job_id, i),
+			None => format!("pbs_logs/*.o{}", job_id),
+		}
+	}
+}
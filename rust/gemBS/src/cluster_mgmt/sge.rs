@@ -0,0 +1,69 @@
+use super::{ClusterBackend, JobNode};
+use crate::common::defs::{MemSize, Section};
+use crate::config::GemBS;
+
+// Sun/Univa/Open Grid Engine.
+// As with PBS, dependencies on array jobs wait for the whole array to complete.
+// Multi-core jobs are requested through a parallel environment (smp by default,
+// can be changed with the sge_parallel_env config variable).  Memory requests 
+// in SGE are per slot, so the memory for a job is divided by the number of cores.
+pub struct Sge {
+	parallel_env: String,
+}
+
+impl Sge {
+	pub fn new(gem_bs: &GemBS) -> Self { 
+		let parallel_env = gem_bs.get_config_str(Section::Default, "sge_parallel_env").unwrap_or("smp").to_string();
+		Sge{parallel_env} 
+	}
+}
+
+impl ClusterBackend for Sge {
+	fn name(&self) -> &'static str { "sge" }
+	fn submit_command(&self) -> &'static str { "qsub" }
+	fn job_id_regex(&self) -> &'static str { r"^(\d+)" }
+	fn array_index_var(&self) -> &'static str { "SGE_TASK_ID" }
+	fn array_base(&self) -> usize { 1 }
+	fn job_args(&self, desc: &str, node: &JobNode, n_tasks: usize) -> Vec<String> {
+		let secs = node.time.secs();
+		let cores = node.cores.max(1);
+		let slot_mem = MemSize::from(node.mem.mem().div_ceil(cores));
+		let mut args = vec!(
+			"-terse".to_string(),
+			"-N".to_string(), desc.to_string(),
+			"-cwd".to_string(),
+			"-V".to_string(),
+			"-S".to_string(), "/bin/bash".to_string(),
+			"-r".to_string(), "n".to_string(),
+			"-l".to_string(), format!("h_rt={:02}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60),
+			"-l".to_string(), format!("h_vmem={:#}", slot_mem),
+		);
+		if cores > 1 {
+			args.push("-pe".to_string());
+			args.push(self.parallel_env.clone());
+			args.push(format!("{}", cores));
+		}
+		if n_tasks > 1 { 
+			args.push("-t".to_string());
+			args.push(format!("1-{}", n_tasks)); 
+		}
+		args
+	}
+	fn log_args(&self, _array: bool) -> Vec<String> {
+		vec!("-j".to_string(), "y".to_string(), "-o".to_string(), "sge_logs/".to_string())
+	}
+	fn pipeline_log_args(&self) -> Vec<String> {
+		vec!("-j".to_string(), "y".to_string(), "-o".to_string(), "sge_logs/sge_gemBS_pipeline.out".to_string())
+	}
+	fn dep_id(&self, job_id: &str, _array_ix: Option<usize>) -> String { job_id.to_string() }
+	fn dependency_args(&self, deps: &[String]) -> Vec<String> {
+		vec!("-hold_jid".to_string(), deps.join(","))
+	}
+	// SGE names log files as <job name>.o<job id>[.<task id>] when output is directed to a directory
+	fn log_file(&self, job_id: &str, array_ix: Option<usize>) -> String {
+		match array_ix {
+			Some(i) => format!("sge_logs/*.o{}.{}", job_id, i),
+			None => format!("sge_logs/*.o{}", job_id),
+		}
+	}
+}
@@ -7,10 +7,14 @@ pub mod index;
 pub mod map;
 pub mod call;
 pub mod extract;
+pub mod dmr;
 pub mod md5sum;
 pub mod report;
 pub mod run;
 pub mod clear;
+pub mod history;
+pub mod status;
+pub mod graph;
 
 pub fn get_barcode_list<'a>(gem_bs: &'a GemBS, options: &'a HashMap<&'static str, DataValue>) -> Result<Vec<&'a String>, String> {
 	let mut barcodes = Vec::new();
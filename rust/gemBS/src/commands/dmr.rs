@@ -0,0 +1,31 @@
+use clap::ArgMatches;
+use crate::cli::utils::handle_options;
+use crate::config::GemBS;
+use crate::common::defs::{Section, Command};
+use crate::common::{dry_run, utils};
+use crate::scheduler;
+
+// The DMR and DMC files are the final outputs of the DMR calling tasks
+fn get_required_asset_list(gem_bs: &GemBS) -> Result<Vec<usize>, String> {
+	let asset_list: Vec<usize> = gem_bs.get_tasks().iter().filter(|t| t.command() == Command::Dmr && t.id().starts_with("dmr_call_"))
+		.flat_map(|t| t.outputs().copied()).collect();
+	if asset_list.is_empty() {
+		Err("No DMR calling tasks: the sample metadata should have a group column with two groups (or dmr_groups should be set in the [dmr] section)".to_string())
+	} else { Ok(asset_list) }
+}
+
+pub fn dmr_command(m: &ArgMatches, gem_bs: &mut GemBS) -> Result<(), String> {
+	gem_bs.setup_fs(false)?;
+	gem_bs.read_config()?;
+
+	let options = handle_options(m, gem_bs, Section::Dmr);
+	if let Some(x) = options.get("_dmr_groups") { gem_bs.set_config(Section::Dmr, "dmr_groups", x.clone()) }
+	let task_path = gem_bs.get_task_file_path();
+	let flock = utils::wait_for_lock(gem_bs.get_signal_clone(), &task_path)?;
+	gem_bs.setup_assets_and_tasks(&flock)?;
+	let asset_ids = get_required_asset_list(gem_bs)?;
+	let task_list = if gem_bs.all() { gem_bs.get_required_tasks_from_asset_list(&asset_ids, &[Command::Index, Command::Map, Command::MergeBams, Command::Call, Command::MergeBcfs, Command::MD5SumMap, Command::MD5SumCall, Command::IndexBcf, Command::Dmr])}
+	else { gem_bs.get_required_tasks_from_asset_list(&asset_ids, &[Command::Dmr]) };
+	if gem_bs.execute_flag() { scheduler::schedule_jobs(gem_bs, &options, &task_list, flock) }
+	else { dry_run::handle_nonexec(gem_bs, &options, &task_list) }
+}
@@ -14,6 +14,7 @@ fn get_required_asset_list(gem_bs: &GemBS, options: &HashMap<&'static str, DataV
 		if gem_bs.get_config_bool(Section::Extract, "make_cpg") { asset_list.push(gem_bs.get_asset(format!("{}_cpg.txt.gz", bc).as_str()).unwrap().idx()) }
 		if gem_bs.get_config_bool(Section::Extract, "make_non_cpg") { asset_list.push(gem_bs.get_asset(format!("{}_non_cpg.txt.gz", bc).as_str()).unwrap().idx()) }
 		if gem_bs.get_config_bool(Section::Extract, "make_bedmthyl") { asset_list.push(gem_bs.get_asset(format!("{}_cpg.bed.gz", bc).as_str()).unwrap().idx()) }
+		if gem_bs.get_config_bool(Section::Extract, "make_segmentation") { asset_list.push(gem_bs.get_asset(format!("{}_segments.bed.gz", bc).as_str()).unwrap().idx()) }
 		if gem_bs.get_config_bool(Section::Extract, "make_snps") { asset_list.push(gem_bs.get_asset(format!("{}_snps.txt.gz", bc).as_str()).unwrap().idx()) }
 	}
 	Ok(asset_list)
@@ -30,6 +31,6 @@ pub fn extract_command(m: &ArgMatches, gem_bs: &mut GemBS) -> Result<(), String>
 	let asset_ids = get_required_asset_list(gem_bs, &options)?;
 	let task_list = if gem_bs.all() { gem_bs.get_required_tasks_from_asset_list(&asset_ids, &[Command::Index, Command::Map, Command::MergeBams, Command::Call, Command::MergeBcfs, Command::MD5SumMap, Command::MD5SumCall, Command::IndexBcf, Command::Extract])} 
 	else { gem_bs.get_required_tasks_from_asset_list(&asset_ids, &[Command::Extract]) };
-	if gem_bs.execute_flag() { scheduler::schedule_jobs(gem_bs, &options, &task_list, &asset_ids, &[Command::Extract], flock) }	
+	if gem_bs.execute_flag() { scheduler::schedule_jobs(gem_bs, &options, &task_list, flock) }	
 	else { dry_run::handle_nonexec(gem_bs, &options, &task_list) }
 }
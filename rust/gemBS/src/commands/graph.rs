@@ -0,0 +1,181 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write, BufWriter};
+use std::path::Path;
+
+use clap::ArgMatches;
+use crate::cli::utils::handle_options;
+use crate::config::GemBS;
+use crate::common::defs::{Section, Command, DataValue};
+use crate::common::tasks::TaskStatus;
+use crate::common::assets::{AssetType, AssetStatus, GetAsset};
+use crate::common::utils;
+use super::get_barcode_list;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GraphFormat { Dot, GraphML, Mermaid }
+
+struct TaskNode {
+	idx: usize,
+	id: String,
+	command: String,
+	status: TaskStatus,
+}
+
+struct AssetNode {
+	idx: usize,
+	id: String,
+	path: String,
+	asset_type: AssetType,
+	status: AssetStatus,
+}
+
+// Subgraph of the task / asset DAG selected for output
+struct Graph {
+	tasks: Vec<TaskNode>,
+	assets: Vec<AssetNode>,
+	edges: Vec<(String, String)>,
+}
+
+fn task_colour(st: TaskStatus) -> &'static str {
+	match st {
+		TaskStatus::Complete => "palegreen",
+		TaskStatus::Ready => "khaki",
+		TaskStatus::Running => "lightskyblue",
+		TaskStatus::Waiting => "lightgrey",
+	}
+}
+
+fn asset_colour(st: AssetStatus) -> &'static str {
+	match st {
+		AssetStatus::Present => "palegreen",
+		AssetStatus::Outdated => "orange",
+		AssetStatus::Absent => "white",
+		AssetStatus::Incomplete => "lightskyblue",
+		AssetStatus::Deleted => "lightgrey",
+	}
+}
+
+fn make_graph(gem_bs: &GemBS, barcodes: Option<HashSet<&String>>, commands: Option<&Vec<String>>, tasks_only: bool) -> Graph {
+	let tlist = gem_bs.get_tasks();
+	let selected: Vec<_> = tlist.iter().filter(|t| t.command() != Command::MergeCallJsons)
+		.filter(|t| barcodes.as_ref().map(|h| t.barcode().map(|b| h.contains(b)).unwrap_or(false)).unwrap_or(true))
+		.filter(|t| commands.map(|v| v.iter().any(|c| *c == format!("{:#}", t.command()))).unwrap_or(true))
+		.collect();
+	let task_set: HashSet<usize> = selected.iter().map(|t| t.idx()).collect();
+	let mut asset_set = HashSet::new();
+	let mut graph = Graph{tasks: Vec::new(), assets: Vec::new(), edges: Vec::new()};
+	for task in selected.iter() {
+		let status = task.status().unwrap_or_else(|| gem_bs.task_status(task));
+		graph.tasks.push(TaskNode{idx: task.idx(), id: task.id().to_owned(), command: format!("{:#}", task.command()), status});
+		if tasks_only {
+			for p in task.parents().iter().filter(|p| task_set.contains(p)) { graph.edges.push((format!("t{}", p), format!("t{}", task.idx()))) }
+		} else {
+			for a in task.inputs().chain(task.outputs()) { asset_set.insert(*a); }
+			for a in task.inputs() { graph.edges.push((format!("a{}", a), format!("t{}", task.idx()))) }
+			for a in task.outputs() { graph.edges.push((format!("t{}", task.idx()), format!("a{}", a))) }
+		}
+	}
+	let mut alist: Vec<_> = asset_set.drain().collect();
+	alist.sort_unstable();
+	for asset in alist.iter().filter_map(|x| gem_bs.get_asset(*x)) {
+		if asset.asset_type() == AssetType::Log { continue }
+		graph.assets.push(AssetNode{idx: asset.idx(), id: asset.id().to_owned(), path: asset.path().to_string_lossy().to_string(),
+			asset_type: asset.asset_type(), status: asset.status()});
+	}
+	// Remove edges to log files
+	let nodes: HashSet<String> = graph.assets.iter().map(|a| format!("a{}", a.idx)).chain(graph.tasks.iter().map(|t| format!("t{}", t.idx))).collect();
+	graph.edges.retain(|(a, b)| nodes.contains(a) && nodes.contains(b));
+	graph
+}
+
+fn xml_escape(s: &str) -> String {
+	s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn dot_escape(s: &str) -> String {
+	s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn write_dot(w: &mut dyn Write, g: &Graph) -> io::Result<()> {
+	writeln!(w, "digraph gemBS {{\n  rankdir=LR;\n  node [style=filled, fontname=\"Helvetica\"];")?;
+	for t in g.tasks.iter() {
+		writeln!(w, "  t{} [shape=box, fillcolor={}, label=\"{}\\n{} ({:?})\"];", t.idx, task_colour(t.status), dot_escape(&t.id), dot_escape(&t.command), t.status)?;
+	}
+	for a in g.assets.iter() {
+		let shape = if a.asset_type == AssetType::Supplied { "note" } else { "ellipse" };
+		writeln!(w, "  a{} [shape={}, fillcolor={}, label=\"{}\\n{}\", tooltip=\"{}\"];", a.idx, shape, asset_colour(a.status), dot_escape(&a.id), a.status, dot_escape(&a.path))?;
+	}
+	for (x, y) in g.edges.iter() { writeln!(w, "  {} -> {};", x, y)? }
+	writeln!(w, "}}")
+}
+
+fn write_graphml(w: &mut dyn Write, g: &Graph) -> io::Result<()> {
+	writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+	writeln!(w, "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">")?;
+	for (k, n) in [("d0", "kind"), ("d1", "label"), ("d2", "command"), ("d3", "status"), ("d4", "asset_type"), ("d5", "path")].iter() {
+		writeln!(w, "  <key id=\"{}\" for=\"node\" attr.name=\"{}\" attr.type=\"string\"/>", k, n)?;
+	}
+	writeln!(w, "  <graph id=\"gemBS\" edgedefault=\"directed\">")?;
+	for t in g.tasks.iter() {
+		writeln!(w, "    <node id=\"t{}\"><data key=\"d0\">task</data><data key=\"d1\">{}</data><data key=\"d2\">{}</data><data key=\"d3\">{:?}</data></node>",
+			t.idx, xml_escape(&t.id), xml_escape(&t.command), t.status)?;
+	}
+	for a in g.assets.iter() {
+		writeln!(w, "    <node id=\"a{}\"><data key=\"d0\">asset</data><data key=\"d1\">{}</data><data key=\"d3\">{}</data><data key=\"d4\">{:?}</data><data key=\"d5\">{}</data></node>",
+			a.idx, xml_escape(&a.id), a.status, a.asset_type, xml_escape(&a.path))?;
+	}
+	for (i, (x, y)) in g.edges.iter().enumerate() { writeln!(w, "    <edge id=\"e{}\" source=\"{}\" target=\"{}\"/>", i, x, y)? }
+	writeln!(w, "  </graph>\n</graphml>")
+}
+
+fn write_mermaid(w: &mut dyn Write, g: &Graph) -> io::Result<()> {
+	let esc = |s: &str| s.replace('"', "#quot;");
+	writeln!(w, "flowchart LR")?;
+	for t in g.tasks.iter() {
+		writeln!(w, "  t{}[\"{}<br/>{} ({:?})\"]:::{}", t.idx, esc(&t.id), esc(&t.command), t.status, task_colour(t.status))?;
+	}
+	for a in g.assets.iter() {
+		writeln!(w, "  a{}([\"{}<br/>{}\"]):::{}", a.idx, esc(&a.id), a.status, asset_colour(a.status))?;
+	}
+	for (x, y) in g.edges.iter() { writeln!(w, "  {} --> {}", x, y)? }
+	for c in ["palegreen", "khaki", "lightskyblue", "lightgrey", "orange", "white"].iter() {
+		writeln!(w, "  classDef {} fill:{}", c, c)?;
+	}
+	Ok(())
+}
+
+pub fn graph_command(m: &ArgMatches, gem_bs: &mut GemBS) -> Result<(), String> {
+	gem_bs.setup_fs(false)?;
+	// Get config file from disk
+	gem_bs.read_config()?;
+
+	let options = handle_options(m, gem_bs, Section::Default);
+	let format = match options.get("_graph_format") {
+		Some(DataValue::String(s)) => match s.to_lowercase().as_str() {
+			"graphml" => GraphFormat::GraphML,
+			"mermaid" => GraphFormat::Mermaid,
+			_ => GraphFormat::Dot,
+		},
+		_ => GraphFormat::Dot,
+	};
+	let task_path = gem_bs.get_task_file_path();
+	{
+		let flock = utils::wait_for_lock(gem_bs.get_signal_clone(), &task_path)?;
+		gem_bs.setup_assets_and_tasks(&flock)?;
+	}
+	let barcodes = if options.contains_key("_barcode") || options.contains_key("_sample") {
+		Some(get_barcode_list(gem_bs, &options)?.drain(..).collect::<HashSet<_>>())
+	} else { None };
+	let commands = if let Some(DataValue::StringVec(v)) = options.get("_task_command") { Some(v) } else { None };
+	let graph = make_graph(gem_bs, barcodes, commands, options.contains_key("_tasks_only"));
+	let mut wrt: Box<dyn Write> = match options.get("_graph_output") {
+		Some(DataValue::String(s)) => Box::new(BufWriter::new(fs::File::create(Path::new(s)).map_err(|e| format!("Couldn't open output file {}: {}", s, e))?)),
+		_ => Box::new(BufWriter::new(io::stdout())),
+	};
+	match format {
+		GraphFormat::Dot => write_dot(&mut wrt, &graph),
+		GraphFormat::GraphML => write_graphml(&mut wrt, &graph),
+		GraphFormat::Mermaid => write_mermaid(&mut wrt, &graph),
+	}.and_then(|_| wrt.flush()).map_err(|e| format!("Error writing graph: {}", e))
+}
@@ -0,0 +1,60 @@
+use clap::ArgMatches;
+use crate::cli::utils::handle_options;
+use crate::config::GemBS;
+use crate::common::defs::{Section, DataValue};
+use crate::common::history::{TaskRecord, read_history, format_epoch, format_duration};
+
+fn format_mem(x: Option<usize>) -> String {
+	match x {
+		Some(m) if m >= 1 << 30 => format!("{:.1}G", (m as f64) / 1073741824.0),
+		Some(m) => format!("{:.1}M", (m as f64) / 1048576.0),
+		None => "-".to_string(),
+	}
+}
+
+fn print_record(rec: &TaskRecord, stages: bool) {
+	println!("{}  {:>10}  {:<6}  {:>3}  {:<30}  {:<20}  {:>8}  {:>10}", format_epoch(rec.start()), format_duration(rec.elapsed()),
+		if rec.success() { "OK" } else { "FAILED" }, rec.attempt(), rec.id(), rec.command(), format_mem(rec.max_rss()), format_duration(rec.cpu_time()));
+	if stages {
+		println!("\tHost: {}", rec.host());
+		if !rec.args().is_empty() { println!("\tArgs: {}", rec.args()); }
+		if let Some(e) = rec.error() { println!("\tError: {}", e); }
+		for st in rec.stages() {
+			let status = match (st.exit_status, st.signal) {
+				(Some(x), _) => format!("exit {}", x),
+				(None, Some(s)) => format!("signal {}", s),
+				_ => "-".to_string(),
+			};
+			println!("\t{:<40}  {:>10}  mem {:>8}  user {:>10}  sys {:>10}", st.command, status, format_mem(st.max_rss),
+				format_duration(st.user_time), format_duration(st.sys_time));
+		}
+	}
+}
+
+pub fn history_command(m: &ArgMatches, gem_bs: &mut GemBS) -> Result<(), String> {
+	gem_bs.setup_fs(false)?;
+	let options = handle_options(m, gem_bs, Section::Default);
+	let records = read_history(&gem_bs.get_history_file_path())?;
+	let task_ids = if let Some(DataValue::StringVec(v)) = options.get("_task_id") { Some(v) } else { None };
+	let commands = if let Some(DataValue::StringVec(v)) = options.get("_task_command") { Some(v) } else { None };
+	let failed = options.contains_key("_failed");
+	let mut selected: Vec<_> = records.iter().filter(|r| {
+		task_ids.map(|v| v.iter().any(|x| x == r.id())).unwrap_or(true) &&
+		commands.map(|v| v.iter().any(|x| x == r.command())).unwrap_or(true) &&
+		!(failed && r.success())
+	}).collect();
+	if let Some(DataValue::Int(n)) = options.get("_last") {
+		let n = (*n).max(0) as usize;
+		if selected.len() > n { selected.drain(..selected.len() - n); }
+	}
+	if options.contains_key("_history_json") {
+		for rec in selected.iter() {
+			println!("{}", serde_json::to_string(rec).map_err(|e| format!("Error serializing task record: {}", e))?);
+		}
+	} else if !selected.is_empty() {
+		println!("{:<19}  {:>10}  {:<6}  {:>3}  {:<30}  {:<20}  {:>8}  {:>10}", "Start (UTC)", "Elapsed", "Status", "Try", "Task", "Command", "Max RSS", "CPU time");
+		let stages = options.contains_key("_stages");
+		for rec in selected.iter() { print_record(rec, stages) }
+	}
+	Ok(())
+}
@@ -34,6 +34,6 @@ pub fn index_command(m: &ArgMatches, gem_bs: &mut GemBS) -> Result<(), String> {
 	}
 	let com_set = [Command::Index];
 	let task_list = gem_bs.get_required_tasks_from_asset_list(&asset_ids, &com_set);
-	if gem_bs.execute_flag() { scheduler::schedule_jobs(gem_bs, &options, &task_list, &asset_ids, &com_set, flock) }	
+	if gem_bs.execute_flag() { scheduler::schedule_jobs(gem_bs, &options, &task_list, flock) }	
 	else { dry_run::handle_nonexec(gem_bs, &options, &task_list) }
 }
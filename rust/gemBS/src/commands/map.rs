@@ -68,7 +68,7 @@ fn gen_map_command(gem_bs: &mut GemBS, options: &HashMap<&'static str, DataValue
 	let asset_ids: Vec<_> = assets.iter().copied().collect();
 	let com_set: Vec<_> = coms.iter().copied().collect();
 	let task_list = gem_bs.get_required_tasks_from_asset_list(&asset_ids, &com_set);
-	if gem_bs.execute_flag() { scheduler::schedule_jobs(gem_bs, &options, &task_list, &asset_ids, &com_set, flock) }
+	if gem_bs.execute_flag() { scheduler::schedule_jobs(gem_bs, &options, &task_list, flock) }
 	else { dry_run::handle_nonexec(gem_bs, &options, &task_list) }
 }
 
@@ -5,6 +5,7 @@ use std::path::Path;
 
 use crate::common::defs::*;
 use crate::config::GemBS;
+use utils::chemistry::Chemistry;
 	
 mod lex;
 mod find_var;
@@ -73,11 +74,11 @@ fn make_known_var_list() -> KnownVarList {
 	kv_list.add_known_var("sampling_rate", VarType::Int, vec!(Section::Index));
 	kv_list.add_known_var("min_contig_size", VarType::Int, vec!(Section::Index));
 	kv_list.add_known_var("populate_cache", VarType::Bool, vec!(Section::Index));
-	kv_list.add_known_var("threads", VarType::Int, vec!(Section::Index, Section::DbSnp, Section::Mapping, Section::Calling, Section::Extract, Section::Report, Section::MD5Sum));
-	kv_list.add_known_var("cores", VarType::Int, vec!(Section::Index, Section::DbSnp, Section::Mapping, Section::Calling, Section::Extract, Section::Report, Section::MD5Sum));
-	kv_list.add_known_var("time", VarType::JobLen, vec!(Section::Index, Section::DbSnp, Section::Mapping, Section::Calling, Section::Extract, Section::Report, Section::MD5Sum));
-	kv_list.add_known_var("memory", VarType::MemSize, vec!(Section::Index, Section::DbSnp, Section::Mapping, Section::Calling, Section::Extract, Section::Report, Section::MD5Sum));
-	kv_list.add_known_var("keep_logs", VarType::Bool, vec!(Section::Index, Section::DbSnp, Section::Mapping, Section::Calling, Section::Extract, Section::Report, Section::MD5Sum));
+	kv_list.add_known_var("threads", VarType::Int, vec!(Section::Index, Section::DbSnp, Section::Mapping, Section::Calling, Section::Extract, Section::Dmr, Section::Report, Section::MD5Sum));
+	kv_list.add_known_var("cores", VarType::Int, vec!(Section::Index, Section::DbSnp, Section::Mapping, Section::Calling, Section::Extract, Section::Dmr, Section::Report, Section::MD5Sum));
+	kv_list.add_known_var("time", VarType::JobLen, vec!(Section::Index, Section::DbSnp, Section::Mapping, Section::Calling, Section::Extract, Section::Dmr, Section::Report, Section::MD5Sum));
+	kv_list.add_known_var("memory", VarType::MemSize, vec!(Section::Index, Section::DbSnp, Section::Mapping, Section::Calling, Section::Extract, Section::Dmr, Section::Report, Section::MD5Sum));
+	kv_list.add_known_var("keep_logs", VarType::Bool, vec!(Section::Index, Section::DbSnp, Section::Mapping, Section::Calling, Section::Extract, Section::Dmr, Section::Report, Section::MD5Sum));
 	kv_list.add_known_var("merge_threads", VarType::Int, vec!(Section::Mapping, Section::Calling));
 	kv_list.add_known_var("merge_cores", VarType::Int, vec!(Section::Mapping, Section::Calling));
 	kv_list.add_known_var("merge_memory", VarType::MemSize, vec!(Section::Mapping, Section::Calling));
@@ -98,7 +99,7 @@ fn make_known_var_list() -> KnownVarList {
 	kv_list.add_known_var("sequence_dir", VarType::String, vec!(Section::Mapping));
 	kv_list.add_known_var("benchmark_mode", VarType::Bool, vec!(Section::Mapping, Section::Calling));
 	kv_list.add_known_var("make_cram", VarType::Bool, vec!(Section::Mapping));
-	kv_list.add_known_var("jobs", VarType::Int, vec!(Section::Index, Section::DbSnp, Section::Mapping, Section::Calling, Section::Extract, Section::Report));
+	kv_list.add_known_var("jobs", VarType::Int, vec!(Section::Index, Section::DbSnp, Section::Mapping, Section::Calling, Section::Extract, Section::Dmr, Section::Report));
 	kv_list.add_known_var("bcf_dir", VarType::String, vec!(Section::Calling));
 	kv_list.add_known_var("mapq_threshold", VarType::Int, vec!(Section::Calling, Section::Report));
 	kv_list.add_known_var("qual_threshold", VarType::Int, vec!(Section::Calling));
@@ -111,7 +112,11 @@ fn make_known_var_list() -> KnownVarList {
 	kv_list.add_known_var("call_threads", VarType::Int, vec!(Section::Calling));
 	kv_list.add_known_var("remove_individual_bcfs", VarType::Bool, vec!(Section::Calling));
 	kv_list.add_known_var("haploid", VarType::Bool, vec!(Section::Calling));
-	kv_list.add_known_var("reference_bias", VarType::Float, vec!(Section::Calling, Section::Extract));
+	kv_list.add_known_var("rrbs", VarType::Bool, vec!(Section::Calling));
+	kv_list.add_known_var("dbsnp_priors", VarType::Bool, vec!(Section::Calling));
+	kv_list.add_known_var("ploidy_map", VarType::String, vec!(Section::Calling));
+	kv_list.add_known_var("chemistry", VarType::String, vec!(Section::Calling));
+	kv_list.add_known_var("reference_bias", VarType::Float, vec!(Section::Calling, Section::Extract, Section::Dmr));
 	kv_list.add_known_var("over_conversion_rate", VarType::Float, vec!(Section::Calling));
 	kv_list.add_known_var("under_conversion_rate", VarType::Float, vec!(Section::Calling));
 	kv_list.add_known_var("conversion", VarType::FloatVec, vec!(Section::Calling));
@@ -128,13 +133,28 @@ fn make_known_var_list() -> KnownVarList {
 	kv_list.add_known_var("make_cpg", VarType::Bool, vec!(Section::Extract));
 	kv_list.add_known_var("make_non_cpg", VarType::Bool, vec!(Section::Extract));
 	kv_list.add_known_var("make_bedmethyl", VarType::Bool, vec!(Section::Extract));
+	kv_list.add_known_var("make_segmentation", VarType::Bool, vec!(Section::Extract));
 	kv_list.add_known_var("make_snps", VarType::Bool, vec!(Section::Extract));
 	kv_list.add_known_var("bigwig_strand_specific", VarType::Bool, vec!(Section::Extract));
 	kv_list.add_known_var("strand_specific", VarType::Bool, vec!(Section::Extract));
+	kv_list.add_known_var("dmr_dir", VarType::String, vec!(Section::Dmr));
+	kv_list.add_known_var("dmr_groups", VarType::StringVec, vec!(Section::Dmr));
+	kv_list.add_known_var("dmr_test", VarType::String, vec!(Section::Dmr));
+	kv_list.add_known_var("dmr_min_cov", VarType::Int, vec!(Section::Dmr));
+	kv_list.add_known_var("dmr_min_samples", VarType::Int, vec!(Section::Dmr));
+	kv_list.add_known_var("dmr_fdr", VarType::Float, vec!(Section::Dmr));
+	kv_list.add_known_var("dmr_min_diff", VarType::Float, vec!(Section::Dmr));
+	kv_list.add_known_var("dmr_max_gap", VarType::Int, vec!(Section::Dmr));
+	kv_list.add_known_var("dmr_min_cpg", VarType::Int, vec!(Section::Dmr));
 	kv_list.add_known_var("project", VarType::String, vec!(Section::Report));
 	kv_list.add_known_var("report_dir", VarType::String, vec!(Section::Report));
 	kv_list.add_known_var("paper_size", VarType::PageSize, vec!(Section::Report));
 	kv_list.add_known_var("pdf", VarType::Bool, vec!(Section::Report));
+	kv_list.add_known_var("max_attempts", VarType::Int, vec!(Section::Index, Section::DbSnp, Section::Mapping, Section::Calling, Section::Extract, Section::Dmr, Section::Report, Section::MD5Sum));
+	kv_list.add_known_var("retry_memory_factor", VarType::Float, vec!(Section::Index, Section::DbSnp, Section::Mapping, Section::Calling, Section::Extract, Section::Dmr, Section::Report, Section::MD5Sum));
+	kv_list.add_known_var("retry_time_factor", VarType::Float, vec!(Section::Index, Section::DbSnp, Section::Mapping, Section::Calling, Section::Extract, Section::Dmr, Section::Report, Section::MD5Sum));
+	kv_list.add_known_var("sge_parallel_env", VarType::String, vec!());
+	kv_list.add_known_var("hash_check", VarType::Bool, vec!());
 	kv_list
 }
 
@@ -374,10 +394,20 @@ impl PrepConfig {
 	}	
 }
 
+// Check the conversion chemistry (if set), storing it in normalized form
+fn check_chemistry(gem_bs: &mut GemBS) -> Result<(), String> {
+	if let Some(s) = gem_bs.get_config_str(Section::Calling, "chemistry").map(|s| s.to_owned()) {
+		let chemistry = Chemistry::from_str(&s).map_err(|_| format!("Unknown chemistry '{}' in config file: should be bisulfite, em-seq or taps", s))?;
+		gem_bs.set_config(Section::Calling, "chemistry", DataValue::String(chemistry.to_string()));
+	}
+	Ok(())
+}
+
 pub fn process_config_file(file_name: &str, gem_bs: &mut GemBS) -> Result<(), String> {
 	let mut prep_config = PrepConfig::new(&gem_bs.get_config_script_path());
 	prep_config.start_parse(file_name)?;
 	prep_config.parse(gem_bs)?;
+	check_chemistry(gem_bs)?;
 	gembs_json::check_gembs_json(gem_bs, &prep_config.kv_list)?;
 	Ok(())
 }
@@ -34,6 +34,8 @@ impl SampleData {
 				"sample_name" => if let Some(s) = val.as_str() { Some((Metadata::SampleName, DataValue::String(s.to_string()))) } else { None },
 				"platform" => if let Some(s) = val.as_str() { Some((Metadata::Platform, DataValue::String(s.to_string()))) } else { None },
 				"centre" => if let Some(s) = val.as_str() { Some((Metadata::Centre, DataValue::String(s.to_string()))) } else { None },
+				"sex" => if let Some(s) = val.as_str() { Some((Metadata::Sex, DataValue::String(s.to_string()))) } else { None },
+				"group" => val.as_str().map(|s| (Metadata::Group, DataValue::String(s.to_string()))),
 				"application" => if let Some(s) = val.as_str() { Some((Metadata::Bisulfite, DataValue::Bool(BISULFITE_APPS.contains(&(s.to_lowercase().as_str()))))) } else { None },
 				_ => None,
 			};
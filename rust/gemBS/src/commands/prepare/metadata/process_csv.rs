@@ -72,6 +72,8 @@ lazy_static! {
         m.insert(Metadata::Centre, VarType::String);
         m.insert(Metadata::Platform, VarType::String);
         m.insert(Metadata::Bisulfite, VarType::Bool);
+        m.insert(Metadata::Sex, VarType::String);
+        m.insert(Metadata::Group, VarType::String);
         m
     };
 }
@@ -61,6 +61,6 @@ pub fn report_command(m: &ArgMatches, gem_bs: &mut GemBS) -> Result<(), String>
 	}
 	let assets: Vec<usize> = asset_set.into_iter().collect();
 	let task_list = gem_bs.get_required_tasks_from_asset_list(&assets, &com_set);
-	if gem_bs.execute_flag() { scheduler::schedule_jobs(gem_bs, &options, &task_list, &assets, &com_set, flock) }		
+	if gem_bs.execute_flag() { scheduler::schedule_jobs(gem_bs, &options, &task_list, flock) }		
 	else { dry_run::handle_nonexec(gem_bs, &options, &task_list) }
 }
@@ -9,7 +9,7 @@ use plotters::prelude::*;
 
 use crate::common::utils::check_signal;
 use utils::compress;
-use crate::common::json_call_stats::{CallJson, FSReadLevelType, FSBaseLevelType, FSCounts, Counts, QCCounts, MutCounts};
+use crate::common::json_call_stats::{CallJson, FSReadLevelType, FSBaseLevelType, FSCounts, Counts, QCCounts, MutCounts, RrbsStats, SegJson, SampleSegSummary, SegTypeSummary};
 use crate::scheduler::report::CallJsonFiles;
 use super::report_utils::*;
 use super::make_map_report;
@@ -418,6 +418,54 @@ fn make_base_level_latex_tab(json: &CallJson) -> LatexContent {
 	LatexContent::Table(table)
 }
 
+fn make_rrbs_tab<T: Table>(table: &mut T, rrbs: &RrbsStats) {
+	table.add_header(vec!("MspI Fragments", "#", "%"));
+	let tot = rrbs.mspi_reads + rrbs.non_mspi_reads;
+	let f = |name: &str, x| vec!(name.to_owned(), format!("{}", x), format!("{:.2}", pct(x, tot)));
+	table.add_row(f("Reads", tot));
+	table.add_row(f("Reads at MspI site", rrbs.mspi_reads));
+	table.add_row(f("Reads not at MspI site", rrbs.non_mspi_reads));
+	table.add_row(f("End repair trimmed reads", rrbs.end_repair_trimmed_reads));
+	table.add_row(Vec::new());
+	table.add_row(vec!("Fragment ends covered".to_owned(), format!("{}", rrbs.mspi_fragment_ends), String::new()));
+	table.add_row(vec!("Median reads per fragment end".to_owned(), format!("{}", rrbs.median_coverage()), String::new()));
+}
+
+fn make_rrbs_table(rrbs: &RrbsStats) -> Content {
+	let mut table = HtmlTable::new("hor-zebra");
+	make_rrbs_tab(&mut table, rrbs);
+	Content::Table(table)
+}
+
+fn make_rrbs_latex_tab(rrbs: &RrbsStats) -> LatexContent {
+	let mut table = LatexTable::new();
+	make_rrbs_tab(&mut table, rrbs);
+	LatexContent::Table(table)
+}
+
+fn make_seg_tab<T: Table>(table: &mut T, seg: &SampleSegSummary) {
+	table.add_header(vec!("Segment Type", "#", "Total Length (bp)", "CpGs"));
+	let f = |name: &str, st: &SegTypeSummary| vec!(name.to_owned(), format!("{}", st.count), format!("{}", st.total_length), format!("{}", st.cpgs));
+	table.add_row(f("UMR", &seg.umr));
+	table.add_row(f("LMR", &seg.lmr));
+	table.add_row(f("PMD", &seg.pmd));
+	table.add_row(Vec::new());
+	let min_cpgs = seg.min_cpgs.map(|x| format!("{}", x)).unwrap_or_else(|| "-".to_string());
+	table.add_row(vec!("Minimum CpGs for UMR/LMR".to_owned(), min_cpgs, String::new(), String::new()));
+}
+
+fn make_seg_table(seg: &SampleSegSummary) -> Content {
+	let mut table = HtmlTable::new("hor-zebra");
+	make_seg_tab(&mut table, seg);
+	Content::Table(table)
+}
+
+fn make_seg_latex_tab(seg: &SampleSegSummary) -> LatexContent {
+	let mut table = LatexTable::new();
+	make_seg_tab(&mut table, seg);
+	LatexContent::Table(table)
+}
+
 fn make_variant_count_tab<T: Table>(table: &mut T, json: &CallJson) {
 	table.add_header(vec!("Type", "Total", "Passed", "% Passed"));
 	let bs = json.basic_stats();
@@ -661,6 +709,11 @@ fn create_mapping_report_body(project: &str, bc: &str, dir: &Path, json: &CallJs
 	body.push_element(make_section("Base Level Counts"));
 	body.push(make_base_level_table(json));
 	body.push_element(HtmlElement::new("BR><BR><BR", None, false));
+	if let Some(rrbs) = json.rrbs() {
+		body.push_element(make_section("RRBS MspI Fragment Counts"));
+		body.push(make_rrbs_table(rrbs));
+		body.push_element(HtmlElement::new("BR><BR><BR", None, false));
+	}
 	body.push_element(make_section("Coverage and Quality"));
 	let get_path = |name: &str| {
 		let mut tp = img_dir.clone();
@@ -742,7 +795,7 @@ fn create_variant_report_body(project: &str, bc: &str, dir: &Path, json: &CallJs
 	Ok(body)
 }
 
-fn create_meth_report_body(project: &str, bc: &str, dir: &Path, json: &CallJson, msumm: &mut MethSummary) -> HtmlElement {
+fn create_meth_report_body(project: &str, bc: &str, dir: &Path, json: &CallJson, seg: Option<&SampleSegSummary>, msumm: &mut MethSummary) -> HtmlElement {
 	let mut img_dir = dir.to_owned();
 	img_dir.push("images");
 	let mut body = new_body(project, bc, "methylation");
@@ -786,6 +839,11 @@ fn create_meth_report_body(project: &str, bc: &str, dir: &Path, json: &CallJson,
 			break;
 		}
 	}
+	if let Some(s) = seg {
+		body.push_element(HtmlElement::new("BR><BR><BR", None, false));
+		body.push_element(make_section("Methylome Segmentation"));
+		body.push(make_seg_table(s));
+	}
 	body
 }
 
@@ -817,6 +875,10 @@ fn create_mapping_latex_section(bc: &str, json: &CallJson) -> LatexSection {
 	sec.push(make_read_level_latex_tab(json));
 	sec.push_str("\\subsubsection{{Base Level Counts}}");
 	sec.push(make_base_level_latex_tab(json));
+	if let Some(rrbs) = json.rrbs() {
+		sec.push_str("\\subsubsection{{RRBS MspI Fragment Counts}}");
+		sec.push(make_rrbs_latex_tab(rrbs));
+	}
 	sec.push_str("\\subsubsection{{Coverage Distribution}}");
 	sec.push_string(format!("\\includegraphics[width=12cm]{{{}}}", img_dir.join(format!("{}_coverage_all", bc).as_str()).display()));
 	sec.push_str("\\subsubsection{{Quality Distribution}}");
@@ -864,7 +926,7 @@ fn create_variant_latex_section(bc: &str, json: &CallJson) -> Result<LatexSectio
 	Ok(sec)
 }
 
-fn create_meth_latex_section(bc: &str, json: &CallJson) -> LatexSection {
+fn create_meth_latex_section(bc: &str, json: &CallJson, seg: Option<&SampleSegSummary>) -> LatexSection {
 	info!("Create latex methylation report for {}", bc);
 	let mut img_dir = PathBuf::from_str(bc).expect("Couldn't get Path from barcode");
 	img_dir.push("images");
@@ -887,6 +949,10 @@ fn create_meth_latex_section(bc: &str, json: &CallJson) -> LatexSection {
 	set_img("methylation_levels", &mut sec);
 	sec.push_str("\\subsubsection{CpG Methylation Profiles}");
 	sec.push(make_cpg_meth_profile_latex_tab(json));
+	if let Some(s) = seg {
+		sec.push_str("\\subsubsection{Methylome Segmentation}");
+		sec.push(make_seg_latex_tab(s));
+	}
 	sec
 }
 
@@ -940,15 +1006,31 @@ fn create_variant_report(bc: &str, dir: &Path, project: &str, call_json: &CallJs
 	} else { Err("Couldn't obtain lock on latex doc".to_string()) }
 }
 
-fn create_meth_report(bc: &str, dir: &Path, project: &str, call_json: &CallJson, summary: Arc<Mutex<HashMap<String, CallSummary>>>, latex_doc: Arc<Mutex<LatexBare>>) -> Result<(), String> {
+// The segmentation summary is only present if segmentation was requested and the extract step has been run
+fn load_seg_json(path: Option<&Path>) -> Result<Option<SegJson>, String> {
+	match path {
+		Some(p) if p.exists() => {
+			let rdr = compress::open_bufreader(p).map_err(|e| format!("{}", e))?;
+			let seg = SegJson::from_reader(rdr)?;
+			debug!("Read in segmentation JSON file {}", p.to_string_lossy());
+			Ok(Some(seg))
+		},
+		_ => Ok(None),
+	}
+}
+
+fn create_meth_report(bc: &str, dir: &Path, project: &str, call_json: &CallJson, seg_json_file: Option<&Path>, summary: Arc<Mutex<HashMap<String, CallSummary>>>, latex_doc: Arc<Mutex<LatexBare>>) -> Result<(), String> {
 	debug!("Create methylation report for {}", bc);
 	let path: PathBuf = [dir, Path::new(format!("{}_methylation.html", bc).as_str())].iter().collect();
 	let mut html = new_page(&path)?;
 	let mut meth_summ = MethSummary::new();
-	html.push_element(create_meth_report_body(project, bc, dir, call_json, &mut meth_summ));	
+	// There is one sample per BCF file, so the summary will only have one entry
+	let seg_json = load_seg_json(seg_json_file)?;
+	let seg = seg_json.as_ref().and_then(|s| s.segmentation.first());
+	html.push_element(create_meth_report_body(project, bc, dir, call_json, seg, &mut meth_summ));	
 	let mut shash = summary.lock().expect("Couldn't lock CallSummary");
 	shash.get_mut(&bc.to_owned()).expect("Couldn't find CallSummary for sample").meth = Some(meth_summ);
-	let sec = create_meth_latex_section(bc, call_json);
+	let sec = create_meth_latex_section(bc, call_json, seg);
 	if let Ok(mut ldoc) = latex_doc.lock() { 
 		let sa = get_section_array_for_bc(&mut ldoc, bc)?;
 		sa.push(sec);
@@ -1074,7 +1156,7 @@ fn make_call_job(bc: &str, bc_dir: &Path, project: &str, job: MakeCallJob) -> Re
 		},
 		CallJob::MappingReport => create_mapping_report(bc, bc_dir, project, cj, job.summary, job.latex_doc),
 		CallJob::VariantReport => create_variant_report(bc, bc_dir, project, cj, job.summary, job.latex_doc),
-		CallJob::MethylationReport => create_meth_report(bc, bc_dir, project, cj, job.summary, job.latex_doc),
+		CallJob::MethylationReport => create_meth_report(bc, bc_dir, project, cj, job.seg_json_file.as_deref(), job.summary, job.latex_doc),
 	}
 }
 
@@ -1123,7 +1205,7 @@ fn prepare_jobs(svec: &[CallJsonFiles], project: &str, summary: Arc<Mutex<HashMa
 		let ld_json_ix = v.len();
 		v.push(ReportJob::new(&cjson.barcode, project, &cjson.bc_dir, RepJob::CallJson(load_json)));
 		for job_type in CallJob::iter() {
-			let mk_graph = MakeCallJob{job_type, depend: ld_json_ix, call_json: call_json.clone(), summary: summary.clone(), latex_doc: latex_doc.clone()};
+			let mk_graph = MakeCallJob{job_type, depend: ld_json_ix, call_json: call_json.clone(), seg_json_file: cjson.seg_json_file.clone(), summary: summary.clone(), latex_doc: latex_doc.clone()};
 			v.push(ReportJob::new(&cjson.barcode, project, &cjson.bc_dir, RepJob::CallJob(mk_graph)));
 		}
 	}
@@ -110,6 +110,7 @@ pub struct MakeCallJob {
 	pub job_type: CallJob,
 	pub depend: usize,
 	pub call_json: Arc<RwLock<Option<CallJson>>>,	
+	pub seg_json_file: Option<PathBuf>,
 	pub summary: Arc<Mutex<HashMap<String, CallSummary>>>,
 	pub latex_doc: Arc<Mutex<LatexBare>>,
 }
@@ -0,0 +1,170 @@
+use std::collections::{HashMap, BTreeMap};
+use std::path::Path;
+use serde::Serialize;
+
+use clap::ArgMatches;
+use crate::cli::utils::handle_options;
+use crate::config::GemBS;
+use crate::common::defs::{Section, Command};
+use crate::common::tasks::TaskStatus;
+use crate::common::assets::{Asset, AssetStatus, GetAsset};
+use crate::common::utils;
+
+// Per barcode stages in pipeline order
+const STAGES: [Command; 8] = [Command::Map, Command::MergeBams, Command::MD5SumMap, Command::Call,
+	Command::MergeBcfs, Command::IndexBcf, Command::MD5SumCall, Command::Extract];
+
+#[derive(Serialize, Default, Clone, Copy)]
+struct StageCount {
+	complete: usize,
+	ready: usize,
+	running: usize,
+	waiting: usize,
+}
+
+impl StageCount {
+	fn add(&mut self, st: TaskStatus) {
+		match st {
+			TaskStatus::Complete => self.complete += 1,
+			TaskStatus::Ready => self.ready += 1,
+			TaskStatus::Running => self.running += 1,
+			TaskStatus::Waiting => self.waiting += 1,
+		}
+	}
+	fn total(&self) -> usize { self.complete + self.ready + self.running + self.waiting }
+}
+
+#[derive(Serialize)]
+struct OutdatedAsset<'a> {
+	path: &'a Path,
+	newer: Option<&'a Path>, // Ancestor with a more recent modification time
+}
+
+struct SampleStatus<'a> {
+	barcode: String,
+	name: Option<String>,
+	stages: BTreeMap<usize, StageCount>, // Keyed on index in STAGES
+	outdated: Vec<OutdatedAsset<'a>>,
+}
+
+#[derive(Serialize)]
+struct OtherTask<'a> {
+	id: &'a str,
+	command: String,
+	status: TaskStatus,
+}
+
+#[derive(Serialize)]
+struct JsonStatus<'a> {
+	samples: Vec<JsonSample<'a>>,
+	tasks: Vec<OtherTask<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonStage {
+	stage: String,
+	#[serde(flatten)]
+	count: StageCount,
+}
+
+#[derive(Serialize)]
+struct JsonSample<'a> {
+	barcode: &'a str,
+	name: Option<&'a str>,
+	stages: Vec<JsonStage>,
+	outdated: &'a [OutdatedAsset<'a>],
+}
+
+// Find the closest ancestor of an outdated asset that has a more recent modification time
+fn find_newer_ancestor<'a>(gem_bs: &'a GemBS, asset: &'a Asset) -> Option<&'a Asset> {
+	let mt = asset.mod_time()?;
+	let mut cur = asset;
+	loop {
+		let par = cur.parents().iter().filter_map(|x| gem_bs.get_asset(*x))
+			.find(|p| p.mod_time_ances().map(|t| t > mt).unwrap_or(false))?;
+		if par.mod_time().map(|t| t > mt).unwrap_or(false) { return Some(par) }
+		cur = par;
+	}
+}
+
+pub fn status_command(m: &ArgMatches, gem_bs: &mut GemBS) -> Result<(), String> {
+	gem_bs.setup_fs(false)?;
+	// Get config file from disk
+	gem_bs.read_config()?;
+
+	let options = handle_options(m, gem_bs, Section::Default);
+	let task_path = gem_bs.get_task_file_path();
+	{
+		let flock = utils::wait_for_lock(gem_bs.get_signal_clone(), &task_path)?;
+		gem_bs.setup_assets_and_tasks(&flock)?;
+	}
+	let mut samples: Vec<SampleStatus> = gem_bs.get_samples().drain(..).map(|(barcode, name)| SampleStatus{barcode, name, stages: BTreeMap::new(), outdated: Vec::new()}).collect();
+	samples.sort_by(|a, b| a.barcode.cmp(&b.barcode));
+	let bc_hash: HashMap<String, usize> = samples.iter().enumerate().map(|(i, s)| (s.barcode.clone(), i)).collect();
+	let mut other = Vec::new();
+	for task in gem_bs.get_tasks().iter().filter(|t| t.command() != Command::MergeCallJsons) {
+		let status = task.status().unwrap_or_else(|| gem_bs.task_status(task));
+		let stage = STAGES.iter().position(|c| *c == task.command());
+		match (task.barcode().and_then(|b| bc_hash.get(b)), stage) {
+			(Some(ix), Some(st)) => {
+				let sample = &mut samples[*ix];
+				sample.stages.entry(st).or_default().add(status);
+				for asset in task.outputs().filter_map(|x| gem_bs.get_asset(*x)) {
+					if asset.status() == AssetStatus::Outdated {
+						let newer = find_newer_ancestor(gem_bs, asset).map(|a| a.path());
+						sample.outdated.push(OutdatedAsset{path: asset.path(), newer});
+					}
+				}
+			},
+			_ => other.push(OtherTask{id: task.id(), command: format!("{:#}", task.command()), status}),
+		}
+	}
+	if options.contains_key("_status_json") {
+		let jsamples: Vec<_> = samples.iter().map(|s| JsonSample{barcode: &s.barcode, name: s.name.as_deref(),
+			stages: s.stages.iter().map(|(i, c)| JsonStage{stage: format!("{:#}", STAGES[*i]), count: *c}).collect(), outdated: &s.outdated}).collect();
+		let js = JsonStatus{samples: jsamples, tasks: other};
+		println!("{}", serde_json::to_string_pretty(&js).map_err(|e| format!("Error serializing status: {}", e))?);
+		return Ok(())
+	}
+	// Only show stages that are used
+	let used: Vec<usize> = (0..STAGES.len()).filter(|i| samples.iter().any(|s| s.stages.contains_key(i))).collect();
+	let width = samples.iter().map(|s| s.barcode.len() + s.name.as_ref().map(|n| n.len() + 3).unwrap_or(0)).max().unwrap_or(0).max(6);
+	print!("{:<w$}", "Sample", w = width);
+	for i in used.iter() { print!("  {:>14}", format!("{:#}", STAGES[*i])) }
+	println!();
+	for s in samples.iter() {
+		let label = match &s.name {
+			Some(n) => format!("{} ({})", s.barcode, n),
+			None => s.barcode.clone(),
+		};
+		print!("{:<w$}", label, w = width);
+		for i in used.iter() {
+			let cell = match s.stages.get(i) {
+				Some(c) if c.complete == c.total() => "done".to_string(),
+				Some(c) => format!("{}/{}/{}/{}", c.complete, c.ready, c.running, c.waiting),
+				None => "-".to_string(),
+			};
+			print!("  {:>14}", cell);
+		}
+		println!();
+	}
+	println!("\nIncomplete stages are shown as complete/ready/running/waiting task counts");
+	if !other.is_empty() {
+		println!("\nOther tasks:");
+		for t in other.iter() { println!("  {:<30} {:<16} {:?}", t.id, t.command, t.status) }
+	}
+	let mut header = false;
+	for s in samples.iter() {
+		for od in s.outdated.iter() {
+			if !header {
+				println!("\nOutdated files:");
+				header = true;
+			}
+			match od.newer {
+				Some(p) => println!("  {} ({} is newer)", od.path.display(), p.display()),
+				None => println!("  {}", od.path.display()),
+			}
+		}
+	}
+	Ok(())
+}
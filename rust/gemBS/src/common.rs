@@ -1,5 +1,7 @@
 pub mod defs;
 pub mod utils;
+pub mod history;
+pub mod manifest;
 pub mod assets;
 pub mod tasks;
 pub mod dry_run;
@@ -80,7 +80,7 @@ impl Asset {
 		self.creator = Some(idx);
 		pvec.iter().for_each(|x| self.parents.push(*x)); 
 	}
-//	pub fn mod_time(&self) -> Option<SystemTime> { self.mod_time }
+	pub fn mod_time(&self) -> Option<SystemTime> { self.mod_time }
 	pub fn mod_time_ances(&self) -> Option<SystemTime> { self.mod_time_ances }
 	pub fn parents(&self) -> &[usize] { &self.parents }
 	pub fn asset_type(&self) -> AssetType { self.asset_type }
@@ -220,6 +220,54 @@ impl AssetList {
 		}
 	}
 	
+	// As calc_mod_time_ances(), but a derived asset with an older modification time than its ancestors
+	// is not marked as Outdated if check() returns true (i.e., the content of the inputs has not changed).
+	// In that case the modification time of the asset is treated as being that of its most recent ancestor.
+	// This relies on parent assets always having a lower index than their children.
+	pub fn calc_mod_time_ances_checked<F: FnMut(&Asset, &[&Path]) -> bool>(&mut self, mut check: F) {
+		let len = self.assets.len();
+		let mut mtime: Vec<Option<SystemTime>> = vec!(None; len);
+		for ix in 0..len {
+			let asset = &self.assets[ix];
+			if let AssetType::Supplied = asset.asset_type { 
+				mtime[ix] = asset.mod_time;
+				continue;
+			}
+			let latest_time = asset.parents.iter().fold(asset.mod_time, |t, j| match (t, mtime[*j]) {
+				(Some(m), Some(n)) => if n > m { Some(n) } else { Some(m) },
+				(x, None) => x,
+				(None, y) => y,
+			});
+			mtime[ix] = latest_time;
+			if let (AssetStatus::Present, Some(m), Some(n)) = (asset.status, asset.mod_time, latest_time) {
+				if n > m {
+					let ppaths: Vec<&Path> = asset.parents.iter().map(|j| self.assets[*j].path()).collect();
+					let current = check(asset, &ppaths);
+					let asset = &mut self.assets[ix];
+					if current {
+						debug!("Content of inputs for {} unchanged", asset.path.display());
+						asset.mod_time = latest_time;
+					} else { asset.status = AssetStatus::Outdated; }
+				}
+			}
+			self.assets[ix].mod_time_ances = mtime[ix];
+		}
+	}
+
+	// Mark assets as Outdated along with any derived assets that are present
+	pub fn mark_outdated(&mut self, hs: &HashSet<usize>) {
+		let len = self.assets.len();
+		let mut outdated = vec!(false; len);
+		for ix in 0..len {
+			let asset = &mut self.assets[ix];
+			if asset.asset_type == AssetType::Log { continue }
+			if hs.contains(&ix) || asset.parents.iter().any(|j| outdated[*j]) {
+				outdated[ix] = true;
+				if asset.status == AssetStatus::Present { asset.status = AssetStatus::Outdated }
+			}
+		}
+	}
+
 	pub fn check_delete_status(&mut self) {
 		let len = self.assets.len();
 		let mut missing_desc = vec!(false; len);
@@ -9,7 +9,7 @@ use super::latex_utils::PageSize;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Section {
-	Default, Index, DbSnp, Mapping, Calling, Extract, Report, MD5Sum,
+	Default, Index, DbSnp, Mapping, Calling, Extract, Dmr, Report, MD5Sum,
 }
 
 impl FromStr for Section {
@@ -23,6 +23,7 @@ impl FromStr for Section {
             "mapping" => Ok(Section::Mapping),
             "calling" => Ok(Section::Calling),
             "extract" => Ok(Section::Extract),
+            "dmr" => Ok(Section::Dmr),
             "report" => Ok(Section::Report),
             "md5sum" => Ok(Section::MD5Sum),
             _ => Err("no match"),
@@ -32,7 +33,7 @@ impl FromStr for Section {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Metadata { SampleBarcode, SampleName, LibraryBarcode, Dataset, AltDataset, FileType,
-	FilePath, FilePath1, FilePath2, ReadEnd, Description, Centre, Platform,	Bisulfite,
+	FilePath, FilePath1, FilePath2, ReadEnd, Description, Centre, Platform,	Bisulfite, Sex, Group,
 }
 
 impl FromStr for Metadata {
@@ -55,6 +56,8 @@ impl FromStr for Metadata {
 			"centre" | "center" => Ok(Metadata::Centre),
 			"platform" => Ok(Metadata::Platform),
 			"bisulfite" | "bisulphite" | "bis" => Ok(Metadata::Bisulfite),
+			"sex" | "gender" => Ok(Metadata::Sex),
+			"group" | "condition" => Ok(Metadata::Group),
             _ => Err("no match"),
         }
     }
@@ -169,6 +172,11 @@ pub struct JobLen {
 	secs: usize,	
 }
 
+impl JobLen {
+	#[cfg(feature = "slurm")]
+	pub fn secs(&self) -> usize { self.secs }
+}
+
 impl FromStr for JobLen {
     type Err = &'static str;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -218,6 +226,32 @@ impl From<usize> for JobLen {
 }
 
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClusterType { Slurm, Pbs, Sge }
+
+impl FromStr for ClusterType {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {		
+        match s.to_lowercase().as_str() {
+			"slurm" => Ok(ClusterType::Slurm),
+			"pbs" | "torque" | "pbspro" => Ok(ClusterType::Pbs),
+			"sge" | "uge" | "gridengine" => Ok(ClusterType::Sge),
+            _ => Err("ClusterType: no match"),
+        }
+    }
+}
+
+impl fmt::Display for ClusterType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ClusterType::Slurm => write!(f, "slurm"),
+			ClusterType::Pbs => write!(f, "pbs"),
+			ClusterType::Sge => write!(f, "sge"),
+		}
+	}
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum ReadEnd { End1, End2 }
 
@@ -284,7 +318,7 @@ pub enum VarType {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Command {
-	Index, Map, MergeBams, Call, MergeBcfs, Extract, MapReport, CallReport, Report, MD5SumMap, MD5SumCall,	IndexBcf, MergeCallJsons
+	Index, Map, MergeBams, Call, MergeBcfs, Extract, MapReport, CallReport, Report, MD5SumMap, MD5SumCall,	IndexBcf, MergeCallJsons, Dmr
 }
 
 impl fmt::Display for Command {
@@ -304,6 +338,7 @@ impl fmt::Display for Command {
 				Command::MD5SumCall => write!(f, "md5_bcf"),
 				Command::IndexBcf => write!(f, "index_bcf"),
 				Command::MergeCallJsons => write!(f, "merge-call-jsons"),
+				Command::Dmr => write!(f, "dmr"),
 			}
 		} else {
 			match *self {
@@ -320,6 +355,7 @@ impl fmt::Display for Command {
 				Command::MD5SumCall => write!(f, "call --md5"),
 				Command::IndexBcf => write!(f, "call --index"),
 				Command::MergeCallJsons => write!(f, "merge-call-jsons"),
+				Command::Dmr => write!(f, "dmr"),
 			}
 		}
 	}
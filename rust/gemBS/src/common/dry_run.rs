@@ -6,7 +6,7 @@ use crate::common::tasks::{Task, JsonTask};
 use crate::common::defs::{Command, DataValue};
 use crate::common::assets::GetAsset;
 #[cfg(feature = "slurm")]
-use crate::cluster_mgmt::slurm;
+use crate::cluster_mgmt;
 
 use std::path::Path;
 
@@ -35,6 +35,9 @@ fn handle_dry_run(gem_bs: &GemBS, options: &HashMap<&'static str, DataValue>, ta
 		let task = &gem_bs.get_tasks()[*ix];
 		if task.command() != Command::MergeCallJsons {
 			let arg_string = get_arg_string(task, options);
+			if let Some(v) = gem_bs.option_changes(*ix) {
+				for s in v.iter() { println!("# {}: {}", task.id(), s); }
+			}
 			println!("gemBS {} {}", task.command(), arg_string);
 		}
 	}	
@@ -72,7 +75,7 @@ pub fn handle_nonexec(gem_bs: &GemBS, options: &HashMap<&'static str, DataValue>
 	if let Some(json_file) = gem_bs.json_out() { handle_json_tasks(gem_bs, &options, &task_list, json_file)?; }
 	
 	#[cfg(feature = "slurm")]
-	if gem_bs.slurm() || gem_bs.slurm_script().is_some() { slurm::handle_slurm(gem_bs, &options, &task_list)?; }
+	if let Some(ctype) = gem_bs.cluster() { cluster_mgmt::handle_cluster(gem_bs, ctype, &options, &task_list)?; }
 	
 	Ok(())
 }
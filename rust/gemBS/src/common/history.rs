@@ -0,0 +1,166 @@
+// Persistent record of task execution
+//
+// Each attempt to run a task is appended as a single JSON line to the history
+// file (gemBS_history.jsonl) in the .gemBS directory.  For external pipelines, 
+// the resource usage of each pipeline stage is recorded (obtained using wait4() 
+// when the stage is reaped).
+//
+
+use std::fs::OpenOptions;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::{thread, time};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+
+use utils::compress::open_bufreader;
+use super::utils::{get_user_host_string, FileLock};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StageUsage {
+	pub command: String,
+	pub exit_status: Option<i32>,
+	pub signal: Option<i32>,
+	pub max_rss: Option<usize>, // bytes
+	pub user_time: f64, // seconds
+	pub sys_time: f64,
+}
+
+impl StageUsage {
+	pub fn from_rusage(command: &str, status: Option<libc::c_int>, ru: Option<&libc::rusage>) -> Self {
+		let tv = |t: &libc::timeval| (t.tv_sec as f64) + (t.tv_usec as f64) / 1000000.0;
+		let (exit_status, signal) = match status {
+			Some(st) if libc::WIFEXITED(st) => (Some(libc::WEXITSTATUS(st)), None),
+			Some(st) if libc::WIFSIGNALED(st) => (None, Some(libc::WTERMSIG(st))),
+			_ => (None, None),
+		};
+		match ru {
+			// ru_maxrss is in kilobytes on Linux
+			Some(r) => StageUsage{command: command.to_owned(), exit_status, signal, max_rss: Some((r.ru_maxrss as usize) << 10),
+				user_time: tv(&r.ru_utime), sys_time: tv(&r.ru_stime)},
+			None => StageUsage{command: command.to_owned(), exit_status, signal, max_rss: None, user_time: 0.0, sys_time: 0.0},
+		}
+	}
+	pub fn cpu_time(&self) -> f64 { self.user_time + self.sys_time }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TaskRecord {
+	id: String,
+	command: String,
+	args: String,
+	host: String,
+	#[serde(default = "first_attempt")]
+	attempt: usize,
+	start: f64, // Seconds since Unix epoch
+	end: f64,
+	error: Option<String>,
+	stages: Vec<StageUsage>,
+}
+
+impl TaskRecord {
+	pub fn new(id: &str, command: &str, args: &str) -> Self {
+		let start = epoch_secs(SystemTime::now());
+		TaskRecord{id: id.to_owned(), command: command.to_owned(), args: args.to_owned(), host: get_user_host_string(),
+			attempt: 1, start, end: start, error: None, stages: Vec::new()}
+	}
+	pub fn set_attempt(&mut self, attempt: usize) { self.attempt = attempt }
+	// Set end time and error status (if any)
+	pub fn finish(&mut self, err: Option<&str>) {
+		self.end = epoch_secs(SystemTime::now());
+		self.error = err.map(|e| e.to_owned());
+	}
+	pub fn add_stages(&mut self, stages: &[StageUsage]) { self.stages.extend_from_slice(stages) }
+	pub fn id(&self) -> &str { &self.id }
+	pub fn command(&self) -> &str { &self.command }
+	pub fn args(&self) -> &str { &self.args }
+	pub fn host(&self) -> &str { &self.host }
+	pub fn attempt(&self) -> usize { self.attempt }
+	pub fn start(&self) -> f64 { self.start }
+	pub fn error(&self) -> Option<&str> { self.error.as_deref() }
+	pub fn success(&self) -> bool { self.error.is_none() }
+	pub fn stages(&self) -> &[StageUsage] { &self.stages }
+	pub fn elapsed(&self) -> f64 { self.end - self.start }
+	pub fn cpu_time(&self) -> f64 { self.stages.iter().map(|s| s.cpu_time()).sum() }
+	// As the stages of a pipeline run concurrently, the peak memory of the task is
+	// estimated as the sum of the peak memory of each stage
+	pub fn max_rss(&self) -> Option<usize> {
+		self.stages.iter().fold(None, |m, s| match (m, s.max_rss) {
+			(None, x) => x,
+			(Some(a), Some(b)) => Some(a + b),
+			(Some(a), None) => Some(a),
+		})
+	}
+}
+
+fn first_attempt() -> usize { 1 }
+
+fn epoch_secs(t: SystemTime) -> f64 {
+	t.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+}
+
+// We don't use wait_for_lock() here as that would interfere with signal handling
+// in the calling thread.  The lock is only held for the duration of a single write.
+fn get_history_lock(path: &Path) -> Result<FileLock<'_>, String> {
+	let delay = time::Duration::from_millis(100);
+	let mut tries = 0;
+	loop {
+		match FileLock::new(path) {
+			Ok(f) => return Ok(f),
+			Err(e) => {
+				tries += 1;
+				if !e.starts_with("File locked") || tries >= 300 { return Err(format!("Couldn't lock history file {}: {}", path.display(), e)) }
+			},
+		}
+		thread::sleep(delay);
+	}
+}
+
+pub fn append_history(path: &Path, rec: &TaskRecord) -> Result<(), String> {
+	let line = serde_json::to_string(rec).map_err(|e| format!("Error serializing task record: {}", e))?;
+	let _lock = get_history_lock(path)?;
+	let mut file = OpenOptions::new().create(true).append(true).open(path)
+		.map_err(|e| format!("Couldn't open history file {} for output: {}", path.display(), e))?;
+	writeln!(file, "{}", line).map_err(|e| format!("Error writing to history file {}: {}", path.display(), e))
+}
+
+pub fn read_history(path: &Path) -> Result<Vec<TaskRecord>, String> {
+	let mut records = Vec::new();
+	if !path.exists() { return Ok(records) }
+	let reader = open_bufreader(path).map_err(|e| format!("Couldn't open history file {}: {}", path.display(), e))?;
+	for (i, line) in reader.lines().enumerate() {
+		let line = line.map_err(|e| format!("Error reading history file {}: {}", path.display(), e))?;
+		if line.trim().is_empty() { continue }
+		match serde_json::from_str(&line) {
+			Ok(rec) => records.push(rec),
+			Err(e) => warn!("Skipping invalid record at line {} of history file {}: {}", i + 1, path.display(), e),
+		}
+	}
+	Ok(records)
+}
+
+// Format seconds since Unix epoch as UTC date and time (YYYY-MM-DD hh:mm:ss)
+pub fn format_epoch(t: f64) -> String {
+	let secs = t.max(0.0) as i64;
+	let days = secs / 86400;
+	let rem = secs % 86400;
+	// Civil from days algorithm (Howard Hinnant)
+	let z = days + 719_468;
+	let era = z / 146_097;
+	let doe = z - era * 146_097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let d = doy - (153 * mp + 2) / 5 + 1;
+	let m = if mp < 10 { mp + 3 } else { mp - 9 };
+	let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+	format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", y, m, d, rem / 3600, (rem / 60) % 60, rem % 60)
+}
+
+pub fn format_duration(t: f64) -> String {
+	let secs = t.max(0.0).round() as usize;
+	let (h, m, s) = (secs / 3600, (secs / 60) % 60, secs % 60);
+	if h > 0 { format!("{}h{:02}m{:02}s", h, m, s) }
+	else if m > 0 { format!("{}m{:02}s", m, s) }
+	else { format!("{}s", s) }
+}
@@ -470,11 +470,84 @@ impl TSType {
 	}	
 }
 
+// MspI fragment statistics (RRBS mode only)
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RrbsStats {
+	#[serde(rename = "MspIReads")]
+	pub mspi_reads: usize,
+	#[serde(rename = "NonMspIReads")]
+	pub non_mspi_reads: usize,
+	pub end_repair_trimmed_reads: usize,
+	#[serde(rename = "MspIFragmentEnds")]
+	pub mspi_fragment_ends: usize,
+	#[serde(rename = "MspIFragmentEndCoverage")]
+	pub mspi_fragment_end_coverage: Vec<usize>,
+}
+
+impl RrbsStats {
+	fn merge(&mut self, other: &Self) {
+		self.mspi_reads += other.mspi_reads;
+		self.non_mspi_reads += other.non_mspi_reads;
+		self.end_repair_trimmed_reads += other.end_repair_trimmed_reads;
+		self.mspi_fragment_ends += other.mspi_fragment_ends;
+		add_assign_vec(&mut self.mspi_fragment_end_coverage, &other.mspi_fragment_end_coverage, 0);
+	}
+	// Median number of reads per covered fragment end
+	pub fn median_coverage(&self) -> usize {
+		let mut tmp = 0;
+		for (ix, x) in self.mspi_fragment_end_coverage.iter().enumerate() {
+			tmp += x;
+			if tmp > self.mspi_fragment_ends >> 1 { return ix }
+		}
+		0
+	}
+}
+
+// Methylome segmentation summary (from the JSON file generated by mextr --segmentation)
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SegTypeSummary {
+	pub count: usize,
+	pub total_length: u64,
+	#[serde(rename = "CpGs")]
+	pub cpgs: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SampleSegSummary {
+	pub sample: String,
+	#[serde(rename = "MinCpGs")]
+	pub min_cpgs: Option<usize>,
+	#[serde(rename = "UMR")]
+	pub umr: SegTypeSummary,
+	#[serde(rename = "LMR")]
+	pub lmr: SegTypeSummary,
+	#[serde(rename = "PMD")]
+	pub pmd: SegTypeSummary,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SegJson {
+	pub segmentation: Vec<SampleSegSummary>,
+}
+
+impl SegJson {
+	pub fn from_reader<T: Read>(rdr: T) -> Result<Self, String> {
+		serde_json::from_reader(rdr).map_err(|e| format!("Couldn't parse segmentation JSON file {}", e))
+	}
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CallJson {
 	source: String,
 	date: String,
+	#[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+	rrbs: Option<RrbsStats>,
 	filter_stats: FSType,
 	contig_stats: HashMap<String, CSType>,
 	total_stats: TSType,
@@ -498,6 +571,11 @@ impl CallJson {
 		
 		// Merge contig stats
 		for (ctg, ct) in other.contig_stats.iter() { *(self.contig_stats.entry(ctg.to_owned()).or_insert_with(CSType::new)) += *ct; }
+		
+		// Merge RRBS stats
+		if let Some(b) = &other.rrbs {
+			if let Some(a) = &mut self.rrbs { a.merge(b) } else { self.rrbs = Some(b.clone()) }
+		}
 	}
 	pub fn coverage(&self) -> &Coverage { &self.total_stats.coverage }
 	pub fn quality(&self) -> &Quality { &self.total_stats.quality }
@@ -507,4 +585,5 @@ impl CallJson {
 	pub fn basic_stats(&self) -> &BasicStats { &self.total_stats.basic_stats }
 	pub fn vcf_filter_stats(&self) -> &HashMap<String, QCCounts> { &self.total_stats.vcf_filter_stats }
 	pub fn mutations(&self) -> &HashMap<String, MutCounts> { &self.total_stats.mutations }
+	pub fn rrbs(&self) -> Option<&RrbsStats> { self.rrbs.as_ref() }
 }
\ No newline at end of file
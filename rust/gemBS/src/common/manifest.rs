@@ -0,0 +1,215 @@
+// Manifest of content hashes for pipeline files
+//
+// When hash checking is enabled, gemBS records the MD5 checksum of each output file of
+// a task together with the checksums of the task inputs and the task parameters.  A file
+// is then only considered outdated if the content of an input or the parameters of the
+// producing task have changed, rather than simply the modification times.
+//
+// Checksums are cached by file size and modification time so that unchanged files are
+// not re-read.
+//
+// The manifest also records the effective options used for each task so that task outputs
+// can be marked as outdated if the options are changed.
+//
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use std::{thread, time};
+use serde::{Serialize, Deserialize};
+use md5::{Md5, Digest};
+
+use super::utils::FileLock;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct FileStamp {
+	size: u64,
+	mtime: (u64, u32),
+}
+
+impl FileStamp {
+	fn from_path(path: &Path) -> Option<Self> {
+		let md = path.metadata().ok()?;
+		let d = md.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+		Some(FileStamp{size: md.len(), mtime: (d.as_secs(), d.subsec_nanos())})
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestEntry {
+	stamp: FileStamp,
+	hash: String,
+	inputs: Vec<(PathBuf, String)>, // Paths and hashes of task inputs when file was generated
+	params: Option<String>, // Parameters of task that generated the file
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Manifest {
+	entries: HashMap<PathBuf, ManifestEntry>,
+	#[serde(default)]
+	options: HashMap<String, Vec<(String, String)>>, // Options used for last successful run of each task
+	#[serde(skip)]
+	updated: HashSet<PathBuf>, // Entries changed since the manifest was read
+}
+
+pub fn file_hash(path: &Path) -> Result<String, String> {
+	let mut file = fs::File::open(path).map_err(|e| format!("Couldn't open {} for reading: {}", path.display(), e))?;
+	let mut hasher = Md5::new();
+	let mut buf = vec!(0u8; 1 << 20);
+	loop {
+		let n = file.read(&mut buf).map_err(|e| format!("Error reading from {}: {}", path.display(), e))?;
+		if n == 0 { break }
+		hasher.update(&buf[..n]);
+	}
+	Ok(hasher.finalize().iter().map(|x| format!("{:02x}", x)).collect())
+}
+
+// The manifest is only locked for the duration of a read-modify-write cycle, so we
+// just poll for the lock rather than using wait_for_lock(), which interferes with signal handling
+fn get_manifest_lock(path: &Path) -> Result<FileLock<'_>, String> {
+	let delay = time::Duration::from_millis(100);
+	let mut tries = 0;
+	loop {
+		match FileLock::new(path) {
+			Ok(f) => return Ok(f),
+			Err(e) => {
+				tries += 1;
+				if !e.starts_with("File locked") || tries >= 600 { return Err(format!("Couldn't lock manifest file {}: {}", path.display(), e)) }
+			},
+		}
+		thread::sleep(delay);
+	}
+}
+
+impl Manifest {
+	fn read(path: &Path) -> Result<Self, String> {
+		if !path.exists() { return Ok(Manifest::default()) }
+		let file = fs::File::open(path).map_err(|e| format!("Couldn't open manifest file {}: {}", path.display(), e))?;
+		serde_json::from_reader(file).map_err(|e| format!("Error reading manifest file {}: {}", path.display(), e))
+	}
+	fn write(&self, lock: &FileLock) -> Result<(), String> {
+		let writer = lock.writer()?;
+		serde_json::to_writer(writer, self).map_err(|e| format!("Error writing manifest file {}: {}", lock.path().display(), e))
+	}
+	pub fn load(path: &Path) -> Result<Self, String> {
+		let _lock = get_manifest_lock(path)?;
+		Manifest::read(path)
+	}
+	fn merge_into(&self, m: &mut Manifest) {
+		for p in self.updated.iter() {
+			if let Some(e) = self.entries.get(p) { m.entries.insert(p.clone(), e.clone()); }
+		}
+	}
+	// Merge any new cached hashes into the manifest on disk
+	pub fn save_cache(&self, path: &Path) -> Result<(), String> {
+		if self.updated.is_empty() { return Ok(()) }
+		let lock = get_manifest_lock(path)?;
+		let mut m = Manifest::read(path)?;
+		self.merge_into(&mut m);
+		m.write(&lock)
+	}
+
+	// Get hash of file, using the cached value if the file has not been changed
+	pub fn current_hash(&mut self, path: &Path) -> Option<String> {
+		let stamp = FileStamp::from_path(path)?;
+		if let Some(e) = self.entries.get(path) {
+			if e.stamp == stamp { return Some(e.hash.clone()) }
+		}
+		debug!("Calculating MD5 checksum for {}", path.display());
+		match file_hash(path) {
+			Ok(hash) => {
+				let (inputs, params) = match self.entries.remove(path) {
+					Some(e) if e.hash == hash => (e.inputs, e.params),
+					_ => (Vec::new(), None),
+				};
+				self.entries.insert(path.to_owned(), ManifestEntry{stamp, hash: hash.clone(), inputs, params});
+				self.updated.insert(path.to_owned());
+				Some(hash)
+			},
+			Err(e) => {
+				warn!("{}", e);
+				None
+			},
+		}
+	}
+
+	pub fn task_opts(&self, id: &str) -> Option<&Vec<(String, String)>> { self.options.get(id) }
+
+	// Check whether a derived file is up to date with respect to its inputs and the task parameters
+	pub fn check_current(&mut self, path: &Path, inputs: &[&Path], params: &str) -> bool {
+		let entry = match self.entries.get(path) {
+			Some(e) => e.clone(),
+			None => return false,
+		};
+		if entry.params.as_deref() != Some(params) || entry.inputs.len() != inputs.len() { return false }
+		if self.current_hash(path).as_ref() != Some(&entry.hash) { return false }
+		for (p, h) in entry.inputs.iter() {
+			if !inputs.contains(&p.as_path()) || self.current_hash(p).as_ref() != Some(h) { return false }
+		}
+		true
+	}
+}
+
+// Describe differences between the recorded and current options for a task
+pub fn diff_opts(old: &[(String, String)], new: &[(String, String)]) -> Vec<String> {
+	let mut diff = Vec::new();
+	for (k, v) in new.iter() {
+		match old.iter().find(|(k1, _)| k1 == k) {
+			Some((_, v1)) if v1 == v => (),
+			Some((_, v1)) => diff.push(format!("{} changed from {} to {}", k, v1, v)),
+			None => diff.push(format!("{} set to {}", k, v)),
+		}
+	}
+	for (k, v) in old.iter().filter(|(k, _)| !new.iter().any(|(k1, _)| k1 == k)) {
+		diff.push(format!("{} unset (was {})", k, v));
+	}
+	diff
+}
+
+// Record options, and optionally output checksums, of successfully completed task
+#[derive(Debug)]
+pub struct ManifestUpdate {
+	manifest: PathBuf,
+	task: String,
+	options: Vec<(String, String)>,
+	hash: Option<(Vec<OutputInputs>, String)>, // Outputs with their inputs, and parameters if hash checking
+}
+
+type OutputInputs = (PathBuf, Vec<PathBuf>);
+
+impl ManifestUpdate {
+	pub fn new(manifest: PathBuf, task: &str, options: Vec<(String, String)>) -> Self {
+		ManifestUpdate{manifest, task: task.to_owned(), options, hash: None}
+	}
+	// The inputs for each output should be the paths of the parents of the output asset, as these
+	// are what is checked in Manifest::check_current()
+	pub fn set_hash(&mut self, outputs: Vec<OutputInputs>, params: String) {
+		self.hash = Some((outputs, params));
+	}
+	pub fn record(&self) -> Result<(), String> {
+		// Calculate hashes before taking the lock as this can take some time
+		let mut m = Manifest::default();
+		let mut outputs = Vec::new();
+		if let Some((out, params)) = &self.hash {
+			m = Manifest::load(&self.manifest)?;
+			for (p, inp) in out.iter() {
+				let mut inputs = Vec::new();
+				for p1 in inp.iter() {
+					let h = m.current_hash(p1).ok_or_else(|| format!("Couldn't get checksum for {} (input for {})", p1.display(), p.display()))?;
+					inputs.push((p1.clone(), h))
+				}
+				if let (Some(stamp), Ok(hash)) = (FileStamp::from_path(p), file_hash(p)) {
+					outputs.push((p.clone(), ManifestEntry{stamp, hash, inputs, params: Some(params.clone())}))
+				}
+			}
+		}
+		let lock = get_manifest_lock(&self.manifest)?;
+		let mut m1 = Manifest::read(&self.manifest)?;
+		m.merge_into(&mut m1);
+		for (p, e) in outputs.drain(..) { m1.entries.insert(p, e); }
+		m1.options.insert(self.task.clone(), self.options.clone());
+		m1.write(&lock)
+	}
+}
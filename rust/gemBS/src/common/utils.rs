@@ -13,6 +13,7 @@ use std::convert::AsRef;
 
 use utils::compress::{open_bufreader, open_pipe_writer};
 use crate::common::defs::{SIGTERM, SIGINT, SIGQUIT, SIGHUP, signal_msg};
+use crate::common::history::StageUsage;
 
 pub fn get_inode(name: &str) -> Option<u64> {
    	match fs::metadata(name) {
@@ -56,6 +57,7 @@ where
 	input: PipelineInput,
 	log: Option<PathBuf>,
 	expected_outputs: Vec<&'a Path>,
+	usage: Vec<StageUsage>,
 }
 
 impl<'a, I, S> Pipeline<'a, I, S>
@@ -64,7 +66,7 @@ where
     S: AsRef<OsStr>,
 {
 	pub fn new() -> Self {
-		Pipeline{stage: Vec::new(), output: PipelineOutput::None, input: PipelineInput::None, log: None, expected_outputs: Vec::new() }
+		Pipeline{stage: Vec::new(), output: PipelineOutput::None, input: PipelineInput::None, log: None, expected_outputs: Vec::new(), usage: Vec::new() }
 	}
 	// Add pipeline stage (command + optional vector of arguments)
 	pub fn add_stage(&mut self, command: &'a Path, args: Option<I>) -> &mut Pipeline<'a, I, S> {
@@ -109,6 +111,8 @@ where
 		self.expected_outputs.push(file);
 		self
 	}
+	// Resource usage of the pipeline stages that have been reaped (in pipeline order)
+	pub fn stage_usage(&self) -> &[StageUsage] { &self.usage }
 	// Execute the pipeline
 	pub fn run(&mut self, sig: Arc<AtomicUsize>) -> Result<(), String> {
 		let log_file = if let Some(file) = &self.log {
@@ -196,7 +200,7 @@ where
 			self.output = PipelineOutput::String(Some(s));
 			cinfo.push((child, com));
 		}
-		match wait_sub_proc(sig.clone(), &mut cinfo) {
+		match wait_sub_proc(sig.clone(), &mut cinfo, &mut self.usage) {
 			Some(com) => {
 				match get_signal(sig) {
 					SIGTERM => Err("Pipeline terminated with a SIGTERM signal".to_string()),
@@ -214,7 +218,7 @@ where
 	}
 }
 
-fn wait_sub_proc(sig: Arc<AtomicUsize>, cinfo: &mut Vec<(Child, &Path)>) -> Option<String> {
+fn wait_sub_proc(sig: Arc<AtomicUsize>, cinfo: &mut Vec<(Child, &Path)>, usage: &mut Vec<StageUsage>) -> Option<String> {
 	let mut err_com = None;
 	let delay = time::Duration::from_millis(250);
 	for (child, com) in cinfo.iter_mut().rev() {
@@ -223,25 +227,31 @@ fn wait_sub_proc(sig: Arc<AtomicUsize>, cinfo: &mut Vec<(Child, &Path)>) -> Opti
 			let _ = child.kill(); 
 		} else {	
 			trace!("Waiting for {} to finish", com.to_string_lossy());
+			// We use wait4() rather than try_wait() so that we can collect the resource usage of the child
+			let pid = child.id() as libc::pid_t;
 			loop {
-				if match child.try_wait() {
-					Ok(Some(st)) => {
-						if !st.success() { err_com = Some(format!("Error from pipeline: {} exited with error", com.to_string_lossy())) }
-						true
-					},
-					Ok(None) => {
+				let mut status: libc::c_int = 0;
+				let mut ru: libc::rusage = unsafe { std::mem::zeroed() };
+				if match unsafe { libc::wait4(pid, &mut status, libc::WNOHANG, &mut ru) } {
+					0 => {
 						if get_signal(sig.clone()) != 0 { let _ = child.kill(); } 
 						false
 					},
-					Err(e) => {
-						err_com = Some(format!("Error from pipeline: {} exited with error {}", com.to_string_lossy(), e));
+					x if x < 0 => {
+						err_com = Some(format!("Error from pipeline: {} exited with error {}", com.to_string_lossy(), std::io::Error::last_os_error()));
 						true
-					}, 
+					},
+					_ => {
+						if !(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0) { err_com = Some(format!("Error from pipeline: {} exited with error", com.to_string_lossy())) }
+						usage.push(StageUsage::from_rusage(&com.to_string_lossy(), Some(status), Some(&ru)));
+						true
+					},
 				} { break;}
 				thread::sleep(delay);
 			}
 		}
 	}
+	usage.reverse();
 	err_com
 }
 		
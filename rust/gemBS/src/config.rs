@@ -3,7 +3,7 @@
 // Holds all of the information from the config files, JSON files, sqlite db etc.
 //
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, BTreeMap, BTreeSet};
 use std::path::PathBuf;
 use std::{env,option_env};
 use std::sync::Arc;
@@ -15,10 +15,12 @@ use std::str::FromStr;
 use std::time::Instant;
 use std::os::unix::fs::PermissionsExt;
 
-use crate::common::defs::{Section, Metadata, DataValue, JobLen, MemSize, Command, SIGTERM, SIGINT, SIGQUIT, SIGHUP, signal_msg};
+use crate::common::defs::{Section, Metadata, DataValue, JobLen, MemSize, Command, ClusterType, SIGTERM, SIGINT, SIGQUIT, SIGHUP, signal_msg};
 use crate::common::assets::{Asset, AssetList, AssetType, AssetStatus, GetAsset};
 use crate::common::tasks::{Task, TaskList, TaskStatus, RunningTask};
 use crate::common::utils::{FileLock, timed_wait_for_lock, get_phys_memory};
+use crate::common::manifest::{Manifest, diff_opts};
+use crate::scheduler::get_task_opts;
 
 use crate::config::contig::{Contig, ContigPool};
 use utils::{log_level::LogLevel, find_exec_path};
@@ -29,6 +31,7 @@ mod check_map;
 mod check_report;
 mod check_call;
 mod check_extract;
+mod check_dmr;
 mod md5_fasta;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -54,11 +57,13 @@ pub struct GemBS {
 	signal: Arc<AtomicUsize>,
 	ignore_times: bool,
 	ignore_status: bool,
+	hash_check: bool,
+	option_changes: HashMap<usize, Vec<String>>, // Changes in task options since last run
 	keep_logs: bool,
 	json_out: Option<String>,
 	all: bool,
-	slurm: bool,
-	slurm_script: Option<String>,
+	cluster: Option<ClusterType>,
+	cluster_script: Option<String>,
 	dry_run: bool,
 	verbose: LogLevel,
 }
@@ -73,8 +78,8 @@ impl GemBS {
 			GemBSData::ContigPools(HashMap::new()),			
 		];
 		let gem_bs = GemBS{var, fs: None, 
-			ignore_times: false, ignore_status: false, keep_logs: false, total_mem,
-			json_out: None, all: false, slurm: false, slurm_script: None, dry_run: false, verbose: LogLevel::from_str("error").unwrap(),
+			ignore_times: false, ignore_status: false, hash_check: false, option_changes: HashMap::new(), keep_logs: false, total_mem,
+			json_out: None, all: false, cluster: None, cluster_script: None, dry_run: false, verbose: LogLevel::from_str("error").unwrap(),
 			assets: AssetList::new(), tasks: TaskList::new(), signal: Arc::new(AtomicUsize::new(0))};
 		gem_bs.mask_signals();
 		gem_bs
@@ -281,6 +286,12 @@ impl GemBS {
 	pub fn get_task_file_path(&self) -> PathBuf {
 		[&self.fs.as_ref().unwrap().config_dir, Path::new("gemBS_tasks.json")].iter().collect()
 	}
+	pub fn get_history_file_path(&self) -> PathBuf {
+		[&self.fs.as_ref().unwrap().config_dir, Path::new("gemBS_history.jsonl")].iter().collect()
+	}
+	pub fn get_manifest_file_path(&self) -> PathBuf {
+		[&self.fs.as_ref().unwrap().config_dir, Path::new("gemBS_manifest.json")].iter().collect()
+	}
 	pub fn get_config_script_path(&self) -> PathBuf {
 		let root = &self.fs.as_ref().unwrap().gem_bs_root;
 		[root, Path::new("etc"), Path::new("config_scripts")].iter().collect()
@@ -308,6 +319,21 @@ impl GemBS {
 		}
 		sample
 	}
+
+	// Barcodes for each sample group (from the group column of the metadata)
+	pub fn get_sample_groups(&self) -> Result<BTreeMap<String, BTreeSet<String>>, String> {
+		let mut bc_group: HashMap<&String, &String> = HashMap::new();
+		let mut groups: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+		for href in self.get_sample_data_ref().values() {
+			if let (Some(DataValue::String(bc)), Some(DataValue::String(gp))) = (href.get(&Metadata::SampleBarcode), href.get(&Metadata::Group)) {
+				if let Some(g) = bc_group.insert(bc, gp) {
+					if g != gp { return Err(format!("Barcode {} is assigned to more than one group ({}, {})", bc, g, gp)) }
+				}
+				groups.entry(gp.clone()).or_default().insert(bc.clone());
+			}
+		}
+		Ok(groups)
+	}
 	
 	pub fn setup_assets_and_tasks(&mut self, lock: &FileLock) -> Result<(), String> {
 		self.check_signal()?;
@@ -320,6 +346,7 @@ impl GemBS {
 		check_call::check_call(self)?;
 		check_report::check_call_report(self)?;
 		check_extract::check_extract(self)?;
+		check_dmr::check_dmr(self)?;
 		check_report::check_report(self)?;
 		for asset in self.get_assets().iter() {
 			let i = asset.idx();
@@ -329,7 +356,7 @@ impl GemBS {
 				}
 			}
 		}
-		self.assets.calc_mod_time_ances();
+		self.calc_mod_time_ances();
 		self.assets.check_delete_status();
 		self.rescan_assets_and_tasks(lock)
 	}
@@ -341,12 +368,52 @@ impl GemBS {
 			h
 		});
 		self.assets.recheck_status(&running_ids);
-		self.assets.calc_mod_time_ances();
+		self.calc_mod_time_ances();
 		self.assets.check_delete_status();		
 		self.handle_status(&running);
 		Ok(())				
 	}
 
+	// If hash checking is enabled, derived files that are older than their inputs are only
+	// considered outdated if the content of the inputs or the task parameters have changed.
+	// Outputs of tasks whose options have changed since they were last run are also marked as outdated.
+	fn calc_mod_time_ances(&mut self) {
+		let path = self.get_manifest_file_path();
+		let mut manifest = match Manifest::load(&path) {
+			Ok(m) => m,
+			Err(e) => {
+				warn!("{}", e);
+				Manifest::default()
+			},
+		};
+		if self.hash_check() {
+			let tasks = &self.tasks;
+			self.assets.calc_mod_time_ances_checked(|asset, inputs| asset.creator().map(|c| {
+				let task = &tasks[c];
+				manifest.check_current(asset.path(), inputs, &format!("{:#} {}", task.command(), task.args()))
+			}).unwrap_or(false));
+			if let Err(e) = manifest.save_cache(&path) { warn!("{}", e); }
+		} else { self.assets.calc_mod_time_ances(); }
+		self.option_changes.clear();
+		let mut changed = HashSet::new();
+		for task in self.tasks.iter() {
+			let old = match manifest.task_opts(task.id()) {
+				Some(v) => v,
+				None => continue,
+			};
+			// Only check tasks where outputs are present
+			if !task.outputs().filter_map(|x| self.assets.get_asset(*x)).any(|a| a.status() == AssetStatus::Present) { continue }
+			let diff = diff_opts(old, &get_task_opts(self, task.idx()));
+			if !diff.is_empty() {
+				debug!("Options for task {} have changed: {}", task.id(), diff.join(", "));
+				changed.extend(task.outputs().copied());
+				self.option_changes.insert(task.idx(), diff);
+			}
+		}
+		if !changed.is_empty() { self.assets.mark_outdated(&changed) }
+	}
+	pub fn option_changes(&self, ix: usize) -> Option<&Vec<String>> { self.option_changes.get(&ix) }
+	
 	fn handle_status(&mut self, running: &[RunningTask]) {
 		self.tasks.iter_mut().for_each(|x| x.clear_status());
 		self.calc_task_statuses(running);	
@@ -426,6 +493,8 @@ impl GemBS {
 	}
 	pub fn set_ignore_times(&mut self, x: bool) { self.ignore_times = x; }
 	pub fn ignore_times(&self) -> bool { self.ignore_times }
+	pub fn set_hash_check(&mut self, x: bool) { self.hash_check = x; }
+	pub fn hash_check(&self) -> bool { self.hash_check || self.get_config_bool(Section::Default, "hash_check") }
 	pub fn set_ignore_status(&mut self, x: bool) { self.ignore_status = x; }
 	pub fn ignore_status(&self) -> bool { self.ignore_status }
 	pub fn set_keep_logs(&mut self, x: bool) { self.keep_logs = x; }
@@ -436,13 +505,16 @@ impl GemBS {
 	pub fn verbose(&self) -> LogLevel { self.verbose }
 	pub fn set_dry_run(&mut self, x: bool) { self.dry_run = x; }
 	pub fn dry_run(&self) -> bool { self.dry_run }
-	pub fn set_slurm(&mut self, x: bool) { self.slurm = x; }
-	pub fn set_slurm_script(&mut self, x: &str) { self.slurm_script = Some(x.to_owned()); }
-	pub fn slurm(&self) -> bool { self.slurm }
-	pub fn slurm_script(&self) -> &Option<String> { &self.slurm_script }
+	pub fn set_cluster(&mut self, x: ClusterType) { self.cluster = Some(x); }
+	pub fn set_cluster_script(&mut self, x: ClusterType, s: &str) { 
+		self.cluster = Some(x);
+		self.cluster_script = Some(s.to_owned()); 
+	}
+	pub fn cluster(&self) -> Option<ClusterType> { self.cluster }
+	pub fn cluster_script(&self) -> &Option<String> { &self.cluster_script }
 	pub fn set_json_out(&mut self, s: &str) { self.json_out = Some(s.to_owned()); }
 	pub fn json_out(&self) -> Option<&str> { self.json_out.as_deref() }
-	pub fn execute_flag(&self) -> bool { !(self.dry_run || self.slurm || self.json_out.is_some() || self.slurm_script.is_some())  }
+	pub fn execute_flag(&self) -> bool { !(self.dry_run || self.cluster.is_some() || self.json_out.is_some())  }
 	pub fn get_required_tasks_from_asset_list(&self, assets: &[usize], com_list: &[Command]) -> Vec<usize> {
 		let com_set = com_list.iter().fold(HashSet::new(), |mut hs, x| { hs.insert(*x); hs });
 		fn check_reqd(i: usize, reqd: &mut HashSet<usize>, tlist: &mut Vec<usize>, rf: &TaskList, arf: &AssetList, com_set: &HashSet<Command>, ignore: bool) {
@@ -0,0 +1,80 @@
+// Check requirements for differential methylation calling between two groups of samples
+// Make asset list for the merged BCF and the DMC / DMR files
+
+use std::path::{Path, PathBuf};
+use crate::common::defs::{Section, DataValue, Command};
+use crate::common::assets::{AssetType, GetAsset};
+use crate::common::assets;
+use super::GemBS;
+
+pub fn check_dmr(gem_bs: &mut GemBS) -> Result<(), String> {
+	let groups = gem_bs.get_sample_groups()?;
+	if groups.is_empty() { return Ok(()) }
+	// The two groups to compare are either set in the config file or are the only two groups in the metadata
+	let (g1, g2) = match gem_bs.get_config_stringvec(Section::Dmr, "dmr_groups") {
+		Some(v) => {
+			if v.len() != 2 || v[0] == v[1] { return Err(format!("Config variable dmr_groups should have two distinct groups, not {:?}", v)) }
+			for g in v.iter() {
+				if !groups.contains_key(g) { return Err(format!("DMR group {} not found in sample metadata", g)) }
+			}
+			(v[0].clone(), v[1].clone())
+		},
+		None => {
+			if groups.len() != 2 {
+				if groups.len() > 2 { warn!("Sample metadata has {} groups: set dmr_groups in the [dmr] section to select two groups for DMR calling", groups.len()) }
+				return Ok(())
+			}
+			let mut it = groups.keys();
+			(it.next().unwrap().clone(), it.next().unwrap().clone())
+		},
+	};
+	let dmr_dir = if let Some(DataValue::String(x)) = gem_bs.get_config(Section::Dmr, "dmr_dir") { x.to_owned() } else { ".".to_owned() };
+	let dmr_path = Path::new(&dmr_dir);
+	let cores = gem_bs.get_config_int(Section::Dmr, "cores").map(|x| x as usize).or(Some(2));
+	let memory = gem_bs.get_config_memsize(Section::Dmr, "memory");
+	let time = gem_bs.get_config_joblen(Section::Dmr, "time").or_else(|| Some(3600.into()));
+	let name = format!("{}_vs_{}", g1, g2);
+	let args = format!("--groups {} {}", g1, g2);
+	let handle_file = |gb: &mut GemBS, nm: String, st: AssetType| {
+		let tpath = Path::new(nm.as_str());
+		let path: PathBuf = [dmr_path, tpath].iter().collect();
+		gb.insert_asset(nm, &path, st)
+	};
+
+	// Merge the BCFs of all samples in the two groups
+	let mut in_vec = Vec::new();
+	for bc in groups[&g1].iter().chain(groups[&g2].iter()) {
+		for ext in ["bcf", "bcf.csi", "bcf.md5"].iter() {
+			if let Some(x) = gem_bs.get_asset(format!("{}.{}", bc, ext).as_str()) { in_vec.push(x.idx()) }
+			else { panic!("BCF file {}.{} not found", bc, ext); }
+		}
+	}
+	let bcf = handle_file(gem_bs, format!("{}.bcf", name), AssetType::Derived);
+	let id = format!("dmr_merge_{}", name);
+	let (lname, lpath) = assets::make_ext_asset(&id, dmr_path, "log");
+	let log_index = gem_bs.insert_asset(&lname, &lpath, AssetType::Log);
+	let task = gem_bs.add_task(&id, format!("Merge BCFs for DMR calling ({} vs {})", g1, g2).as_str(), Command::Dmr, &args);
+	gem_bs.add_task_inputs(task, &in_vec).add_outputs(&[bcf]).set_log(Some(log_index))
+		.add_cores(cores).add_memory(memory).add_time(time);
+	gem_bs.get_asset_mut(bcf).unwrap().set_creator(task, &in_vec);
+
+	// mextr reads the input file by region, so the merged BCF must be indexed
+	let csi = handle_file(gem_bs, format!("{}.bcf.csi", name), AssetType::Derived);
+	let id = format!("dmr_index_{}", name);
+	let task = gem_bs.add_task(&id, format!("Calc Index for {}.bcf", name).as_str(), Command::Dmr, &args);
+	gem_bs.add_task_inputs(task, &[bcf]).add_outputs(&[csi]).add_cores(cores).add_memory(memory).add_time(time);
+	gem_bs.get_asset_mut(csi).unwrap().set_creator(task, &[bcf]);
+
+	let mut out_vec = Vec::new();
+	for suff in ["dmc.txt.gz", "dmc.txt.gz.md5", "dmr.bed.gz", "dmr.bed.gz.md5", "dmr.bb", "dmr.bb.md5"].iter() {
+		out_vec.push(handle_file(gem_bs, format!("{}_{}", name, suff), AssetType::Derived))
+	}
+	let id = format!("dmr_call_{}", name);
+	let (lname, lpath) = assets::make_ext_asset(&id, dmr_path, "log");
+	let log_index = gem_bs.insert_asset(&lname, &lpath, AssetType::Log);
+	let task = gem_bs.add_task(&id, format!("Call DMRs for {} vs {}", g1, g2).as_str(), Command::Dmr, &args);
+	gem_bs.add_task_inputs(task, &[bcf, csi]).add_outputs(&out_vec).set_log(Some(log_index))
+		.add_cores(cores).add_memory(memory).add_time(time);
+	out_vec.iter().for_each(|id| gem_bs.get_asset_mut(*id).unwrap().set_creator(task, &[bcf]));
+	Ok(())
+}
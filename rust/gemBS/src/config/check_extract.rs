@@ -17,7 +17,8 @@ pub fn check_extract(gem_bs: &mut GemBS) -> Result<(), String> {
 	let non_cpg = gem_bs.get_config_bool(Section::Extract, "make_non_cpg");
 	let snps = gem_bs.get_config_bool(Section::Extract, "make_snps");
 	let bedmethyl = gem_bs.get_config_bool(Section::Extract, "make_bedmethyl");
-	let cpg = gem_bs.get_config_bool(Section::Extract, "make_cpg")  || !(non_cpg || bedmethyl || snps);
+	let segmentation = gem_bs.get_config_bool(Section::Extract, "make_segmentation");
+	let cpg = gem_bs.get_config_bool(Section::Extract, "make_cpg")  || !(non_cpg || bedmethyl || snps || segmentation);
 	let samples = gem_bs.get_samples();
 	let mut snpxtr_inputs = Vec::new();
 	if let Some(x) = gem_bs.get_asset("dbsnp_index") { snpxtr_inputs.push(x.idx()) } 
@@ -40,6 +41,11 @@ pub fn check_extract(gem_bs: &mut GemBS) -> Result<(), String> {
 		} else { [".bw", ".bw.md5"].iter().for_each(|x| mextr_suff.push(x)); }
 		mextr_comm.push_str(" --bed-methyl");
 	}
+	// mextr can only generate one set of bigBed files per run, so segmentation is a separate task
+	let mut mseg_suff: Vec<&str> = Vec::new();
+	if segmentation {
+		["segments.bed.gz", "segments.bed.gz.md5", "segments.bb", "segments.bb.md5", "segments.json", "segments.json.md5"].iter().for_each(|x| mseg_suff.push(x));
+	}
 	let mut snpxtr_suff: Vec<&str> = Vec::new();
 	if snps { ["snps.txt.gz", "snps.txt.gz.md5", "snps.txt.gz.tbi"].iter().for_each(|x| snpxtr_suff.push(x)) }
 	let snp_list = if let Some(DataValue::String(s)) = gem_bs.get_config(Section::Index, "snp_list") { Some(PathBuf::from(s)) } else { None };
@@ -74,6 +80,18 @@ pub fn check_extract(gem_bs: &mut GemBS) -> Result<(), String> {
 				.add_cores(cores).add_memory(memory).add_time(time);
 			out_vec.iter().for_each(|id| gem_bs.get_asset_mut(*id).unwrap().set_creator(task, &[bcf]));
 		}		
+		if !mseg_suff.is_empty() {
+			let mut out_vec = Vec::new();
+			for suff in mseg_suff.iter() { out_vec.push(handle_file(gem_bs, format!("{}_{}", bc, suff), extract_path))}
+			let id = format!("msegment_{}", bc);
+			let (lname, lpath) = assets::make_ext_asset(&id, extract_path, "log");
+			let log_index = gem_bs.insert_asset(&lname, &lpath, AssetType::Log);				
+			let task = gem_bs.add_task(&id, format!("Methylome segmentation for barcode {}", bc).as_str(),
+					Command::Extract, format!("--segmentation --barcode {}", bc).as_str());
+			gem_bs.add_task_inputs(task, &[bcf, bcf_ix, bcf_md5]).add_outputs(&out_vec).set_log(Some(log_index)).set_barcode(bc)
+				.add_cores(cores).add_memory(memory).add_time(time);
+			out_vec.iter().for_each(|id| gem_bs.get_asset_mut(*id).unwrap().set_creator(task, &[bcf]));
+		}		
 		if !snpxtr_suff.is_empty() {
 			let mut out_vec = Vec::new();
 			for suff in snpxtr_suff.iter() { out_vec.push(handle_file(gem_bs, format!("{}_{}", bc, suff), extract_path))}
@@ -1,10 +1,11 @@
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, mpsc};
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::io::Write;
 use std::{fs, thread, time};
 use custom_error::custom_error;
 use regex::Regex;
@@ -14,9 +15,11 @@ use crate::config::GemBS;
 use crate::common::defs::{DataValue, Command, Section, VarType};
 use crate::common::tasks::{TaskStatus, RunningTask};
 use crate::common::utils::{Pipeline, FileLock};
+use crate::common::history::{TaskRecord, StageUsage, append_history, read_history};
+use crate::common::manifest::ManifestUpdate;
 use crate::common::utils;
 use crate::common::latex_utils::PageSize;
-use crate::common::assets::{GetAsset};
+use crate::common::assets::{GetAsset, AssetType};
 use crate::commands::report::{make_map_report, make_call_report, make_report};
 
 use report::{MergeJsonFiles, SampleJsonFiles, CallJsonFiles};
@@ -25,6 +28,7 @@ mod map;
 mod index;
 mod md5sum;
 mod extract;
+mod dmr;
 pub mod call;
 pub mod report;
 
@@ -67,16 +71,24 @@ impl Drop for RunJob {
 enum SchedState {
 	Ready,
 	NoSlots,
-	Waiting(Option<time::SystemTime>),
+	Waiting(Option<time::SystemTime>), // Waiting for tasks run by other processes (modification time of run queue)
 	Abort,
 }
 
+// The scheduler walks the task DAG within this process.  A task can be started once all of its parents in the task list
+// have been completed.  The run queue file is shared with other gemBS processes working on the same project; tasks
+// listed there by other processes are not started here, and when we are only waiting on these tasks the run queue
+// and assets are rescanned whenever the run queue changes
 #[derive(Debug)]
-pub struct Scheduler<'a> {
+pub struct Scheduler {
 	running: Rc<RefCell<Vec<usize>>>, // Tasks running on this machine
-	lock: Option<FileLock<'a>>,
+	path: PathBuf, // Run queue file
 	task_list: Vec<usize>,
+	in_list: HashSet<usize>,
+	done: HashSet<usize>, // Tasks completed by this scheduler (or already complete)
+	external: HashSet<usize>, // Tasks running in other processes
 	state: SchedState,
+	failed: HashMap<usize, (usize, usize)>, // Number of failed attempts for tasks and memory of first attempt
 }
 
 custom_error!{pub SchedulerError
@@ -84,7 +96,6 @@ custom_error!{pub SchedulerError
 	NoTasksReady = "No tasks ready to run", 
 	WaitingForTasks = "Waiting for tasks on this machine", 
 	NoSlots = "No execution slots available",
-	NoLock = "No lock obtained",
 	TaskTaken = "Task already taken (internal error)",
 	IoErr{desc: String} = "IO error: {desc}",
 	Signal = "Caught signal - quitting",
@@ -131,6 +142,7 @@ pub fn get_command_req(gem_bs: &GemBS, com: Command) -> (f64, usize) {
 		Command::Call => get_requirements(gem_bs, Section::Calling, false),
 		Command::IndexBcf => get_requirements(gem_bs, Section::Calling, false),
 		Command::Extract => get_requirements(gem_bs, Section::Extract, false),
+		Command::Dmr => get_requirements(gem_bs, Section::Dmr, false),
 		Command::MapReport => get_requirements(gem_bs, Section::Report, false),
 		Command::CallReport => get_requirements(gem_bs, Section::Report, false),
 		Command::Report => get_requirements(gem_bs, Section::Report, false),
@@ -141,22 +153,167 @@ pub fn get_command_req(gem_bs: &GemBS, com: Command) -> (f64, usize) {
 	}			
 }
 
-impl<'a> Scheduler<'a> {
-	fn new(task_list: Vec<usize>) -> Self { 
-		Scheduler{running: Rc::new(RefCell::new(Vec::new())), lock: None, task_list, state: SchedState::Ready }
+fn get_command_section(com: Command) -> Section {
+	match com {
+		Command::Index => Section::Index,
+		Command::Map | Command::MergeBams => Section::Mapping,
+		Command::Call | Command::MergeBcfs | Command::IndexBcf | Command::MergeCallJsons => Section::Calling,
+		Command::Extract => Section::Extract,
+		Command::Dmr => Section::Dmr,
+		Command::MapReport | Command::CallReport | Command::Report => Section::Report,
+		Command::MD5SumMap | Command::MD5SumCall => Section::MD5Sum,
+	}
+}
+
+// Policy for re-running failed tasks.  Each retry multiplies the memory and time 
+// requirements of the previous attempt by the given factors.  Local tasks have no
+// time limit, so the time factor is only used for cluster jobs
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+	pub max_attempts: usize,
+	pub memory_factor: f64,
+	#[cfg(feature = "slurm")]
+	pub time_factor: f64,
+}
+
+impl RetryPolicy {
+	// Memory for attempt (1 based), rounded up to the next MB
+	pub fn memory(&self, mem: usize, attempt: usize) -> usize {
+		let m = (mem as f64) * self.memory_factor.powi(attempt as i32 - 1);
+		((m.ceil() as usize + 0xfffff) >> 20) << 20
+	}
+	#[cfg(feature = "slurm")]
+	pub fn time(&self, secs: usize, attempt: usize) -> usize {
+		((secs as f64) * self.time_factor.powi(attempt as i32 - 1)).ceil() as usize
+	}
+}
+
+pub fn get_retry_policy(gem_bs: &GemBS, com: Command) -> RetryPolicy {
+	let section = get_command_section(com);
+	let max_attempts = gem_bs.get_config_int(section, "max_attempts").unwrap_or(1).max(1) as usize;
+	let memory_factor = gem_bs.get_config_float(section, "retry_memory_factor").unwrap_or(1.5).max(1.0);
+	RetryPolicy{max_attempts, memory_factor,
+		#[cfg(feature = "slurm")]
+		time_factor: gem_bs.get_config_float(section, "retry_time_factor").unwrap_or(1.5).max(1.0),
+	}
+}
+
+// Peak memory of the last recorded attempt of a task
+fn last_max_rss(path: &Path, id: &str) -> Option<usize> {
+	match read_history(path) {
+		Ok(v) => v.iter().rev().find(|r| r.id() == id).and_then(|r| r.max_rss()),
+		Err(e) => {
+			warn!("{}", e);
+			None
+		},
+	}
+}
+
+// If we are running within a cluster job, retries are handled by the cluster job submission
+// and the attempt number is passed in the environment
+pub fn get_base_attempt() -> Option<usize> {
+	std::env::var("GEMBS_ATTEMPT").ok().and_then(|x| x.parse::<usize>().ok())
+}
+
+impl Scheduler {
+	fn new(gem_bs: &GemBS, task_list: Vec<usize>, path: PathBuf) -> Self { 
+		let in_list: HashSet<usize> = task_list.iter().copied().collect();
+		let mut sched = Scheduler{running: Rc::new(RefCell::new(Vec::new())), path, task_list, in_list, done: HashSet::new(), 
+			external: HashSet::new(), state: SchedState::Ready, failed: HashMap::new() };
+		sched.update_status(gem_bs);
+		sched
+	}
+	// Pick up tasks completed or running elsewhere from the task statuses
+	fn update_status(&mut self, gem_bs: &GemBS) {
+		let tasks = gem_bs.get_tasks();
+		let running = self.running.borrow();
+		for ix in self.task_list.iter() {
+			match tasks[*ix].status() {
+				Some(TaskStatus::Complete) => { self.done.insert(*ix); },
+				Some(TaskStatus::Running) if !running.contains(ix) => { self.external.insert(*ix); },
+				_ => (),
+			}
+		}
+	}
+	// Rescan the run queue and the assets to find tasks that have been completed or started by other processes
+	fn rescan(&mut self, gem_bs: &mut GemBS) -> Result<(), String> {
+		let flock = utils::wait_for_lock(gem_bs.get_signal_clone(), &self.path)?;
+		gem_bs.rescan_assets_and_tasks(&flock)?;
+		drop(flock);
+		self.external.clear();
+		self.update_status(gem_bs);
+		Ok(())
+	}
+	fn queue_mod_time(&self) -> Option<time::SystemTime> { self.path.metadata().ok().and_then(|md| md.modified().ok()) }
+	fn queue_changed(&self, t: Option<time::SystemTime>) -> bool {
+		match (t, self.queue_mod_time()) {
+			(Some(x), Some(y)) => y != x,
+			_ => true,
+		}
+	}
+	// Attempt number for the next run of a task
+	fn attempt(&self, ix: usize) -> usize { 
+		get_base_attempt().unwrap_or(1) + self.failed.get(&ix).map(|(n, _)| *n).unwrap_or(0)
 	}
-	fn set_task_list(&mut self, task_list: Vec<usize>) { self.task_list = task_list; }
+	// Resource requirements for a task, taking into account previous failed attempts
+	fn task_req(&self, gem_bs: &GemBS, ix: usize) -> (f64, usize) {
+		let com = gem_bs.get_tasks()[ix].command();
+		let (n, mem) = get_command_req(gem_bs, com);
+		match self.failed.get(&ix) {
+			Some((x, base)) => (n, get_retry_policy(gem_bs, com).memory(*base, x + 1).min(gem_bs.total_mem())),
+			None => (n, mem),
+		}
+	}
+	// Record failure of task.  Returns true if the task should be retried.  The memory for the retry is escalated
+	// from the configured requirement or, if none is set, from the peak memory recorded for the failed attempt.  If 
+	// neither is available (or memory can not be increased further) then the retry would be identical so is not attempted
+	fn task_failed(&mut self, gem_bs: &GemBS, ix: usize) -> bool {
+		let task = &gem_bs.get_tasks()[ix];
+		let policy = get_retry_policy(gem_bs, task.command());
+		let attempt = self.attempt(ix);
+		if get_base_attempt().is_some() || gem_bs.get_signal() != 0 || attempt >= policy.max_attempts { return false }
+		let (_, old_mem) = self.task_req(gem_bs, ix);
+		let base = match self.failed.get(&ix) {
+			Some((_, b)) => *b,
+			None => if old_mem > 0 { old_mem } else { last_max_rss(&gem_bs.get_history_file_path(), task.id()).unwrap_or(0) },
+		};
+		if base == 0 {
+			warn!("Task {} failed (attempt {} of {}) - not retrying as there is no memory requirement to escalate", task.id(), attempt, policy.max_attempts);
+			return false
+		}
+		let nf = self.failed.entry(ix).or_insert((0, base));
+		nf.0 += 1;
+		let (_, mem) = self.task_req(gem_bs, ix);
+		if mem <= old_mem {
+			warn!("Task {} failed (attempt {} of {}) - not retrying as memory can not be increased", task.id(), attempt, policy.max_attempts);
+			return false
+		}
+		warn!("Task {} failed (attempt {} of {}) - retrying with memory {:.1} GB", task.id(), attempt, policy.max_attempts, (mem as f64) / 1073741824.0);
+		true
+	}
+	fn task_done(&mut self, ix: usize) { self.done.insert(ix); }
 	fn is_empty(&self) -> bool { self.running.borrow().is_empty() }
-	fn set_lock(&mut self, lock: FileLock<'a>) { self.lock = Some(lock); }
-	fn drop_lock(&mut self) { self.lock = None; }
-	fn check_lock(&self) -> bool {self.lock.is_some() }
-	fn get_avail_slots_mem(&mut self, gem_bs: &mut GemBS) -> (f64, usize) {
+	fn pending(&self) -> usize { self.task_list.iter().filter(|ix| !self.done.contains(ix)).count() }
+	// A task is ready if it is not running or complete and all of its parents from the task list are complete.
+	// Tasks that were waiting on inputs also require that parents outside of the task list are complete
+	fn is_ready(&self, gem_bs: &GemBS, ix: usize) -> bool {
+		if self.done.contains(&ix) || self.external.contains(&ix) || self.running.borrow().contains(&ix) { return false }
+		let task = &gem_bs.get_tasks()[ix];
+		let waiting = match task.status() {
+			Some(TaskStatus::Ready) => false,
+			Some(TaskStatus::Waiting) => true,
+			_ => return false,
+		};
+		task.parents().iter().all(|p| if self.in_list.contains(p) { self.done.contains(p) } 
+			else { !waiting || gem_bs.get_tasks()[*p].status() == Some(TaskStatus::Complete) })
+	}
+	fn get_avail_slots_mem(&self, gem_bs: &GemBS) -> (f64, usize) {
 		let ncpus = num_cpus::get() as f64;
 		let mut tmem = gem_bs.total_mem();
 		let mut avail = ncpus;
 		let rf = self.running.borrow();
 		for ix in rf.iter() {
-			let (n, mem) = get_command_req(gem_bs, gem_bs.get_tasks()[*ix].command());		
+			let (n, mem) = self.task_req(gem_bs, *ix);		
 			if tmem > mem { tmem -= mem }
 			else { tmem = 0 }
 			if n < avail { avail -= n }
@@ -165,17 +322,38 @@ impl<'a> Scheduler<'a> {
 		(avail + 0.0001, tmem)
 	}
 	
-	fn add_task(&mut self, gem_bs: &mut GemBS, ix: usize) -> Result<(), SchedulerError> {
-		let lock = self.lock.as_ref().unwrap();
-		let mut running: Vec<RunningTask> = if lock.path().exists() {
+	// Read the run queue file, noting tasks from our list that are being run by other processes
+	fn read_queue(&mut self, gem_bs: &GemBS, lock: &FileLock) -> Result<Vec<RunningTask>, SchedulerError> {
+		let running: Vec<RunningTask> = if lock.path().exists() {
 			let reader = lock.reader()
 				.map_err(|e| SchedulerError::IoErr{desc: format!("Error: Could not open JSON config file {} for reading: {}", lock.path().to_string_lossy(), e)})?;
 			 serde_json::from_reader(reader)
 				.map_err(|e| SchedulerError::IoErr{desc: format!("Error: failed to read JSON config file {}: {}", lock.path().to_string_lossy(), e)})?
 		} else { Vec::new() };	
+		let tasks = gem_bs.get_tasks();
+		let local = self.running.borrow();
+		for ix in self.task_list.iter().filter(|ix| !local.contains(ix)) {
+			if running.iter().any(|x| x.id() == tasks[*ix].id()) { self.external.insert(*ix); }
+		}
+		Ok(running)
+	}
+	// Check whether any tasks are listed in the run queue file by other processes
+	fn queue_busy(&mut self, gem_bs: &GemBS) -> Result<bool, SchedulerError> {
+		let path = self.path.clone();
+		let lock = utils::timed_wait_for_lock(gem_bs.get_signal_clone(), &path).map_err(|e| SchedulerError::IoErr{desc: e})?;
+		let running = self.read_queue(gem_bs, &lock)?;
+		let tasks = gem_bs.get_tasks();
+		let local = self.running.borrow();
+		Ok(running.iter().any(|x| !local.iter().any(|ix| tasks[*ix].id() == x.id())))
+	}
+	// Record task in the run queue file.  Returns TaskTaken if the task has been started by another process
+	fn add_task(&mut self, gem_bs: &GemBS, ix: usize) -> Result<(), SchedulerError> {
+		let path = self.path.clone();
+		let lock = utils::timed_wait_for_lock(gem_bs.get_signal_clone(), &path).map_err(|e| SchedulerError::IoErr{desc: e})?;
+		let mut running = self.read_queue(gem_bs, &lock)?;
 		let task = &gem_bs.get_tasks()[ix];
 		trace!("Scheduling task {} with status {:?}", task.id(), task.status());
-		if running.iter().any(|x| x.id() == task.id()) { return Err(SchedulerError::TaskTaken) }
+		if self.external.contains(&ix) { return Err(SchedulerError::TaskTaken) }
 		running.push(RunningTask::from_task(task));
 		let writer = lock.writer().map_err(|e| SchedulerError::IoErr{desc: format!("Error: Could not open JSON config file {} for writing: {}", lock.path().to_string_lossy(), e)})?;
 		serde_json::to_writer_pretty(writer, &running).map_err(|e| SchedulerError::IoErr{desc: format!("Error: failed to write JSON config file {}: {}", lock.path().to_string_lossy(), e)})?;		
@@ -183,60 +361,50 @@ impl<'a> Scheduler<'a> {
 		Ok(())
 	}
 	
-	fn get_task(&mut self, gem_bs: &mut GemBS) -> Result<RunJob, SchedulerError> {
-		if self.task_list.is_empty() { 
-			self.drop_lock();
-			return Err(SchedulerError::NoTasks)
-		}
-		if self.lock.is_none() {return Err(SchedulerError::NoLock)}
-		let lock = self.lock.as_ref().unwrap();
-		let running_jobs = lock.path().exists();
-		let lmod_time = match lock.path().metadata() {
-			Ok(md) => md.modified().ok(),
-			_ => None
-		};
+	// Select the largest ready task (in terms of cores) that fits in the available cores and memory
+	fn get_task(&mut self, gem_bs: &GemBS) -> Result<RunJob, SchedulerError> {
+		if self.pending() == 0 { return Err(SchedulerError::NoTasks) }
 		let (avail_slots, avail_mem) = self.get_avail_slots_mem(gem_bs);		
 		debug!("Avail slots: {}, avail memory: {:.1} GB", avail_slots, (avail_mem as f64) / 1073741824.0);
-
-		let mut task_idx = None;
-		let mut avail_tasks = true;
-		let mut max = 0.0;
-		if avail_slots > 0.0 {
-			avail_tasks = false;
-			for ix in self.task_list.iter() {
-				let task = &gem_bs.get_tasks()[*ix];
-				debug!("Task {}: {:?}", task.id(), task.status());
-				if let Some(TaskStatus::Ready) = task.status() {
-					avail_tasks = true;
-					let (n, mem) = get_command_req(gem_bs, gem_bs.get_tasks()[*ix].command());
-
-					if n <= avail_slots && mem <= avail_mem && n > max { 
-						max = n; 
-						task_idx = Some(*ix);
-					}
-				} 
+		loop {
+			let mut task_idx = None;
+			let mut avail_tasks = false;
+			let mut max = 0.0;
+			for ix in self.task_list.iter().filter(|ix| self.is_ready(gem_bs, **ix)) {
+				avail_tasks = true;
+				let (n, mem) = self.task_req(gem_bs, *ix);
+				if n <= avail_slots && mem <= avail_mem && n > max { 
+					max = n; 
+					task_idx = Some(*ix);
+				}
 			}
-		}
-		if let Some(x) = task_idx { 
-		  debug!("Tasks available to run");
-			self.add_task(gem_bs, x)?;
-			let task = &gem_bs.get_tasks()[x];
-			let runlist = Rc::clone(&self.running);			
-			let rj = RunJob{id: task.id().to_string(), task_idx: x, path: self.lock.as_ref().unwrap().path().to_owned(), runlist, signal: gem_bs.get_signal_clone() };
-			self.drop_lock();
-			Ok(rj) 
-		} else {
-		  debug!("No tasks available to run");
-			self.drop_lock();	
-			if avail_tasks { 
-		    debug!("No slots");
+			if let Some(x) = task_idx { 
+				debug!("Tasks available to run");
+				match self.add_task(gem_bs, x) {
+					// Task has been started by another process since we last scanned - try again
+					Err(SchedulerError::TaskTaken) => {
+						debug!("Task {} taken by another process", gem_bs.get_tasks()[x].id());
+						continue
+					},
+					Err(e) => return Err(e),
+					Ok(_) => (),
+				}
+				let task = &gem_bs.get_tasks()[x];
+				let runlist = Rc::clone(&self.running);			
+				return Ok(RunJob{id: task.id().to_string(), task_idx: x, path: self.path.clone(), runlist, signal: gem_bs.get_signal_clone() }) 
+			} else if avail_tasks { 
+				debug!("No slots");
 				self.state = SchedState::NoSlots;
-				Err(SchedulerError::NoSlots)
-			} else if !running_jobs { Err(SchedulerError::NoTasksReady) }
-			else {
-		    debug!("No tasks");
-				self.state = SchedState::Waiting(lmod_time);
-				Err(SchedulerError::WaitingForTasks)
+				return Err(SchedulerError::NoSlots)
+			} else {
+				// Nothing ready.  If tasks are running here or elsewhere then wait for the run queue to change.
+				// If tasks that were running elsewhere have since finished then rescan immediately
+				let mod_time = self.queue_mod_time();
+				let busy = !self.is_empty() || self.queue_busy(gem_bs)?;
+				if !busy && self.external.is_empty() { return Err(SchedulerError::NoTasksReady) }
+				debug!("Waiting for running tasks");
+				self.state = SchedState::Waiting(if busy { mod_time } else { None });
+				return Err(SchedulerError::WaitingForTasks)
 			}
 		}
 	}
@@ -265,11 +433,13 @@ pub struct QPipe {
 	output: Option<PathBuf>,
 	log: Option<PathBuf>,
 	remove_log: bool,
+	history: Option<(PathBuf, TaskRecord)>,
+	manifest: Option<ManifestUpdate>,
 	sig: Arc<AtomicUsize>,
 } 
 
 impl QPipe {
-	pub fn new(sig: Arc<AtomicUsize>) -> Self { QPipe{ stages: QPipeStage::None, remove: Vec::new(), outputs: Vec::new(), output: None, log: None, remove_log: true, sig} }
+	pub fn new(sig: Arc<AtomicUsize>) -> Self { QPipe{ stages: QPipeStage::None, remove: Vec::new(), outputs: Vec::new(), output: None, log: None, remove_log: true, history: None, manifest: None, sig} }
 	pub fn add_stage(&mut self, path: &Path, args: &str) -> &mut Self {
 		let stage = (path.to_owned(), args.to_owned());
 		match &mut self.stages {
@@ -290,6 +460,9 @@ impl QPipe {
 	pub fn get_remove_iter(&self) -> std::slice::Iter<'_, PathBuf> {self.remove.iter() }
 	pub fn get_outputs_iter(&self) -> std::slice::Iter<'_, PathBuf> {self.outputs.iter() }
 	pub fn set_output(&mut self, out: Option<PathBuf>) { self.output = out; }
+	pub fn set_history(&mut self, path: PathBuf, rec: TaskRecord) { self.history = Some((path, rec)); }
+	pub fn set_manifest(&mut self, update: ManifestUpdate) { self.manifest = Some(update); }
+	pub fn set_attempt(&mut self, attempt: usize) { if let Some((_, rec)) = self.history.as_mut() { rec.set_attempt(attempt) } }
 }
 
 fn handle_job(gem_bs: &GemBS, options: &HashMap<&'static str, DataValue>, job: usize) -> QPipe {
@@ -299,7 +472,7 @@ fn handle_job(gem_bs: &GemBS, options: &HashMap<&'static str, DataValue>, job: u
 			fs::create_dir_all(par).expect("Could not create required output directories for command");
 		}
 	}
-	match task.command() {
+	let mut qpipe = match task.command() {
 		Command::Index => index::make_index_pipeline(gem_bs, options, job),
 		Command::Map => map::make_map_pipeline(gem_bs, options, job),
 		Command::MergeBams => map::make_merge_bams_pipeline(gem_bs, options, job),
@@ -308,11 +481,31 @@ fn handle_job(gem_bs: &GemBS, options: &HashMap<&'static str, DataValue>, job: u
 		Command::IndexBcf => call::make_index_bcf_pipeline(gem_bs, job),
 		Command::MD5SumMap | Command::MD5SumCall => md5sum::make_md5sum_pipeline(gem_bs, job),
 		Command::Extract => extract::make_extract_pipeline(gem_bs, job),
+		Command::Dmr => dmr::make_dmr_pipeline(gem_bs, job),
 		Command::MapReport => report::make_map_report_pipeline(gem_bs, job),
 		Command::CallReport => report::make_call_report_pipeline(gem_bs, job),
 		Command::Report => report::make_report_pipeline(gem_bs, job),
 		Command::MergeCallJsons => report::make_merge_call_jsons_pipeline(gem_bs, job),
+	};
+	qpipe.set_history(gem_bs.get_history_file_path(), TaskRecord::new(task.id(), &format!("{:#}", task.command()), task.args()));
+	let mut update = ManifestUpdate::new(gem_bs.get_manifest_file_path(), task.id(), get_task_opts(gem_bs, job));
+	if gem_bs.hash_check() {
+		let outputs = task.outputs().filter_map(|x| gem_bs.get_asset(*x)).filter(|a| a.asset_type() != AssetType::Log)
+			.map(|a| (a.path().to_owned(), a.parents().iter().filter_map(|x| gem_bs.get_asset(*x)).map(|b| b.path().to_owned()).collect())).collect();
+		update.set_hash(outputs, format!("{:#} {}", task.command(), task.args()));
 	}
+	qpipe.set_manifest(update);
+	qpipe
+}
+
+// CPU time used by the calling thread (for internal commands)
+fn thread_usage(command: &str) -> StageUsage {
+	let mut ru: libc::rusage = unsafe { std::mem::zeroed() };
+	if unsafe { libc::getrusage(libc::RUSAGE_THREAD, &mut ru) } == 0 {
+		let mut usage = StageUsage::from_rusage(command, None, Some(&ru));
+		usage.max_rss = None;
+		usage
+	} else { StageUsage::from_rusage(command, None, None) }
 }
 
 fn worker_thread(tx: mpsc::Sender<isize>, rx: mpsc::Receiver<Option<QPipe>>, idx: isize) -> Result<(), String> {
@@ -324,6 +517,8 @@ fn worker_thread(tx: mpsc::Sender<isize>, rx: mpsc::Receiver<Option<QPipe>>, idx
 				let out_list: Vec<_> = qpipe.get_outputs_iter().cloned().collect();
 				debug!("Worker thread {} received job: {:?}", idx, qpipe);
 				let log = &qpipe.log.to_owned();
+				let mut history = qpipe.history;
+				let manifest = qpipe.manifest;
 				let res = match qpipe.stages {
 					QPipeStage::External(stages) => {
 						let mut pipeline = Pipeline::new();
@@ -335,9 +530,11 @@ fn worker_thread(tx: mpsc::Sender<isize>, rx: mpsc::Receiver<Option<QPipe>>, idx
 						trace!("Launching external pipeline");
 						let res = pipeline.run(qpipe.sig);
 						trace!("External pipeline ended");
+						if let Some((_, rec)) = history.as_mut() { rec.add_stages(pipeline.stage_usage()) }
 						res
 					},
 					QPipeStage::Internal(com) => {
+						let start_usage = thread_usage("");
 						let ret = match com {
 							QPipeCom::MergeCallJsons(x) => report::merge_call_jsons(Arc::clone(&qpipe.sig), &qpipe.outputs, &x),
 							QPipeCom::MapReport((prj, cdir, thresh, nc, x)) => make_map_report::make_map_report(Arc::clone(&qpipe.sig), &qpipe.outputs, prj, &cdir, thresh, nc, x),
@@ -353,10 +550,20 @@ fn worker_thread(tx: mpsc::Sender<isize>, rx: mpsc::Receiver<Option<QPipe>>, idx
 								}
 							}
 						} 
+						if let Some((_, rec)) = history.as_mut() { 
+							let mut usage = thread_usage(rec.command());
+							usage.user_time -= start_usage.user_time;
+							usage.sys_time -= start_usage.sys_time;
+							rec.add_stages(&[usage]);
+						}
 						ret
 					},
 					QPipeStage::None => Err("No pipeline stages".to_string())
 				};
+				if let Some((path, mut rec)) = history.take() {
+					rec.finish(res.as_ref().err().map(|e| e.as_str()));
+					if let Err(e) = append_history(&path, &rec) { warn!("Could not record task history: {}", e); }
+				}
 				match res {
 					Ok(_) => {
 						debug!("Worker thread {} finished job", idx);
@@ -368,6 +575,10 @@ fn worker_thread(tx: mpsc::Sender<isize>, rx: mpsc::Receiver<Option<QPipe>>, idx
 								}
 							}
 						}
+						// Update manifest before removing files as these may be task inputs
+						if let Some(update) = manifest {
+							if let Err(e) = update.record() { warn!("Could not update manifest: {}", e); }
+						}
 						for p in rm_list.iter() {
 							trace!("Removing file {} after normal task completion", p.display());
 							if let Err(e) = std::fs::remove_file(&p) {
@@ -377,9 +588,9 @@ fn worker_thread(tx: mpsc::Sender<isize>, rx: mpsc::Receiver<Option<QPipe>>, idx
 						tx.send(idx).expect("Error sending message to parent")
 					},
 					Err(e) => {
+						// The worker is kept alive as the scheduler may retry the task
+						debug!("Worker thread {} finished job with error {}", idx, e);
 						tx.send(-(idx + 1)).expect("Error sending message to parent");
-						debug!("Worker thread {} shutting down after error {}", idx, e);
-						return Err(e);
 					},
 				}			
 			},
@@ -403,20 +614,95 @@ struct Worker {
 	ix: usize,
 }
 
-pub fn schedule_jobs(gem_bs: &mut GemBS, options: &HashMap<&'static str, DataValue>, task_list: &[usize], asset_ids: &[usize], com_set: &[Command], flock: FileLock) -> Result<(), String> {
+// Live status line for the local scheduler.  Only active if stderr is a terminal
+struct StatusLine {
+	active: bool,
+	width: usize,
+	completed: usize,
+}
+
+impl StatusLine {
+	fn new() -> Self { StatusLine{active: unsafe { libc::isatty(2) } != 0, width: 0, completed: 0} }
+	fn job_done(&mut self) { self.completed += 1 }
+	fn update(&mut self, gem_bs: &GemBS, sched: &Scheduler) {
+		if !self.active { return }
+		let (avail_slots, avail_mem) = sched.get_avail_slots_mem(gem_bs);
+		let ncpus = num_cpus::get() as f64;
+		let total_mem = gem_bs.total_mem();
+		let tasks = gem_bs.get_tasks();
+		let ready = sched.task_list.iter().filter(|ix| sched.is_ready(gem_bs, **ix)).count();
+		let running = sched.running.borrow();
+		let mut line = format!("gemBS: {} completed, {} running, {} ready | cores {:.0}/{:.0}, mem {:.1}/{:.1} GB",
+			self.completed, running.len(), ready, (ncpus - avail_slots).max(0.0), ncpus,
+			((total_mem - avail_mem) as f64) / 1073741824.0, (total_mem as f64) / 1073741824.0);
+		if !running.is_empty() {
+			line.push_str(" |");
+			for ix in running.iter() { line.push_str(format!(" {}", tasks[*ix].id()).as_str()) }
+		}
+		self.write(&line);
+	}
+	fn write(&mut self, line: &str) {
+		let width = line.chars().count();
+		let pad = self.width.saturating_sub(width);
+		eprint!("\r{}{:pad$}", line, "", pad = pad);
+		let _ = std::io::stderr().flush();
+		self.width = width;
+	}
+	fn clear(&mut self) {
+		if self.active && self.width > 0 {
+			eprint!("\r{:w$}\r", "", w = self.width);
+			self.width = 0;
+		}
+	}
+}
+
+// Handle completion message from worker thread.  Returns false if the scheduler should abort
+fn job_finished(gem_bs: &GemBS, sched: &mut Scheduler, jobs: &mut Vec<(RunJob, isize)>, avail: &mut Vec<isize>, status: &mut StatusLine, x: isize) -> bool {
+	let (worker, ok) = if x >= 0 { (x, true) } else { (-(x + 1), false) };
+	let task_idx = match jobs.iter().position(|(_, ix)| *ix == worker) {
+		Some(i) => jobs.remove(i).0.task_idx,
+		None => {
+			error!("Unknown job for worker thread {}", worker);
+			sched.state = SchedState::Abort;
+			return false
+		},
+	};
+	if ok {
+		debug!("Job completion by worker thread {}", worker);
+		sched.task_done(task_idx);
+		status.job_done();
+	} else {
+		error!("Error received from worker thread {}", worker);
+		if !sched.task_failed(gem_bs, task_idx) {
+			sched.state = SchedState::Abort;
+			return false
+		}
+	}
+	avail.push(worker);
+	status.update(gem_bs, sched);
+	sched.state = SchedState::Ready;
+	true
+}
+
+pub fn schedule_jobs(gem_bs: &mut GemBS, options: &HashMap<&'static str, DataValue>, task_list: &[usize], flock: FileLock) -> Result<(), String> {
 	gem_bs.check_signal()?;
-	let tlist: Vec<_> = task_list.iter().copied().collect();
-	debug!("Schedule_jobs started with {} tasks", tlist.len());
-	let mut sched = Scheduler::new(tlist);
-	let task_path = flock.path();
-	sched.set_lock(flock);
+	debug!("Schedule_jobs started with {} tasks", task_list.len());
+	// The lock on the run queue is only required for the initial scan of the assets and tasks 
+	let task_path = flock.path().to_owned();
+	drop(flock);
+	let mut sched = Scheduler::new(gem_bs, task_list.to_vec(), task_path);
 	
-	// Set up workers
+	// Set up workers - one for each job that can be run in parallel
+	let n_workers = match options.get("_max_jobs") {
+		Some(DataValue::Int(x)) if *x > 0 => *x,
+		_ => 8,
+	};
 	let (ctr_tx, ctr_rx) = mpsc::channel();
 	let mut avail = Vec::new();
 	let mut workers = Vec::new();
 	let mut jobs = Vec::new();
-	for ix in 0..8 {
+	let mut status = StatusLine::new();
+	for ix in 0..n_workers {
 		let (tx, rx) = mpsc::channel();
 		let ctr = mpsc::Sender::clone(&ctr_tx);
 		let handle = thread::spawn(move || { worker_thread(ctr, rx, ix)});
@@ -428,95 +714,53 @@ pub fn schedule_jobs(gem_bs: &mut GemBS, options: &HashMap<&'static str, DataVal
 			info!("{}", e);
 			break;
 		}
+		// Launch ready tasks until we run out of workers or resources
 		let worker_ix = match sched.state {
 			SchedState::Ready => avail.pop(),
-			SchedState::Waiting(t) => {
-				// Check if run file has changed state since we entered SchedState::Waiting
-				let mt = match task_path.metadata() {
-					Ok(md) => md.modified().ok(),
-					_ => None,
-				};
-				if match (t, mt) {
-					(Some(x), Some(y)) => y > x,
-					_ => true, 
-				} {
-					sched.state = SchedState::Ready;
-					avail.pop()
-				} else { None }
+			SchedState::Waiting(t) if sched.queue_changed(t) => {
+				// Run queue has changed, so tasks may have been completed (or failed) elsewhere
+				if let Err(e) = sched.rescan(gem_bs) {
+					error!("Could not rescan run queue: {}", e);
+					sched.state = SchedState::Abort;
+					break;
+				}
+				sched.state = SchedState::Ready;
+				avail.pop()
 			},
 			_ => None,
 		};
 		if let Some(idx) =  worker_ix {
-			if !sched.check_lock() {
-				let flock = utils::wait_for_lock(gem_bs.get_signal_clone(), &task_path)?;
-				gem_bs.rescan_assets_and_tasks(&flock)?;
-				if !asset_ids.is_empty() {
-					let tlist = gem_bs.get_required_tasks_from_asset_list(asset_ids, com_set);
-					sched.set_task_list(tlist);
-				}
-				sched.set_lock(flock);
-			}	
 			match sched.get_task(gem_bs) {
 				Ok(job) => {
-					let qpipe = handle_job(gem_bs, options, job.task_idx);
+					let mut qpipe = handle_job(gem_bs, options, job.task_idx);
+					qpipe.set_attempt(sched.attempt(job.task_idx));
 					jobs.push((job, idx));					
 					workers[idx as usize].tx.send(Some(qpipe)).expect("Error sending new command to worker thread");
+					status.update(gem_bs, &sched);
 				},
-				Err(SchedulerError::NoSlots) => {
-					debug!("No execution slots");
-					thread::sleep(time::Duration::from_millis(1000));
-					avail.push(idx);
-				},
-				Err(SchedulerError::WaitingForTasks) | Err(SchedulerError::NoTasksReady) => {
-					debug!("Waiting for tasks");
-					thread::sleep(time::Duration::from_millis(1000));
+				Err(SchedulerError::NoSlots) | Err(SchedulerError::WaitingForTasks) => {
+					debug!("Waiting for running tasks to complete");
 					avail.push(idx);
 				},
 				Err(SchedulerError::NoTasks) => {
 					debug!("No tasks to do");
 					break;
 				},	
-				Err(e) => {
-					error!("Scheduler thread received error: {}", e);
-					break;					
-				},
-			}
-			match ctr_rx.try_recv() {
-				Ok(x) if x >= 0 => {
-					debug!("Job completion by worker thread {}", x);
-					jobs.retain(|(_, ix)| *ix != x);
-					avail.push(x);
-					sched.state = SchedState::Ready;
-				},
-				Ok(x) => {
-					let x1 = -(x+1);
-					error!("Error received from worker thread {}", x1);
-					jobs.retain(|(_, ix)| *ix != x1);
+				Err(SchedulerError::NoTasksReady) => {
+					error!("{} tasks can not be run as their dependencies are not complete", sched.pending());
 					sched.state = SchedState::Abort;
-					break;
+					break;					
 				},
-				Err(mpsc::TryRecvError::Empty) => {},
 				Err(e) => {
 					error!("Scheduler thread received error: {}", e);
 					sched.state = SchedState::Abort;
-					break;
-				}				
+					break;					
+				},
 			}
-							
 		} else if !sched.is_empty() {
+			// Wait for a running task to complete
 			match ctr_rx.recv_timeout(time::Duration::from_millis(1000)) {
-				Ok(x) if x >= 0 => {
-					debug!("Job completion by worker thread {}", x);
-					jobs.retain(|(_, ix)| *ix != x);
-					avail.push(x);
-					sched.state = SchedState::Ready;
-				},
-				Ok(x) => {
-					let x1 = -(x+1);
-					error!("Error received from worker thread {}", x1);
-					jobs.retain(|(_, ix)| *ix != x1);
-					break;
-				},
+				Ok(x) => if !job_finished(gem_bs, &mut sched, &mut jobs, &mut avail, &mut status, x) { break },
 				Err(mpsc::RecvTimeoutError::Timeout) => {},
 				Err(e) => {
 					error!("Scheduler thread received error: {}", e);
@@ -524,11 +768,19 @@ pub fn schedule_jobs(gem_bs: &mut GemBS, options: &HashMap<&'static str, DataVal
 					break;
 				}				
 			}
-		} else { thread::sleep(time::Duration::from_secs(5)) }
+		} else if let SchedState::Waiting(_) = sched.state {
+			// Only waiting on tasks run by other processes
+			thread::sleep(time::Duration::from_millis(1000));
+		} else { 
+			// No workers available and nothing running (should not happen)
+			error!("No worker threads available");
+			sched.state = SchedState::Abort;
+			break;
+		}
 	}
 	// If a signal has been caught, we still want to wait for the jobs to complete if possible
 	// but we will abort if a second signal is caught.
-    sched.drop_lock();
+	status.clear();
 	let mut signal = gem_bs.swap_signal(0);
 	debug!("Job loop finished - cleaning up");
 	if !sched.is_empty() { debug!("Waiting for running jobs to finish") }
@@ -574,28 +826,52 @@ pub fn schedule_jobs(gem_bs: &mut GemBS, options: &HashMap<&'static str, DataVal
 	else { Ok(()) }
 }
 
+// Value of config option as passed to command (Bool options are returned as "true" if set)
+fn get_opt_value(gem_bs: &GemBS, sec: Section, name: &str, vtype: VarType) -> Option<String> {
+	match vtype {
+		VarType::Bool => if gem_bs.get_config_bool(sec, name) { Some("true".to_string()) } else { None },
+		VarType::Int => gem_bs.get_config_int(sec, name).map(|i| format!("{}", i)),
+		VarType::IntVec => gem_bs.get_config_intvec(sec, name).map(|v| v.iter().map(|i| format!("{}", i)).collect::<Vec<_>>().join(",")),
+		VarType::String => gem_bs.get_config_str(sec, name).map(|s| s.to_owned()),
+		VarType::Float => gem_bs.get_config_float(sec, name).map(|z| format!("{}", z)),
+		VarType::FloatVec => if let Some(DataValue::FloatVec(v)) = gem_bs.get_config(sec, name) {
+			Some(v.iter().map(|z| format!("{}", z)).collect::<Vec<_>>().join(","))
+		} else { None },
+		_ => None,
+	}
+}
+
 pub fn add_command_opts(gem_bs: &GemBS, args: &mut String, sec: Section, opt_list: &[(&'static str, &'static str, VarType)]) {
 	for (x, y, t) in opt_list.iter() {
-		match t {
-			VarType::Bool => if gem_bs.get_config_bool(sec, x) { 
-				args.push_str(format!("--{}\x1e", y).as_str()) },
-			VarType::Int => if let Some(i) = gem_bs.get_config_int(sec, x) { 
-				args.push_str(format!("--{}\x1e{}\x1e", y, i).as_str()) },
-			VarType::IntVec => if let Some(v) = gem_bs.get_config_intvec(sec, x) { 
-				if v.len() == 1 { args.push_str(format!("--{}\x1e{}\x1e", y, v[0]).as_str()) }
-				else {
-					args.push_str(format!("--{}\x1e{}", y, v[0]).as_str());
-					for i in v[1..].iter() { args.push_str(format!(",{}", i).as_str()) }
-					args.push('\x1e');
-				}
-			},
-			VarType::String => if let Some(s) = gem_bs.get_config_str(sec, x) { 
-				args.push_str(format!("--{}\x1e{}\x1e", y, s).as_str()) },
-			VarType::Float => if let Some(z) = gem_bs.get_config_float(sec, x) { 
-				args.push_str(format!("--{}\x1e{}\x1e", y, z).as_str()) },
-			_ => (),
+		if let Some(v) = get_opt_value(gem_bs, sec, x, *t) {
+			if let VarType::Bool = t { args.push_str(format!("--{}\x1e", y).as_str()) }
+			else { args.push_str(format!("--{}\x1e{}\x1e", y, v).as_str()) }
 		}
 	}
 }
 
+// Config options that only affect resource usage and so are not included in the task options
+const RESOURCE_OPTS: [&str; 4] = ["threads", "jobs", "dbsnp_jobs", "call_threads"];
+
+// Effective options for a command as (config variable, value) pairs
+pub fn get_command_opts(gem_bs: &GemBS, sec: Section, opt_list: &[(&'static str, &'static str, VarType)]) -> Vec<(String, String)> {
+	opt_list.iter().filter(|(x, _, _)| !RESOURCE_OPTS.contains(x))
+		.filter_map(|(x, _, t)| get_opt_value(gem_bs, sec, x, *t).map(|v| ((*x).to_string(), v))).collect()
+}
+
+// Options used for a task.  If these change between runs then the task outputs are considered to be outdated
+pub fn get_task_opts(gem_bs: &GemBS, job: usize) -> Vec<(String, String)> {
+	let task = &gem_bs.get_tasks()[job];
+	let mut opts = match task.command() {
+		Command::Index => index::get_index_opts(gem_bs, job),
+		Command::Map => map::get_map_opts(gem_bs),
+		Command::Call => call::get_call_opts(gem_bs),
+		Command::Extract => extract::get_extract_opts(gem_bs, job),
+		Command::Dmr => dmr::get_dmr_opts(gem_bs, job),
+		_ => Vec::new(),
+	};
+	if !task.args().is_empty() { opts.push(("args".to_string(), task.args().to_owned())) }
+	opts
+}
+
 
@@ -2,12 +2,14 @@ use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::fs;
 use std::io::{BufWriter, Write, BufReader};
+use std::str::FromStr;
 use regex::Regex;
+use utils::chemistry::Chemistry;
 use lazy_static::lazy_static;
 
 use crate::config::GemBS;
 use crate::common::assets::GetAsset;
-use crate::common::defs::{DataValue, Section, VarType};
+use crate::common::defs::{DataValue, Section, VarType, Metadata};
 use crate::common::tasks::Task;
 use crate::common::json_map_stats::{MapJson, BaseCounts, Counts};
 use super::QPipe;
@@ -75,11 +77,21 @@ pub fn calc_conversion(cts: &BaseCounts<Counts>) -> Option<f64> {
 		Some(a / b)	
 	} else { None }
 }
+// The chemistry is checked when the configuration is read
+fn get_chemistry(gem_bs: &GemBS) -> Chemistry {
+	gem_bs.get_config_str(Section::Calling, "chemistry").and_then(|s| Chemistry::from_str(s).ok()).unwrap_or(Chemistry::Bisulfite)
+}
+
 fn get_conversion_rate(gem_bs: &GemBS, barcode: &str) -> (f64, f64) {
+	let chemistry = get_chemistry(gem_bs);
+	let defaults = chemistry.default_conversion();
+	let taps = chemistry.converts_methylated();
 	let (mut under, mut over) = if gem_bs.get_config_bool(Section::Calling, "auto_conversion") {	
 		let json_files = gem_bs.get_mapping_json_files_for_barcode(barcode);
 		let mut counts = [BaseCounts::<Counts>::new(), BaseCounts::<Counts>::new()];
 		for f in json_files.iter() { add_conversion_counts(gem_bs, *f, &mut counts); }
+		// With TAPS it is the methylated control that is converted
+		if taps { counts.swap(0, 1) }
 		// Do some sanity checking to avoid using crazy values.
 		let under = calc_conversion(&counts[0]).and_then(|z| {
 			if z < 0.9 { None }
@@ -98,29 +110,58 @@ fn get_conversion_rate(gem_bs: &GemBS, barcode: &str) -> (f64, f64) {
 		if under.is_none() && !v.is_empty() { under = Some(v[0]) }
 		if over.is_none() && v.len() > 1 { over = Some(v[1]) }
 	}
-	(under.unwrap_or(0.01), over.unwrap_or(0.05))
+	(under.unwrap_or(defaults.0), over.unwrap_or(defaults.1))
+}
+
+lazy_static! {
+	static ref OPT_LIST: Vec<(&'static str, &'static str, VarType)> = {
+		let mut m = Vec::new();
+		m.push(("left_trim", "left-trim", VarType::IntVec));
+		m.push(("right_trim", "right-trim", VarType::IntVec));
+		m.push(("keep_unmatched", "keep-unmatched", VarType::Bool));
+		m.push(("keep_duplicates", "keep-duplicates", VarType::Bool));
+		m.push(("keep_improper_pairs", "keep-unmatched", VarType::Bool));
+		m.push(("ignore_duplicate_flag", "ignore-duplicates", VarType::Bool));
+		m.push(("benchmark_mode", "benchmark-mode", VarType::Bool));
+		m.push(("haploid", "haploid", VarType::Bool));
+		m.push(("rrbs", "rrbs", VarType::Bool));
+		m.push(("dbsnp_priors", "dbsnp-priors", VarType::Bool));
+		m.push(("ploidy_map", "ploidy-map", VarType::String));
+		m.push(("chemistry", "chemistry", VarType::String));
+		m.push(("reference_bias", "reference-bias", VarType::Float));
+		m.push(("mapq_threshold", "mapq-threshold", VarType::Int));
+		m.push(("qual_threshold", "bq-threshold", VarType::Int));
+		m.push(("dbsnp_index", "dbsnp", VarType::String));
+		m
+	};
+}
+
+// Options affecting the output of the calling step.  The conversion rates are
+// represented by the config options used to derive them
+pub fn get_call_opts(gem_bs: &GemBS) -> Vec<(String, String)> {
+	let mut opts = super::get_command_opts(gem_bs, Section::Calling, &OPT_LIST);
+	opts.extend(super::get_command_opts(gem_bs, Section::Calling, &[("auto_conversion", "", VarType::Bool), ("conversion", "", VarType::FloatVec)]));
+	opts
+}
+
+// Sex of sample from the metadata (if present) normalized to male / female
+fn get_sample_sex(gem_bs: &GemBS, barcode: &str) -> Option<&'static str> {
+	for hr in gem_bs.get_sample_data_ref().values() {
+		if let (Some(DataValue::String(bc)), Some(DataValue::String(s))) = (hr.get(&Metadata::SampleBarcode), hr.get(&Metadata::Sex)) {
+			if bc == barcode {
+				match s.to_lowercase().as_str() {
+					"m" | "male" => return Some("male"),
+					"f" | "female" => return Some("female"),
+					_ => warn!("Unknown sex '{}' for sample {} (ignored)", s, barcode),
+				}
+			}
+		}
+	}
+	None
 }
 
 pub fn make_call_pipeline(gem_bs: &GemBS, job: usize) -> QPipe
 {
-	lazy_static! {
-    	static ref OPT_LIST: Vec<(&'static str, &'static str, VarType)> = {
-        	let mut m = Vec::new();
-        	m.push(("left_trim", "left-trim", VarType::IntVec));
-        	m.push(("right_trim", "right-trim", VarType::IntVec));
-        	m.push(("keep_unmatched", "keep-unmatched", VarType::Bool));
-        	m.push(("keep_duplicates", "keep-duplicates", VarType::Bool));
-        	m.push(("keep_improper_pairs", "keep-unmatched", VarType::Bool));
-        	m.push(("ignore_duplicate_flag", "ignore-duplicates", VarType::Bool));
-        	m.push(("benchmark_mode", "benchmark-mode", VarType::Bool));
-	       	m.push(("haploid", "haploid", VarType::Bool));
-	       	m.push(("reference_bias", "reference-bias", VarType::Float));
-	       	m.push(("mapq_threshold", "mapq-threshold", VarType::Int));
-	       	m.push(("qual_threshold", "bq-threshold", VarType::Int));
-	       	m.push(("dbsnp_index", "dbsnp", VarType::String));
-			m
-		};
-	}
 	let threads = gem_bs.get_config_int(Section::Calling, "threads");
 	let call_threads = gem_bs.get_config_int(Section::Calling, "call_threads").or(threads);
 	let mut pipeline = QPipe::new(gem_bs.get_signal_clone());
@@ -145,6 +186,7 @@ pub fn make_call_pipeline(gem_bs: &GemBS, job: usize) -> QPipe
 	}
 	if let Some(t) = call_threads { args.push_str(format!("--threads\x1e{}\x1e", t).as_str()); }
 	args.push_str(format!("--conversion\x1e{},{}\x1e", under, over).as_str());
+	if let Some(s) = get_sample_sex(gem_bs, barcode) { args.push_str(format!("--sex\x1e{}\x1e", s).as_str()); }
 	super::add_command_opts(gem_bs, &mut args, Section::Calling, &OPT_LIST);
 	args.push_str(&gem_bs.get_asset(in_bam).unwrap().path().to_string_lossy());
 
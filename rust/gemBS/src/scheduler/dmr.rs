@@ -0,0 +1,133 @@
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::io::{BufWriter, Write};
+use regex::Regex;
+use lazy_static::lazy_static;
+
+use crate::config::GemBS;
+use crate::common::assets::GetAsset;
+use crate::common::defs::{Section, VarType};
+use crate::common::tasks::Task;
+use super::QPipe;
+use super::extract::make_contig_file;
+
+const DMR_OPT_LIST: [(&str, &str, VarType); 9] = [
+	("threads", "threads", VarType::Int),
+	("reference_bias", "reference-bias", VarType::Float),
+	("dmr_test", "dmr-test", VarType::String),
+	("dmr_min_cov", "dmr-min-cov", VarType::Int),
+	("dmr_min_samples", "dmr-min-samples", VarType::Int),
+	("dmr_fdr", "dmr-fdr", VarType::Float),
+	("dmr_min_diff", "dmr-min-diff", VarType::Float),
+	("dmr_max_gap", "dmr-max-gap", VarType::Int),
+	("dmr_min_cpg", "dmr-min-cpg", VarType::Int),
+];
+
+fn get_command_and_name(id: &str) -> (&str, &str) {
+	lazy_static! { static ref RE: Regex = Regex::new(r"^dmr_(merge|index|call)_(.*)$").unwrap(); }
+	if let Some(cap) = RE.captures(id) {
+		(cap.get(1).unwrap().as_str(), cap.get(2).unwrap().as_str())
+	} else { panic!("Couldn't parse dmr task id") }
+}
+
+// The two groups being compared are given in the task arguments
+fn get_groups(task: &Task) -> (&str, &str) {
+	lazy_static! { static ref RE: Regex = Regex::new(r"--groups (\S+) (\S+)").unwrap(); }
+	if let Some(cap) = RE.captures(task.args()) {
+		(cap.get(1).unwrap().as_str(), cap.get(2).unwrap().as_str())
+	} else { panic!("Couldn't parse groups for dmr task") }
+}
+
+// Groups file for mextr has lines with the sample (barcode) and group, with the first group listed first
+fn make_groups_file(gem_bs: &GemBS, task: &Task, name: &str, output_dir: &Path) -> PathBuf {
+	let grp_path: PathBuf = [output_dir, Path::new(format!("{}_groups.txt", name).as_str())].iter().collect();
+	let groups = gem_bs.get_sample_groups().unwrap_or_else(|e| panic!("{}", e));
+	let (g1, g2) = get_groups(task);
+	let mut wr = BufWriter::new(fs::File::create(&grp_path)
+		.unwrap_or_else(|e| panic!("Couldn't open groups file {} for output: {}", grp_path.display(), e)));
+	for gp in [g1, g2].iter() {
+		for bc in groups.get(*gp).unwrap_or_else(|| panic!("DMR group {} not found in sample metadata", gp)).iter() {
+			writeln!(wr, "{}\t{}", bc, gp).unwrap_or_else(|e| panic!("Error writing to file {}: {}", grp_path.display(), e))
+		}
+	}
+	grp_path
+}
+
+fn make_merge_pipeline(gem_bs: &GemBS, job: usize) -> QPipe {
+	let threads = gem_bs.get_config_int(Section::Dmr, "threads");
+	let mut pipeline = QPipe::new(gem_bs.get_signal_clone());
+	let bcftools_path = gem_bs.get_exec_path("bcftools");
+	let task = &gem_bs.get_tasks()[job];
+	let output_bcf = gem_bs.get_asset(*task.outputs().next().expect("No output files for dmr merge step")).expect("Couldn't get asset").path();
+
+	// Setup arguments
+	let mut args = format!("merge\x1e--output\x1e{}\x1e--output-type\x1eb\x1e", output_bcf.to_string_lossy());
+	if let Some(t) = threads { args.push_str(format!("--threads\x1e{}\x1e", t).as_str()); }
+	for asset in task.inputs().map(|x| gem_bs.get_asset(*x).expect("Couldn't get asset")).filter(|x| x.id().ends_with(".bcf")) {
+		args.push_str(format!("{}\x1e", asset.path().to_string_lossy()).as_str());
+	}
+	if let Some(x) = task.log() { pipeline.log = Some(gem_bs.get_asset(x).expect("Couldn't get log file").path().to_owned()) }
+	for out in task.outputs() { pipeline.add_outputs(gem_bs.get_asset(*out).expect("Couldn't get dmr merge output asset").path()); }
+	if gem_bs.keep_logs() || gem_bs.get_config_bool(Section::Dmr, "keep_logs") { pipeline.set_remove_log(false) }
+	pipeline.add_stage(&bcftools_path, &args);
+	pipeline
+}
+
+fn make_index_pipeline(gem_bs: &GemBS, job: usize) -> QPipe {
+	let threads = gem_bs.get_config_int(Section::Dmr, "threads");
+	let mut pipeline = QPipe::new(gem_bs.get_signal_clone());
+	let bcftools_path = gem_bs.get_exec_path("bcftools");
+	let task = &gem_bs.get_tasks()[job];
+	let input = gem_bs.get_asset(*task.inputs().next().expect("No input file for dmr index step")).expect("Couldn't get asset").path();
+
+	// Setup arguments
+	let mut args = String::from("index\x1e");
+	if let Some(t) = threads { args.push_str(format!("--threads\x1e{}\x1e", t).as_str()); }
+	args.push_str(&input.to_string_lossy());
+	for out in task.outputs() { pipeline.add_outputs(gem_bs.get_asset(*out).expect("Couldn't get dmr index output asset").path()); }
+	pipeline.add_stage(&bcftools_path, &args);
+	pipeline
+}
+
+fn make_call_pipeline(gem_bs: &GemBS, job: usize, name: &str) -> QPipe {
+	let task = &gem_bs.get_tasks()[job];
+	let first_output = gem_bs.get_asset(*task.outputs().next().expect("No output files for dmr step")).expect("Couldn't get asset").path();
+	let in_bcf = gem_bs.get_asset(*task.inputs().next().expect("No input files for dmr step")).expect("Couldn't get asset").path();
+	let output_dir = first_output.parent().unwrap_or_else(|| Path::new("."));
+	let contig_file = make_contig_file(gem_bs, name, "dmr", output_dir);
+	let groups_file = make_groups_file(gem_bs, task, name, output_dir);
+	let mextr_path = gem_bs.get_exec_path("mextr");
+	let outbase: PathBuf = [output_dir, Path::new(name)].iter().collect();
+
+	// Set up arg list
+	let mut args = format!("--loglevel\x1e{}\x1e--compress\x1e--md5\x1e--regions-file\x1e{}\x1e--dmr\x1e{}\x1e--dmr-groups\x1e{}\x1e",
+		gem_bs.verbose(), contig_file.to_string_lossy(), outbase.to_string_lossy(), groups_file.to_string_lossy());
+	super::add_command_opts(gem_bs, &mut args, Section::Dmr, &DMR_OPT_LIST);
+	args.push_str(&in_bcf.to_string_lossy());
+
+	// Setup mextr pipeline
+	let mut pipeline = QPipe::new(gem_bs.get_signal_clone());
+	if let Some(x) = task.log() { pipeline.log = Some(gem_bs.get_asset(x).expect("Couldn't get log file").path().to_owned()) }
+	for out in task.outputs() { pipeline.add_outputs(gem_bs.get_asset(*out).expect("Couldn't get mextr output asset").path()); }
+	if gem_bs.keep_logs() || gem_bs.get_config_bool(Section::Dmr, "keep_logs") { pipeline.set_remove_log(false) }
+	pipeline.add_remove_file(&groups_file);
+	pipeline.add_stage(&mextr_path, &args).add_remove_file(&contig_file);
+	pipeline
+}
+
+// Options affecting the output of the DMR calling step
+pub fn get_dmr_opts(gem_bs: &GemBS, job: usize) -> Vec<(String, String)> {
+	match get_command_and_name(gem_bs.get_tasks()[job].id()) {
+		("call", _) => super::get_command_opts(gem_bs, Section::Dmr, &DMR_OPT_LIST),
+		_ => Vec::new(),
+	}
+}
+
+pub fn make_dmr_pipeline(gem_bs: &GemBS, job: usize) -> QPipe
+{
+	match get_command_and_name(gem_bs.get_tasks()[job].id()) {
+		("merge", _) => make_merge_pipeline(gem_bs, job),
+		("index", _) => make_index_pipeline(gem_bs, job),
+		(_, name) => make_call_pipeline(gem_bs, job, name),
+	}
+}
@@ -10,8 +10,8 @@ use crate::common::assets::GetAsset;
 use crate::common::defs::{Section, VarType};
 use super::QPipe;
 
-fn make_contig_file(gem_bs: &GemBS, barcode: &str, output_dir: &Path) -> PathBuf {
-	let ctg_path: PathBuf = [output_dir, Path::new(format!("{}_mextr_ctgs.bed", barcode).as_str())].iter().collect();
+pub fn make_contig_file(gem_bs: &GemBS, barcode: &str, com: &str, output_dir: &Path) -> PathBuf {
+	let ctg_path: PathBuf = [output_dir, Path::new(format!("{}_{}_ctgs.bed", barcode, com).as_str())].iter().collect();
 	let vr_ctg = gem_bs.get_contigs();
 	let omit_hash = {
 		if let Some(oc) = gem_bs.get_config_stringvec(Section::Index, "omit_ctgs") {
@@ -27,12 +27,37 @@ fn make_contig_file(gem_bs: &GemBS, barcode: &str, output_dir: &Path) -> PathBuf
 	ctg_path
 }
 
-fn make_mextr_pipeline(gem_bs: &GemBS, job: usize, bc: &str) -> QPipe {
+fn mextr_opt_list(cpg: bool, noncpg: bool, bedmethyl: bool) -> Vec<(&'static str, &'static str, VarType)> {
+	let mut opt_list = Vec::new();
+	opt_list.push(("threads", "threads", VarType::Int));
+   	opt_list.push(("reference_bias", "reference-bias", VarType::Float));
+   	opt_list.push(("qual_threshold", "bq-threshold", VarType::Int));
+	if cpg || noncpg { 
+	  	opt_list.push(("phred_threshold", "threshold", VarType::Int));
+		opt_list.push(("min_inform", "inform", VarType::Int));
+		opt_list.push(("allow_het", "select het", VarType::Bool));
+	}
+	if noncpg { opt_list.push(("min_nc", "min-nc", VarType::Int)); }
+	if cpg { opt_list.push(("strand_specific", "mode\x1estrand-specific", VarType::Bool)); }
+	if bedmethyl { opt_list.push(("bigwig_strand_specific", "bw-mode\x1estrand-specific", VarType::Bool)); }
+	opt_list
+}
+
+fn snpxtr_opt_list() -> Vec<(&'static str, &'static str, VarType)> {
+	let mut opt_list = Vec::new();
+	opt_list.push(("threads", "threads", VarType::Int));
+	opt_list.push(("snp_list", "snps", VarType::String));
+	opt_list.push(("dbsnp_index", "dbsnp", VarType::String));
+	opt_list
+}
+
+// Used for both the mextr and msegment (segmentation) tasks
+fn make_mextr_pipeline(gem_bs: &GemBS, job: usize, com: &str, bc: &str) -> QPipe {
 	let task = &gem_bs.get_tasks()[job];
 	let first_output = gem_bs.get_asset(*task.outputs().next().expect("No output files for extract step")).expect("Couldn't get asset").path();
 	let in_bcf = gem_bs.get_asset(*task.inputs().next().expect("No output files for extract step")).expect("Couldn't get asset").path();
 	let output_dir = first_output.parent().unwrap_or_else(|| Path::new("."));
-	let contig_file = make_contig_file(gem_bs, bc, output_dir);
+	let contig_file = make_contig_file(gem_bs, bc, com, output_dir);
 	let mextr_path = gem_bs.get_exec_path("mextr");
 	
 	// Set up arg list
@@ -50,22 +75,13 @@ fn make_mextr_pipeline(gem_bs: &GemBS, job: usize, bc: &str) -> QPipe {
 			bedmethyl = true;
 			let outbase: PathBuf = [output_dir, Path::new(bc)].iter().collect();	
 			args.push_str(format!("--bed-methyl\x1e{}\x1e", outbase.to_string_lossy()).as_str())
+		} else if oname.ends_with("segments.bed.gz") {
+			let outbase: PathBuf = [output_dir, Path::new(bc)].iter().collect();	
+			args.push_str(format!("--segmentation\x1e{}\x1e", outbase.to_string_lossy()).as_str())
 		}
 	}
-	let mut opt_list = Vec::new();
-	opt_list.push(("threads", "threads", VarType::Int));
-   	opt_list.push(("reference_bias", "reference-bias", VarType::Float));
-   	opt_list.push(("qual_threshold", "bq-threshold", VarType::Int));
-	if cpg || noncpg { 
-		args.push_str("--tabix\x1e");
-	  	opt_list.push(("phred_threshold", "threshold", VarType::Int));
-		opt_list.push(("min_inform", "inform", VarType::Int));
-		opt_list.push(("allow_het", "select het", VarType::Bool));
-	}
-	if noncpg { opt_list.push(("min_nc", "min-nc", VarType::Int)); }
-	if cpg { opt_list.push(("strand_specific", "mode\x1estrand-specific", VarType::Bool)); }
-	if bedmethyl { opt_list.push(("bigwig_strand_specific", "bw-mode\x1estrand-specific", VarType::Bool)); }
-	super::add_command_opts(gem_bs, &mut args, Section::Extract, &opt_list);
+	if cpg || noncpg { args.push_str("--tabix\x1e"); }
+	super::add_command_opts(gem_bs, &mut args, Section::Extract, &mextr_opt_list(cpg, noncpg, bedmethyl));
 	args.push_str(&in_bcf.to_string_lossy());
 
 	// Setup mextr pipeline
@@ -85,11 +101,7 @@ fn make_snpxtr_pipeline(gem_bs: &GemBS, job: usize) -> QPipe {
 
 	// Set up arg list
 	let mut args = format!("--loglevel\x1e{}\x1e--compress\x1e--md5\x1e--tabix\x1e--output\x1e{}\x1e", gem_bs.verbose(), first_out.to_string_lossy());
-	let mut opt_list = Vec::new();
-	opt_list.push(("threads", "threads", VarType::Int));
-	opt_list.push(("snp_list", "snps", VarType::String));
-	opt_list.push(("dbsnp_index", "dbsnp", VarType::String));
-	super::add_command_opts(gem_bs, &mut args, Section::Extract, &opt_list);
+	super::add_command_opts(gem_bs, &mut args, Section::Extract, &snpxtr_opt_list());
 	args.push_str(&in_bcf.to_string_lossy());
 
 	// Setup snpxtr pipeline
@@ -102,16 +114,34 @@ fn make_snpxtr_pipeline(gem_bs: &GemBS, job: usize) -> QPipe {
 }
 
 fn get_command_and_barcode(id: &str) -> (&str, &str) {
-	lazy_static! { static ref RE: Regex = Regex::new(r"^(mextr|snpxtr)_(.*)$").unwrap(); }
+	lazy_static! { static ref RE: Regex = Regex::new(r"^(mextr|msegment|snpxtr)_(.*)$").unwrap(); }
 	if let Some(cap) = RE.captures(id) {
 		(cap.get(1).unwrap().as_str(), cap.get(2).unwrap().as_str())	
 	} else { panic!("Couldn't parse extract task id") }
 }
 
+// Options affecting the output of the extract step
+pub fn get_extract_opts(gem_bs: &GemBS, job: usize) -> Vec<(String, String)> {
+	let task = &gem_bs.get_tasks()[job];
+	match get_command_and_barcode(task.id()) {
+		("mextr", _) | ("msegment", _) => {
+			let (mut cpg, mut noncpg, mut bedmethyl) = (false, false, false);
+			for out in task.outputs() {
+				let oname = gem_bs.get_asset(*out).expect("Couldn't get output asset").path().to_string_lossy();
+				if oname.ends_with("non_cpg.txt.gz") { noncpg = true }
+				else if oname.ends_with("cpg.txt.gz") { cpg = true }
+				else if oname.ends_with("cpg.bed.gz") { bedmethyl = true }
+			}
+			super::get_command_opts(gem_bs, Section::Extract, &mextr_opt_list(cpg, noncpg, bedmethyl))
+		},
+		_ => super::get_command_opts(gem_bs, Section::Extract, &snpxtr_opt_list()),
+	}
+}
+
 pub fn make_extract_pipeline(gem_bs: &GemBS, job: usize) -> QPipe
 {
 	match get_command_and_barcode(gem_bs.get_tasks()[job].id()) {
-		("mextr", bc) => make_mextr_pipeline(gem_bs, job, bc),
+		(com @ "mextr", bc) | (com @ "msegment", bc) => make_mextr_pipeline(gem_bs, job, com, bc),
 		("snpxtr", _) => make_snpxtr_pipeline(gem_bs, job),
 		_ => panic!("Couldn't parse extract task id"),
 	}
@@ -26,19 +26,23 @@ fn make_gem_index(gem_bs: &GemBS, job: usize, bisulfite: bool) -> QPipe
 	pipeline	
 }
 
-fn make_dbsnp_index(gem_bs: &GemBS, _options: &HashMap<&'static str, DataValue>, job: usize) -> QPipe
-{
-	let index = gem_bs.get_asset("dbsnp_index").expect("Couldn't find dbSNP index asset").path();
-	let dbsnp_index = gem_bs.get_exec_path("dbsnp_index");
-	let task = &gem_bs.get_tasks()[job];
-	let mut args = format!("--loglevel\x1e{}\x1e", gem_bs.verbose());
+fn dbsnp_opt_list() -> Vec<(&'static str, &'static str, VarType)> {
 	let mut opt_list = Vec::new();
 	opt_list.push(("dbsnp_jobs", "jobs", VarType::Int));
 	opt_list.push(("threads", "threads", VarType::Int));
 	opt_list.push(("dbsnp_selected", "selected", VarType::String));
 	opt_list.push(("dbsnp_chrom_alias", "chrom-alias", VarType::String));
 	opt_list.push(("dbsnp_type", "type", VarType::DbSnpFileType));
-	super::add_command_opts(gem_bs, &mut args, Section::DbSnp, &opt_list);
+	opt_list
+}
+
+fn make_dbsnp_index(gem_bs: &GemBS, _options: &HashMap<&'static str, DataValue>, job: usize) -> QPipe
+{
+	let index = gem_bs.get_asset("dbsnp_index").expect("Couldn't find dbSNP index asset").path();
+	let dbsnp_index = gem_bs.get_exec_path("dbsnp_index");
+	let task = &gem_bs.get_tasks()[job];
+	let mut args = format!("--loglevel\x1e{}\x1e", gem_bs.verbose());
+	super::add_command_opts(gem_bs, &mut args, Section::DbSnp, &dbsnp_opt_list());
 	args.push_str(format!("--output\x1e{}", index.display()).as_str());	
 	for asset in task.inputs().map(|ix| gem_bs.get_asset(*ix).expect("Missing asset")).filter(|asset| asset.id().starts_with("dbsnp_file_")) {
 		args.push_str(format!("\x1e{}", asset.path().display()).as_str())
@@ -53,6 +57,14 @@ fn make_dbsnp_index(gem_bs: &GemBS, _options: &HashMap<&'static str, DataValue>,
 	pipeline	
 }
 
+// Options affecting the output of the index step
+pub fn get_index_opts(gem_bs: &GemBS, job: usize) -> Vec<(String, String)> {
+	match gem_bs.get_tasks()[job].id() {
+		"dbsnp_index" => super::get_command_opts(gem_bs, Section::DbSnp, &dbsnp_opt_list()),
+		_ => super::get_command_opts(gem_bs, Section::Index, &[("sampling_rate", "text-sampling-rate", VarType::Int)]),
+	}
+}
+
 pub fn make_index_pipeline(gem_bs: &GemBS, options: &HashMap<&'static str, DataValue>, job: usize) -> QPipe
 {
 	// First check what type of index we are meant to be building
@@ -75,17 +75,25 @@ fn get_read_groups(dataset: &str, href: &HashMap<Metadata, DataValue>) -> String
 	read_groups
 }
 
+lazy_static! {
+	static ref OPT_LIST: Vec<(&'static str, &'static str, VarType)> = {
+		let mut m = Vec::new();
+		m.push(("underconversion_sequence", "underconversion-sequence", VarType::String));
+		m.push(("overconversion_sequence", "overconversion-sequence", VarType::String));
+		m.push(("benchmark_mode", "benchmark-mode", VarType::Bool));
+		m
+	};
+}
+
+// Options affecting the output of the mapping step
+pub fn get_map_opts(gem_bs: &GemBS) -> Vec<(String, String)> {
+	let mut opts = super::get_command_opts(gem_bs, Section::Mapping, &OPT_LIST);
+	opts.extend(super::get_command_opts(gem_bs, Section::Mapping, &[("non_stranded", "", VarType::Bool), ("reverse_conversion", "", VarType::Bool)]));
+	opts
+}
+
 pub fn make_map_pipeline(gem_bs: &GemBS, options: &HashMap<&'static str, DataValue>, job: usize) -> QPipe
 {
-	lazy_static! {
-    	static ref OPT_LIST: Vec<(&'static str, &'static str, VarType)> = {
-        	let mut m = Vec::new();
-        	m.push(("underconversion_sequence", "underconversion-sequence", VarType::String));
-        	m.push(("overconversion_sequence", "overconversion-sequence", VarType::String));
-        	m.push(("benchmark_mode", "benchmark-mode", VarType::Bool));
-			m
-		};
-	}
 	let threads = gem_bs.get_config_int(Section::Mapping, "threads");
 	let mapping_threads = gem_bs.get_config_int(Section::Mapping, "mapping_threads").or(threads);
 	let sort_threads = gem_bs.get_config_int(Section::Mapping, "sort_threads").or(mapping_threads);
@@ -24,6 +24,7 @@ pub struct CallJsonFiles {
 	pub barcode: String,
 	pub bc_dir: PathBuf,
 	pub json_file: PathBuf,
+	pub seg_json_file: Option<PathBuf>, // Segmentation summary from the extract step (if requested)
 }
 
 #[derive(Debug)]
@@ -87,7 +88,8 @@ pub fn make_call_report_pipeline(gem_bs: &GemBS, job: usize) -> QPipe
 		let bc_dir = gem_bs.get_asset(format!("{}_mapping_coverage.html", bc).as_str()).expect("Couldn't find call report asset")
 			.path().parent().expect("No parent dir found for call report file").to_owned();
 		let json_file = gem_bs.get_asset(format!("{}_call.json", bc).as_str()).expect("Couldn't find call JSON asset for call report").path().to_owned();
-		json_files.push(CallJsonFiles{barcode: bc.to_owned(), bc_dir, json_file});
+		let seg_json_file = gem_bs.get_asset(format!("{}_segments.json", bc).as_str()).map(|a| a.path().to_owned());
+		json_files.push(CallJsonFiles{barcode: bc.to_owned(), bc_dir, json_file, seg_json_file});
 	}
 	let mut css_dir = gem_bs.get_css_path();
 	css_dir.push("style.css");
@@ -73,21 +73,24 @@ pub struct BbiBlockBuild {
 }
 
 impl BbiBlockBuild {
-	pub fn add_bb_rec(&mut self, id: u32, pos: u32, desc: &str, s: &Sender<BbiMsg>) {
+	pub fn add_bb_rec(&mut self, id: u32, pos: u32, desc: &str, s: &Sender<BbiMsg>) { self.add_bb_region(id, pos, pos + 1, desc, s) }
+
+	// Add record covering [start, end)
+	pub fn add_bb_region(&mut self, id: u32, start: u32, end: u32, desc: &str, s: &Sender<BbiMsg>) {
 		let v = if let Some((bblk, v)) = self.block.as_mut() { 
-			bblk.end = pos + 1;
+			bblk.end = bblk.end.max(end);
 			v
 		} else { 
-			self.block = Some((BbiBlock{start: pos, id, end: pos + 1, idx: self.idx, bbi_type: self.bbi_type}, Vec::new())); 
+			self.block = Some((BbiBlock{start, id, end, idx: self.idx, bbi_type: self.bbi_type}, Vec::new())); 
 			self.idx += 1;
 			&mut self.block.as_mut().unwrap().1
 		};	
-		let tbuf = [id, pos, pos + 1];
+		let tbuf = [id, start, end];
 		write_u32_slice(v, &tbuf).expect("Error writing bb data");
 		let cdesc = CString::new(desc.as_bytes()).unwrap();		
 		v.write_all(cdesc.as_bytes_with_nul()).expect("Error writing bb data");
 		self.n_items += 1;
-		self.zoom_counts.add_count(pos, &self.zoom_scales);
+		self.zoom_counts.add_count(start, &self.zoom_scales);
 		if self.n_items >= BB_ITEMS_PER_SLOT { self.finish_bbi(s) }		
 	}
 	
@@ -204,19 +207,22 @@ impl BbiBlockBuild {
 pub struct BbiFile {
 	name: String,
 	build: RwLock<BbiBlockBuild>,
+	autosql: Option<AutoSql>,
 }
 
 impl BbiFile {
-	pub fn new<S: AsRef<str>>(name: S, ix: usize, zoom_scales: Arc<Vec<u32>>, bb_flag: bool) -> io::Result<Self> {
+	// autosql should be set for bigBed files and None for bigWig files
+	pub fn new<S: AsRef<str>>(name: S, ix: usize, zoom_scales: Arc<Vec<u32>>, autosql: Option<AutoSql>) -> io::Result<Self> {
 		let name = name.as_ref().to_owned();
-		let (bbi_type, items_per_slot) = if bb_flag { (BbiBlockType::Bb(ix as u8), BB_ITEMS_PER_SLOT) } else { (BbiBlockType::Bw(ix as u8), BW_ITEMS_PER_SLOT) };
+		let (bbi_type, items_per_slot) = if autosql.is_some() { (BbiBlockType::Bb(ix as u8), BB_ITEMS_PER_SLOT) } else { (BbiBlockType::Bw(ix as u8), BW_ITEMS_PER_SLOT) };
 		let build = RwLock::new(BbiBlockBuild{ block: None, n_rec: 0, idx: 0, n_items: 0, bbi_type, zoom_scales, 
 			items_per_slot, n_zoom_items: Default::default(), n_zoom_rec: Default::default(),
 			zidx: Default::default(), zblock: Default::default(), zrec: Default::default(), summary: Default::default(), zoom_counts: Default::default()});
-		Ok(Self{name, build } )
+		Ok(Self{name, build, autosql } )
 	}
 	pub fn build(&self) -> &RwLock<BbiBlockBuild> { &self.build }	
 	pub fn name(&self) -> &str { &self.name }
+	pub fn autosql(&self) -> Option<AutoSql> { self.autosql }
 }
 
 pub enum BbiMsg {
@@ -243,18 +249,25 @@ impl Bbi {
 		let n_output_ctgs = chash.vcf_contigs().iter().filter(|x| x.out_ix().is_some()).count();
 		
 		let bb_files = vec!(
-			BbiFile::new(format!("{}_cpg.bb", prefix.as_ref()), 0, bb_zoom_scales.clone(), true)?,
-			BbiFile::new(format!("{}_chg.bb", prefix.as_ref()), 1, bb_zoom_scales.clone(), true)?,
-			BbiFile::new(format!("{}_chh.bb", prefix.as_ref()), 2, bb_zoom_scales, true)?
+			BbiFile::new(format!("{}_cpg.bb", prefix.as_ref()), 0, bb_zoom_scales.clone(), Some(AutoSql::BedMethyl))?,
+			BbiFile::new(format!("{}_chg.bb", prefix.as_ref()), 1, bb_zoom_scales.clone(), Some(AutoSql::BedMethyl))?,
+			BbiFile::new(format!("{}_chh.bb", prefix.as_ref()), 2, bb_zoom_scales, Some(AutoSql::BedMethyl))?
 		);
 		let bw_files = if strand_specific { vec!( 
-			BbiFile::new(format!("{}_pos.bw", prefix.as_ref()), 0, bw_zoom_scales.clone(), false)?,
-			BbiFile::new(format!("{}_neg.bw", prefix.as_ref()), 1, bw_zoom_scales, false)?
-		)} else { vec!(BbiFile::new(format!("{}.bw", prefix.as_ref()), 0, bw_zoom_scales, false)?)};
+			BbiFile::new(format!("{}_pos.bw", prefix.as_ref()), 0, bw_zoom_scales.clone(), None)?,
+			BbiFile::new(format!("{}_neg.bw", prefix.as_ref()), 1, bw_zoom_scales, None)?
+		)} else { vec!(BbiFile::new(format!("{}.bw", prefix.as_ref()), 0, bw_zoom_scales, None)?)};
 		
 		Ok(Bbi{bb_files, bw_files, sender: Some(sender), n_output_ctgs})
 
 	}
+	// Single bigBed file (i.e., for differentially methylated regions or segmentation)
+	pub fn init_bb<S: AsRef<str>>(name: S, autosql: AutoSql, sender: Sender<BbiMsg>, chash: &ConfHash) -> io::Result<Self> {
+		let (bb_zoom_scales, _) = make_zoom_scales();
+		let n_output_ctgs = chash.vcf_contigs().iter().filter(|x| x.out_ix().is_some()).count();
+		let bb_files = vec!(BbiFile::new(name, 0, Arc::new(bb_zoom_scales), Some(autosql))?);
+		Ok(Bbi{bb_files, bw_files: Vec::new(), sender: Some(sender), n_output_ctgs})
+	}
 	pub fn drop_sender(&mut self) { 
 		self.sender = None;
 		trace!("Bbi drop_sender()");
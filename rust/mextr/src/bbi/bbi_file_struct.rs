@@ -13,7 +13,7 @@ const EXT_HEADER_SIZE: u64 = 64;
 const ZOOM_HEADER_SIZE: u64 = 24;
 const TOTAL_SUMMARY_SIZE: u64 = 40;
 
-const _AUTOSQL_DESC: &str = "table BisulfiteSeq
+const _BED_METHYL_AUTOSQL_DESC: &str = "table BisulfiteSeq
 \"BED9+5 scores for bisulfite-seq data\"
 \t(
 \tstring\tchrom;\t\"Reference chromosome or scaffold\"
@@ -33,10 +33,69 @@ const _AUTOSQL_DESC: &str = "table BisulfiteSeq
 \t)
 ";
 
+const _DMR_AUTOSQL_DESC: &str = "table DifferentialMethylation
+\"BED6+5 differentially methylated regions\"
+\t(
+\tstring\tchrom;\t\"Reference chromosome or scaffold\"
+\tuint\tchromStart;\t\"Start position in chromosome\"
+\tuint\tchromEnd;\t\"End position in chromosome\"
+\tstring\tname;\t\"hyper or hypo (methylation in group 2 relative to group 1)\"
+\tuint\tscore;\t\"Score from 0-1000.  Absolute methylation difference x 1000\"
+\tchar[1]\tstrand;\t\"+ or - or . for unknown\"
+\tuint\tcpgCount;\t\"Number of differentially methylated CpGs in region\"
+\tfloat\tmeth1;\t\"Mean methylation in group 1\"
+\tfloat\tmeth2;\t\"Mean methylation in group 2\"
+\tfloat\tmethDiff;\t\"Mean methylation difference (group 2 - group 1)\"
+\tfloat\tqValue;\t\"Minimum q-value of CpGs in region\"
+\t)
+";
+
+const _SEGMENT_AUTOSQL_DESC: &str = "table MethylationSegments
+\"BED6+3 methylome segmentation\"
+\t(
+\tstring\tchrom;\t\"Reference chromosome or scaffold\"
+\tuint\tchromStart;\t\"Start position in chromosome\"
+\tuint\tchromEnd;\t\"End position in chromosome\"
+\tstring\tname;\t\"Segment type (UMR, LMR or PMD)\"
+\tuint\tscore;\t\"Score from 0-1000.  Mean methylation x 1000\"
+\tchar[1]\tstrand;\t\"+ or - or . for unknown\"
+\tstring\tsample;\t\"Sample name\"
+\tuint\tcpgCount;\t\"Number of CpGs in segment\"
+\tfloat\tmeth;\t\"Mean methylation of CpGs in segment\"
+\t)
+";
+
 lazy_static! {
-	static ref AUTOSQL_DESC: CString = {
-		CString::new(_AUTOSQL_DESC.as_bytes()).unwrap()		
+	static ref SEGMENT_AUTOSQL_DESC: CString = {
+		CString::new(_SEGMENT_AUTOSQL_DESC.as_bytes()).unwrap()		
 	};
+	static ref BED_METHYL_AUTOSQL_DESC: CString = {
+		CString::new(_BED_METHYL_AUTOSQL_DESC.as_bytes()).unwrap()		
+	};
+	static ref DMR_AUTOSQL_DESC: CString = {
+		CString::new(_DMR_AUTOSQL_DESC.as_bytes()).unwrap()		
+	};
+}
+
+// Record description for bigBed files
+#[derive(Debug, Copy, Clone)]
+pub enum AutoSql { BedMethyl, Dmr, Segment }
+
+impl AutoSql {
+	fn desc(&self) -> &CString {
+		match self {
+			AutoSql::BedMethyl => &BED_METHYL_AUTOSQL_DESC,
+			AutoSql::Dmr => &DMR_AUTOSQL_DESC,
+			AutoSql::Segment => &SEGMENT_AUTOSQL_DESC,
+		}
+	}
+	fn field_count(&self) -> u16 {
+		match self {
+			AutoSql::BedMethyl => 14,
+			AutoSql::Dmr => 11,
+			AutoSql::Segment => 9,
+		}
+	}
 }
 
 // Main header of bbi file
@@ -54,6 +113,7 @@ pub struct BbiHeader {
 	total_summary_offset: u64,
 	uncompress_buf_size: u32,
 	extension_offset: u64,	
+	autosql: Option<AutoSql>,
 }
 
 impl BbiHeader {
@@ -61,19 +121,21 @@ impl BbiHeader {
 	pub fn set_full_data_offset(&mut self, off: u64) { self.full_data_offset = off }
 	pub fn set_full_index_offset(&mut self, off: u64) { self.full_index_offset = off }
 	pub fn set_uncompress_buf_size(&mut self, sz: u32) { self.uncompress_buf_size = sz }
-	pub fn new(bb_flag: bool) -> Self {
+	pub fn autosql(&self) -> Option<AutoSql> { self.autosql }
+	// autosql is set for bigBed files and None for bigWig files
+	pub fn new(autosql: Option<AutoSql>) -> Self {
 		let version = 4;
 		let zoom_levels = ZOOM_LEVELS as u16;
 		let off = BBI_HEADER_SIZE + (ZOOM_LEVELS as u64) * ZOOM_HEADER_SIZE as u64;
-		let (magic, field_count, defined_field_count, auto_sql_offset, total_summary_offset) = if bb_flag {
-			(0x8789F2EB, 14, 0, off, off + (AUTOSQL_DESC.to_bytes().len() + 1) as u64)
+		let (magic, field_count, defined_field_count, auto_sql_offset, total_summary_offset) = if let Some(a) = autosql {
+			(0x8789F2EB, a.field_count(), 0, off, off + (a.desc().to_bytes().len() + 1) as u64)
 		} else {
 			(0x888FFC26, 0, 0, 0, off)
 		};
 		let extension_offset = total_summary_offset + TOTAL_SUMMARY_SIZE;
 		let chromosome_tree_offset = extension_offset + EXT_HEADER_SIZE;
 		Self {magic, version, zoom_levels, chromosome_tree_offset, full_data_offset: 0, full_index_offset: 0, field_count, 
-			defined_field_count, auto_sql_offset, total_summary_offset, uncompress_buf_size: 0, extension_offset} 
+			defined_field_count, auto_sql_offset, total_summary_offset, uncompress_buf_size: 0, extension_offset, autosql} 
 	}
 	pub fn write(&self, fp: &mut BufWriter<File>) -> io::Result<()> {
 		fp.seek(SeekFrom::Start(0))?;
@@ -106,7 +168,7 @@ impl BbiHeader {
 	
 } 
 
-pub fn write_autosql<W: Write>(w: &mut W) -> io::Result<()> { w.write_all(AUTOSQL_DESC.as_bytes_with_nul())	}
+pub fn write_autosql<W: Write>(w: &mut W, autosql: AutoSql) -> io::Result<()> { w.write_all(autosql.desc().as_bytes_with_nul())	}
 
 #[repr(C)]
 pub struct BwDataHeader {
@@ -24,6 +24,14 @@ use super::bbi_file_struct::*;
 pub fn bbi_finish(ch: Arc<ConfHash>, mut writer: BbiWriter) {
 	let bbi_type = writer.bbi_type();
 	debug!("bbi_finish starting for {:?}", bbi_type);
+	// No records (i.e., no DMRs found)
+	if ch.max_uncomp_size(bbi_type).is_none() {
+		warn!("No records for bbi file {}: file not written", writer.name);
+		let name = writer.name.clone();
+		drop(writer);
+		let _ = std::fs::remove_file(&name);
+		return
+	}
 
 	let (n_rec, n_zoom_rec, summary, zoom_scales) = {	
 		let bbi_ref = ch.bbi().read().unwrap();
@@ -76,7 +84,7 @@ pub fn bbi_finish(ch: Arc<ConfHash>, mut writer: BbiWriter) {
 	header.set_full_index_offset(writer.index_offset);
 	header.write(&mut writer.fp).expect("Error writing out main header");
 	for zhdr in zoom_hdr.iter() { zhdr.write(&mut writer.fp).expect("Error writing zoom headers"); }
-	if matches!(bbi_type, BbiBlockType::Bb(_)) { write_autosql(&mut writer.fp).expect("Error writing autoSql text")}	
+	if let Some(a) = header.autosql() { write_autosql(&mut writer.fp, a).expect("Error writing autoSql text")}	
 	summary.write(&mut writer.fp).expect("Error writing out summary table");
 	header.write_ext_header(&mut writer.fp).expect("Error writing out extended header");
 	
@@ -87,7 +95,7 @@ pub fn bbi_finish(ch: Arc<ConfHash>, mut writer: BbiWriter) {
 	header.write_terminator(&mut writer.fp).expect("Error writing out terminator");
 	
 	// Trigger MD5 generation
-	ch.add_file(&writer.name, false);
+	ch.add_file(&writer.name, None);
 	
 	debug!("bbi_finish ending for {:?}", bbi_type);
 }
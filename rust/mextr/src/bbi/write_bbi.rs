@@ -27,8 +27,7 @@ impl BbiWriter {
 	pub fn header(&mut self) -> &mut BbiHeader { &mut self.header }
 	pub fn init(bbi_file: &BbiFile, bbi_type: BbiBlockType, n_ctgs: usize) -> Self {
 		trace!("In init for {:?}", bbi_type);
-		let bb_flag = matches!(bbi_type, BbiBlockType::Bb(_));
-		let header = BbiHeader::new(bb_flag);
+		let header = BbiHeader::new(bbi_file.autosql());
 		let name = bbi_file.name().to_owned();
 		let fp = match File::create(&name) {
 			Ok(f) => BufWriter::new(f),
@@ -1,17 +1,46 @@
 use std::io;
+use std::str::FromStr;
 use std::collections::HashMap;
 use clap::ArgMatches;
 
 use r_htslib::*;
+use utils::compress;
 use super::cli_utils;
 use crate::config::*;
+use crate::read_vcf::model::Chemistry;
 
-pub const OPTS: [(&str, ConfVar);21] = [
+pub const OPTS: [(&str, ConfVar);47] = [
 	("cpgfile", ConfVar::String(None)),
 	("noncpgfile", ConfVar::String(None)),
 	("bed_methyl", ConfVar::String(None)),
+	("matrix", ConfVar::String(None)),
+	("matrix_beta", ConfVar::Bool(false)),
+	("min_samples", ConfVar::Int(1)),
+	("region_summary", ConfVar::String(None)),
+	("summary_regions", ConfVar::String(None)),
+	("summary_window", ConfVar::Int(0)),
 	("bed_track_line", ConfVar::String(None)),
+	("bismark_cov", ConfVar::String(None)),
+	("cx_report", ConfVar::String(None)),
+	("methylkit", ConfVar::String(None)),
+	("methyldackel", ConfVar::String(None)),
 	("report_file", ConfVar::String(None)),
+	("dmr", ConfVar::String(None)),
+	("dmr_groups", ConfVar::String(None)),
+	("dmr_test", ConfVar::String(None)),
+	("dmr_min_cov", ConfVar::Int(5)),
+	("dmr_min_samples", ConfVar::Int(1)),
+	("dmr_fdr", ConfVar::Float(0.05)),
+	("dmr_min_diff", ConfVar::Float(0.1)),
+	("dmr_max_gap", ConfVar::Int(300)),
+	("dmr_min_cpg", ConfVar::Int(3)),
+	("segmentation", ConfVar::String(None)),
+	("seg_min_cov", ConfVar::Int(5)),
+	("seg_meth_cut", ConfVar::Float(0.5)),
+	("seg_umr_meth", ConfVar::Float(0.1)),
+	("seg_fdr", ConfVar::Float(0.05)),
+	("pmd_min_size", ConfVar::Int(100000)),
+	("chemistry", ConfVar::String(None)),
 	("no_header", ConfVar::Bool(false)),
 	("common_gt", ConfVar::Bool(false)),
 	("reference_bias", ConfVar::Float(2.0)),
@@ -30,7 +59,8 @@ pub const OPTS: [(&str, ConfVar);21] = [
 	("select", ConfVar::Select(Select::Hom)),
 ];
 
-fn read_header(infile: &str) -> io::Result<(usize, Vec<VcfContig>)> {
+// Returns the number of samples, the contigs and the conversion chemistry (if recorded by bs_call)
+fn read_header(infile: &str) -> io::Result<(usize, Vec<VcfContig>, Option<String>)> {
 	let fp = HtsFile::new(infile, "rz")?;
 	let hdr = VcfHeader::read(fp)?;
 	let ns = hdr.nsamples();
@@ -44,7 +74,15 @@ fn read_header(infile: &str) -> io::Result<(usize, Vec<VcfContig>)> {
 				let (s, l) = hdr.ctg_name_len(ix)?;
 				v.push(VcfContig::new(s, l))
 			}
-			Ok((ns, v))
+			let mut chemistry = None;
+			for ix in 0..hdr.nhrec() {
+				let hr = hdr.hrec(ix)?;
+				if hr.get_type() == BCF_HL_GEN && hr.key() == "bs_call_chemistry" {
+					chemistry = Some(hr.val().to_owned());
+					break;
+				}
+			}
+			Ok((ns, v, chemistry))
 		}
 	}
 }
@@ -108,9 +146,13 @@ fn handle_regions(m: &ArgMatches, chash: &mut ConfHash, sr: &mut BcfSrs) -> io::
 	Ok(())
 }
 
-fn setup_bed_methyl_structs(chash: &mut ConfHash, hdr: &bcf_hdr_t, infile: &str, bed_meth: bool) -> io::Result<()> {
-	if bed_meth {
-		if hdr.nsamples() > 1 { return Err(new_err(format!("Input file {} has {} samples: bedMethyl output incompatible with multi-sample files", infile, hdr.nsamples()))) } 
+// Outputs that require single sample files
+const SINGLE_SAMPLE_OUTPUTS: [(&str, &str); 5] = [("bed_methyl", "bedMethyl"), ("bismark_cov", "Bismark coverage"), ("cx_report", "Bismark CX report"),
+	("methylkit", "methylKit"), ("methyldackel", "MethylDackel bedGraph")];
+
+fn setup_bed_methyl_structs(chash: &mut ConfHash, hdr: &bcf_hdr_t, infile: &str) -> io::Result<()> {
+	if let Some((_, desc)) = SINGLE_SAMPLE_OUTPUTS.iter().find(|(s, _)| chash.get_str(s).is_some()) {
+		if hdr.nsamples() > 1 { return Err(new_err(format!("Input file {} has {} samples: {} output incompatible with multi-sample files", infile, hdr.nsamples(), desc))) } 
 		// Get sample description from VCF header if possible, otherwise use sample name
 		let quotes = ['\'', '\"'];
 		let trim = |s: &str| s.trim_start_matches(&quotes[..]).trim_end_matches(&quotes[..]).to_owned();
@@ -131,6 +173,121 @@ fn setup_bed_methyl_structs(chash: &mut ConfHash, hdr: &bcf_hdr_t, infile: &str,
 	Ok(())	
 }
 
+// Groups file has lines with 2 tab separated columns (sample, group).  There should be exactly two groups; the first
+// group to appear in the file is group 1.  Lines starting with '#' are ignored
+fn read_dmr_groups(fname: &str, hdr: &bcf_hdr_t) -> io::Result<DmrGroups> {
+	let ns = hdr.nsamples();
+	let mut sample_hash = HashMap::new();
+	for ix in 0..ns { sample_hash.insert(hdr.sample_name(ix)?.to_owned(), ix); }
+	let mut rdr = compress::open_bufreader(fname)?;
+	debug!("Reading DMR sample groups from {}", fname);
+	let mut names: Vec<String> = Vec::new();
+	let mut sample_group = vec![None; ns];
+	let mut line = String::with_capacity(256);
+	let mut l = 0;
+	loop {
+		l += 1;
+		if rdr.read_line(&mut line)? == 0 { break }
+		let v: Vec<_> = line.trim_end().split('\t').map(|s| s.trim()).collect();
+		if !(v[0].starts_with('#') || (v.len() == 1 && v[0].is_empty())) {
+			if v.len() != 2 { return Err(new_err(format!("Error parsing DMR groups file {} at line {}", fname, l))) }
+			let ix = *sample_hash.get(v[0]).ok_or_else(|| new_err(format!("Sample {} in DMR groups file {} not present in input file", v[0], fname)))?;
+			let gp = match names.iter().position(|s| s == v[1]) {
+				Some(x) => x,
+				None => {
+					names.push(v[1].to_owned());
+					names.len() - 1
+				},
+			};
+			if gp > 1 { return Err(new_err(format!("More than two groups in DMR groups file {}", fname))) }
+			if sample_group[ix].replace(gp).is_some() { return Err(new_err(format!("Sample {} appears more than once in DMR groups file {}", v[0], fname))) }
+		}
+		line.clear();
+	}
+	if names.len() != 2 { return Err(new_err(format!("DMR groups file {} should have two groups", fname))) }
+	let mut it = names.drain(..);
+	let names = [it.next().unwrap(), it.next().unwrap()];
+	debug!("DMR groups: {} ({} samples), {} ({} samples)", names[0], sample_group.iter().filter(|g| **g == Some(0)).count(),
+		names[1], sample_group.iter().filter(|g| **g == Some(1)).count());
+	Ok(DmrGroups::new(names, sample_group))
+}
+
+fn setup_segmentation(chash: &mut ConfHash) -> io::Result<()> {
+	if chash.get_str("segmentation").is_some() {
+		for var in ["seg_meth_cut", "seg_umr_meth", "seg_fdr"].iter() {
+			let x = chash.get_float(var);
+			if !(0.0..=1.0).contains(&x) { return Err(new_err(format!("Argument {} for option {} not between 0 and 1", x, var))) }
+		}
+		if chash.get_float("seg_umr_meth") > chash.get_float("seg_meth_cut") { return Err(new_err("Option --seg-umr-meth can not be greater than --seg-meth-cut".to_string())) }
+		if chash.get_int("seg_min_cov") < 1 { chash.set("seg_min_cov", ConfVar::Int(1)) }
+	}
+	Ok(())
+}
+
+fn setup_dmr(chash: &mut ConfHash, hdr: &bcf_hdr_t, infile: &str) -> io::Result<()> {
+	if chash.get_str("dmr").is_some() {
+		if hdr.nsamples() < 2 { return Err(new_err(format!("Input file {} has only one sample: DMR calling requires multi-sample files", infile))) }
+		let fname = chash.get_str("dmr_groups").ok_or_else(|| new_err("DMR calling requires a groups file (--dmr-groups)".to_string()))?.to_owned();
+		for (var, x) in [("dmr_fdr", chash.get_float("dmr_fdr")), ("dmr_min_diff", chash.get_float("dmr_min_diff"))].iter() {
+			if !(0.0..=1.0).contains(x) { return Err(new_err(format!("Argument {} for option {} not between 0 and 1", x, var))) }
+		}
+		if chash.get_int("dmr_min_samples") < 1 { chash.set("dmr_min_samples", ConfVar::Int(1)) }
+		let groups = read_dmr_groups(&fname, hdr)?;
+		chash.set_dmr_groups(groups);
+	}
+	Ok(())
+}
+
+// Read BED file of regions for methylation summaries.  Only the first 4 columns (contig, start, end, name) are used.
+// Regions on contigs not present in the input file are ignored
+fn read_summary_regions(fname: &str, chash: &ConfHash) -> io::Result<Vec<CtgRegions>> {
+	let mut ctg_regs: Vec<CtgRegions> = (0..chash.vcf_contigs().len()).map(|_| CtgRegions::default()).collect();
+	let mut rdr = compress::open_bufreader(fname)?;
+	debug!("Reading summary regions from {}", fname);
+	let mut line = String::with_capacity(256);
+	let (mut l, mut n, mut skipped) = (0, 0, 0);
+	loop {
+		l += 1;
+		if rdr.read_line(&mut line)? == 0 { break }
+		let v: Vec<_> = line.trim_end().split('\t').map(|s| s.trim()).collect();
+		if !(v[0].starts_with('#') || v[0].starts_with("track") || v[0].starts_with("browser") || (v.len() == 1 && v[0].is_empty())) {
+			if v.len() < 3 { return Err(new_err(format!("Error parsing summary regions file {} at line {}", fname, l))) }
+			match chash.contig_rid(v[0]) {
+				Some(rid) => match (<u32>::from_str(v[1]), <u32>::from_str(v[2])) {
+					(Ok(start), Ok(end)) if start < end => {
+						let name = v.get(3).filter(|s| !s.is_empty()).map(|s| (*s).to_owned());
+						ctg_regs[rid].add(SummaryRegion{start, end, name});
+						n += 1;
+					},
+					_ => return Err(new_err(format!("Error parsing summary regions file {} at line {}: coordinates invalid", fname, l))),
+				},
+				None => skipped += 1,
+			}
+		}
+		line.clear();
+	}
+	if skipped > 0 { warn!("{} regions in summary regions file {} on contigs not present in input (ignored)", skipped, fname) }
+	if n == 0 { return Err(new_err(format!("No valid regions found in summary regions file {}", fname))) }
+	debug!("Read {} regions", n);
+	for ctg in ctg_regs.iter_mut() { ctg.sort() }
+	Ok(ctg_regs)
+}
+
+fn setup_region_summary(chash: &mut ConfHash) -> io::Result<()> {
+	if chash.get_str("region_summary").is_some() {
+		match (chash.get_str("summary_regions").map(|s| s.to_owned()), chash.get_int("summary_window")) {
+			(Some(_), w) if w > 0 => return Err(new_err("Options --summary-regions and --summary-window can not be used together".to_string())),
+			(Some(fname), _) => {
+				let regs = read_summary_regions(&fname, chash)?;
+				chash.set_summary_regions(regs);
+			},
+			(None, 0) => return Err(new_err("Region summary output requires either a regions file (--summary-regions) or a window size (--summary-window)".to_string())),
+			_ => (),
+		}
+	}
+	Ok(())
+}
+
 pub fn handle_options(m: &ArgMatches) -> io::Result<(ConfHash, BcfSrs)> {
 	
 	let mut conf_hash: HashMap<&'static str, ConfVar> = HashMap::new();
@@ -140,17 +297,26 @@ pub fn handle_options(m: &ArgMatches) -> io::Result<(ConfHash, BcfSrs)> {
 		trace!("Inserting config option {} with value {:?}", opt, x);
 		conf_hash.insert(opt, x);
 	}
-	// Conversion rates
+	let infile = m.value_of("input").expect("No input filename"); // This should not be allowed by Clap	
+	let (ns, vcf_contigs, hdr_chemistry) = read_header(infile)?;
+
+	// Conversion chemistry - from the command line, or the VCF header, or bisulfite (stored in normalized form)
+	let chemistry = match conf_hash.get("chemistry") {
+		Some(ConfVar::String(Some(s))) => Some(s.to_owned()),
+		_ => hdr_chemistry,
+	}.map(|s| <Chemistry>::from_str(&s).map_err(|_| new_err(format!("Couldn't parse chemistry '{}': should be bisulfite, em-seq or taps", s))))
+		.transpose()?.unwrap_or(Chemistry::Bisulfite);
+	debug!("Conversion chemistry: {}", chemistry);
+	conf_hash.insert("chemistry", ConfVar::String(Some(chemistry.to_string())));
+
+	// Conversion rates (defaults depend on the chemistry)
 	let (under, over) = if let Some(v) = cli_utils::get_fvec(m, "conversion", 1.0e-8, 1.0 - 1.0e-8)? { (v[0], v[1]) }
-	else { (0.01, 0.05) };
+	else { chemistry.default_conversion() };
 	conf_hash.insert(&"under_conversion", ConfVar::Float(under));
 	conf_hash.insert(&"over_conversion", ConfVar::Float(over));	
 
 	// Min Proportion
 	let prop = if let Some(x) = cli_utils::get_f64(m, "prop", 0.0, 1.0)? { x } else { 0.0 };
-
-	let infile = m.value_of("input").expect("No input filename"); // This should not be allowed by Clap	
-	let (ns, vcf_contigs) = read_header(infile)?;
 	
 	let mut chash = ConfHash::new(conf_hash, vcf_contigs);
 	
@@ -162,7 +328,7 @@ pub fn handle_options(m: &ArgMatches) -> io::Result<(ConfHash, BcfSrs)> {
 	
 	// Check output names for .gz
 	// If so, strip suffix and set compress option
-	for var in &["cpgfile", "noncpgfile", "bed_methyl"] {
+	for var in &["cpgfile", "noncpgfile", "bed_methyl", "bismark_cov", "cx_report", "methylkit", "methyldackel", "matrix", "region_summary", "dmr", "segmentation"] {
 		let tmp = chash.get_str(var).and_then(|s| s.strip_suffix(".gz")).map(|s| s.to_owned());
 		if let Some(s) = tmp { 
 			chash.set(var, ConfVar::String(Some(s)));
@@ -170,6 +336,10 @@ pub fn handle_options(m: &ArgMatches) -> io::Result<(ConfHash, BcfSrs)> {
 		}
 	}
 
+	// Only one output can generate bigBed files
+	let bb_outputs: Vec<_> = ["bed_methyl", "dmr", "segmentation"].iter().filter(|s| chash.get_str(s).is_some()).collect();
+	if bb_outputs.len() > 1 { return Err(new_err(format!("Options {:?} can not be used together (only one bigBed output is possible)", bb_outputs))) }
+
 	// If tabix option set, check that compress is also set
 	if chash.get_bool("tabix") && !chash.get_bool("compress") { 
 		warn!("tabix option disabled for non-compressed output");
@@ -191,7 +361,10 @@ pub fn handle_options(m: &ArgMatches) -> io::Result<(ConfHash, BcfSrs)> {
 	let mn = mn.max((prop * (ns as f64) + 0.5) as usize);
 	chash.set("number", ConfVar::Int(mn));
 	
-	setup_bed_methyl_structs(&mut chash, hdr, infile, m.is_present("bed_methyl"))?;
+	setup_bed_methyl_structs(&mut chash, hdr, infile)?;
+	setup_dmr(&mut chash, hdr, infile)?;
+	setup_region_summary(&mut chash)?;
+	setup_segmentation(&mut chash)?;
 
 	Ok((chash, sr))
 }
\ No newline at end of file
@@ -27,6 +27,48 @@ impl VcfContig {
 	pub fn out_ix(&self) -> Option<usize> { self.out_ix }
 }
 
+// Sample groups for differential methylation.  Samples not assigned to either group are ignored
+pub struct DmrGroups {
+	names: [String; 2],
+	sample_group: Vec<Option<usize>>,
+}
+
+impl DmrGroups {
+	pub fn new(names: [String; 2], sample_group: Vec<Option<usize>>) -> Self { Self{names, sample_group} }
+	pub fn names(&self) -> &[String; 2] { &self.names }
+	pub fn sample_group(&self) -> &[Option<usize>] { &self.sample_group }
+}
+
+// Region for methylation summaries (0 offset, half open)
+pub struct SummaryRegion {
+	pub start: u32,
+	pub end: u32,
+	pub name: Option<String>,
+}
+
+// Summary regions for a contig, sorted on start position
+#[derive(Default)]
+pub struct CtgRegions {
+	regions: Vec<SummaryRegion>,
+	max_len: u32,
+}
+
+impl CtgRegions {
+	pub fn add(&mut self, reg: SummaryRegion) {
+		self.max_len = self.max_len.max(reg.end - reg.start);
+		self.regions.push(reg);
+	}
+	pub fn sort(&mut self) { self.regions.sort_by_key(|r| (r.start, r.end)) }
+	pub fn regions(&self) -> &[SummaryRegion] { &self.regions }
+	// Indices of regions that include pos
+	pub fn find(&self, pos: u32) -> impl Iterator<Item = usize> + '_ {
+		let ix = self.regions.partition_point(|r| r.start <= pos);
+		let min_start = pos.saturating_sub(self.max_len);
+		self.regions[..ix].iter().enumerate().rev().take_while(move |(_, r)| r.start >= min_start)
+			.filter(move |(_, r)| r.end > pos).map(|(i, _)| i)
+	}
+}
+
 #[derive(Debug,Copy, Clone)]
 pub enum Mode { Combined, StrandSpecific }
 
@@ -45,17 +87,19 @@ pub enum ConfVar {
 
 pub struct ConfHash {
 	hash: HashMap<&'static str, ConfVar>,
-	out_files: RwLock<Vec<(String, bool)>>,
+	out_files: RwLock<Vec<(String, Option<usize>)>>, // File name and, for files to be indexed, the number of header lines
 	vcf_contigs: Vec<VcfContig>,
 	vcf_contig_hash: HashMap<Arc<Box<str>>, usize>,
 	bbi: RwLock<Option<Bbi>>,
 	max_uncomp_size: RwLock<HashMap<BbiBlockType, usize>>,
+	dmr_groups: Option<DmrGroups>,
+	summary_regions: Option<Vec<CtgRegions>>,
 }
 
 impl ConfHash {
 	pub fn new(hash: HashMap<&'static str, ConfVar>, vcf_contigs: Vec<VcfContig>) -> Self { 
 		let vcf_contig_hash = vcf_contigs.iter().enumerate().fold(HashMap::new(), |mut h, (ix, ctg)| {h.insert(ctg.name.clone(), ix); h} );
-		ConfHash {hash, vcf_contigs, vcf_contig_hash, out_files: RwLock::new(Vec::new()), bbi: RwLock::new(None), max_uncomp_size: RwLock::new(Default::default()) } 
+		ConfHash {hash, vcf_contigs, vcf_contig_hash, out_files: RwLock::new(Vec::new()), bbi: RwLock::new(None), max_uncomp_size: RwLock::new(Default::default()), dmr_groups: None, summary_regions: None } 
 	}
 	pub fn vcf_contigs(&self) -> &[VcfContig] { &self.vcf_contigs }	
 	pub fn contig_rid<S: AsRef<str>>(&self, ctg: S) -> Option<usize> { self.vcf_contig_hash.get(&(Box::<str>::from(ctg.as_ref()))).copied() }
@@ -63,6 +107,10 @@ impl ConfHash {
 		assert!(rid < self.vcf_contigs.len());
 		self.vcf_contigs[rid].out_ix = Some(out_ix);
 	}
+	pub fn set_dmr_groups(&mut self, groups: DmrGroups) { self.dmr_groups = Some(groups) }
+	pub fn dmr_groups(&self) -> Option<&DmrGroups> { self.dmr_groups.as_ref() }
+	pub fn set_summary_regions(&mut self, regions: Vec<CtgRegions>) { self.summary_regions = Some(regions) }
+	pub fn summary_regions(&self) -> Option<&[CtgRegions]> { self.summary_regions.as_deref() }
 	pub fn get(&self,  key: &str) -> Option<&ConfVar> { self.hash.get(key) }
 	pub fn set(&mut self, key: &'static str, val: ConfVar) { self.hash.insert(key, val); }
 
@@ -88,12 +136,12 @@ impl ConfHash {
 		if let Some(ConfVar::Mode(x)) = self.get(key) { *x } else { panic!("Bool config var {} not set", key); }
 	}
 	pub fn n_out_files(&self) -> usize { self.out_files.read().unwrap().len() } 
-	pub fn out_files(&self) -> Vec<(String, bool)> {
+	pub fn out_files(&self) -> Vec<(String, Option<usize>)> {
 		let rf = self.out_files.read().unwrap();
 		rf.iter().map(|s| s.to_owned()).collect()
 	} 
-	pub fn add_file<S: AsRef<str>>(&self, fname: S, tabix_flag: bool) {
-		self.out_files.write().unwrap().push((fname.as_ref().to_owned(), tabix_flag));
+	pub fn add_file<S: AsRef<str>>(&self, fname: S, tabix_skip: Option<usize>) {
+		self.out_files.write().unwrap().push((fname.as_ref().to_owned(), tabix_skip));
 	}
 	pub fn set_bbi(&self, bbi: Bbi) { 
 		trace!("set_bbi()");
@@ -7,12 +7,13 @@ use std::thread;
 
 use r_htslib::{HtsFile, VcfHeader};
 use libc::c_int;
-use crossbeam_channel::bounded;
+use crossbeam_channel::{bounded, Sender};
 
 use super::config::*;
 use super::read_vcf::unpack::{Strand, RecordBlock, RecordBlockElem};
 use super::process::{Recv, TPool};
-use super::bbi::Bbi;
+use super::bbi::{Bbi, BbiMsg};
+use super::bbi::bbi_file_struct::AutoSql;
 use super::bbi::compress_bbi::compress_bbi_thread;
 use super::bbi::write_bbi::write_bbi_thread;
 
@@ -22,6 +23,16 @@ pub mod output_noncpg;
 pub use output_noncpg::*;
 mod output_bed_methyl;
 use output_bed_methyl::*;
+mod output_matrix;
+use output_matrix::*;
+mod output_region_summary;
+use output_region_summary::*;
+mod output_formats;
+use output_formats::*;
+mod output_dmr;
+use output_dmr::*;
+mod output_segment;
+use output_segment::*;
 pub mod md5;
 pub mod tabix;
 
@@ -36,6 +47,7 @@ pub struct MethRec {
 	mq: u8,
 	cx: [u8; 5],
 	max_gt: Option<u8>,
+	taps: bool,
 }
 
 const GT_IDX: [[Option<u8>; 10]; 2] = [
@@ -44,10 +56,15 @@ const GT_IDX: [[Option<u8>; 10]; 2] = [
 ];	
 
 impl MethRec {
-	pub fn new(counts: [c_int; 8], gt_probs: [f64; 10], meth: [f64; 6], cx: [u8; 5], mq: u8, max_gt: Option<u8>) -> Self {
-		Self{counts, gt_probs, meth, cx, mq, max_gt}
+	pub fn new(counts: [c_int; 8], gt_probs: [f64; 10], meth: [f64; 6], cx: [u8; 5], mq: u8, max_gt: Option<u8>, taps: bool) -> Self {
+		Self{counts, gt_probs, meth, cx, mq, max_gt, taps}
 	}	
 	pub fn counts(&self) -> &[c_int] { &self.counts }
+	// Methylated and non-methylated counts for strand (with TAPS it is the methylated Cs that are converted)
+	pub fn meth_counts(&self, strand: Strand) -> (c_int, c_int) {
+		let (a, b) = if matches!(strand, Strand::C) { (self.counts[5], self.counts[7]) } else { (self.counts[6], self.counts[4]) };
+		if self.taps { (b, a) } else { (a, b) }
+	}
 	pub fn gt_probs(&self) -> &[f64] { &self.gt_probs }
 	pub fn gt_probs_mut(&mut self) -> &mut[f64] { &mut self.gt_probs }
 	pub fn mq(&self) -> u8 { self.mq }
@@ -89,6 +106,23 @@ impl Record {
 	}
 }
 
+type RecSlice<'a> = (&'a Record, &'a [MethRec]);
+
+// Records from a block (and the last record from the previous block) with the MethRecs as slices, so that single
+// and multi-sample blocks can be handled in the same way
+fn rec_slices<'a>(rec_blk: &'a RecordBlock, prev: Option<RecordBlockElem<'a>>) -> (Option<RecSlice<'a>>, Vec<RecSlice<'a>>) {
+	let prev = match prev {
+		Some(RecordBlockElem::Single((r, m))) => Some((r, std::slice::from_ref(m))),
+		Some(RecordBlockElem::Multi((r, mv))) => Some((r, mv)),
+		None => None,
+	};
+	let recs = match rec_blk {
+		RecordBlock::Single(svec) => svec.iter().map(|(r, m)| (r, std::slice::from_ref(m))).collect(),
+		RecordBlock::Multi(mvec) => mvec.iter().map(|(r, mv)| (r, mv as &[MethRec])).collect(),
+	};
+	(prev, recs)
+}
+
 pub fn calc_phred(z: f64) -> u8 {
 	if z <= 0.0 { 255 } else { ((-10.0 * z.log10()) as usize).min(255) as u8 }	
 }
@@ -127,7 +161,18 @@ impl <'a>OutputOpts<'a> {
 	pub fn sample_desc(&self) -> Option<&str> { self.sample_desc }
 }
 
-fn open_output_file(name: &str, chash: &ConfHash, tp: TPool) -> HtsFile {
+// header should be false for formats without a header line
+fn open_output_file(name: &str, chash: &ConfHash, tp: TPool, header: bool) -> HtsFile {
+	open_hts_output(name, chash, tp, Some(if header && !chash.get_bool("no_header") { 1 } else { 0 }))
+}
+
+// For files that can not be indexed by the tabix thread (i.e., the first two columns are not the contig and a unique,
+// strictly increasing 1 based position)
+fn open_unindexed_output_file(name: &str, chash: &ConfHash, tp: TPool) -> HtsFile {
+	open_hts_output(name, chash, tp, None)
+}
+
+fn open_hts_output(name: &str, chash: &ConfHash, tp: TPool, tabix_skip: Option<usize>) -> HtsFile {
 	let mut fname = String::from_str(name).unwrap();
 	let compress = chash.get_bool("compress");
 	let output_mode = if compress { 
@@ -136,7 +181,7 @@ fn open_output_file(name: &str, chash: &ConfHash, tp: TPool) -> HtsFile {
 	} else { "w" };
 	match HtsFile::new(&fname, output_mode) {
 		Ok(mut f) => {
-			chash.add_file(&fname, true);
+			chash.add_file(&fname, tabix_skip);
 			if let Some(tpool) = tp.deref() { f.set_thread_pool(tpool); }
 			f
 		},
@@ -186,7 +231,8 @@ fn print_tsv_header(f: &mut HtsFile, hdr: &VcfHeader, _chash: &ConfHash) -> io::
 	writeln!(f)
 }
 
-pub fn output_handler(chash: &ConfHash, hdr: &VcfHeader, r: Recv, outfiles: &mut [HtsFile], ph: PrintHeader, ob: OutputBlock) {
+pub fn output_handler<F>(chash: &ConfHash, hdr: &VcfHeader, r: Recv, outfiles: &mut [HtsFile], ph: PrintHeader, mut ob: F)
+where F: FnMut(&mut [HtsFile], &RecordBlock, Option<RecordBlockElem>, &ConfHash, &VcfHeader) -> io::Result<()> {
 	if !chash.get_bool("no_header") { for mut outfile in outfiles.iter_mut() { ph(&mut outfile, hdr, chash).expect("Error writing header") } }
 	let mut blk_store: HashMap<usize, Arc<RecordBlock>> = HashMap::new();
 	let mut curr_ix = 0;	
@@ -210,7 +256,7 @@ pub fn output_handler(chash: &ConfHash, hdr: &VcfHeader, r: Recv, outfiles: &mut
 
 pub fn output_cpg_thread(chash: Arc<ConfHash>, hdr: Arc<VcfHeader>, r: Recv, tp: TPool) {
 	let output = chash.get_str("cpgfile").expect("CpG output filename is missing");
-	let outfile = open_output_file(output, &chash, tp);
+	let outfile = open_output_file(output, &chash, tp, true);
 	debug!("output_cpg_thread starting up");
 	output_handler(&chash, &hdr, r, &mut[outfile], print_tsv_header, output_cpg);
 	debug!("output_cpg_thread closing down")
@@ -218,23 +264,116 @@ pub fn output_cpg_thread(chash: Arc<ConfHash>, hdr: Arc<VcfHeader>, r: Recv, tp:
 
 pub fn output_noncpg_thread(chash: Arc<ConfHash>, hdr: Arc<VcfHeader>, r: Recv, tp: TPool) {
 	let output = chash.get_str("noncpgfile").expect("Non CpG output filename is missing");
-	let outfile = open_output_file(output, &chash, tp);
+	let outfile = open_output_file(output, &chash, tp, true);
 	debug!("output_noncpg_thread starting up");
 	output_handler(&chash, &hdr, r, &mut[outfile], print_tsv_header, output_noncpg);
 	debug!("output_noncpg_thread closing down")
 }
 
+pub fn output_matrix_thread(chash: Arc<ConfHash>, hdr: Arc<VcfHeader>, r: Recv, tp: TPool) {
+	let output = chash.get_str("matrix").expect("Matrix output filename is missing");
+	let outfile = open_output_file(output, &chash, tp, true);
+	debug!("output_matrix_thread starting up");
+	output_handler(&chash, &hdr, r, &mut[outfile], print_matrix_header, output_matrix);
+	debug!("output_matrix_thread closing down")
+}
+
+pub fn output_region_summary_thread(chash: Arc<ConfHash>, hdr: Arc<VcfHeader>, r: Recv, tp: TPool) {
+	let output = chash.get_str("region_summary").expect("Region summary output filename is missing");
+	// Regions can share a start position, so the summary file is not indexed
+	let mut outfiles = [open_unindexed_output_file(output, &chash, tp)];
+	debug!("output_region_summary_thread starting up");
+	let mut summ = RegionSummary::new(&chash, hdr.nsamples());
+	output_handler(&chash, &hdr, r, &mut outfiles, print_region_summary_header, |f, blk, prev, ch, hd| summ.output_block(f, blk, prev, ch, hd));
+	summ.flush(&mut outfiles[0], &chash).expect("Error writing file");
+	debug!("output_region_summary_thread closing down")
+}
+
+// Output thread for single file formats from other methylation callers.  If index is false the file is not tabix indexed
+#[allow(clippy::too_many_arguments)]
+fn output_format_thread(chash: Arc<ConfHash>, hdr: Arc<VcfHeader>, r: Recv, tp: TPool, key: &str, header: bool, index: bool, ph: PrintHeader, ob: OutputBlock) {
+	let output = chash.get_str(key).expect("Output filename is missing");
+	let outfile = if index { open_output_file(output, &chash, tp, header) } else { open_unindexed_output_file(output, &chash, tp) };
+	debug!("output thread for {} starting up", key);
+	output_handler(&chash, &hdr, r, &mut[outfile], ph, ob);
+	debug!("output thread for {} closing down", key)
+}
+
+pub fn output_bismark_cov_thread(chash: Arc<ConfHash>, hdr: Arc<VcfHeader>, r: Recv, tp: TPool) {
+	output_format_thread(chash, hdr, r, tp, "bismark_cov", false, true, print_no_header, output_bismark_cov)
+}
+
+pub fn output_cx_report_thread(chash: Arc<ConfHash>, hdr: Arc<VcfHeader>, r: Recv, tp: TPool) {
+	output_format_thread(chash, hdr, r, tp, "cx_report", false, true, print_no_header, output_cx_report)
+}
+
+pub fn output_methylkit_thread(chash: Arc<ConfHash>, hdr: Arc<VcfHeader>, r: Recv, tp: TPool) {
+	// The first column of methylKit files is chr.base, so they can not be indexed
+	output_format_thread(chash, hdr, r, tp, "methylkit", true, false, print_methylkit_header, output_methylkit)
+}
+
+pub fn output_methyldackel_thread(chash: Arc<ConfHash>, hdr: Arc<VcfHeader>, r: Recv, tp: TPool) {
+	output_format_thread(chash, hdr, r, tp, "methyldackel", true, true, print_methyldackel_header, output_methyldackel)
+}
+
 pub fn output_bed_methyl_thread(chash: Arc<ConfHash>, hdr: Arc<VcfHeader>, r: Recv, tp: TPool) {
 	let tc: &[_] = &['.', '_'];
 	let prefix = chash.get_str("bed_methyl").expect("bedMethyl prefix is missing")
 		.trim_end_matches(".bed").trim_end_matches("cpg").trim_end_matches("chg").trim_end_matches("chh").trim_end_matches(tc);
-	let mut outfiles: Vec<_> = ["cpg", "chg", "chh"].iter().map(|s| open_output_file(format!("{}_{}.bed", prefix, s).as_str(), &chash, tp.clone())).collect();
+	let mut outfiles: Vec<_> = ["cpg", "chg", "chh"].iter().map(|s| open_output_file(format!("{}_{}.bed", prefix, s).as_str(), &chash, tp.clone(), true)).collect();
 	
 	// Prepare bbi files (BigBed and BigWig)
+	let bbi_threads = setup_bbi_threads(&chash, |s| Bbi::init(&prefix, s, &chash)).unwrap_or_else(|e| panic!("Error creating BigBed / BigWig files: {}", e));
+	
+	debug!("output_bed_methyl_thread thread starting up");
+	output_handler(&chash, &hdr, r, &mut outfiles, print_bed_methyl_header, output_bed_methyl);
+
+	finish_bbi_threads(&chash, bbi_threads);
+	debug!("output_bed_methyl_thread closing down")	
+}
+
+pub fn output_dmr_thread(chash: Arc<ConfHash>, hdr: Arc<VcfHeader>, r: Recv, tp: TPool) {
+	let prefix = chash.get_str("dmr").expect("DMR prefix is missing").trim_end_matches(".bed").to_owned();
+	let mut outfiles = [open_output_file(format!("{}_dmc.txt", prefix).as_str(), &chash, tp.clone(), true)];
+	let bbi_threads = setup_bbi_threads(&chash, |s| Bbi::init_bb(format!("{}_dmr.bb", prefix), AutoSql::Dmr, s, &chash)).unwrap_or_else(|e| panic!("Error creating BigBed file: {}", e));
+	
+	debug!("output_dmr_thread starting up");
+	let mut dmr = DmrCalc::new(&chash);
+	output_handler(&chash, &hdr, r, &mut outfiles, print_dmc_header, |f, blk, prev, ch, hd| dmr.test_block(f, blk, prev, ch, hd));
+	
+	// The DMRs can only be called once all CpGs have been tested
+	let mut dmr_file = open_output_file(format!("{}_dmr.bed", prefix).as_str(), &chash, tp, true);
+	if !chash.get_bool("no_header") { print_dmr_header(&mut dmr_file, &hdr, &chash).expect("Error writing header") }
+	dmr.finish(&mut dmr_file, &chash, &hdr).expect("Error writing DMR file");
+	finish_bbi_threads(&chash, bbi_threads);
+	debug!("output_dmr_thread closing down")	
+}
+
+pub fn output_segmentation_thread(chash: Arc<ConfHash>, hdr: Arc<VcfHeader>, r: Recv, tp: TPool) {
+	let prefix = chash.get_str("segmentation").expect("Segmentation prefix is missing").trim_end_matches(".bed").to_owned();
+	let bbi_threads = setup_bbi_threads(&chash, |s| Bbi::init_bb(format!("{}_segments.bb", prefix), AutoSql::Segment, s, &chash)).unwrap_or_else(|e| panic!("Error creating BigBed file: {}", e));
+
+	debug!("output_segmentation_thread starting up");
+	let mut seg = SegCalc::new(&chash, hdr.nsamples());
+	output_handler(&chash, &hdr, r, &mut [], print_no_header, |f, blk, prev, ch, hd| seg.output_block(f, blk, prev, ch, hd));
+
+	// The minimum size for UMR/LMR calls is set genome wide, so nothing is written until all CpGs have been seen.
+	// Segments from different samples can start at the same position, so the BED file is not indexed
+	let mut seg_file = open_unindexed_output_file(format!("{}_segments.bed", prefix).as_str(), &chash, tp);
+	if !chash.get_bool("no_header") { print_segment_header(&mut seg_file, &hdr, &chash).expect("Error writing header") }
+	seg.finish(&mut seg_file, &chash, &hdr, format!("{}_segments.json", prefix).as_str()).expect("Error writing segmentation files");
+	finish_bbi_threads(&chash, bbi_threads);
+	debug!("output_segmentation_thread closing down")	
+}
+
+type BbiThreads = (Vec<thread::JoinHandle<()>>, Sender<BbiMsg>);
+
+// Set up Bbi structure and start compress and write threads
+fn setup_bbi_threads<F: FnOnce(Sender<BbiMsg>) -> io::Result<Bbi>>(chash: &Arc<ConfHash>, init: F) -> io::Result<BbiThreads> {
 	let nt = chash.get_int("threads");
 	let (comp_send, comp_recv) = bounded(nt * 10);
 	let (wrt_send, wrt_recv) = bounded(nt * 10);
-	let bbi = Bbi::init(&prefix, comp_send, &chash).unwrap_or_else(|e| panic!("Error creating BigBed / BigWig files: {}", e));
+	let bbi = init(comp_send)?;
 	chash.set_bbi(bbi);
 	
 	// setup compress threads
@@ -250,10 +389,12 @@ pub fn output_bed_methyl_thread(chash: Arc<ConfHash>, hdr: Arc<VcfHeader>, r: Re
 	// setup write thread
 	let ch = chash.clone();
 	threads.push(thread::spawn(move || write_bbi_thread(ch, wrt_recv)));
-	
-	debug!("output_bed_methyl_thread thread starting up");
-	output_handler(&chash, &hdr, r, &mut outfiles, print_bed_methyl_header, output_bed_methyl);
+	Ok((threads, wrt_send))
+}
 
+fn finish_bbi_threads(chash: &ConfHash, bbi_threads: BbiThreads) {
+	let (mut threads, wrt_send) = bbi_threads;
+	
 	// Finish sending last bbi blocks
 	let bbi_ref = chash.bbi().read().unwrap();
 	bbi_ref.as_ref().expect("Bbi not set").finish();
@@ -267,6 +408,4 @@ pub fn output_bed_methyl_thread(chash: Arc<ConfHash>, hdr: Arc<VcfHeader>, r: Re
 	debug!("wait for compress and write threads");
 	// Wait for compress and write threads
 	for th in threads.drain(..) { th.join().unwrap() }
-	
-	debug!("output_bed_methyl_thread closing down")	
 }
@@ -6,7 +6,7 @@ use lazy_static::lazy_static;
 use r_htslib::{HtsFile, VcfHeader};
 
 use crate::config::*;
-use crate::read_vcf::unpack::{RecordBlock, RecordBlockElem};
+use crate::read_vcf::unpack::{RecordBlock, RecordBlockElem, Strand};
 
 use super::{OutputOpts, calc_phred, Record, MethRec};
 
@@ -27,7 +27,7 @@ const BM_TYPE_CHH: usize = 2;
 const RGB_TAB: [&str; 11] = [ "0,255,0", "55,255,0", "105,255,0", "155,255,0", "205,255,0", "255,255,0",
 		"255,205,0", "255,155,0", "255,105,0", "255,55,0", "255,0,0" ];
 		
-fn strand_and_context(rf: &[u8], call: &[u8]) -> Option<(char, [u8; 3], [u8; 3])> {
+pub(super) fn strand_and_context(rf: &[u8], call: &[u8]) -> Option<(char, [u8; 3], [u8; 3])> {
 	let mut ref_cx = [0; 3];
 	let mut call_cx = [0; 3];
 	match rf[2] {
@@ -101,7 +101,7 @@ fn output_bed_methyl_rec<W: Write>(files: &mut[W], chash: &ConfHash, hdr: &VcfHe
 				} else { (BM_TYPE_CHH, &ref_cx[..], &call_cx[..]) }			
 			}; 
 			
-			let (a, b) = if strand == '+' { (meth_rec.counts[5], meth_rec.counts[7]) } else { (meth_rec.counts[6], meth_rec.counts[4]) };
+			let (a, b) = meth_rec.meth_counts(if strand == '+' { Strand::C } else { Strand::G });
 			let cov = a + b;
 			if cov > 0 {
 				let m = (a as f64) / (cov as f64);
@@ -29,6 +29,20 @@ fn calc_prob(mrec1: &MethRec, mrec2: &MethRec, opts: &OutputOpts) -> (bool, f64)
 	} else { (false, 0.0) }
 }
 
+// Check if CpG passes the filters for a sample
+pub(super) fn cpg_pass(mrec1: &MethRec, mrec2: &MethRec, opts: &OutputOpts) -> bool {
+	let (pass, q) = calc_prob(mrec1, mrec2, opts);
+	pass && calc_phred(1.0 - q) >= opts.threshold()
+}
+
+// Methylated and non-methylated counts (both strands) for a sample at a CpG if the CpG passes the filters
+pub(super) fn cpg_meth_counts(mrec1: &MethRec, mrec2: &MethRec, opts: &OutputOpts) -> Option<(c_int, c_int)> {
+	if cpg_pass(mrec1, mrec2, opts) {
+		let ((a1, b1), (a2, b2)) = (mrec1.meth_counts(Strand::C), mrec2.meth_counts(Strand::G));
+		Some((a1 + a2, b1 + b2))
+	} else { None }
+}
+
 fn output_combined_mrec<W: Write>(f: &mut W, mrec1: &MethRec, mrec2: &MethRec) -> io::Result<()> {
 	if let (Some(gt1), Some(gt2)) = (mrec1.max_gt(), mrec2.max_gt()) {
 		let gq = calc_phred(1.0 - (mrec1.gt_probs()[gt1 as usize] + mrec2.gt_probs()[gt2 as usize]).exp());
@@ -44,7 +58,8 @@ fn output_combined_mrec<W: Write>(f: &mut W, mrec1: &MethRec, mrec2: &MethRec) -
 		if gt1 != 4 || gt2 != 7 { write!(f, ";DQ={}", calc_phred((mrec1.gt_probs()[4] + mrec2.gt_probs[7]).exp()))? }
 
 		// Collect counts
-		let (ct0, ct1) = (mrec1.counts[5] + mrec2.counts[6], mrec1.counts[7] + mrec2.counts[4]);
+		let ((a1, b1), (a2, b2)) = (mrec1.meth_counts(Strand::C), mrec2.meth_counts(Strand::G));
+		let (ct0, ct1) = (a1 + a2, b1 + b2);
 		let (ct2, ct3) = {
 			let mut x = 0;
 			let mut m = 1;
@@ -0,0 +1,316 @@
+use std::io::{self, Write};
+use std::f64::consts::SQRT_2;
+
+use libc::{c_int, c_double};
+use r_htslib::{HtsFile, VcfHeader};
+
+use crate::config::*;
+use crate::read_vcf::unpack::{RecordBlock, RecordBlockElem};
+use super::{OutputOpts, Record, MethRec, rec_slices};
+use super::output_cpg::cpg_meth_counts;
+
+#[link(name = "m")]
+extern "C" {
+	fn lgamma(x: c_double) -> c_double;
+	fn erfc(x: c_double) -> c_double;
+}
+
+// Prior for the beta-binomial dispersion parameter.  The estimate from the samples is shrunk towards
+// the prior, which is also used for groups with a single sample
+const DMR_PRIOR_DISP: f64 = 0.02;
+const DMR_PRIOR_DF: f64 = 2.0;
+
+#[derive(Debug, Copy, Clone)]
+enum DmrTest { BetaBinomial, Fisher }
+
+// Differentially methylated CpG candidate (p value below the FDR level)
+struct DmcCand {
+	rid: u32,
+	pos: u32,
+	p: f64,
+	meth: [f64; 2],
+}
+
+impl DmcCand {
+	fn diff(&self) -> f64 { self.meth[1] - self.meth[0] }
+}
+
+struct DmrRegion {
+	rid: u32,
+	start: u32,
+	last: u32, // Position of last CpG
+	n_cpg: usize,
+	meth: [f64; 2], // Sum of methylation levels
+	q: f64,
+}
+
+impl DmrRegion {
+	fn new(c: &DmcCand, q: f64) -> Self { Self{rid: c.rid, start: c.pos, last: c.pos, n_cpg: 1, meth: c.meth, q} }
+	fn hyper(&self) -> bool { self.meth[1] > self.meth[0] }
+	fn add(&mut self, c: &DmcCand, q: f64) {
+		self.last = c.pos;
+		self.n_cpg += 1;
+		self.meth[0] += c.meth[0];
+		self.meth[1] += c.meth[1];
+		self.q = self.q.min(q);
+	}
+}
+
+fn lchoose(n: c_int, k: c_int) -> f64 {
+	unsafe { lgamma((n + 1) as f64) - lgamma((k + 1) as f64) - lgamma((n - k + 1) as f64) }
+}
+
+// Two sided Fisher's exact test on the methylated (a, c) and non-methylated (b, d) counts pooled across the samples of each group
+fn fisher_test(a: c_int, b: c_int, c: c_int, d: c_int) -> f64 {
+	let (r1, r2, c1) = (a + b, c + d, a + c);
+	let n = r1 + r2;
+	let lp = |x: c_int| lchoose(r1, x) + lchoose(r2, c1 - x) - lchoose(n, c1);
+	let lp0 = lp(a) + 1.0e-7;
+	let p: f64 = ((c1 - r2).max(0)..=r1.min(c1)).map(lp).filter(|z| *z <= lp0).map(|z| z.exp()).sum();
+	p.min(1.0)
+}
+
+// Mean methylation and variance of the mean for a group assuming a beta-binomial distribution of counts.
+// The dispersion is estimated by the method of moments when there is more than one sample
+fn beta_binomial_stats(cts: &[(c_int, c_int)]) -> (f64, f64) {
+	let (m, n) = cts.iter().fold((0.0, 0.0), |(m, n), (a, b)| (m + *a as f64, n + (*a + *b) as f64));
+	let mu = (m + 0.5) / (n + 1.0);
+	let k = cts.len() as f64;
+	let phi = if cts.len() > 1 {
+		let (mut s, mut wa, mut wb) = (0.0, 0.0, 0.0);
+		for (a, b) in cts.iter() {
+			let ns = (*a + *b) as f64;
+			let w = 1.0 - ns / n;
+			s += ns * (*a as f64 / ns - mu).powi(2);
+			wa += w;
+			wb += w * (ns - 1.0);
+		}
+		let phi = if wb > 0.0 { ((s / (mu * (1.0 - mu)) - wa) / wb).clamp(0.0, 1.0) } else { 0.0 };
+		((k - 1.0) * phi + DMR_PRIOR_DF * DMR_PRIOR_DISP) / (k - 1.0 + DMR_PRIOR_DF)
+	} else { DMR_PRIOR_DISP };
+	let z = cts.iter().fold(0.0, |s, (a, b)| {
+		let ns = (*a + *b) as f64;
+		s + ns * (1.0 + (ns - 1.0) * phi)
+	});
+	(mu, mu * (1.0 - mu) * z / (n * n))
+}
+
+// Wald test on the difference in group means
+fn beta_binomial_test(cts: &[Vec<(c_int, c_int)>; 2]) -> f64 {
+	let (mu1, v1) = beta_binomial_stats(&cts[0]);
+	let (mu2, v2) = beta_binomial_stats(&cts[1]);
+	if v1 + v2 > 0.0 {
+		let z = (mu2 - mu1) / (v1 + v2).sqrt();
+		unsafe { erfc(z.abs() / SQRT_2) }
+	} else { 1.0 }
+}
+
+// Benjamini-Hochberg q values for the p values in p.  As only the CpGs with p values below the FDR level are kept,
+// the total number of tests (m) can be larger than the number of p values
+fn bh_qvalues(p: &[f64], m: usize) -> Vec<f64> {
+	let m = m as f64;
+	let mut ix: Vec<_> = (0..p.len()).collect();
+	ix.sort_by(|a, b| p[*a].partial_cmp(&p[*b]).unwrap());
+	let mut qval = vec![1.0; p.len()];
+	let mut qmin: f64 = 1.0;
+	for (rank, i) in ix.iter().enumerate().rev() {
+		qmin = qmin.min(p[*i] * m / ((rank + 1) as f64));
+		qval[*i] = qmin;
+	}
+	qval
+}
+
+pub fn print_dmc_header(f: &mut HtsFile, _hdr: &VcfHeader, chash: &ConfHash) -> io::Result<()> {
+	let names = chash.dmr_groups().expect("DMR groups not set").names();
+	writeln!(f, "Contig\tPos0\tPos1\t{}:Meth\t{}:Cov\t{}:Meth\t{}:Cov\tDiff\tPValue", names[0], names[0], names[1], names[1])
+}
+
+pub fn print_dmr_header(f: &mut HtsFile, _hdr: &VcfHeader, chash: &ConfHash) -> io::Result<()> {
+	let names = chash.dmr_groups().expect("DMR groups not set").names();
+	writeln!(f, "track name=\"DMR\" description=\"Differentially methylated regions {} vs {}\" visibility=2", names[1], names[0])
+}
+
+// Per CpG tests for differential methylation between two groups of samples.  All tested CpGs are written to the
+// DMC file with their (unadjusted) p values as they are processed.  CpGs with p values below the FDR level are kept
+// so that q values (Benjamini-Hochberg) can be calculated once all CpGs have been seen, and the significant CpGs are
+// then merged into DMRs
+pub struct DmrCalc {
+	test: DmrTest,
+	min_cov: c_int,
+	min_samples: usize,
+	fdr: f64,
+	min_diff: f64,
+	max_gap: u32,
+	min_cpg: usize,
+	n_tests: usize,
+	cands: Vec<DmcCand>,
+	counts: [Vec<(c_int, c_int)>; 2],
+}
+
+impl DmrCalc {
+	pub fn new(chash: &ConfHash) -> Self {
+		let test = match chash.get_str("dmr_test").map(|s| s.to_lowercase()) {
+			Some(s) if s == "fisher" => DmrTest::Fisher,
+			_ => DmrTest::BetaBinomial,
+		};
+		debug!("DMR test: {:?}", test);
+		Self {
+			test,
+			min_cov: chash.get_int("dmr_min_cov").max(1) as c_int,
+			min_samples: chash.get_int("dmr_min_samples"),
+			fdr: chash.get_float("dmr_fdr"),
+			min_diff: chash.get_float("dmr_min_diff"),
+			max_gap: chash.get_int("dmr_max_gap") as u32,
+			min_cpg: chash.get_int("dmr_min_cpg"),
+			n_tests: 0,
+			cands: Vec::new(),
+			counts: [Vec::new(), Vec::new()],
+		}
+	}
+
+	// Collect methylated and non-methylated counts for samples in each group that pass the filters
+	fn collect_counts(&mut self, mvec1: &[MethRec], mvec2: &[MethRec], groups: &[Option<usize>], opts: &OutputOpts) {
+		for v in self.counts.iter_mut() { v.clear() }
+		for ((m1, m2), gp) in mvec1.iter().zip(mvec2.iter()).zip(groups.iter()) {
+			if let (Some(g), Some((a, b))) = (gp, cpg_meth_counts(m1, m2, opts)) {
+				if a + b >= self.min_cov { self.counts[*g].push((a, b)) }
+			}
+		}
+	}
+
+	fn test_cpg<W: Write>(&mut self, f: &mut W, hdr: &VcfHeader, rec1: &Record, rec2: &Record) -> io::Result<()> {
+		if self.counts.iter().any(|v| v.len() < self.min_samples) { return Ok(()) }
+		let pooled: Vec<_> = self.counts.iter().map(|v| v.iter().fold((0, 0), |(m, u), (a, b)| (m + *a, u + *b))).collect();
+		let p = match self.test {
+			DmrTest::Fisher => fisher_test(pooled[0].0, pooled[0].1, pooled[1].0, pooled[1].1),
+			DmrTest::BetaBinomial => beta_binomial_test(&self.counts),
+		};
+		let meth_lev = |(a, b): (c_int, c_int)| a as f64 / (a + b) as f64;
+		let meth = [meth_lev(pooled[0]), meth_lev(pooled[1])];
+		self.n_tests += 1;
+		writeln!(f, "{}\t{}\t{}\t{:.3}\t{}\t{:.3}\t{}\t{:.3}\t{:.4e}", hdr.ctg_name(rec1.rid as usize).unwrap(), rec1.pos, rec2.pos + 1,
+			meth[0], pooled[0].0 + pooled[0].1, meth[1], pooled[1].0 + pooled[1].1, meth[1] - meth[0], p)?;
+		if p <= self.fdr { self.cands.push(DmcCand{rid: rec1.rid, pos: rec1.pos, p, meth}) }
+		Ok(())
+	}
+
+	pub fn test_block(&mut self, outfiles: &mut [HtsFile], rec_blk: &RecordBlock, prev: Option<RecordBlockElem>, chash: &ConfHash, hdr: &VcfHeader) -> io::Result<()> {
+		assert_eq!(outfiles.len(), 1);
+		let f = &mut outfiles[0];
+		let opts = OutputOpts::new(chash);
+		let groups = chash.dmr_groups().expect("DMR groups not set").sample_group();
+		if let RecordBlock::Single(_) = rec_blk { panic!("DMR calling requires multi sample files") }
+		let (mut prev, recs) = rec_slices(rec_blk, prev);
+		for (rec2, mvec2) in recs {
+			if let Some((rec1, mvec1)) = prev {
+				if rec1.gt().is_some() && rec2.gt().is_some() && rec1.rid == rec2.rid && rec1.pos + 1 == rec2.pos {
+					self.collect_counts(mvec1, mvec2, groups, &opts);
+					self.test_cpg(f, hdr, rec1, rec2)?;
+				}
+			}
+			prev = Some((rec2, mvec2));
+		}
+		Ok(())
+	}
+
+	fn output_region<W: Write>(&self, f: &mut W, chash: &ConfHash, hdr: &VcfHeader, reg: &DmrRegion, prev_ctg: &mut Option<u32>) -> io::Result<()> {
+		if reg.n_cpg < self.min_cpg { return Ok(()) }
+		let bbi_ref = chash.bbi().read().unwrap();
+		let bbi = bbi_ref.as_ref().expect("Bbi not set");
+		let sender = bbi.sender().expect("Bbi sender not set");
+		let mut bb_build = bbi.bb_files()[0].build().write().unwrap();
+		if matches!(prev_ctg, Some(rid) if *rid != reg.rid) {
+			bb_build.finish(sender);
+			bb_build.clear_counts();
+		}
+		*prev_ctg = Some(reg.rid);
+		let n = reg.n_cpg as f64;
+		let (m1, m2) = (reg.meth[0] / n, reg.meth[1] / n);
+		let diff = m2 - m1;
+		let (start, end) = (reg.start, reg.last + 2);
+		let sbuf = format!("{}\t{}\t.\t{}\t{:.3}\t{:.3}\t{:.3}\t{:.3e}", if reg.hyper() { "hyper" } else { "hypo" },
+			((diff.abs() * 1000.0 + 0.5) as usize).min(1000), reg.n_cpg, m1, m2, diff, reg.q);
+		writeln!(f, "{}\t{}\t{}\t{}", hdr.ctg_name(reg.rid as usize).unwrap(), start, end, &sbuf)?;
+		let out_ix = chash.vcf_contigs()[reg.rid as usize].out_ix().expect("Missing out index for contig") as u32;
+		bb_build.add_bb_region(out_ix, start, end, &sbuf, sender);
+		bb_build.add_zoom_obs(out_ix, start, diff as f32, sender);
+		Ok(())
+	}
+
+	// Calculate q values and merge significant CpGs with the same direction of change into DMRs
+	pub fn finish<W: Write>(&mut self, f: &mut W, chash: &ConfHash, hdr: &VcfHeader) -> io::Result<()> {
+		let pval: Vec<_> = self.cands.iter().map(|c| c.p).collect();
+		let qval = bh_qvalues(&pval, self.n_tests);
+		let mut n_dmc = 0;
+		let mut curr: Option<DmrRegion> = None;
+		let mut prev_ctg = None;
+		for (c, q) in self.cands.iter().zip(qval.iter().copied()) {
+			if q > self.fdr || c.diff().abs() < self.min_diff { continue }
+			n_dmc += 1;
+			match curr.as_mut() {
+				Some(reg) if reg.rid == c.rid && reg.hyper() == (c.diff() > 0.0) && c.pos - reg.last <= self.max_gap => reg.add(c, q),
+				_ => {
+					if let Some(reg) = curr.take() { self.output_region(f, chash, hdr, &reg, &mut prev_ctg)? }
+					curr = Some(DmrRegion::new(c, q));
+				},
+			}
+		}
+		if let Some(reg) = curr.take() { self.output_region(f, chash, hdr, &reg, &mut prev_ctg)? }
+		info!("DMR calling: {} CpGs tested, {} differentially methylated", self.n_tests, n_dmc);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_close(x: f64, y: f64) { assert!((x - y).abs() < 1.0e-6, "{} != {}", x, y) }
+
+	#[test]
+	fn fisher_known_values() {
+		// Fisher's tea tasting example and a textbook table: two sided p values as given by R's fisher.test()
+		assert_close(fisher_test(3, 1, 1, 3), 0.485714);
+		assert_close(fisher_test(1, 9, 11, 3), 0.002759);
+		assert_close(fisher_test(5, 5, 5, 5), 1.0);
+		// Symmetric under swapping the groups
+		assert_close(fisher_test(11, 3, 1, 9), fisher_test(1, 9, 11, 3));
+	}
+
+	#[test]
+	fn beta_binomial_single_sample() {
+		// A single sample uses the prior dispersion
+		let (mu, var) = beta_binomial_stats(&[(8, 2)]);
+		assert_close(mu, 8.5 / 11.0);
+		assert_close(var, mu * (1.0 - mu) * (1.0 + 9.0 * DMR_PRIOR_DISP) / 10.0);
+	}
+
+	#[test]
+	fn beta_binomial_overdispersion() {
+		// Same pooled counts, but the variance of the mean should be larger when the samples disagree
+		let (mu1, v1) = beta_binomial_stats(&[(5, 5), (5, 5)]);
+		let (mu2, v2) = beta_binomial_stats(&[(9, 1), (1, 9)]);
+		assert_close(mu1, 0.5);
+		assert_close(mu2, 0.5);
+		assert!(v2 > v1);
+	}
+
+	#[test]
+	fn beta_binomial_test_groups() {
+		let a = vec![(20, 0), (19, 1), (18, 2)];
+		let b = vec![(1, 19), (0, 20), (2, 18)];
+		assert_close(beta_binomial_test(&[a.clone(), a.clone()]), 1.0);
+		let p = beta_binomial_test(&[a.clone(), b.clone()]);
+		assert!(p < 1.0e-6);
+		assert_close(beta_binomial_test(&[b, a]), p);
+	}
+
+	#[test]
+	fn bh_known_values() {
+		let p = [0.01, 0.04, 0.03, 0.005];
+		for (q, x) in bh_qvalues(&p, 4).iter().zip([0.02, 0.04, 0.04, 0.02].iter()) { assert_close(*q, *x) }
+		// Tests with p values above the FDR level are not kept but still count towards m
+		for (q, x) in bh_qvalues(&p, 10).iter().zip([0.05, 0.1, 0.1, 0.05].iter()) { assert_close(*q, *x) }
+		assert_close(bh_qvalues(&[0.5], 4)[0], 1.0);
+	}
+}
@@ -0,0 +1,120 @@
+use std::io::{self, Write};
+use std::str::from_utf8;
+
+use libc::c_int;
+use r_htslib::{HtsFile, VcfHeader};
+
+use crate::config::*;
+use crate::read_vcf::unpack::{RecordBlock, RecordBlockElem, Strand};
+use super::{OutputOpts, rec_slices};
+use super::output_cpg::cpg_pass;
+use super::output_bed_methyl::strand_and_context;
+
+// Output formats used by other methylation callers (Bismark coverage and CX report, methylKit and MethylDackel bedGraph).
+// These are all single sample formats.
+
+// CpG site passing filters.  With Mode::Combined there is one site per CpG (with the counts from both strands),
+// otherwise there is a site for each strand.  Coordinates are 0 offset, half open
+struct CpgSite {
+	rid: u32,
+	start: u32,
+	end: u32,
+	strand: Strand,
+	meth: c_int,
+	unmeth: c_int,
+}
+
+impl CpgSite {
+	fn cov(&self) -> c_int { self.meth + self.unmeth }
+	fn meth_pc(&self) -> f64 { 100.0 * (self.meth as f64) / (self.cov() as f64) }
+}
+
+fn cpg_sites(rec_blk: &RecordBlock, prev: Option<RecordBlockElem>, opts: &OutputOpts) -> Vec<CpgSite> {
+	let (mut prev, recs) = rec_slices(rec_blk, prev);
+	let mut sites = Vec::new();
+	for (rec2, mvec2) in recs {
+		if let Some((rec1, mvec1)) = prev {
+			if rec1.gt().is_some() && rec2.gt().is_some() && rec1.rid == rec2.rid && rec1.pos + 1 == rec2.pos && cpg_pass(&mvec1[0], &mvec2[0], opts) {
+				let ((a1, b1), (a2, b2)) = (mvec1[0].meth_counts(Strand::C), mvec2[0].meth_counts(Strand::G));
+				let (rid, pos) = (rec1.rid, rec1.pos);
+				if matches!(opts.mode(), Mode::Combined) {
+					sites.push(CpgSite{rid, start: pos, end: pos + 2, strand: Strand::C, meth: a1 + a2, unmeth: b1 + b2})
+				} else {
+					sites.push(CpgSite{rid, start: pos, end: pos + 1, strand: Strand::C, meth: a1, unmeth: b1});
+					sites.push(CpgSite{rid, start: pos + 1, end: pos + 2, strand: Strand::G, meth: a2, unmeth: b2});
+				}
+			}
+		}
+		prev = Some((rec2, mvec2));
+	}
+	sites.retain(|s| s.cov() > 0);
+	sites
+}
+
+pub fn print_no_header(_f: &mut HtsFile, _hdr: &VcfHeader, _chash: &ConfHash) -> io::Result<()> { Ok(()) }
+
+pub fn print_methylkit_header(f: &mut HtsFile, _hdr: &VcfHeader, _chash: &ConfHash) -> io::Result<()> {
+	writeln!(f, "chrBase\tchr\tbase\tstrand\tcoverage\tfreqC\tfreqT")
+}
+
+pub fn print_methyldackel_header(f: &mut HtsFile, _hdr: &VcfHeader, chash: &ConfHash) -> io::Result<()> {
+	let sn = chash.get_str("sample_name").expect("No sample name set");
+	writeln!(f, "track type=\"bedGraph\" description=\"{} CpG methylation levels\"", sn)
+}
+
+// Bismark coverage: chrom, start, end (1 offset, inclusive), methylation percentage, methylated and non-methylated counts
+pub fn output_bismark_cov(outfiles: &mut [HtsFile], rec_blk: &RecordBlock, prev: Option<RecordBlockElem>, chash: &ConfHash, hdr: &VcfHeader) -> io::Result<()> {
+	assert_eq!(outfiles.len(), 1);
+	let f = &mut outfiles[0];
+	let opts = OutputOpts::new(chash);
+	for s in cpg_sites(rec_blk, prev, &opts).iter() {
+		writeln!(f, "{}\t{}\t{}\t{}\t{}\t{}", hdr.ctg_name(s.rid as usize).unwrap(), s.start + 1, s.end, s.meth_pc(), s.meth, s.unmeth)?
+	}
+	Ok(())
+}
+
+// methylKit: chrBase, chr, base (1 offset), strand (F/R), coverage, percentage methylated and non-methylated
+pub fn output_methylkit(outfiles: &mut [HtsFile], rec_blk: &RecordBlock, prev: Option<RecordBlockElem>, chash: &ConfHash, hdr: &VcfHeader) -> io::Result<()> {
+	assert_eq!(outfiles.len(), 1);
+	let f = &mut outfiles[0];
+	let opts = OutputOpts::new(chash);
+	for s in cpg_sites(rec_blk, prev, &opts).iter() {
+		let ctg = hdr.ctg_name(s.rid as usize).unwrap();
+		let m = s.meth_pc();
+		writeln!(f, "{}.{}\t{}\t{}\t{}\t{}\t{:.2}\t{:.2}", ctg, s.start + 1, ctg, s.start + 1, if matches!(s.strand, Strand::C) { 'F' } else { 'R' }, s.cov(), m, 100.0 - m)?
+	}
+	Ok(())
+}
+
+// MethylDackel bedGraph: chrom, start, end (0 offset, half open), methylation percentage (rounded), methylated and non-methylated counts
+pub fn output_methyldackel(outfiles: &mut [HtsFile], rec_blk: &RecordBlock, prev: Option<RecordBlockElem>, chash: &ConfHash, hdr: &VcfHeader) -> io::Result<()> {
+	assert_eq!(outfiles.len(), 1);
+	let f = &mut outfiles[0];
+	let opts = OutputOpts::new(chash);
+	for s in cpg_sites(rec_blk, prev, &opts).iter() {
+		writeln!(f, "{}\t{}\t{}\t{}\t{}\t{}", hdr.ctg_name(s.rid as usize).unwrap(), s.start, s.end, (s.meth_pc() + 0.5) as usize, s.meth, s.unmeth)?
+	}
+	Ok(())
+}
+
+// Bismark CX report: chrom, position (1 offset), strand, methylated and non-methylated counts, context (CG, CHG, CHH) and
+// trinucleotide context.  All cytosines with a called genotype and coverage are reported (as for bedMethyl output)
+pub fn output_cx_report(outfiles: &mut [HtsFile], rec_blk: &RecordBlock, _prev: Option<RecordBlockElem>, _chash: &ConfHash, hdr: &VcfHeader) -> io::Result<()> {
+	assert_eq!(outfiles.len(), 1);
+	let f = &mut outfiles[0];
+	let svec = match rec_blk {
+		RecordBlock::Single(svec) => svec,
+		RecordBlock::Multi(_) => panic!("Multi sample files not compatible with CX report"),
+	};
+	for (rec, meth_rec) in svec.iter().filter(|(_, m)| m.max_gt().is_some()) {
+		let (strand, ref_cx, _) = match strand_and_context(&rec.cx, &meth_rec.cx) {
+			Some(v) => v,
+			None => continue,
+		};
+		let (a, b) = meth_rec.meth_counts(if strand == '+' { Strand::C } else { Strand::G });
+		if a + b == 0 { continue }
+		let cx = if ref_cx[1] == b'G' { "CG" } else if ref_cx[2] == b'G' { "CHG" } else { "CHH" };
+		writeln!(f, "{}\t{}\t{}\t{}\t{}\t{}\t{}", hdr.ctg_name(rec.rid as usize).unwrap(), rec.pos + 1, strand, a, b, cx, from_utf8(&ref_cx).unwrap())?
+	}
+	Ok(())
+}
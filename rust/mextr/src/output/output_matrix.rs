@@ -0,0 +1,59 @@
+use std::io::{self, Write};
+
+use libc::c_int;
+use r_htslib::{HtsFile, VcfHeader};
+
+use crate::config::*;
+use crate::read_vcf::unpack::{RecordBlock, RecordBlockElem};
+use super::{OutputOpts, Record, MethRec, rec_slices};
+use super::output_cpg::cpg_meth_counts;
+
+// Matrix output has one line per CpG (strands combined) with a column for each sample (beta values) or
+// two columns for each sample (methylated and non-methylated counts).  Samples where the CpG does not
+// pass the filters are given as NA
+pub fn print_matrix_header(f: &mut HtsFile, hdr: &VcfHeader, chash: &ConfHash) -> io::Result<()> {
+	let beta = chash.get_bool("matrix_beta");
+	write!(f, "Contig\tPos0\tPos1")?;
+	for i in 0..hdr.nsamples() {
+		let name = hdr.sample_name(i)?;
+		if beta { write!(f, "\t{}", name)? } else { write!(f, "\t{}:meth\t{}:unmeth", name, name)? }
+	}
+	writeln!(f)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn output_matrix_rec<W: Write>(f: &mut W, hdr: &VcfHeader, opts: &OutputOpts, rec1: &Record, rec2: &Record, mvec1: &[MethRec], mvec2: &[MethRec],
+	beta: bool, min_samples: usize, cts: &mut Vec<Option<(c_int, c_int)>>) -> io::Result<()> {
+	cts.clear();
+	for (m1, m2) in mvec1.iter().zip(mvec2.iter()) { cts.push(cpg_meth_counts(m1, m2, opts).filter(|(a, b)| a + b > 0)) }
+	if cts.iter().filter(|x| x.is_some()).count() < min_samples { return Ok(()) }
+	write!(f, "{}\t{}\t{}", hdr.ctg_name(rec1.rid as usize).unwrap(), rec1.pos, rec2.pos + 1)?;
+	for x in cts.iter() {
+		match (x, beta) {
+			(Some((a, b)), true) => write!(f, "\t{:.3}", (*a as f64) / ((a + b) as f64))?,
+			(Some((a, b)), false) => write!(f, "\t{}\t{}", a, b)?,
+			(None, true) => write!(f, "\tNA")?,
+			(None, false) => write!(f, "\tNA\tNA")?,
+		}
+	}
+	writeln!(f)
+}
+
+pub fn output_matrix(outfiles: &mut [HtsFile], rec_blk: &RecordBlock, prev: Option<RecordBlockElem>, chash: &ConfHash, hdr: &VcfHeader) -> io::Result<()> {
+	assert_eq!(outfiles.len(), 1);
+	let f = &mut outfiles[0];
+	let opts = OutputOpts::new(chash);
+	let beta = chash.get_bool("matrix_beta");
+	let min_samples = chash.get_int("min_samples");
+	let mut cts = Vec::with_capacity(hdr.nsamples());
+	let (mut prev, recs) = rec_slices(rec_blk, prev);
+	for (rec2, mvec2) in recs {
+		if let Some((rec1, mvec1)) = prev {
+			if rec1.gt().is_some() && rec2.gt().is_some() && rec1.rid == rec2.rid && rec1.pos + 1 == rec2.pos {
+				output_matrix_rec(f, hdr, &opts, rec1, rec2, mvec1, mvec2, beta, min_samples, &mut cts)?;
+			}
+		}
+		prev = Some((rec2, mvec2));
+	}
+	Ok(())
+}
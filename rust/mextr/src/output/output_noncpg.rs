@@ -32,7 +32,7 @@ pub fn output_mrec<W: Write>(f: &mut W, mrec: &MethRec, strand: Strand, output_c
 		if output_cx { write!(f,";CX={}", from_utf8(&mrec.cx()[2..]).unwrap())? }
 		let exp_gt = if matches!(strand, Strand::C) { 4 } else { 7 };
 		if gt != exp_gt { write!(f, ";DQ={}", calc_phred(mrec.gt_probs()[exp_gt as usize].exp()))? }
-		let (ct0, ct1) = if matches!(strand, Strand::C) { (mrec.counts[5], mrec.counts[7]) } else { (mrec.counts[6], mrec.counts[4]) };
+		let (ct0, ct1) = mrec.meth_counts(strand);
 		let (ct2, ct3) = {
 			let mut x = 0;
 			let mut y = 0;
@@ -0,0 +1,122 @@
+use std::io::{self, Write};
+use std::collections::BTreeMap;
+
+use r_htslib::{HtsFile, VcfHeader};
+
+use crate::config::*;
+use crate::read_vcf::unpack::{RecordBlock, RecordBlockElem};
+use super::{OutputOpts, Record, MethRec, rec_slices};
+use super::output_cpg::cpg_meth_counts;
+
+// Summary statistics for a sample in a region
+#[derive(Default, Copy, Clone)]
+struct SampleSummary {
+	n_cpg: usize,
+	cov: usize,
+	meth: usize, // Methylated counts
+	sum_meth: f64, // Sum of per CpG methylation levels
+}
+
+impl SampleSummary {
+	fn add(&mut self, a: usize, b: usize) {
+		self.n_cpg += 1;
+		self.cov += a + b;
+		self.meth += a;
+		self.sum_meth += (a as f64) / ((a + b) as f64);
+	}
+}
+
+pub fn print_region_summary_header(f: &mut HtsFile, hdr: &VcfHeader, _chash: &ConfHash) -> io::Result<()> {
+	write!(f, "Contig\tStart\tEnd\tName")?;
+	for i in 0..hdr.nsamples() {
+		let name = hdr.sample_name(i)?;
+		write!(f, "\t{}:n_cpg\t{}:cov\t{}:mean_meth\t{}:weighted_meth", name, name, name, name)?;
+	}
+	writeln!(f)
+}
+
+// Methylation summaries per region and per sample for the regions from a BED file or for fixed size windows.  The
+// CpGs are filtered as for the CpG output.  For each sample we report the number of CpGs passing the filters, the
+// total coverage, the mean of the CpG methylation levels and the weighted methylation (total methylated counts / coverage).
+// Summaries are accumulated for a contig and written out when the contig is finished.  With a regions file all regions
+// are output (sorted on start position) for contigs present in the input; with fixed windows only windows with at
+// least one CpG are output
+pub struct RegionSummary {
+	ns: usize,
+	window: u32,
+	curr_rid: Option<u32>,
+	summ: BTreeMap<usize, Vec<SampleSummary>>, // Key is region index or window index
+}
+
+impl RegionSummary {
+	pub fn new(chash: &ConfHash, ns: usize) -> Self {
+		Self{ns, window: chash.get_int("summary_window") as u32, curr_rid: None, summ: BTreeMap::new()}
+	}
+
+	fn write_line<W: Write>(&self, f: &mut W, ctg: &str, start: u32, end: u32, name: Option<&str>, summ: Option<&Vec<SampleSummary>>) -> io::Result<()> {
+		write!(f, "{}\t{}\t{}\t{}", ctg, start, end, name.unwrap_or("."))?;
+		for ix in 0..self.ns {
+			match summ.map(|v| &v[ix]).filter(|s| s.n_cpg > 0) {
+				Some(s) => write!(f, "\t{}\t{}\t{:.3}\t{:.3}", s.n_cpg, s.cov, s.sum_meth / (s.n_cpg as f64), (s.meth as f64) / (s.cov as f64))?,
+				None => write!(f, "\t0\t0\tNA\tNA")?,
+			}
+		}
+		writeln!(f)
+	}
+
+	// Output summaries for current contig
+	pub fn flush<W: Write>(&mut self, f: &mut W, chash: &ConfHash) -> io::Result<()> {
+		if let Some(rid) = self.curr_rid.take() {
+			let ctg = &chash.vcf_contigs()[rid as usize];
+			if let Some(regs) = chash.summary_regions() {
+				for (ix, reg) in regs[rid as usize].regions().iter().enumerate() {
+					self.write_line(f, ctg.name(), reg.start, reg.end, reg.name.as_deref(), self.summ.get(&ix))?
+				}
+			} else {
+				let w = self.window as usize;
+				for (ix, summ) in self.summ.iter() {
+					self.write_line(f, ctg.name(), (ix * w) as u32, ((ix + 1) * w).min(ctg.length()) as u32, None, Some(summ))?
+				}
+			}
+			self.summ.clear();
+		}
+		Ok(())
+	}
+
+	fn add_cpg<W: Write>(&mut self, f: &mut W, chash: &ConfHash, opts: &OutputOpts, rec: &Record, mvec1: &[MethRec], mvec2: &[MethRec]) -> io::Result<()> {
+		if self.curr_rid != Some(rec.rid) {
+			self.flush(f, chash)?;
+			self.curr_rid = Some(rec.rid);
+		}
+		let ixs: Vec<usize> = match chash.summary_regions() {
+			Some(regs) => regs[rec.rid as usize].find(rec.pos).collect(),
+			None => vec![(rec.pos / self.window) as usize],
+		};
+		if ixs.is_empty() { return Ok(()) }
+		for (sample, (m1, m2)) in mvec1.iter().zip(mvec2.iter()).enumerate() {
+			if let Some((a, b)) = cpg_meth_counts(m1, m2, opts).filter(|(a, b)| a + b > 0) {
+				for ix in ixs.iter() {
+					let ns = self.ns;
+					self.summ.entry(*ix).or_insert_with(|| vec![SampleSummary::default(); ns])[sample].add(a as usize, b as usize)
+				}
+			}
+		}
+		Ok(())
+	}
+
+	pub fn output_block(&mut self, outfiles: &mut [HtsFile], rec_blk: &RecordBlock, prev: Option<RecordBlockElem>, chash: &ConfHash, _hdr: &VcfHeader) -> io::Result<()> {
+		assert_eq!(outfiles.len(), 1);
+		let f = &mut outfiles[0];
+		let opts = OutputOpts::new(chash);
+		let (mut prev, recs) = rec_slices(rec_blk, prev);
+		for (rec2, mvec2) in recs {
+			if let Some((rec1, mvec1)) = prev {
+				if rec1.gt().is_some() && rec2.gt().is_some() && rec1.rid == rec2.rid && rec1.pos + 1 == rec2.pos {
+					self.add_cpg(f, chash, &opts, rec1, mvec1, mvec2)?;
+				}
+			}
+			prev = Some((rec2, mvec2));
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,421 @@
+use std::io::{self, Write, BufWriter};
+use std::fs::File;
+
+use libc::c_int;
+use serde::Serialize;
+use r_htslib::{HtsFile, VcfHeader};
+
+use crate::config::*;
+use crate::read_vcf::unpack::{RecordBlock, RecordBlockElem};
+use super::{OutputOpts, MethRec, rec_slices};
+use super::output_cpg::cpg_meth_counts;
+
+const PMD_WIN: usize = 100; // CpGs per window for PMD detection
+// Distributions (mean, sd) of mean window methylation for the non-PMD and PMD states, and the probability of
+// switching between states
+const PMD_EMISSION: [(f64, f64); 2] = [(0.75, 0.12), (0.45, 0.15)];
+const PMD_SWITCH: f64 = 0.01;
+const SEG_MIN_CPG: usize = 3; // Minimum CpGs for UMR/LMR candidates
+const SEG_MAX_HIST: usize = 256;
+const SEG_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum SegType { Umr, Lmr, Pmd }
+
+impl SegType {
+	fn name(&self) -> &'static str {
+		match self {
+			SegType::Umr => "UMR",
+			SegType::Lmr => "LMR",
+			SegType::Pmd => "PMD",
+		}
+	}
+}
+
+struct Segment {
+	out_ix: usize,
+	rid: u32,
+	start: u32,
+	end: u32,
+	sample: usize,
+	seg_type: SegType,
+	n_cpg: usize,
+	meth: f64,
+}
+
+#[derive(Copy, Clone)]
+struct SegCpg {
+	pos: u32,
+	meth: c_int,
+	unmeth: c_int,
+}
+
+impl SegCpg {
+	fn level(&self) -> f64 { (self.meth as f64) / ((self.meth + self.unmeth) as f64) }
+}
+
+#[derive(Default, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SegTypeSummary {
+	count: usize,
+	total_length: u64,
+	#[serde(rename = "CpGs")]
+	cpgs: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SampleSegSummary {
+	sample: String,
+	#[serde(rename = "MinCpGs")]
+	min_cpgs: Option<usize>,
+	#[serde(rename = "UMR")]
+	umr: SegTypeSummary,
+	#[serde(rename = "LMR")]
+	lmr: SegTypeSummary,
+	#[serde(rename = "PMD")]
+	pmd: SegTypeSummary,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SegSummary {
+	segmentation: Vec<SampleSegSummary>,
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+	fn next(&mut self) -> u64 {
+		let mut x = self.0;
+		x ^= x << 13;
+		x ^= x >> 7;
+		x ^= x << 17;
+		self.0 = x;
+		x
+	}
+	fn shuffle<T>(&mut self, v: &mut [T]) {
+		for i in (1..v.len()).rev() {
+			let j = (self.next() % ((i + 1) as u64)) as usize;
+			v.swap(i, j)
+		}
+	}
+}
+
+#[derive(Default)]
+struct SampleSeg {
+	cpgs: Vec<SegCpg>, // CpGs for current contig
+	segs: Vec<Segment>, // PMDs and UMR/LMR candidates
+	hist: [Vec<usize>; 2], // UMR/LMR candidates by number of CpGs for the observed and shuffled data
+}
+
+fn log_norm(x: f64, (mu, sd): (f64, f64)) -> f64 {
+	let z = (x - mu) / sd;
+	-0.5 * z * z - sd.ln()
+}
+
+// Most likely state sequence (true for PMD) for the window methylation levels
+fn pmd_viterbi(x: &[f64]) -> Vec<bool> {
+	let (l_stay, l_switch) = ((1.0 - PMD_SWITCH).ln(), PMD_SWITCH.ln());
+	let mut v = [log_norm(x[0], PMD_EMISSION[0]), log_norm(x[0], PMD_EMISSION[1])];
+	let mut tb = Vec::with_capacity(x.len());
+	for xi in x[1..].iter() {
+		let mut nv = [0.0; 2];
+		let mut t = [0; 2];
+		for s in 0..2 {
+			let (a, b) = (v[s] + l_stay, v[1 - s] + l_switch);
+			let (z, prev) = if a >= b { (a, s) } else { (b, 1 - s) };
+			nv[s] = z + log_norm(*xi, PMD_EMISSION[s]);
+			t[s] = prev;
+		}
+		tb.push(t);
+		v = nv;
+	}
+	let mut s = if v[1] > v[0] { 1 } else { 0 };
+	let mut st = vec![false; x.len()];
+	for i in (0..x.len()).rev() {
+		st[i] = s == 1;
+		if i > 0 { s = tb[i - 1][s] }
+	}
+	st
+}
+
+// PMDs from a 2 state HMM on the mean methylation of non-overlapping windows of PMD_WIN CpGs.  Returns index ranges [a, b)
+fn find_pmds(cpgs: &[SegCpg], min_size: u32) -> Vec<(usize, usize)> {
+	let nw = cpgs.len() / PMD_WIN;
+	let mut pmds = Vec::new();
+	if nw < 2 { return pmds }
+	let x: Vec<f64> = cpgs.chunks_exact(PMD_WIN).map(|w| w.iter().map(|c| c.level()).sum::<f64>() / (PMD_WIN as f64)).collect();
+	let st = pmd_viterbi(&x);
+	let mut i = 0;
+	while i < nw {
+		if st[i] {
+			let j = (i..nw).find(|k| !st[*k]).unwrap_or(nw);
+			let (a, b) = (i * PMD_WIN, j * PMD_WIN);
+			if cpgs[b - 1].pos + 2 - cpgs[a].pos >= min_size { pmds.push((a, b)) }
+			i = j
+		} else { i += 1 }
+	}
+	pmds
+}
+
+// Runs of at least SEG_MIN_CPG CpGs where the smoothed methylation (pooled over the CpG and its neighbours) is below cut.
+// Returns index ranges [a, b)
+fn low_meth_runs(cpgs: &[SegCpg], cut: f64) -> Vec<(usize, usize)> {
+	let n = cpgs.len();
+	let mut runs = Vec::new();
+	let mut start = None;
+	for i in 0..=n {
+		let low = i < n && {
+			let (m, t) = cpgs[i.saturating_sub(1)..(i + 2).min(n)].iter().fold((0, 0), |(m, t), c| (m + c.meth, t + c.meth + c.unmeth));
+			(m as f64) < cut * (t as f64)
+		};
+		match (low, start) {
+			(true, None) => start = Some(i),
+			(false, Some(a)) => {
+				if i - a >= SEG_MIN_CPG { runs.push((a, i)) }
+				start = None
+			},
+			_ => (),
+		}
+	}
+	runs
+}
+
+// Smallest number of CpGs for UMR/LMR calls so that the ratio of calls from shuffled data to calls from observed data
+// is within the FDR level for this and all higher cutoffs (the ratio is not monotone in the cutoff).  Cutoffs with no
+// observed calls are ignored
+fn calc_min_cpgs(hist: &[Vec<usize>; 2], fdr: f64) -> Option<usize> {
+	let (mut obs, mut exp) = (0, 0);
+	let mut best = None;
+	for n in (SEG_MIN_CPG..SEG_MAX_HIST).rev() {
+		obs += hist[0][n];
+		exp += hist[1][n];
+		if obs > 0 {
+			if (exp as f64) > fdr * (obs as f64) { break }
+			best = Some(n)
+		}
+	}
+	best
+}
+
+pub fn print_segment_header(f: &mut HtsFile, _hdr: &VcfHeader, _chash: &ConfHash) -> io::Result<()> {
+	writeln!(f, "track name=\"Segmentation\" description=\"UMR, LMR and PMD segmentation\" visibility=2")
+}
+
+// Segmentation of the methylome of each sample into unmethylated regions (UMRs), low methylated regions (LMRs) and
+// partially methylated domains (PMDs), along the lines of MethylSeekR.  CpGs passing the filters for the CpG output with
+// sufficient coverage are collected for each contig.  PMDs are called first using a 2 state HMM on windows of CpGs.
+// Outside of PMDs, UMR/LMR candidates are runs of CpGs with smoothed methylation below the cutoff.  The same procedure
+// is applied to the data after shuffling the methylation values between CpGs, and the minimum number of CpGs for a call
+// is chosen (genome wide for each sample) to control the FDR.  Calls with mean methylation below umr_meth are UMRs,
+// otherwise LMRs.  All segments are written out once the input has been processed
+pub struct SegCalc {
+	min_cov: c_int,
+	meth_cut: f64,
+	umr_meth: f64,
+	fdr: f64,
+	pmd_min_size: u32,
+	curr_rid: Option<u32>,
+	rng: XorShift,
+	samples: Vec<SampleSeg>,
+}
+
+impl SegCalc {
+	pub fn new(chash: &ConfHash, ns: usize) -> Self {
+		Self {
+			min_cov: chash.get_int("seg_min_cov").max(1) as c_int,
+			meth_cut: chash.get_float("seg_meth_cut"),
+			umr_meth: chash.get_float("seg_umr_meth"),
+			fdr: chash.get_float("seg_fdr"),
+			pmd_min_size: chash.get_int("pmd_min_size") as u32,
+			curr_rid: None,
+			rng: XorShift(SEG_SEED),
+			samples: (0..ns).map(|_| SampleSeg{hist: [vec![0; SEG_MAX_HIST], vec![0; SEG_MAX_HIST]], ..Default::default()}).collect(),
+		}
+	}
+
+	// Segment current contig
+	fn process_contig(&mut self, chash: &ConfHash) {
+		let rid = match self.curr_rid.take() {
+			Some(r) => r,
+			None => return,
+		};
+		let out_ix = chash.vcf_contigs()[rid as usize].out_ix().expect("Missing out index for contig");
+		for (sample, ss) in self.samples.iter_mut().enumerate() {
+			let cpgs = &ss.cpgs;
+			let mk_seg = |a: usize, b: usize, seg_type: SegType| Segment{out_ix, rid, start: cpgs[a].pos, end: cpgs[b - 1].pos + 2, sample, seg_type,
+				n_cpg: b - a, meth: cpgs[a..b].iter().map(|c| c.level()).sum::<f64>() / ((b - a) as f64)};
+			let pmds = find_pmds(cpgs, self.pmd_min_size);
+			// Non PMD stretches
+			let mut stretches = Vec::with_capacity(pmds.len() + 1);
+			let mut x = 0;
+			for (a, b) in pmds.iter() {
+				if *a > x { stretches.push((x, *a)) }
+				ss.segs.push(mk_seg(*a, *b, SegType::Pmd));
+				x = *b;
+			}
+			if x < cpgs.len() { stretches.push((x, cpgs.len())) }
+			let mut shuffled: Vec<_> = stretches.iter().flat_map(|(a, b)| cpgs[*a..*b].iter().copied()).collect();
+			for (a, b) in stretches.iter() {
+				for (c, d) in low_meth_runs(&cpgs[*a..*b], self.meth_cut) {
+					let seg = mk_seg(a + c, a + d, SegType::Lmr);
+					let seg_type = if seg.meth < self.umr_meth { SegType::Umr } else { SegType::Lmr };
+					ss.hist[0][seg.n_cpg.min(SEG_MAX_HIST - 1)] += 1;
+					ss.segs.push(Segment{seg_type, ..seg});
+				}
+			}
+			// Shuffle methylation values but keep positions
+			let pos: Vec<_> = shuffled.iter().map(|c| c.pos).collect();
+			self.rng.shuffle(&mut shuffled);
+			for (c, p) in shuffled.iter_mut().zip(pos) { c.pos = p }
+			let mut x = 0;
+			for (a, b) in stretches.iter() {
+				let l = b - a;
+				for (c, d) in low_meth_runs(&shuffled[x..x + l], self.meth_cut) { ss.hist[1][(d - c).min(SEG_MAX_HIST - 1)] += 1 }
+				x += l;
+			}
+			ss.cpgs.clear();
+		}
+	}
+
+	pub fn output_block(&mut self, _outfiles: &mut [HtsFile], rec_blk: &RecordBlock, prev: Option<RecordBlockElem>, chash: &ConfHash, _hdr: &VcfHeader) -> io::Result<()> {
+		let opts = OutputOpts::new(chash);
+		let (mut prev, recs) = rec_slices(rec_blk, prev);
+		for (rec2, mvec2) in recs {
+			if let Some((rec1, mvec1)) = prev {
+				if rec1.gt().is_some() && rec2.gt().is_some() && rec1.rid == rec2.rid && rec1.pos + 1 == rec2.pos {
+					if self.curr_rid != Some(rec1.rid) {
+						self.process_contig(chash);
+						self.curr_rid = Some(rec1.rid);
+					}
+					self.add_cpg(rec1.pos, mvec1, mvec2, &opts);
+				}
+			}
+			prev = Some((rec2, mvec2));
+		}
+		Ok(())
+	}
+
+	fn add_cpg(&mut self, pos: u32, mvec1: &[MethRec], mvec2: &[MethRec], opts: &OutputOpts) {
+		for ((m1, m2), ss) in mvec1.iter().zip(mvec2.iter()).zip(self.samples.iter_mut()) {
+			if let Some((meth, unmeth)) = cpg_meth_counts(m1, m2, opts) {
+				if meth + unmeth >= self.min_cov { ss.cpgs.push(SegCpg{pos, meth, unmeth}) }
+			}
+		}
+	}
+
+	// Select UMR/LMR calls and write out all segments (BED, bigBed and JSON summary)
+	pub fn finish<W: Write>(&mut self, f: &mut W, chash: &ConfHash, hdr: &VcfHeader, json_name: &str) -> io::Result<()> {
+		self.process_contig(chash);
+		let mut summary = SegSummary{segmentation: Vec::with_capacity(self.samples.len())};
+		let mut segs = Vec::new();
+		for (sample, ss) in self.samples.iter_mut().enumerate() {
+			let min_cpgs = calc_min_cpgs(&ss.hist, self.fdr);
+			let name = hdr.sample_name(sample)?.to_owned();
+			match min_cpgs {
+				Some(n) => info!("Segmentation for sample {}: minimum CpGs for UMR/LMR calls {}", name, n),
+				None => warn!("Segmentation for sample {}: no UMR/LMR calls within FDR", name),
+			}
+			let mut summ = SampleSegSummary{sample: name, min_cpgs, umr: Default::default(), lmr: Default::default(), pmd: Default::default()};
+			for s in ss.segs.drain(..).filter(|s| s.seg_type == SegType::Pmd || min_cpgs.map(|n| s.n_cpg >= n).unwrap_or(false)) {
+				let st = match s.seg_type {
+					SegType::Umr => &mut summ.umr,
+					SegType::Lmr => &mut summ.lmr,
+					SegType::Pmd => &mut summ.pmd,
+				};
+				st.count += 1;
+				st.total_length += (s.end - s.start) as u64;
+				st.cpgs += s.n_cpg;
+				segs.push(s);
+			}
+			summary.segmentation.push(summ);
+		}
+		segs.sort_by_key(|s| (s.out_ix, s.start, s.end, s.sample));
+
+		let bbi_ref = chash.bbi().read().unwrap();
+		let bbi = bbi_ref.as_ref().expect("Bbi not set");
+		let sender = bbi.sender().expect("Bbi sender not set");
+		let mut bb_build = bbi.bb_files()[0].build().write().unwrap();
+		let mut prev_ctg = None;
+		for s in segs.iter() {
+			if matches!(prev_ctg, Some(rid) if rid != s.rid) {
+				bb_build.finish(sender);
+				bb_build.clear_counts();
+			}
+			prev_ctg = Some(s.rid);
+			let sbuf = format!("{}\t{}\t.\t{}\t{}\t{:.3}", s.seg_type.name(), ((s.meth * 1000.0 + 0.5) as usize).min(1000),
+				summary.segmentation[s.sample].sample, s.n_cpg, s.meth);
+			writeln!(f, "{}\t{}\t{}\t{}", hdr.ctg_name(s.rid as usize).unwrap(), s.start, s.end, &sbuf)?;
+			bb_build.add_bb_region(s.out_ix as u32, s.start, s.end, &sbuf, sender);
+			bb_build.add_zoom_obs(s.out_ix as u32, s.start, s.meth as f32, sender);
+		}
+
+		let wrt = BufWriter::new(File::create(json_name)?);
+		serde_json::to_writer_pretty(wrt, &summary).map_err(|e| new_err(format!("Error writing JSON file {}: {}", json_name, e)))?;
+		chash.add_file(json_name, None);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// CpGs every 100bp with the given (methylated, non-methylated) counts
+	fn make_cpgs(cts: &[(c_int, c_int)]) -> Vec<SegCpg> {
+		cts.iter().enumerate().map(|(i, (meth, unmeth))| SegCpg{pos: (i * 100) as u32, meth: *meth, unmeth: *unmeth}).collect()
+	}
+
+	#[test]
+	fn viterbi_states() {
+		let x: Vec<f64> = [0.75; 10].iter().chain([0.45; 10].iter()).chain([0.75; 10].iter()).copied().collect();
+		let st = pmd_viterbi(&x);
+		assert!(st.iter().enumerate().all(|(i, s)| *s == (10..20).contains(&i)));
+		// A single low window is not enough to switch state
+		let mut x = vec![0.75; 20];
+		x[10] = 0.45;
+		assert!(pmd_viterbi(&x).iter().all(|s| !*s));
+	}
+
+	#[test]
+	fn pmds_from_windows() {
+		// 20 windows with windows 5..15 partially methylated
+		let cts: Vec<_> = (0..20 * PMD_WIN).map(|i| if (5 * PMD_WIN..15 * PMD_WIN).contains(&i) { (9, 11) } else { (15, 5) }).collect();
+		let cpgs = make_cpgs(&cts);
+		assert_eq!(find_pmds(&cpgs, 50_000), vec![(5 * PMD_WIN, 15 * PMD_WIN)]);
+		// The PMD spans 99,902bp
+		assert!(find_pmds(&cpgs, 100_000).is_empty());
+		// Need at least 2 windows
+		assert!(find_pmds(&cpgs[..PMD_WIN + 50], 0).is_empty());
+	}
+
+	#[test]
+	fn low_meth_run_limits() {
+		let (hi, lo) = ((10, 0), (0, 10));
+		let cpgs = make_cpgs(&[hi, hi, hi, hi, hi, lo, lo, lo, lo, lo, hi, hi, hi, hi, hi]);
+		assert_eq!(low_meth_runs(&cpgs, 0.5), vec![(5, 10)]);
+		// Runs shorter than SEG_MIN_CPG are dropped
+		let cpgs = make_cpgs(&[hi, hi, hi, lo, lo, hi, hi, hi]);
+		assert!(low_meth_runs(&cpgs, 0.5).is_empty());
+		// Runs at the ends of the CpG list
+		let cpgs = make_cpgs(&[lo, lo, lo, lo, hi, hi, hi, lo, lo, lo, lo]);
+		assert_eq!(low_meth_runs(&cpgs, 0.5), vec![(0, 4), (7, 11)]);
+	}
+
+	#[test]
+	fn min_cpgs_cutoff() {
+		let mut hist = [vec![0; SEG_MAX_HIST], vec![0; SEG_MAX_HIST]];
+		assert_eq!(calc_min_cpgs(&hist, 0.05), None);
+		hist[0][20] = 100;
+		hist[0][4] = 1000;
+		assert_eq!(calc_min_cpgs(&hist, 0.05), Some(SEG_MIN_CPG));
+		// The cumulative ratio fails at 8 but is within the FDR again at 4, so the cutoff should stay above 8
+		hist[1][8] = 50;
+		assert_eq!(calc_min_cpgs(&hist, 0.05), Some(9));
+		// Shuffled calls above the longest observed call don't count
+		hist[1][8] = 0;
+		hist[1][100] = 1;
+		assert_eq!(calc_min_cpgs(&hist, 0.05), Some(SEG_MIN_CPG));
+	}
+}
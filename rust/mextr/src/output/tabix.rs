@@ -171,9 +171,8 @@ pub fn tabix_thread(chash: Arc<ConfHash>, r: Receiver<bool>) {
 	let mut new_files = chash.n_out_files() > n_files;
 	loop {
 		if new_files {
-			for (s, _) in chash.out_files().drain(tabix_files.len()..).filter(|(_, fg)| *fg) {
+			for (s, skip_lines) in chash.out_files().drain(n_files..).filter_map(|(s, k)| k.map(|x| (s, x))) {
 				debug!("tabix_thread: Adding file {}", s);
-				let skip_lines = if chash.get_bool("no_header") { 0 } else { 1 };
 				tabix_files.push(TabixFile::new(s, skip_lines))
 			}
 			n_files = chash.n_out_files();
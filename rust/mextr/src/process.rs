@@ -18,10 +18,18 @@ pub type TPool = Arc<Option<htsThreadPool>>;
 type ExtraFn = fn(Arc<ConfHash>, Receiver<bool>);
 type OutputFn = fn(Arc<ConfHash>, Arc<VcfHeader>, Recv, TPool);
 
-const OUTPUTS: [(&str, OutputFn); 3] = [
+const OUTPUTS: [(&str, OutputFn); 11] = [
 	("cpgfile", output_cpg_thread),
 	("noncpgfile", output_noncpg_thread),
+	("matrix", output_matrix_thread),
+	("region_summary", output_region_summary_thread),
 	("bed_methyl", output_bed_methyl_thread),
+	("bismark_cov", output_bismark_cov_thread),
+	("cx_report", output_cx_report_thread),
+	("methylkit", output_methylkit_thread),
+	("methyldackel", output_methyldackel_thread),
+	("dmr", output_dmr_thread),
+	("segmentation", output_segmentation_thread),
 ];
 
 const EXTRAS: [(&str, ExtraFn); 2] = [("md5", md5_thread), ("tabix", tabix_thread)];
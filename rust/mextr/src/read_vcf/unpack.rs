@@ -1,13 +1,14 @@
 use std::sync::Arc;
 use std::convert::TryInto;
 use std::f64::consts::LN_10;
+use std::str::FromStr;
 
 use libc::c_int;
 use crossbeam_channel::{Sender, Receiver};
 use r_htslib::*;
 use crate::config::*;
 
-use super::model::{Model, MAX_QUAL};
+use super::model::{Chemistry, Model, MAX_QUAL};
 use super::BrecBlock;
 use crate::output::{Record, MethRec};
 use crate::read_vcf::BREC_BLOCK_SIZE;
@@ -73,10 +74,15 @@ fn handle_aq(aq: &[i32], ct: &[c_int]) -> [c_int; 8] {
 	} else { aq.try_into().unwrap() }
 }
 
+fn get_chemistry(cf: &ConfHash) -> Chemistry {
+	cf.get_str("chemistry").and_then(|s| <Chemistry>::from_str(s).ok()).unwrap_or(Chemistry::Bisulfite)
+}
+
 fn setup_model(cf: &ConfHash) -> Model {
 	Model::new(
 		(cf.get_float("under_conversion"), cf.get_float("over_conversion")),
 		cf.get_float("reference_bias"),
+		get_chemistry(cf),
 		cf.get_bool("haploid"), 
 		false // Get natural logs
 	)
@@ -149,6 +155,7 @@ pub struct UnpackData {
 	model: Model,
 	bq: c_int,
 	common_gt: bool,
+	taps: bool,
 	ns: usize,
 	idx: usize,
 	mrec_vec: Option<Vec<MethRec>>,
@@ -164,10 +171,11 @@ impl UnpackData {
 		let model = setup_model(&chash);
 		let bq = chash.get_int("bq_threshold").min(MAX_QUAL) as c_int;
 		let common_gt = chash.get_bool("common_gt");
+		let taps = get_chemistry(chash).converts_methylated();
 		let mrec_vec = Some(Vec::with_capacity(ns));
 		let rec_blk = Some(if ns > 1 { RecordBlock::Multi(Vec::with_capacity(BREC_BLOCK_SIZE))}
 		else { RecordBlock::Single(Vec::with_capacity(BREC_BLOCK_SIZE))});
-		Self{ mdb_mc8, mdb_aq, mdb_mq, mdb_cx, model, bq, common_gt, ns, mrec_vec, rec_blk, idx: 0}		
+		Self{ mdb_mc8, mdb_aq, mdb_mq, mdb_cx, model, bq, common_gt, taps, ns, mrec_vec, rec_blk, idx: 0}		
 	}	
 }
 
@@ -210,6 +218,7 @@ pub fn unpack_vcf(brec: &mut BcfRec, hdr: &VcfHeader, udata: &mut UnpackData) {
 	
 	let mut mrec_vec = udata.mrec_vec.as_mut().unwrap();	 
 	let ne_aq = udata.mdb_aq.len() / ns;
+	let taps = udata.taps;
 	mrec_vec.clear();
 	for ix in 0..ns {
 		let cx: [u8; 5] = (&udata.mdb_cx[ix * cx_step..(ix * cx_step) + 5] as &[u8]).try_into().unwrap();
@@ -219,12 +228,12 @@ pub fn unpack_vcf(brec: &mut BcfRec, hdr: &VcfHeader, udata: &mut UnpackData) {
  		let mq = udata.mdb_mq[ix] as u8;
 		let mut meth = [0.0; 6];
 		let (max_gt, gt_probs) = if counts.iter().any(|x| *x > 0) {
-			let gt = udata.model.calc_gt_prob(&counts, &aq, ref_base, Some(&mut meth));
+			let gt = udata.model.calc_gt_prob(&counts, &aq, ref_base, None, Some(&mut meth));
 			(Some(gt.0 as u8), gt.1)
 		} else {		
 			(None, [0.0; 10])
 		};
-		mrec_vec.push(MethRec::new(counts, gt_probs, meth, cx, mq, max_gt));
+		mrec_vec.push(MethRec::new(counts, gt_probs, meth, cx, mq, max_gt, taps));
 	}
 	// Get common genotype call 
 	let gt_strand = if udata.ns > 1 { calc_common_gt(&mut mrec_vec, udata.common_gt) } 
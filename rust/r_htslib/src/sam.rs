@@ -5,7 +5,7 @@ use std::convert::TryInto;
 use std::ops::{Deref, DerefMut};
 use std::marker::PhantomData;
 use std::mem::MaybeUninit;
-use libc::{c_char, c_int, size_t};
+use libc::{c_char, c_int, c_void, size_t};
 use super::{hts_err, get_cstr, from_cstr, htsFile, HtsPos};
 
 pub const BAM_FPAIRED: u16 = 1;
@@ -181,6 +181,13 @@ impl bam1_t {
 			_ => Err(hts_err("Failed to update string tag".to_string())),
 		}
 	}
+	pub fn aux_update_u8_array(&mut self, tag: &str, data: &[u8]) -> io::Result<()> {
+		if tag.len() != 2 { return Err(hts_err("Failed to update array tag: tag length is not 2".to_string())) }
+		match unsafe { bam_aux_update_array(self, get_cstr(tag).as_ptr(), b'C', data.len() as u32, data.as_ptr() as *const c_void)} {
+			0 => Ok(()),
+			_ => Err(hts_err("Failed to update array tag".to_string())),
+		}
+	}
 	pub fn get_aux(&self) -> Option<&[u8]> {
 		unsafe {
 			let core = &self.core;
@@ -250,6 +257,7 @@ extern "C" {
 	fn bam_destroy1(b: *mut bam1_t);
 	fn bam_endpos(pt_: *const bam1_t) -> HtsPos;
 	fn bam_aux_update_str(pt_: *mut bam1_t, tag_: *const c_char, len_: c_int, data_: *const c_char) -> c_int;
+	fn bam_aux_update_array(pt_: *mut bam1_t, tag_: *const c_char, type_: u8, items_: u32, data_: *const c_void) -> c_int;
 	fn sam_read1(fp_: *mut htsFile, hd_: *mut sam_hdr_t, b_: *mut bam1_t) -> c_int;
 	fn sam_write1(fp_: *mut htsFile, hd_: *mut sam_hdr_t, b_: *const bam1_t) -> c_int;
 }
@@ -549,6 +557,71 @@ fn aux_type2size(tp: u8) -> u8 {
 	}
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BSStrand {Unknown, StrandC2T, StrandG2A, Unconverted}
+
+impl BSStrand {
+	pub fn get_num(&self) -> u16 {
+		match self {
+			BSStrand::Unknown => 0,
+			BSStrand::StrandC2T => 1,
+			BSStrand::StrandG2A => 2,
+			BSStrand::Unconverted => 3,
+		}
+	}
+}
+
+#[derive(PartialEq)]
+pub enum Aligner { Unknown, Gem, Bowtie, Novoalign, BSMap, BWAMeth }
+
+// Get bisulfite strand and the SA tag (if present) from the aligner specific tags
+pub fn get_bs_strand(b: &BamRec) -> (BSStrand, Option<&[u8]>) {
+	let mut sa_tag = None;
+	let mut strand = BSStrand::Unconverted;
+	if let Some(itr) = b.get_aux_iter() {
+		for tag in itr {
+			let aligner = {
+				if tag[0] == b'Z' {
+					if tag[1] == b'B' { Aligner::Novoalign }
+					else if tag[1] == b'S' { Aligner::BSMap }
+					else {Aligner::Unknown}
+				} else if tag[0] == b'X' {
+					if tag[1] == b'G' { Aligner::Bowtie }
+					else if tag[1] == b'B' { Aligner::Gem }
+					else { Aligner::Unknown }
+				} else if tag[0] == b'Y' && tag[1] == b'D' { Aligner::BWAMeth }
+				else { Aligner::Unknown } 
+			};
+			if aligner != Aligner::Unknown {
+				match tag[2] {
+					b'A' if aligner == Aligner::Gem => {
+						if tag[3] == b'C' { strand = BSStrand::StrandC2T }
+						else if tag[3] == b'G' { strand = BSStrand::StrandG2A }
+					},
+					b'Z' => match aligner {
+						Aligner::Bowtie | Aligner::Novoalign => {
+							if tag[3] == b'C' { strand = BSStrand::StrandC2T }
+							else if tag[3] == b'G' { strand = BSStrand::StrandG2A }						
+						},
+						Aligner::BSMap => {
+							if tag[3] == b'+' { strand = BSStrand::StrandC2T }
+							else if tag[3] == b'-' { strand = BSStrand::StrandG2A }													
+						}
+						Aligner::BWAMeth => {
+							if tag[3] == b'f' { strand = BSStrand::StrandC2T }
+							else if tag[3] == b'r' { strand = BSStrand::StrandG2A }													
+						}
+						_ => (),
+					},
+					_ => (),
+				}
+			} else if tag[0] == b'S' && tag[1] == b'A' && tag[2] == b'Z' { sa_tag = Some(&tag[3..]) }
+			if strand != BSStrand::Unconverted && sa_tag.is_some() { break }
+		}
+	}
+	(strand, sa_tag)
+}
+
 pub struct BamAuxIter<'a> { data: &'a[u8] }
 
 impl <'a>Iterator for BamAuxIter<'a> {
@@ -597,6 +670,10 @@ impl Deref for SeqQual {
 	fn deref(&self) -> &[u8] { self.0.deref() }
 }
 
+impl DerefMut for SeqQual {
+	fn deref_mut(&mut self) -> &mut [u8] { self.0.deref_mut() }
+}
+
 const FMT_BASES: [char;256] = [
 	'N', 'N', 'N', 'N', 'A', 'C', 'G', 'T', 'A', 'C', 'G', 'T', 'A', 'C', 'G', 'T', 
 	'A', 'C', 'G', 'T', 'A', 'C', 'G', 'T', 'A', 'C', 'G', 'T', 'A', 'C', 'G', 'T', 
@@ -24,6 +24,8 @@ pub struct tbx_conf_t {
 
 #[allow(non_upper_case_globals)]
 pub const tbx_conf_vcf: tbx_conf_t = tbx_conf_t{ preset: TBX_VCF, sc: 1, bc: 2, ec: 0, meta_char: b'#' as i32, line_skip: 0};
+#[allow(non_upper_case_globals)]
+pub const tbx_conf_bed: tbx_conf_t = tbx_conf_t{ preset: TBX_UCSC, sc: 1, bc: 2, ec: 3, meta_char: b'#' as i32, line_skip: 0};
 
 #[repr(C)]
 pub struct tbx_t {
@@ -110,6 +112,13 @@ impl Tbx {
 			Some(p) => Ok(Tbx{inner: p, phantom: PhantomData}), 
 		}
 	}
+	// Build tabix index for a bgzip compressed file
+	pub fn build<S: AsRef<str>>(name: S, conf: &tbx_conf_t) -> io::Result<()> {
+		let name = name.as_ref();
+		if unsafe{ tbx_index_build(get_cstr(name).as_ptr(), 0, conf) } < 0 {
+			Err(hts_err(format!("Couldn't build tabix index for file {}", name)))
+		} else { Ok(()) }
+	}
 }
 
 pub enum TbxReadResult { 
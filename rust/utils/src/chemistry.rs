@@ -0,0 +1,46 @@
+use std::str::FromStr;
+use std::fmt;
+
+// Conversion chemistry.  With bisulfite and enzymatic (EM-seq / APOBEC) conversion non-methylated Cs are read as T,
+// while with TAPS methylated Cs are read as T.  In all cases the under conversion rate is the proportion of Cs
+// that should be converted but are not, and the over conversion rate the proportion of Cs that should not be
+// converted but are
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Chemistry { Bisulfite, EmSeq, Taps }
+
+impl FromStr for Chemistry {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"bisulfite" | "bisulphite" | "bs" => Ok(Chemistry::Bisulfite),
+			"em-seq" | "emseq" | "em_seq" | "enzymatic" | "apobec" => Ok(Chemistry::EmSeq),
+			"taps" => Ok(Chemistry::Taps),
+			_ => Err("Chemistry: no match"),
+		}
+	}
+}
+
+impl fmt::Display for Chemistry {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let s = match self {
+			Chemistry::Bisulfite => "bisulfite",
+			Chemistry::EmSeq => "em-seq",
+			Chemistry::Taps => "taps",
+		};
+		write!(f, "{}", s)
+	}
+}
+
+impl Chemistry {
+	// Default (under, over) conversion rates
+	pub fn default_conversion(&self) -> (f64, f64) {
+		match self {
+			Chemistry::Bisulfite => (0.01, 0.05),
+			Chemistry::EmSeq => (0.005, 0.01),
+			Chemistry::Taps => (0.03, 0.005),
+		}
+	}
+	// True if methylated (rather than non-methylated) Cs are converted
+	pub fn converts_methylated(&self) -> bool { matches!(self, Chemistry::Taps) }
+}